@@ -0,0 +1,108 @@
+use crate::config::ImageFormat;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Number of recent transform durations kept per output format.
+pub const LATENCY_WINDOW: usize = 128;
+
+/// `Retry-After` bounds in seconds.
+pub const MIN_RETRY_AFTER_SECS: u64 = 1;
+pub const MAX_RETRY_AFTER_SECS: u64 = 60;
+
+/// Caps concurrent cache-miss transforms and estimates when to retry.
+///
+/// When every permit is taken, `/img` answers 503 with a `Retry-After`
+/// derived from the recent p95 transform time for the requested format,
+/// so clients back off roughly as long as a slot takes to free up.
+///
+/// Cloning shares the same permits and latency history.
+#[derive(Debug, Clone)]
+pub struct TransformLimiter {
+    permits: Arc<Semaphore>,
+    latencies: Arc<Mutex<HashMap<ImageFormat, VecDeque<Duration>>>>,
+}
+
+impl TransformLimiter {
+    /// Allows at most `max_concurrent` transforms at once (minimum 1).
+    pub fn new(max_concurrent: usize) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(max_concurrent.max(1))),
+            latencies: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Takes a transform slot without waiting; `None` means saturated.
+    pub fn try_acquire(&self) -> Option<OwnedSemaphorePermit> {
+        self.permits.clone().try_acquire_owned().ok()
+    }
+
+    /// Records how long a transform to `format` took.
+    pub fn record(&self, format: ImageFormat, duration: Duration) {
+        let mut latencies = self.latencies.lock().unwrap();
+        let window = latencies.entry(format).or_default();
+        if window.len() == LATENCY_WINDOW {
+            window.pop_front();
+        }
+        window.push_back(duration);
+    }
+
+    /// 95th percentile of recent transform durations for `format`.
+    pub fn p95(&self, format: ImageFormat) -> Option<Duration> {
+        let latencies = self.latencies.lock().unwrap();
+        let window = latencies.get(&format)?;
+        if window.is_empty() {
+            return None;
+        }
+        let mut sorted: Vec<Duration> = window.iter().copied().collect();
+        sorted.sort();
+        let idx = ((sorted.len() as f64 * 0.95).ceil() as usize).clamp(1, sorted.len()) - 1;
+        Some(sorted[idx])
+    }
+
+    /// Suggested `Retry-After` in whole seconds for a request targeting `format`.
+    ///
+    /// Rounds the p95 up and clamps it to
+    /// [`MIN_RETRY_AFTER_SECS`]..=[`MAX_RETRY_AFTER_SECS`]; with no history
+    /// the minimum is returned.
+    pub fn retry_after_secs(&self, format: ImageFormat) -> u64 {
+        self.p95(format)
+            .map(|p95| p95.as_secs_f64().ceil() as u64)
+            .unwrap_or(MIN_RETRY_AFTER_SECS)
+            .clamp(MIN_RETRY_AFTER_SECS, MAX_RETRY_AFTER_SECS)
+    }
+}
+
+/// How long a fetch waits for a [`FetchLimiter`] slot by default.
+pub const DEFAULT_FETCH_QUEUE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Caps concurrent outbound source fetches.
+///
+/// A burst of cache misses would otherwise open as many connections as
+/// there are requests, exhausting file descriptors and swamping origins.
+/// Fetches queue for a slot; one still waiting after `max_wait` gives up,
+/// and `/img` answers 503.
+///
+/// Cloning shares the same permits.
+#[derive(Debug, Clone)]
+pub struct FetchLimiter {
+    permits: Arc<Semaphore>,
+    max_wait: Duration,
+}
+
+impl FetchLimiter {
+    /// Allows at most `max_concurrent` fetches at once (minimum 1), each
+    /// waiting up to `max_wait` for a slot.
+    pub fn new(max_concurrent: usize, max_wait: Duration) -> Self {
+        Self { permits: Arc::new(Semaphore::new(max_concurrent.max(1))), max_wait }
+    }
+
+    /// Waits up to `max_wait` for a fetch slot; `None` means it timed out.
+    pub async fn acquire(&self) -> Option<OwnedSemaphorePermit> {
+        tokio::time::timeout(self.max_wait, self.permits.clone().acquire_owned())
+            .await
+            .ok()
+            .and_then(Result::ok)
+    }
+}
@@ -1,5 +1,5 @@
 use axum::{
-    http::{header, HeaderValue, Request, Response},
+    http::{header, HeaderMap, HeaderValue, Request, Response},
     middleware::Next,
     body::Body,
 };
@@ -87,6 +87,49 @@ impl CloudflareCacheConfig {
         }
     }
     
+    /// Bounds every cache lifetime by the remaining validity of a signed URL.
+    ///
+    /// A URL carrying an expiry (`t`) must not outlive itself in any cache,
+    /// so browser/edge TTLs and stale windows are clamped to `remaining_secs`
+    /// and `immutable` is dropped. A zero remaining lifetime disables caching.
+    pub fn bounded_by(mut self, remaining_secs: u32) -> Self {
+        self.edge_max_age = self.edge_max_age.min(remaining_secs);
+        self.browser_max_age = self.browser_max_age.min(remaining_secs);
+        self.stale_if_error = self.stale_if_error.map(|s| s.min(remaining_secs));
+        self.stale_while_revalidate = self.stale_while_revalidate.map(|s| s.min(remaining_secs));
+        self.immutable = false;
+        self
+    }
+
+    /// Writes `Cache-Control`, `CDN-Cache-Control` and `Expires` for this policy.
+    pub fn apply_headers(&self, headers: &mut HeaderMap) {
+        if let Ok(value) = HeaderValue::from_str(&self.cache_control_value()) {
+            headers.insert(header::CACHE_CONTROL, value);
+        }
+
+        if let Some(expires) = self.expires_value(std::time::SystemTime::now()) {
+            if let Ok(value) = HeaderValue::from_str(&expires) {
+                headers.insert(header::EXPIRES, value);
+            }
+        }
+
+        if let Ok(value) = HeaderValue::from_str(&self.cdn_cache_control_value()) {
+            headers.insert(header::HeaderName::from_static("cdn-cache-control"), value);
+        }
+    }
+
+    /// `Expires` equivalent of `max-age` for caches that predate
+    /// `Cache-Control`: `now + browser_max_age` as an HTTP date.
+    ///
+    /// `None` when the policy disables caching.
+    pub fn expires_value(&self, now: std::time::SystemTime) -> Option<String> {
+        if self.edge_max_age == 0 {
+            return None;
+        }
+        let expires = now + std::time::Duration::from_secs(self.browser_max_age.into());
+        Some(httpdate::fmt_http_date(expires))
+    }
+
     /// Generates RFC 7234 compliant Cache-Control header value.
     ///
     /// Combines directives optimized for Cloudflare's caching behavior,
@@ -141,6 +184,8 @@ impl CloudflareCacheConfig {
 ///
 /// # Behavior
 /// - Only modifies successful responses to avoid caching error states
+/// - Leaves responses alone when the handler already chose a Cache-Control
+///   (e.g. expiring signed URLs or `no-store` uploads)
 /// - Sets Cache-Control with dual TTLs for browser and edge caching
 /// - Adds CDN-Cache-Control for Cloudflare-specific configuration
 /// - Includes Vary: Accept-Encoding to support compression negotiation
@@ -151,17 +196,8 @@ pub async fn cloudflare_cache_middleware(
     let mut response = next.run(req).await;
     
     if response.status().is_success() {
-        let config = CloudflareCacheConfig::for_images();
-        
-        if let Ok(value) = HeaderValue::from_str(&config.cache_control_value()) {
-            response.headers_mut().insert(header::CACHE_CONTROL, value);
-        }
-        
-        if let Ok(value) = HeaderValue::from_str(&config.cdn_cache_control_value()) {
-            response.headers_mut().insert(
-                header::HeaderName::from_static("cdn-cache-control"),
-                value,
-            );
+        if !response.headers().contains_key(header::CACHE_CONTROL) {
+            CloudflareCacheConfig::for_images().apply_headers(response.headers_mut());
         }
         
         // Enable cache variance based on compression negotiation
@@ -208,6 +244,24 @@ mod tests {
         assert!(!value.contains("immutable"));
     }
     
+    #[test]
+    fn test_bounded_by_remaining_lifetime() {
+        let config = CloudflareCacheConfig::for_images().bounded_by(120);
+        let value = config.cache_control_value();
+        
+        assert!(value.contains("max-age=120"));
+        assert!(value.contains("s-maxage=120"));
+        assert!(!value.contains("immutable"));
+        assert_eq!(config.cdn_cache_control_value(), "max-age=120");
+    }
+    
+    #[test]
+    fn test_bounded_by_zero_disables_caching() {
+        let config = CloudflareCacheConfig::for_images().bounded_by(0);
+        
+        assert_eq!(config.cache_control_value(), "no-store, no-cache, must-revalidate");
+    }
+    
     #[test]
     fn test_cdn_cache_control() {
         let config = CloudflareCacheConfig::default();
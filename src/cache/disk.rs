@@ -1,16 +1,100 @@
-use crate::cache::Cache;
+use crate::cache::{hash_key, Cache, ENCODER_VERSION};
 use crate::config::ImageFormat;
-use sha2::{Digest, Sha256};
-use std::{collections::BTreeMap, path::{Path, PathBuf}};
-use tokio::fs;
+use dashmap::DashMap;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::SystemTime,
+};
+use tokio::{fs, io::AsyncWriteExt, sync::Mutex};
+
+/// Extensions `put` writes; only such files count toward (and are evicted
+/// under) `max_size`, so other data sharing the directory is left alone.
+const ENTRY_EXTENSIONS: &[&str] = &[
+    "webp",
+    "jpeg",
+    "avif",
+    "png",
+    "ico",
+    #[cfg(feature = "extra_formats")]
+    "tiff",
+    #[cfg(feature = "extra_formats")]
+    "bmp",
+];
+
+/// Extension of the sidecar holding an entry's BlurHash; see
+/// [`DiskCache::put_blurhash`]. Not an entry itself, but evicted with one.
+const BLURHASH_EXTENSION: &str = "blurhash";
+
+/// Distinguishes temp files of concurrent writers within this process.
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+lazy_static::lazy_static! {
+    /// Per-directory bookkeeping, shared by every `DiskCache` on that
+    /// directory so short-lived handles (one per request) still coordinate
+    static ref DIRS: DashMap<PathBuf, Arc<DirState>> = DashMap::new();
+}
+
+/// State shared by the `DiskCache`s on one directory.
+#[derive(Default)]
+struct DirState {
+    /// Serializes concurrent `put`s of the same key.
+    write_locks: DashMap<String, Arc<Mutex<()>>>,
+    /// Running total of entry bytes, seeded by one scan on the first `put`
+    size: AtomicU64,
+    /// Running entry count, seeded by the same scan
+    count: AtomicUsize,
+    seeded: tokio::sync::OnceCell<()>,
+    /// Held while an eviction pass runs, so only one scans at a time
+    evicting: Mutex<()>,
+}
+
+impl DirState {
+    /// Seeds `size` and `count` from a scan of `dir` once; later calls
+    /// return immediately.
+    async fn seed(&self, dir: &Path) {
+        self.seeded
+            .get_or_init(|| async {
+                // A missing or unreadable directory holds nothing yet
+                let entries = entries(dir).await.unwrap_or_default();
+                self.size.store(entries.iter().map(|(_, len, _)| len).sum(), Ordering::Relaxed);
+                self.count.store(entries.len(), Ordering::Relaxed);
+            })
+            .await;
+    }
+}
+
+/// Cache entries in `dir` as `(modified, len, path)`, skipping other files.
+async fn entries(dir: &Path) -> Result<Vec<(SystemTime, u64, PathBuf)>, String> {
+    let mut entries = Vec::new();
+    let mut read = fs::read_dir(dir).await.map_err(|e| e.to_string())?;
+    while let Some(entry) = read.next_entry().await.map_err(|e| e.to_string())? {
+        let path = entry.path();
+        let is_entry = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| ENTRY_EXTENSIONS.contains(&e));
+        let Ok(meta) = entry.metadata().await else { continue };
+        if !is_entry || !meta.is_file() {
+            continue;
+        }
+        entries.push((meta.modified().unwrap_or(SystemTime::UNIX_EPOCH), meta.len(), path));
+    }
+    Ok(entries)
+}
 
 /// Simple filesystem-based cache implementation.
 ///
 /// **Production Warning:** This implementation has significant limitations:
-/// - No automatic eviction policy (unbounded growth)
-/// - No size tracking or limits
-/// - Potential race conditions on concurrent writes
-/// - No atomic operations or file locking
+/// - Growth is unbounded unless a cap is set with [`with_max_size`](Self::with_max_size);
+///   the size is tracked in memory, but each eviction pass scans the directory
+/// - Writers are only serialized within one process (across all handles on
+///   the same directory); entries are replaced atomically, but two
+///   processes may still race to write the same key
 ///
 /// Suitable for:
 /// - Development and testing environments
@@ -21,35 +105,177 @@ use tokio::fs;
 /// - Automatic LRU eviction
 /// - Size limits and tracking
 /// - Better concurrency handling
-/// - Atomic operations
+/// - Safe access from multiple processes
 pub struct DiskCache {
     dir: PathBuf,
+    encoder_version: String,
+    namespace: String,
+    max_size: Option<u64>,
+    max_entries: Option<usize>,
+    /// Shared with every other handle on `dir`
+    shared: Arc<DirState>,
 }
 
 impl DiskCache {
     /// Creates new disk cache instance at specified directory.
     ///
     /// Directory will be created automatically on first write if it doesn't exist.
+    /// Handles on the same directory share their write locks, so creating one
+    /// per request is cheap and safe.
     pub fn new(dir: PathBuf) -> Self {
-        Self { dir }
+        let shared = DIRS.entry(dir.clone()).or_default().clone();
+        Self {
+            dir,
+            encoder_version: ENCODER_VERSION.to_string(),
+            namespace: String::new(),
+            max_size: None,
+            max_entries: None,
+            shared,
+        }
+    }
+
+    /// Caps the total size of cached files; `None` (the default) is unbounded.
+    ///
+    /// When a `put` takes the cache over the cap, the oldest entries by
+    /// modification time are deleted until it fits again. Puts under the
+    /// cap only update a running total, never touching the directory.
+    pub fn with_max_size(mut self, max_size: Option<u64>) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Caps the number of cached files; `None` (the default) is unbounded.
+    ///
+    /// Evicts oldest first, like [`with_max_size`](Self::with_max_size), so
+    /// many tiny entries can't pile up under a generous byte cap.
+    pub fn with_max_entries(mut self, max_entries: Option<usize>) -> Self {
+        self.max_entries = max_entries;
+        self
+    }
+
+    /// Overrides the encoder version mixed into cache keys.
+    ///
+    /// Defaults to [`ENCODER_VERSION`]; mainly useful for tests that need
+    /// to simulate an encoder upgrade.
+    pub fn with_encoder_version(mut self, version: impl Into<String>) -> Self {
+        self.encoder_version = version.into();
+        self
+    }
+
+    /// Partitions the key space; entries written under another namespace become misses.
+    pub fn with_namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.namespace = namespace.into();
+        self
+    }
+    
+    /// Computes filesystem path for a cache entry.
+    ///
+    /// Keys are used directly as filenames (after hex encoding), with the
+    /// format's extension appended; `get` probes each extension in turn.
+    fn path_for(&self, key: &str, ext: &str) -> PathBuf {
+        self.dir.join(format!("{}.{}", key, ext))
+    }
+    
+    /// Writes `bytes` to `path` via a temp file in the same directory.
+    ///
+    /// The temp file is synced and then renamed over `path`, so readers see
+    /// either the previous entry or the complete new one, never a torn file.
+    /// The temp file is removed if any step fails.
+    async fn write_atomic(&self, path: &Path, bytes: &[u8]) -> Result<(), String> {
+        let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("entry");
+        let tmp = self.dir.join(format!(
+            ".{}.{}-{}.tmp",
+            name,
+            std::process::id(),
+            TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let written = async {
+            let mut file = fs::File::create(&tmp).await?;
+            file.write_all(bytes).await?;
+            file.sync_all().await?;
+            drop(file);
+            fs::rename(&tmp, path).await
+        }
+        .await;
+        if let Err(e) = written {
+            let _ = fs::remove_file(&tmp).await;
+            return Err(e.to_string());
+        }
+        Ok(())
     }
     
-    /// Computes filesystem path for cache key.
+    /// Whether `size` bytes in `count` entries exceed either cap.
+    fn over_cap(&self, size: u64, count: usize) -> bool {
+        self.max_size.is_some_and(|max| size > max) || self.max_entries.is_some_and(|max| count > max)
+    }
+
+    /// Deletes the oldest entries until the cache is within `max_size` and
+    /// `max_entries`.
     ///
-    /// Keys are used directly as filenames (after hex encoding),
-    /// with format extension appended during storage.
-    fn path_for(&self, key: &str) -> PathBuf {
-        self.dir.join(key)
+    /// Only runs once the running totals are over a cap, and only one pass
+    /// runs per directory: a `put` that finds one underway leaves the work
+    /// to it. The pass rescans the directory and resyncs the total, so
+    /// files changed behind the cache's back are picked up.
+    ///
+    /// Returns the number of files removed. Files another `put` removed
+    /// concurrently are skipped.
+    async fn evict(&self) -> Result<usize, String> {
+        let shared = &self.shared;
+        if !self.over_cap(shared.size.load(Ordering::Relaxed), shared.count.load(Ordering::Relaxed)) {
+            return Ok(0);
+        }
+        let Ok(_pass) = shared.evicting.try_lock() else {
+            return Ok(0);
+        };
+        let (size_before, count_before) = (shared.size.load(Ordering::Relaxed), shared.count.load(Ordering::Relaxed));
+        let mut entries = entries(&self.dir).await?;
+        let mut total: u64 = entries.iter().map(|(_, len, _)| len).sum();
+        let mut count = entries.len();
+
+        entries.sort_by_key(|(modified, _, _)| *modified);
+        let mut removed = 0;
+        for (_, len, path) in entries {
+            if !self.over_cap(total, count) {
+                break;
+            }
+            match fs::remove_file(&path).await {
+                Ok(()) => removed += 1,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.to_string()),
+            }
+            let _ = fs::remove_file(path.with_extension(BLURHASH_EXTENSION)).await;
+            total = total.saturating_sub(len);
+            count -= 1;
+        }
+        // Puts that landed during the pass keep their share of the totals
+        let _ = shared.size.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |size| {
+            Some(total + size.saturating_sub(size_before))
+        });
+        let _ = shared.count.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+            Some(count + n.saturating_sub(count_before))
+        });
+        Ok(removed)
     }
     
-    /// Generates ETag header value from cache key.
+    /// The BlurHash stored alongside `key`'s entry, if any.
     ///
-    /// Simple quoted-string format per RFC 7232.
-    /// In production, consider including modification time or content hash.
+    /// Unreadable sidecars read as missing, like a lookup miss.
+    pub async fn blurhash(&self, key: &str) -> Option<String> {
+        fs::read_to_string(self.path_for(key, BLURHASH_EXTENSION)).await.ok()
+    }
+
+    /// Stores `hash` alongside `key`'s entry, so hits can serve it without
+    /// decoding the entry. Written atomically, like the entry itself.
+    pub async fn put_blurhash(&self, key: &str, hash: &str) -> Result<(), String> {
+        self.write_atomic(&self.path_for(key, BLURHASH_EXTENSION), hash.as_bytes()).await
+    }
+
+    /// Quoted cache key, the ETag `/img` used to send.
+    #[deprecated(note = "`/img` ETags are derived from the output bytes; use `cache::etag_for_content`")]
     pub fn etag_for(&self, key: &str) -> String {
         format!("\"{}\"", key)
     }
-    
+
     /// Determines Content-Type from file extension.
     ///
     /// Returns appropriate MIME type for supported image formats.
@@ -59,6 +285,12 @@ impl DiskCache {
             Some("webp") => Some("image/webp".into()),
             Some("jpeg") | Some("jpg") => Some("image/jpeg".into()),
             Some("avif") => Some("image/avif".into()),
+            Some("png") => Some("image/png".into()),
+            Some("ico") => Some("image/x-icon".into()),
+            #[cfg(feature = "extra_formats")]
+            Some("tiff") | Some("tif") => Some("image/tiff".into()),
+            #[cfg(feature = "extra_formats")]
+            Some("bmp") => Some("image/bmp".into()),
             _ => None,
         }
     }
@@ -70,41 +302,30 @@ impl Cache for DiskCache {
     ///
     /// Uses SHA-256 hash of canonical parameter string to produce
     /// collision-resistant keys with uniform distribution. Parameter order
-    /// is normalized via BTreeMap iteration.
+    /// is normalized via BTreeMap iteration, and the encoder version is
+    /// mixed in so encoder upgrades start from a cold cache.
     fn key_for(&self, params: &BTreeMap<String, String>) -> String {
-        let canonical: String = params
-            .iter()
-            .map(|(k, v)| format!("{}={}", k, v))
-            .collect::<Vec<_>>()
-            .join("&");
-            
-        let mut hasher = Sha256::new();
-        hasher.update(canonical.as_bytes());
-        hex::encode(hasher.finalize())
+        hash_key(params, &self.encoder_version, &self.namespace)
     }
     
     /// Retrieves cached data if present.
     ///
-    /// Returns `None` if key doesn't exist (cache miss).
+    /// The key alone doesn't say which format `put` stored, so each entry
+    /// extension is tried. Returns `None` if none exists (cache miss).
     /// Propagates filesystem errors other than NotFound.
     async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, String> {
-        let p = self.path_for(key);
-        match fs::metadata(&p).await {
-            Ok(meta) => {
-                if meta.is_file() {
-                    fs::read(&p).await.map(Some).map_err(|e| e.to_string())
-                } else {
-                    Ok(None)
-                }
-            }
-            Err(e) => {
-                if e.kind() == std::io::ErrorKind::NotFound {
-                    Ok(None)
-                } else {
-                    Err(e.to_string())
+        for ext in ENTRY_EXTENSIONS {
+            let p = self.path_for(key, ext);
+            match fs::metadata(&p).await {
+                Ok(meta) if meta.is_file() => {
+                    return fs::read(&p).await.map(Some).map_err(|e| e.to_string());
                 }
+                Ok(_) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.to_string()),
             }
         }
+        Ok(None)
     }
     
     /// Stores transformed image data in cache.
@@ -112,7 +333,11 @@ impl Cache for DiskCache {
     /// Creates cache directory if it doesn't exist. Filename includes
     /// format extension for easier manual inspection and debugging.
     ///
-    /// **Warning:** No file locking - concurrent writes to same key may corrupt data.
+    /// With a `max_size` or `max_entries`, the oldest entries are then
+    /// evicted if the running totals say the cache has outgrown either.
+    ///
+    /// Writes to the same key are serialized and each lands atomically (see
+    /// `write_atomic`), so `get` never observes a partially written entry.
     async fn put(
         &self,
         key: &str,
@@ -130,11 +355,43 @@ impl Cache for DiskCache {
             ImageFormat::webp => "webp",
             ImageFormat::jpeg => "jpeg",
             ImageFormat::avif => "avif",
+            ImageFormat::png => "png",
+            ImageFormat::ico => "ico",
+            #[cfg(feature = "extra_formats")]
+            ImageFormat::tiff => "tiff",
+            #[cfg(feature = "extra_formats")]
+            ImageFormat::bmp => "bmp",
         };
         
-        let filename = format!("{}.{}", key, ext);
-        let path = self.dir.join(filename);
-        fs::write(&path, bytes).await.map_err(|e| e.to_string())?;
+        let path = self.path_for(key, ext);
+        self.shared.seed(&self.dir).await;
+        let write_locks = &self.shared.write_locks;
+        let lock = write_locks.entry(key.to_string()).or_default().clone();
+        let written = {
+            let _guard = lock.lock().await;
+            // A replaced entry's bytes leave the total, and it isn't a new entry
+            let replaced = fs::metadata(&path).await.ok().map(|m| m.len());
+            let written = self.write_atomic(&path, bytes).await;
+            if written.is_ok() {
+                self.shared.size.fetch_add(bytes.len() as u64, Ordering::Relaxed);
+                match replaced {
+                    Some(len) => {
+                        let _ = self.shared.size.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |size| {
+                            Some(size.saturating_sub(len))
+                        });
+                    }
+                    None => {
+                        self.shared.count.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            }
+            written
+        };
+        drop(lock);
+        // Drop the lock entry unless another writer is still holding or waiting on it
+        write_locks.remove_if(key, |_, l| Arc::strong_count(l) == 1);
+        written?;
+        self.evict().await?;
         Ok(())
     }
 }
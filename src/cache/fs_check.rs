@@ -0,0 +1,99 @@
+use std::io;
+use std::path::Path;
+
+/// Filesystem operations exercised by the cache directory self-test.
+///
+/// Abstracted so tests can simulate filesystems with weaker guarantees
+/// (e.g. NFS/EFS where rename may not be atomic).
+pub trait CacheFs {
+    fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+    fn write(&self, path: &Path, data: &[u8]) -> io::Result<()>;
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+    fn remove(&self, path: &Path) -> io::Result<()>;
+}
+
+/// The real filesystem via `std::fs`.
+pub struct StdFs;
+
+impl CacheFs for StdFs {
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        std::fs::create_dir_all(path)
+    }
+
+    fn write(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        std::fs::write(path, data)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        std::fs::rename(from, to)
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        std::fs::read(path)
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        std::fs::remove_file(path)
+    }
+}
+
+/// Outcome of the cache directory self-test.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FsCheck {
+    /// Write, rename and read-back behaved like a local filesystem.
+    Ok,
+    /// The directory can't be trusted for cache writes; the message says why.
+    Unsafe(String),
+}
+
+/// Size of the self-test payload; large enough to span several pages.
+const PROBE_BYTES: usize = 256 * 1024;
+
+/// Runs the self-test against the real filesystem. See [`self_test_with`].
+pub fn self_test(dir: &Path) -> FsCheck {
+    self_test_with(&StdFs, dir)
+}
+
+/// Writes a probe file, renames it into place and verifies the result.
+///
+/// Cache writes rely on rename replacing the target atomically and leaving
+/// no trace of the source. Network filesystems (NFS, EFS, SMB) may violate
+/// this, and Sled's locking/fsync assumptions can break there too, which
+/// shows up as silent corruption rather than errors. This check can't prove
+/// atomicity, but catches filesystems where the renamed file is missing,
+/// truncated, or the source lingers.
+pub fn self_test_with(fs: &dyn CacheFs, dir: &Path) -> FsCheck {
+    let tmp = dir.join(format!(".imagekit-selftest-{}.tmp", std::process::id()));
+    let dst = dir.join(format!(".imagekit-selftest-{}", std::process::id()));
+    let payload: Vec<u8> = (0..PROBE_BYTES).map(|i| (i % 251) as u8).collect();
+
+    let result = probe(fs, dir, &tmp, &dst, &payload);
+
+    let _ = fs.remove(&tmp);
+    let _ = fs.remove(&dst);
+
+    match result {
+        Ok(()) => FsCheck::Ok,
+        Err(reason) => FsCheck::Unsafe(reason),
+    }
+}
+
+fn probe(fs: &dyn CacheFs, dir: &Path, tmp: &Path, dst: &Path, payload: &[u8]) -> Result<(), String> {
+    fs.create_dir_all(dir).map_err(|e| format!("cannot create cache dir: {}", e))?;
+    fs.write(tmp, payload).map_err(|e| format!("write failed: {}", e))?;
+    fs.rename(tmp, dst).map_err(|e| format!("rename failed: {}", e))?;
+
+    if fs.read(tmp).is_ok() {
+        return Err("rename left the source file behind".to_string());
+    }
+    match fs.read(dst) {
+        Ok(data) if data == payload => Ok(()),
+        Ok(data) => Err(format!(
+            "renamed file has {} of {} bytes or differs from what was written",
+            data.len(),
+            payload.len()
+        )),
+        Err(e) => Err(format!("renamed file unreadable: {}", e)),
+    }
+}
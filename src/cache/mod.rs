@@ -2,14 +2,31 @@
 pub mod disk;
 pub mod sled_cache;
 pub mod cloudflare;
+pub mod originals;
+pub mod source;
+pub mod fs_check;
 
 pub use disk::DiskCache;
-pub use sled_cache::{SledCache, CacheStats};
+pub use sled_cache::{SledCache, CacheStats, ScanMetrics};
 pub use cloudflare::{CloudflareCacheConfig, cloudflare_cache_middleware};
+pub use originals::OriginalStore;
+pub use source::SourceCache;
+pub use fs_check::{self_test, FsCheck};
 
 use crate::config::ImageFormat;
+use sha2::{Digest, Sha256};
 use std::collections::BTreeMap;
 
+/// Identifier for the encoder stack compiled into this binary.
+///
+/// Mixed into every cache key so that upgrading `image`/`webp`/`ravif`
+/// (or the crate itself) naturally invalidates outputs produced by the
+/// previous encoders. Bump the pinned versions alongside `Cargo.toml`.
+pub const ENCODER_VERSION: &str = concat!(
+    "imagekit-", env!("CARGO_PKG_VERSION"),
+    ";image-0.25;webp-0.3;ravif-0.11"
+);
+
 /// Trait for cache backends
 #[async_trait::async_trait]
 pub trait Cache: Send + Sync {
@@ -21,11 +38,91 @@ pub trait Cache: Send + Sync {
     
     /// Store data in cache
     async fn put(&self, key: &str, data: &[u8], format: ImageFormat, params: &str) -> Result<(), String>;
+    
+    /// Report size/entry statistics.
+    ///
+    /// Returns `None` for backends that can't compute them cheaply.
+    async fn stats(&self) -> Option<CacheStats> {
+        None
+    }
+    
+    /// Returns the entry for `key`, or on a miss runs `compute` and stores
+    /// its bytes under `key` unless it opts out.
+    ///
+    /// A failed lookup counts as a miss and a failed store is only logged,
+    /// so the cache never fails a request `compute` can serve. `params` is
+    /// recorded with the entry as for [`Cache::put`].
+    async fn get_or_compute<T, E, F, Fut>(&self, key: &str, params: &str, compute: F) -> Result<Lookup<T>, E>
+    where
+        Self: Sized,
+        T: AsRef<[u8]> + Send,
+        E: Send,
+        F: FnOnce() -> Fut + Send,
+        Fut: std::future::Future<Output = Result<Computed<T>, E>> + Send,
+    {
+        match self.get(key).await {
+            Ok(Some(data)) => return Ok(Lookup::Hit(data)),
+            Ok(None) => {}
+            Err(e) => tracing::warn!("Cache lookup for {} failed, treating it as a miss: {}", key, e),
+        }
+        let computed = compute().await?;
+        let mut stored = false;
+        if let Some(format) = computed.store_as {
+            match self.put(key, computed.value.as_ref(), format, params).await {
+                Ok(()) => stored = true,
+                Err(e) => tracing::warn!("Failed to cache {}: {}", key, e),
+            }
+        }
+        Ok(Lookup::Computed { value: computed.value, stored })
+    }
+}
+
+/// Outcome of [`Cache::get_or_compute`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Lookup<T> {
+    /// The cached bytes
+    Hit(Vec<u8>),
+    /// What `compute` produced on a miss, and whether it was stored
+    Computed { value: T, stored: bool },
+}
+
+/// What a [`Cache::get_or_compute`] computation produced.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Computed<T> {
+    /// Served to the caller; its bytes are what gets stored
+    pub value: T,
+    /// Format to store `value` as; `None` serves it without caching
+    pub store_as: Option<ImageFormat>,
+}
+
+/// Hashes canonical transformation parameters into a cache key.
+///
+/// Parameters are URL-encoded and joined in sorted order (via BTreeMap iteration) and the
+/// encoder version is appended so identical params produced by different
+/// encoder builds never share an entry. A non-empty `namespace` (see
+/// `ImageKitConfig::cache_namespace`) partitions the key space further.
+pub fn hash_key(params: &BTreeMap<String, String>, encoder_version: &str, namespace: &str) -> String {
+    // Same encoding as signing, so a `url` containing `&w=400` can't share
+    // a key with a request that really sets `w=400`
+    let canonical = crate::signature::canonical_string(params);
+
+    let mut hasher = Sha256::new();
+    hasher.update(canonical.as_bytes());
+    hasher.update(b"|encoder=");
+    hasher.update(encoder_version.as_bytes());
+    if !namespace.is_empty() {
+        hasher.update(b"|ns=");
+        hasher.update(namespace.as_bytes());
+    }
+    hex::encode(hasher.finalize())
 }
 
-/// Generate an ETag from a cache key
-pub fn etag_for_key(key: &str) -> String {
-    format!("\"{}\"", key)
+/// Strong ETag for encoded output: a truncated SHA-256 of the bytes.
+///
+/// Derived from content rather than the cache key, so identical output
+/// shares a validator across param sets and changed output never reuses one.
+pub fn etag_for_content(data: &[u8]) -> String {
+    format!("\"{}\"", &hex::encode(Sha256::digest(data))[..32])
 }
 
 /// Generate content type from file extension
@@ -34,6 +131,12 @@ pub fn content_type_from_format(format: ImageFormat) -> &'static str {
         ImageFormat::webp => "image/webp",
         ImageFormat::jpeg => "image/jpeg",
         ImageFormat::avif => "image/avif",
+        ImageFormat::png => "image/png",
+        ImageFormat::ico => "image/x-icon",
+        #[cfg(feature = "extra_formats")]
+        ImageFormat::tiff => "image/tiff",
+        #[cfg(feature = "extra_formats")]
+        ImageFormat::bmp => "image/bmp",
     }
 }
 
@@ -43,6 +146,12 @@ pub fn format_from_extension(ext: &str) -> Option<ImageFormat> {
         "webp" => Some(ImageFormat::webp),
         "jpeg" | "jpg" => Some(ImageFormat::jpeg),
         "avif" => Some(ImageFormat::avif),
+        "png" => Some(ImageFormat::png),
+        "ico" => Some(ImageFormat::ico),
+        #[cfg(feature = "extra_formats")]
+        "tiff" | "tif" => Some(ImageFormat::tiff),
+        #[cfg(feature = "extra_formats")]
+        "bmp" => Some(ImageFormat::bmp),
         _ => None,
     }
 }
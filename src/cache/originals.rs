@@ -0,0 +1,110 @@
+use crate::fetch::Validators;
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+use tokio::fs;
+
+/// Filesystem store for untouched source images, keyed by URL.
+///
+/// Lets `f=original` passthrough requests and later transforms of the same
+/// source skip the origin fetch. Entries expire `ttl` after they were
+/// written (judged by file modification time); expired entries are treated
+/// as misses and overwritten on the next fetch.
+///
+/// Each entry is `<key>` with the raw bytes, `<key>.type` with the origin's
+/// `Content-Type` and, when the origin sent any, `<key>.validators` with its
+/// `ETag`/`Last-Modified` for revalidating the entry once it expires.
+pub struct OriginalStore {
+    dir: PathBuf,
+    ttl: Duration,
+}
+
+impl OriginalStore {
+    /// Creates a store rooted at `dir`; created on first write.
+    pub fn new(dir: PathBuf, ttl: Duration) -> Self {
+        Self { dir, ttl }
+    }
+
+    /// URL-derived key, independent of any transformation parameters.
+    pub fn key_for(&self, url: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(b"original|");
+        hasher.update(url.as_bytes());
+        hex::encode(hasher.finalize())
+    }
+
+    /// Returns the cached bytes and content type for `url`, if fresh.
+    pub async fn get(&self, url: &str) -> Result<Option<(Vec<u8>, String)>, String> {
+        self.read(url, true).await
+    }
+
+    /// Like [`get`](Self::get) but ignores the TTL, for serving stale
+    /// content when the origin is failing.
+    pub async fn get_stale(&self, url: &str) -> Result<Option<(Vec<u8>, String)>, String> {
+        self.read(url, false).await
+    }
+
+    async fn read(&self, url: &str, fresh_only: bool) -> Result<Option<(Vec<u8>, String)>, String> {
+        let key = self.key_for(url);
+        let path = self.dir.join(&key);
+
+        let meta = match fs::metadata(&path).await {
+            Ok(meta) => meta,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.to_string()),
+        };
+        let age = meta
+            .modified()
+            .ok()
+            .and_then(|m| SystemTime::now().duration_since(m).ok())
+            .unwrap_or_default();
+        if fresh_only && age > self.ttl {
+            return Ok(None);
+        }
+
+        let bytes = fs::read(&path).await.map_err(|e| e.to_string())?;
+        let content_type = fs::read_to_string(self.dir.join(format!("{}.type", key)))
+            .await
+            .unwrap_or_else(|_| "application/octet-stream".to_string());
+        Ok(Some((bytes, content_type)))
+    }
+
+    /// Upstream validators stored with `url`'s entry, fresh or not.
+    pub async fn validators(&self, url: &str) -> Option<Validators> {
+        let key = self.key_for(url);
+        let raw = fs::read(self.dir.join(format!("{}.validators", key))).await.ok()?;
+        serde_json::from_slice(&raw).ok()
+    }
+
+    /// Stores the fetched bytes for `url`, replacing any previous entry.
+    pub async fn put(&self, url: &str, bytes: &[u8], content_type: &str, validators: &Validators) -> Result<(), String> {
+        fs::create_dir_all(&self.dir).await.map_err(|e| e.to_string())?;
+        let key = self.key_for(url);
+        fs::write(self.dir.join(format!("{}.type", key)), content_type)
+            .await
+            .map_err(|e| e.to_string())?;
+        let validators_path = self.dir.join(format!("{}.validators", key));
+        if validators.is_empty() {
+            let _ = fs::remove_file(&validators_path).await;
+        } else {
+            let raw = serde_json::to_vec(validators).map_err(|e| e.to_string())?;
+            fs::write(validators_path, raw).await.map_err(|e| e.to_string())?;
+        }
+        fs::write(self.dir.join(&key), bytes).await.map_err(|e| e.to_string())
+    }
+
+    /// Restarts the TTL of `url`'s entry, after the origin confirmed with a
+    /// `304` that it is unchanged.
+    pub async fn touch(&self, url: &str) -> Result<(), String> {
+        let path = self.dir.join(self.key_for(url));
+        tokio::task::spawn_blocking(move || {
+            std::fs::File::options()
+                .append(true)
+                .open(&path)?
+                .set_modified(SystemTime::now())
+        })
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+    }
+}
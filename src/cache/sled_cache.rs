@@ -1,15 +1,24 @@
-use crate::cache::Cache;
+use crate::cache::{etag_for_content, hash_key, Cache, ENCODER_VERSION};
 use crate::config::ImageFormat;
 use sled::Db;
 use serde::{Deserialize, Serialize};
-use sha2::{Digest, Sha256};
 use std::collections::BTreeMap;
 use std::path::Path;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::Semaphore;
 
 /// Default maximum cache size: 10GB
 pub const DEFAULT_MAX_CACHE_SIZE: u64 = 10 * 1024 * 1024 * 1024;
 
+/// Full-database scans (`stats`, eviction) allowed to run at once by default.
+pub const DEFAULT_MAX_CONCURRENT_SCANS: usize = 1;
+
+/// Eviction frees entries until the cache is back under this share of
+/// `max_size` (and of `max_entries`, when set).
+const EVICTION_TARGET_PERCENT: u64 = 90;
+
 /// Metadata stored alongside cached images
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct CacheMetadata {
@@ -19,6 +28,9 @@ pub struct CacheMetadata {
     pub created_at: u64,
     pub accessed_at: u64,
     pub params: String, // JSON-serialized params for debugging
+    /// See `cache::etag_for_content`; empty for entries written before it was stored
+    #[serde(default)]
+    pub etag: String,
 }
 
 /// Statistics about the cache
@@ -27,21 +39,92 @@ pub struct CacheStats {
     pub total_size_bytes: u64,
     pub entry_count: usize,
     pub max_size_bytes: u64,
+    pub max_entries: Option<usize>,
     pub hit_rate: Option<f64>,
 }
 
+/// Scan counters for one `SledCache` (see `SledCache::scan_metrics`).
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ScanMetrics {
+    /// Completed full scans
+    pub scans: u64,
+    /// Wall time spent scanning, summed over all scans
+    pub total_duration_micros: u64,
+    /// Highest number of scans observed running at once
+    pub peak_concurrent: usize,
+}
+
+/// Limits concurrent full scans and measures them.
+///
+/// A stats scrape landing during an eviction pass would otherwise walk the
+/// whole tree twice in parallel, compounding I/O and CPU.
+#[derive(Debug)]
+struct ScanGuard {
+    permits: Arc<Semaphore>,
+    active: AtomicUsize,
+    peak: AtomicUsize,
+    scans: AtomicU64,
+    total_micros: AtomicU64,
+}
+
+impl ScanGuard {
+    fn new(max_concurrent: usize) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(max_concurrent.max(1))),
+            active: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
+            scans: AtomicU64::new(0),
+            total_micros: AtomicU64::new(0),
+        }
+    }
+    
+    /// Runs `scan` and records its duration. Callers hold a permit.
+    fn measure<T>(&self, scan: impl FnOnce() -> T) -> T {
+        let active = self.active.fetch_add(1, Ordering::AcqRel) + 1;
+        self.peak.fetch_max(active, Ordering::AcqRel);
+        let start = Instant::now();
+        
+        let out = scan();
+        
+        let micros = start.elapsed().as_micros() as u64;
+        self.active.fetch_sub(1, Ordering::AcqRel);
+        self.scans.fetch_add(1, Ordering::Relaxed);
+        self.total_micros.fetch_add(micros, Ordering::Relaxed);
+        crate::METRICS.cache_scans.fetch_add(1, Ordering::Relaxed);
+        crate::METRICS.cache_scan_micros.fetch_add(micros, Ordering::Relaxed);
+        out
+    }
+}
+
 /// Sled-based cache with LRU eviction
 /// 
 /// This cache provides:
 /// - Persistent storage with automatic eviction
 /// - LRU (Least Recently Used) eviction policy
+/// - Background eviction: `put` only checks a running size counter against
+///   the high-water mark (`max_size`); the eviction pass itself runs on a
+///   blocking task, and at most one pass runs at a time
 /// - Metadata tracking for debugging and analytics
 /// - Atomic operations
-/// - Configurable size limits
+/// - Configurable size limits, plus an optional entry-count limit
+///   (`with_max_entries`) so many tiny entries can't bloat the index
+/// - Bounded full scans: `stats` and eviction share a semaphore
+///   (`with_max_concurrent_scans`) so heavy scans queue instead of piling up
 /// - Pure Rust (no C++ compilation needed)
 pub struct SledCache {
     db: Db,
     max_size: u64,
+    max_entries: Option<usize>,
+    encoder_version: String,
+    namespace: String,
+    /// Running total of cached bytes, seeded from a scan on open
+    size: Arc<AtomicU64>,
+    /// Running entry count, seeded from the same scan
+    entries: Arc<AtomicUsize>,
+    /// Single-flight guard: set while an eviction pass is running
+    evicting: Arc<AtomicBool>,
+    /// Concurrency limit and timing for full scans
+    scans: Arc<ScanGuard>,
 }
 
 impl SledCache {
@@ -52,13 +135,45 @@ impl SledCache {
     /// * `max_size` - Optional maximum size in bytes (default: 10GB)
     pub fn new(path: impl AsRef<Path>, max_size: Option<u64>) -> Result<Self, String> {
         let db = sled::open(path).map_err(|e| format!("Failed to open Sled database: {}", e))?;
+        let (size, entries) = scan_totals(&db);
         
         Ok(Self {
             db,
             max_size: max_size.unwrap_or(DEFAULT_MAX_CACHE_SIZE),
+            max_entries: None,
+            encoder_version: ENCODER_VERSION.to_string(),
+            namespace: String::new(),
+            size: Arc::new(AtomicU64::new(size)),
+            entries: Arc::new(AtomicUsize::new(entries)),
+            evicting: Arc::new(AtomicBool::new(false)),
+            scans: Arc::new(ScanGuard::new(DEFAULT_MAX_CONCURRENT_SCANS)),
         })
     }
     
+    /// Caps the number of entries; `None` (the default) leaves it unbounded.
+    pub fn with_max_entries(mut self, max_entries: Option<usize>) -> Self {
+        self.max_entries = max_entries;
+        self
+    }
+    
+    /// Sets how many full scans may run at once (minimum 1).
+    pub fn with_max_concurrent_scans(mut self, max: usize) -> Self {
+        self.scans = Arc::new(ScanGuard::new(max));
+        self
+    }
+
+    /// Overrides the encoder version mixed into cache keys (default: [`ENCODER_VERSION`]).
+    pub fn with_encoder_version(mut self, version: impl Into<String>) -> Self {
+        self.encoder_version = version.into();
+        self
+    }
+    
+    /// Partitions the key space; entries written under another namespace become misses.
+    pub fn with_namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.namespace = namespace.into();
+        self
+    }
+    
     /// Generate metadata key from cache key
     fn metadata_key(key: &str) -> String {
         format!("meta:{}", key)
@@ -69,118 +184,197 @@ impl SledCache {
         format!("data:{}", key)
     }
     
-    /// Get current total size of cached data
-    async fn current_size(&self) -> u64 {
-        let mut total = 0u64;
-        
-        for item in self.db.iter() {
-            if let Ok((key, value)) = item {
-                if let Ok(key_str) = std::str::from_utf8(&key) {
-                    if key_str.starts_with("meta:") {
-                        if let Ok(meta) = serde_json::from_slice::<CacheMetadata>(&value) {
-                            total += meta.size as u64;
-                        }
-                    }
-                }
-            }
+    /// Counters for full scans performed through this handle.
+    pub fn scan_metrics(&self) -> ScanMetrics {
+        ScanMetrics {
+            scans: self.scans.scans.load(Ordering::Relaxed),
+            total_duration_micros: self.scans.total_micros.load(Ordering::Relaxed),
+            peak_concurrent: self.scans.peak.load(Ordering::Acquire),
         }
-        
-        total
     }
     
-    /// Evict least recently used entries until under size limit
-    async fn evict_if_needed(&self) -> Result<(), String> {
-        let current = self.current_size().await;
-        
-        if current <= self.max_size {
-            return Ok(());
+    /// Returns true while a background eviction pass is running.
+    pub fn eviction_in_progress(&self) -> bool {
+        self.evicting.load(Ordering::Acquire)
+    }
+    
+    /// Starts a background eviction pass if the cache is over a limit.
+    ///
+    /// Returns immediately. If a pass is already running this is a no-op;
+    /// the running pass repeats while puts that landed during it keep the
+    /// cache over a limit.
+    fn schedule_eviction(&self) {
+        if !over_limits(&self.size, self.max_size, &self.entries, self.max_entries) {
+            return;
         }
         
-        tracing::info!("Cache size {} exceeds limit {}, starting eviction", current, self.max_size);
+        if self.evicting
+            .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            return;
+        }
         
-        // Collect all metadata entries
-        let mut entries: Vec<CacheMetadata> = Vec::new();
+        let db = self.db.clone();
+        let max_size = self.max_size;
+        let max_entries = self.max_entries;
+        let size = self.size.clone();
+        let entries = self.entries.clone();
+        let evicting = self.evicting.clone();
+        let scans = self.scans.clone();
         
-        for item in self.db.iter() {
-            if let Ok((key, value)) = item {
-                if let Ok(key_str) = std::str::from_utf8(&key) {
-                    if key_str.starts_with("meta:") {
-                        if let Ok(meta) = serde_json::from_slice::<CacheMetadata>(&value) {
-                            entries.push(meta);
+        tokio::spawn(async move {
+            // Wait for a scan slot without blocking a worker thread
+            let permit = scans.permits.clone().acquire_owned().await.ok();
+            let _ = tokio::task::spawn_blocking(move || {
+                let _permit = permit;
+                loop {
+                    match scans.measure(|| evict_lru(&db, max_size, max_entries)) {
+                        Ok((freed, evicted)) => {
+                            let _ = size.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |v| {
+                                Some(v.saturating_sub(freed))
+                            });
+                            let _ = entries.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |v| {
+                                Some(v.saturating_sub(evicted))
+                            });
+                            if evicted == 0 || !over_limits(&size, max_size, &entries, max_entries) {
+                                break;
+                            }
+                        }
+                        Err(e) => {
+                            tracing::warn!("Cache eviction failed: {}", e);
+                            break;
                         }
                     }
                 }
-            }
-        }
-        
-        // Sort by access time (oldest first) - LRU eviction
-        entries.sort_by_key(|e| e.accessed_at);
-        
-        // Remove entries until we're under target size (90% of max)
-        let mut freed = 0u64;
-        let target_to_free = current.saturating_sub(self.max_size * 90 / 100);
-        let mut evicted_count = 0;
-        
-        for entry in entries {
-            if freed >= target_to_free {
-                break;
-            }
-            
-            // Delete both metadata and data
-            self.db.remove(Self::metadata_key(&entry.key).as_bytes())
-                .map_err(|e| e.to_string())?;
-            self.db.remove(Self::data_key(&entry.key).as_bytes())
-                .map_err(|e| e.to_string())?;
-            
-            freed += entry.size as u64;
-            evicted_count += 1;
-            
-            tracing::debug!("Evicted cache entry: key={}, size={}, age={}", 
-                           entry.key, entry.size, 
-                           SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() - entry.accessed_at);
-        }
-        
-        self.db.flush().map_err(|e| e.to_string())?;
-        
-        tracing::info!("Eviction complete: freed {} bytes by removing {} entries", freed, evicted_count);
-        
-        Ok(())
+                evicting.store(false, Ordering::Release);
+            })
+            .await;
+        });
+    }
+    
+    /// Content ETag stored with `key`, if the entry exists and has one.
+    pub fn etag(&self, key: &str) -> Option<String> {
+        let raw = self.db.get(Self::metadata_key(key).as_bytes()).ok()??;
+        let meta = serde_json::from_slice::<CacheMetadata>(&raw).ok()?;
+        Some(meta.etag).filter(|etag| !etag.is_empty())
     }
     
     /// Get cache statistics
+    ///
+    /// Walks the whole database, so it waits for a scan slot first.
     pub async fn stats(&self) -> CacheStats {
-        let size = self.current_size().await;
-        let mut count = 0;
-        
-        for item in self.db.iter() {
-            if let Ok((key, _)) = item {
-                if let Ok(key_str) = std::str::from_utf8(&key) {
-                    if key_str.starts_with("meta:") {
-                        count += 1;
-                    }
-                }
-            }
-        }
+        let _permit = self.scans.permits.acquire().await.ok();
+        let (size, count) = self.scans.measure(|| scan_totals(&self.db));
         
         CacheStats {
             total_size_bytes: size,
             entry_count: count,
             max_size_bytes: self.max_size,
+            max_entries: self.max_entries,
             hit_rate: None, // TODO: Track hits/misses for this
         }
     }
 }
 
+/// Whether the running counters exceed the byte or entry limit.
+fn over_limits(size: &AtomicU64, max_size: u64, entries: &AtomicUsize, max_entries: Option<usize>) -> bool {
+    size.load(Ordering::Relaxed) > max_size
+        || max_entries.is_some_and(|max| entries.load(Ordering::Relaxed) > max)
+}
+
+/// Total entry size and entry count, in a single pass over metadata records.
+fn scan_totals(db: &Db) -> (u64, usize) {
+    let mut total = 0u64;
+    let mut count = 0usize;
+    
+    for (key, value) in db.iter().flatten() {
+        if let Ok(key_str) = std::str::from_utf8(&key) {
+            if key_str.starts_with("meta:") {
+                count += 1;
+                if let Ok(meta) = serde_json::from_slice::<CacheMetadata>(&value) {
+                    total += meta.size as u64;
+                }
+            }
+        }
+    }
+    
+    (total, count)
+}
+
+/// Evicts least recently used entries until under the eviction targets.
+///
+/// Returns the number of bytes freed and entries removed.
+fn evict_lru(db: &Db, max_size: u64, max_entries: Option<usize>) -> Result<(u64, usize), String> {
+    let (current, count) = scan_totals(db);
+    let over_entries = max_entries.is_some_and(|max| count > max);
+    
+    if current <= max_size && !over_entries {
+        return Ok((0, 0));
+    }
+    
+    tracing::info!("Cache holds {} bytes in {} entries (limits {} bytes, {:?} entries), starting eviction",
+                   current, count, max_size, max_entries);
+    
+    // Collect all metadata entries
+    let mut entries: Vec<CacheMetadata> = Vec::new();
+    
+    for (key, value) in db.iter().flatten() {
+        if let Ok(key_str) = std::str::from_utf8(&key) {
+            if key_str.starts_with("meta:") {
+                if let Ok(meta) = serde_json::from_slice::<CacheMetadata>(&value) {
+                    entries.push(meta);
+                }
+            }
+        }
+    }
+    
+    // Sort by access time (oldest first) - LRU eviction
+    entries.sort_by_key(|e| e.accessed_at);
+    
+    // Remove entries until we're under target size
+    let mut freed = 0u64;
+    let target_to_free = if current > max_size {
+        current.saturating_sub(max_size * EVICTION_TARGET_PERCENT / 100)
+    } else {
+        0
+    };
+    let entries_to_evict = match max_entries {
+        Some(max) if over_entries => count.saturating_sub(max * EVICTION_TARGET_PERCENT as usize / 100),
+        _ => 0,
+    };
+    let mut evicted_count = 0;
+    
+    for entry in entries {
+        if freed >= target_to_free && evicted_count >= entries_to_evict {
+            break;
+        }
+        
+        // Delete both metadata and data
+        db.remove(SledCache::metadata_key(&entry.key).as_bytes())
+            .map_err(|e| e.to_string())?;
+        db.remove(SledCache::data_key(&entry.key).as_bytes())
+            .map_err(|e| e.to_string())?;
+        
+        freed += entry.size as u64;
+        evicted_count += 1;
+        
+        tracing::debug!("Evicted cache entry: key={}, size={}, age={}", 
+                       entry.key, entry.size, 
+                       SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() - entry.accessed_at);
+    }
+    
+    db.flush().map_err(|e| e.to_string())?;
+    
+    tracing::info!("Eviction complete: freed {} bytes by removing {} entries", freed, evicted_count);
+    
+    Ok((freed, evicted_count))
+}
+
 #[async_trait::async_trait]
 impl Cache for SledCache {
     fn key_for(&self, params: &BTreeMap<String, String>) -> String {
-        let canonical: String = params.iter()
-            .map(|(k,v)| format!("{}={}", k, v))
-            .collect::<Vec<_>>()
-            .join("&");
-        let mut hasher = Sha256::new();
-        hasher.update(canonical.as_bytes());
-        hex::encode(hasher.finalize())
+        hash_key(params, &self.encoder_version, &self.namespace)
     }
     
     async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, String> {
@@ -228,6 +422,7 @@ impl Cache for SledCache {
             created_at: now,
             accessed_at: now,
             params: params.to_string(),
+            etag: etag_for_content(data),
         };
         
         // Store data
@@ -236,18 +431,33 @@ impl Cache for SledCache {
             data
         ).map_err(|e| format!("Failed to write cache data: {}", e))?;
         
-        // Store metadata
-        self.db.insert(
+        // Store metadata, accounting for any entry it replaces
+        let previous = self.db.insert(
             Self::metadata_key(key).as_bytes(),
             serde_json::to_vec(&metadata).unwrap()
         ).map_err(|e| format!("Failed to write cache metadata: {}", e))?;
         
+        if previous.is_none() {
+            self.entries.fetch_add(1, Ordering::Relaxed);
+        }
+        let replaced = previous
+            .and_then(|old| serde_json::from_slice::<CacheMetadata>(&old).ok())
+            .map(|old| old.size as u64)
+            .unwrap_or(0);
+        let _ = self.size.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |v| {
+            Some(v.saturating_sub(replaced) + data.len() as u64)
+        });
+        
         // Flush to disk
         self.db.flush().map_err(|e| e.to_string())?;
         
-        // Check if eviction needed
-        self.evict_if_needed().await?;
+        // Evict in the background when over the high-water mark
+        self.schedule_eviction();
         
         Ok(())
     }
+    
+    async fn stats(&self) -> Option<CacheStats> {
+        Some(SledCache::stats(self).await)
+    }
 }
@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Share of the byte budget a single source may take; larger ones aren't kept.
+const MAX_ENTRY_SHARE: usize = 4;
+
+/// Bounded in-memory cache of fetched source bytes, keyed by URL.
+///
+/// Several output variants of one image (different `w`/`f`) are usually
+/// requested within moments of each other; this lets them share a single
+/// origin download. Entries expire `ttl` after they were fetched. The total
+/// size is capped at `max_bytes`, evicting the oldest entries first, and
+/// sources over a quarter of the budget are never stored so one huge
+/// original can't flush everything else.
+///
+/// Cloning shares the same entries.
+#[derive(Debug, Clone)]
+pub struct SourceCache {
+    inner: Arc<Mutex<Entries>>,
+    max_bytes: usize,
+    ttl: Duration,
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    map: HashMap<String, Entry>,
+    total_bytes: usize,
+}
+
+#[derive(Debug)]
+struct Entry {
+    bytes: Arc<Vec<u8>>,
+    content_type: String,
+    fetched_at: Instant,
+}
+
+impl SourceCache {
+    /// Keeps up to `max_bytes` of sources, each for at most `ttl`.
+    pub fn new(max_bytes: usize, ttl: Duration) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Entries::default())),
+            max_bytes,
+            ttl,
+        }
+    }
+
+    /// Returns the cached bytes and content type for `url`, if fresh.
+    pub fn get(&self, url: &str) -> Option<(Arc<Vec<u8>>, String)> {
+        let mut entries = self.inner.lock().unwrap();
+        let expired = match entries.map.get(url) {
+            Some(entry) if entry.fetched_at.elapsed() <= self.ttl => {
+                return Some((entry.bytes.clone(), entry.content_type.clone()));
+            }
+            Some(_) => true,
+            None => false,
+        };
+        if expired {
+            entries.remove(url);
+        }
+        None
+    }
+
+    /// Stores a fetched source, evicting the oldest entries to stay in budget.
+    pub fn put(&self, url: &str, bytes: Arc<Vec<u8>>, content_type: &str) {
+        if bytes.len() > self.max_bytes / MAX_ENTRY_SHARE {
+            return;
+        }
+
+        let mut entries = self.inner.lock().unwrap();
+        entries.remove(url);
+        let ttl = self.ttl;
+        let expired: Vec<String> = entries
+            .map
+            .iter()
+            .filter(|(_, e)| e.fetched_at.elapsed() > ttl)
+            .map(|(k, _)| k.clone())
+            .collect();
+        for key in expired {
+            entries.remove(&key);
+        }
+
+        while entries.total_bytes + bytes.len() > self.max_bytes {
+            let oldest = entries
+                .map
+                .iter()
+                .min_by_key(|(_, e)| e.fetched_at)
+                .map(|(k, _)| k.clone());
+            match oldest {
+                Some(key) => entries.remove(&key),
+                None => break,
+            }
+        }
+
+        entries.total_bytes += bytes.len();
+        entries.map.insert(
+            url.to_string(),
+            Entry { bytes, content_type: content_type.to_string(), fetched_at: Instant::now() },
+        );
+    }
+
+    /// Bytes currently held.
+    pub fn size_bytes(&self) -> usize {
+        self.inner.lock().unwrap().total_bytes
+    }
+}
+
+impl Entries {
+    fn remove(&mut self, url: &str) {
+        if let Some(entry) = self.map.remove(url) {
+            self.total_bytes -= entry.bytes.len();
+        }
+    }
+}
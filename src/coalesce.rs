@@ -0,0 +1,65 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use tokio::sync::OnceCell;
+
+/// Single-flight coalescing of identical concurrent work, keyed by string.
+///
+/// The first caller for a key runs `work`; callers arriving while it runs
+/// wait and receive a clone of its result instead of repeating it. This
+/// keeps a thundering herd of identical cache misses (e.g. after a deploy
+/// or cache flush) down to one fetch and one encode.
+///
+/// Failures are shared too, so waiters don't retry a failing origin one
+/// after another. Only if the running call is cancelled does one of the
+/// waiters take over and run its own `work`. Once a call finishes its key
+/// is released, so later callers start afresh (and should find the result
+/// in the cache instead).
+#[derive(Debug)]
+pub struct InFlight<T, E> {
+    calls: Mutex<HashMap<String, Arc<OnceCell<Result<T, E>>>>>,
+}
+
+impl<T, E> Default for InFlight<T, E> {
+    fn default() -> Self {
+        Self { calls: Mutex::new(HashMap::new()) }
+    }
+}
+
+impl<T: Clone, E: Clone> InFlight<T, E> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs `work` for `key`, or joins the call already running for it.
+    pub async fn run<F, Fut>(&self, key: &str, work: F) -> Result<T, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let cell = self
+            .calls
+            .lock()
+            .unwrap()
+            .entry(key.to_string())
+            .or_default()
+            .clone();
+
+        let result = cell.get_or_init(work).await.clone();
+
+        let mut calls = self.calls.lock().unwrap();
+        if calls.get(key).is_some_and(|current| Arc::ptr_eq(current, &cell)) {
+            calls.remove(key);
+        }
+        result
+    }
+
+    /// Number of keys with a call in progress.
+    pub fn len(&self) -> usize {
+        self.calls.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
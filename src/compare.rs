@@ -0,0 +1,88 @@
+//! Similarity metrics for checking two renditions against each other.
+
+use image::{imageops::FilterType, DynamicImage, GenericImageView, GrayImage};
+
+/// Side of the square windows SSIM is averaged over.
+const SSIM_WINDOW: u32 = 8;
+
+/// SSIM stabilizing constants for 8-bit samples: `(0.01 * 255)^2` and
+/// `(0.03 * 255)^2`.
+const SSIM_C1: f64 = 6.5025;
+const SSIM_C2: f64 = 58.5225;
+
+/// How far apart two images are.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Comparison {
+    /// Mean squared error over the RGBA channels, on a 0-255 scale
+    pub mse: f64,
+    /// Peak signal-to-noise ratio in dB; infinite for identical images
+    pub psnr: f64,
+    /// Structural similarity of the luma, averaged over 8x8 windows: 1.0
+    /// is identical, values near 0 are unrelated
+    pub ssim: f64,
+}
+
+/// Compares `a` with `b`.
+///
+/// When the dimensions differ, `b` is resized to `a`'s first, so a
+/// rendition can be checked against a reference of another size.
+pub fn compare_images(a: &DynamicImage, b: &DynamicImage) -> Comparison {
+    let (width, height) = a.dimensions();
+    let resized;
+    let b = if b.dimensions() == (width, height) {
+        b
+    } else {
+        resized = b.resize_exact(width, height, FilterType::Triangle);
+        &resized
+    };
+
+    let (rgba_a, rgba_b) = (a.to_rgba8(), b.to_rgba8());
+    let samples = rgba_a.as_raw().len().max(1) as f64;
+    let mse = rgba_a
+        .as_raw()
+        .iter()
+        .zip(rgba_b.as_raw())
+        .map(|(&x, &y)| (x as f64 - y as f64).powi(2))
+        .sum::<f64>()
+        / samples;
+    let psnr = if mse == 0.0 { f64::INFINITY } else { 10.0 * (255.0 * 255.0 / mse).log10() };
+
+    Comparison { mse, psnr, ssim: ssim(&a.to_luma8(), &b.to_luma8()) }
+}
+
+/// Mean SSIM over non-overlapping windows; edge windows are clipped, and
+/// an image smaller than one window is compared as a whole.
+fn ssim(a: &GrayImage, b: &GrayImage) -> f64 {
+    let (width, height) = a.dimensions();
+    if width == 0 || height == 0 {
+        return 1.0;
+    }
+    let mut total = 0.0;
+    let mut windows = 0u32;
+    for y0 in (0..height).step_by(SSIM_WINDOW as usize) {
+        for x0 in (0..width).step_by(SSIM_WINDOW as usize) {
+            let (x1, y1) = ((x0 + SSIM_WINDOW).min(width), (y0 + SSIM_WINDOW).min(height));
+            let n = ((x1 - x0) * (y1 - y0)) as f64;
+            let (mut sum_a, mut sum_b, mut sum_aa, mut sum_bb, mut sum_ab) = (0.0, 0.0, 0.0, 0.0, 0.0);
+            for y in y0..y1 {
+                for x in x0..x1 {
+                    let pa = a.get_pixel(x, y)[0] as f64;
+                    let pb = b.get_pixel(x, y)[0] as f64;
+                    sum_a += pa;
+                    sum_b += pb;
+                    sum_aa += pa * pa;
+                    sum_bb += pb * pb;
+                    sum_ab += pa * pb;
+                }
+            }
+            let (mean_a, mean_b) = (sum_a / n, sum_b / n);
+            let var_a = sum_aa / n - mean_a * mean_a;
+            let var_b = sum_bb / n - mean_b * mean_b;
+            let cov = sum_ab / n - mean_a * mean_b;
+            total += ((2.0 * mean_a * mean_b + SSIM_C1) * (2.0 * cov + SSIM_C2))
+                / ((mean_a * mean_a + mean_b * mean_b + SSIM_C1) * (var_a + var_b + SSIM_C2));
+            windows += 1;
+        }
+    }
+    total / windows as f64
+}
@@ -1,19 +1,44 @@
-use std::path::PathBuf;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
 use thiserror::Error;
 
+use crate::cache::{self_test, CacheStats, CloudflareCacheConfig, FsCheck, SledCache, SourceCache};
+use crate::backpressure::{FetchLimiter, TransformLimiter, DEFAULT_FETCH_QUEUE_TIMEOUT};
+use crate::fetch::{HttpClients, DEFAULT_ALLOWED_SCHEMES};
+use crate::observer::TransformObserver;
+use crate::quota::QuotaTracker;
+use crate::server::ServerTuning;
+use crate::transform::params::{FitMode, FormatParam, Gravity, ResizeFilter};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
 /// Supported output image formats for transformations.
 ///
 /// Format selection impacts both file size and encoding performance:
 /// - JPEG: Fastest encoding, good compression for photos
 /// - WebP: Better compression than JPEG, good browser support
 /// - AVIF: Best compression, slower encoding, limited browser support
+/// - PNG: Lossless, keeps alpha; mainly for `f=auto` on PNG sources
+/// - ICO: A single favicon entry, at most 256x256
+/// - TIFF, BMP: Uncompressed, for print/archival; `extra_formats` feature only
+///
+/// New formats are added over time (and some depend on features), so
+/// matches outside this crate need a wildcard arm.
 #[allow(non_camel_case_types)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Deserialize, serde::Serialize)]
 #[serde(rename_all = "lowercase")]
+#[non_exhaustive]
 pub enum ImageFormat {
     jpeg,
     webp,
     avif,
+    png,
+    ico,
+    #[cfg(feature = "extra_formats")]
+    tiff,
+    #[cfg(feature = "extra_formats")]
+    bmp,
 }
 
 impl std::fmt::Display for ImageFormat {
@@ -22,12 +47,137 @@ impl std::fmt::Display for ImageFormat {
             ImageFormat::jpeg => write!(f, "jpeg"),
             ImageFormat::webp => write!(f, "webp"),
             ImageFormat::avif => write!(f, "avif"),
+            ImageFormat::png => write!(f, "png"),
+            ImageFormat::ico => write!(f, "ico"),
+            #[cfg(feature = "extra_formats")]
+            ImageFormat::tiff => write!(f, "tiff"),
+            #[cfg(feature = "extra_formats")]
+            ImageFormat::bmp => write!(f, "bmp"),
+        }
+    }
+}
+
+impl std::str::FromStr for ImageFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "jpeg" => Ok(ImageFormat::jpeg),
+            "webp" => Ok(ImageFormat::webp),
+            "avif" => Ok(ImageFormat::avif),
+            "png" => Ok(ImageFormat::png),
+            "ico" => Ok(ImageFormat::ico),
+            #[cfg(feature = "extra_formats")]
+            "tiff" => Ok(ImageFormat::tiff),
+            #[cfg(feature = "extra_formats")]
+            "bmp" => Ok(ImageFormat::bmp),
+            _ => Err(format!("Invalid format: {}", s)),
+        }
+    }
+}
+
+/// Transform params a named preset (`/img?preset=thumb`) expands to.
+///
+/// A request using a preset may not also set any of these params; it gets
+/// 400 instead, so presets can't be overridden by clients.
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PresetParams {
+    pub w: Option<u32>,
+    pub h: Option<u32>,
+    pub fit: Option<FitMode>,
+    pub f: Option<FormatParam>,
+    pub q: Option<u8>,
+    pub gravity: Option<Gravity>,
+}
+
+/// Source image formats recognized from magic bytes.
+///
+/// Used to restrict what `/upload` accepts (`allowed_upload_formats`),
+/// independent of the output formats in [`ImageFormat`].
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+#[non_exhaustive]
+pub enum InputFormat {
+    jpeg,
+    png,
+    gif,
+    webp,
+    avif,
+    heif,
+    bmp,
+    tiff,
+    ico,
+}
+
+impl std::fmt::Display for InputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InputFormat::jpeg => write!(f, "jpeg"),
+            InputFormat::png => write!(f, "png"),
+            InputFormat::gif => write!(f, "gif"),
+            InputFormat::webp => write!(f, "webp"),
+            InputFormat::avif => write!(f, "avif"),
+            InputFormat::heif => write!(f, "heif"),
+            InputFormat::bmp => write!(f, "bmp"),
+            InputFormat::tiff => write!(f, "tiff"),
+            InputFormat::ico => write!(f, "ico"),
+        }
+    }
+}
+
+/// Color signaling (CICP) for AVIF output.
+///
+/// Matters when AVIF frames are consumed by video pipelines that honor the
+/// declared primaries/matrix rather than assuming sRGB.
+/// - `srgb`: BT.709 primaries, sRGB transfer (web default)
+/// - `bt709`: BT.709 primaries, transfer and YCbCr matrix (HD video)
+/// - `bt601`: BT.601 (SMPTE 170M) primaries, transfer and matrix (SD video)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Deserialize, serde::Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum AvifColorSpace {
+    #[default]
+    Srgb,
+    Bt709,
+    Bt601,
+}
+
+impl std::fmt::Display for AvifColorSpace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AvifColorSpace::Srgb => write!(f, "srgb"),
+            AvifColorSpace::Bt709 => write!(f, "bt709"),
+            AvifColorSpace::Bt601 => write!(f, "bt601"),
+        }
+    }
+}
+
+/// Lowest TLS version accepted from origins when fetching sources.
+///
+/// `1.3` as the minimum needs reqwest's rustls backend; with the default
+/// native-tls backend the fetch client can't be built (see `validate`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Deserialize, serde::Serialize)]
+pub enum TlsVersion {
+    #[default]
+    #[serde(rename = "1.2")]
+    Tls1_2,
+    #[serde(rename = "1.3")]
+    Tls1_3,
+}
+
+impl std::fmt::Display for TlsVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TlsVersion::Tls1_2 => write!(f, "1.2"),
+            TlsVersion::Tls1_3 => write!(f, "1.3"),
         }
     }
 }
 
 /// Default quality setting balancing file size and visual fidelity.
 /// Value of 80 provides near-lossless quality for most use cases.
+/// Fallback for formats missing from `ImageKitConfig::default_quality`.
 pub const DEFAULT_QUALITY: u8 = 80;
 
 /// Minimum quality threshold to prevent excessive compression artifacts.
@@ -36,6 +186,33 @@ pub const MIN_QUALITY: u8 = 1;
 /// Maximum quality setting for near-lossless encoding.
 pub const MAX_QUALITY: u8 = 100;
 
+/// Default maximum source pixel count (width * height), checked from the
+/// image header before decoding. 50 megapixels covers high-end camera output.
+pub const DEFAULT_MAX_INPUT_PIXELS: u64 = 50_000_000;
+
+/// Default decoder allocation cap per source (512MB, `image`'s own default).
+pub const DEFAULT_MAX_DECODE_ALLOC: u64 = 512 * 1024 * 1024;
+
+/// Default AVIF encoder speed (0 = slowest/smallest, 10 = fastest).
+/// Speed 4 balances encoding time and compression ratio for on-the-fly use.
+pub const DEFAULT_AVIF_SPEED: u8 = 4;
+
+/// Default cap on a single AVIF encode before the request gets a 504.
+pub const DEFAULT_AVIF_ENCODE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Default cap on a whole source fetch (connect through last body byte)
+/// before the request gets a 504.
+pub const DEFAULT_FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Fastest supported AVIF encoder speed.
+pub const MAX_AVIF_SPEED: u8 = 10;
+
+/// Default cap on the length of the `url` parameter (2KB).
+pub const DEFAULT_MAX_URL_LENGTH: usize = 2048;
+
+/// Default free space the cache volume needs for readiness (64MB).
+pub const DEFAULT_MIN_FREE_CACHE_BYTES: u64 = 64 * 1024 * 1024;
+
 /// Aggressive browser cache directive for transformed images.
 ///
 /// 1-year max-age is safe because transformation parameters act as natural
@@ -57,6 +234,12 @@ pub struct ImageKitConfig {
     /// Must be cryptographically random and kept confidential.
     pub secret: String,
     
+    /// Per-tenant signing secrets, keyed by tenant id. A request carrying
+    /// `tenant=<id>` must be signed with that tenant's secret (unknown ids
+    /// get 401), so a leaked secret only exposes its own tenant. Requests
+    /// without `tenant` use `secret`.
+    pub tenants: HashMap<String, String>,
+    
     /// Filesystem path for persistent cache storage.
     /// Directory will be created if it doesn't exist.
     pub cache_dir: PathBuf,
@@ -65,32 +248,562 @@ pub struct ImageKitConfig {
     /// Requests exceeding this limit are rejected with 413.
     pub max_input_size: usize,
     
+    /// Maximum declared source dimensions (width * height) in pixels.
+    /// Checked from the image header so decompression bombs are rejected before decode.
+    pub max_input_pixels: u64,
+    
+    /// Most memory the decoder may allocate for one source, in bytes.
+    /// Enforced by the decoder itself (`image::Limits`), so it also covers
+    /// formats whose headers `max_input_pixels` can't read. None is unbounded.
+    pub max_decode_alloc: Option<u64>,
+    
+    /// Largest source width/height the decoder accepts; None is unbounded.
+    pub max_image_width: Option<u32>,
+    pub max_image_height: Option<u32>,
+    
+    /// Maximum length of the `url` parameter in bytes.
+    /// Longer values are rejected with 400 before they are hashed or logged.
+    pub max_url_length: usize,
+    
     /// Maximum cache size in bytes before LRU eviction begins.
+    /// Also caps the `/img` disk cache, which evicts its oldest files first.
     /// None allows unbounded growth (use with caution).
     pub max_cache_size: Option<u64>,
     
+    /// Maximum number of cache entries before LRU eviction begins.
+    /// Also caps the `/img` disk cache, which evicts its oldest files first.
+    /// Guards against arbitrary w/h/q combinations flooding the cache with
+    /// tiny entries that the byte limit alone would never evict.
+    /// None leaves the entry count unbounded.
+    pub max_cache_entries: Option<usize>,
+    
+    /// Free space required on the cache volume for `/health/ready` to pass.
+    pub min_free_cache_bytes: u64,
+    
     /// Permitted output formats for transformations.
     /// Restricting formats can improve security and reduce attack surface.
+    /// Requests for any other format are rejected with 400, and defaults,
+    /// `f=auto` and encoder fallbacks stay within the list.
     pub allowed_formats: Vec<ImageFormat>,
     
     /// Default format when client doesn't specify preference.
     /// WebP recommended for balance of compression and compatibility.
+    /// Ignored if it isn't in `allowed_formats`; see `default_output_format`.
     pub default_format: Option<ImageFormat>,
+    
+    /// Quality used when the request omits `q`, per output format. The same
+    /// number means different things to each encoder: AVIF at 55 looks
+    /// roughly like JPEG at 82. Formats not listed use `DEFAULT_QUALITY`.
+    pub default_quality: HashMap<ImageFormat, u8>,
+    
+    /// Treat a missing `f` as `f=auto`: keep the source's format when it can
+    /// be encoded, falling back to `default_format` otherwise.
+    pub preserve_source_format: bool,
+    
+    /// Caching policy emitted on transformed images.
+    /// Requests carrying an expiry (`t`) are further bounded by the URL's remaining lifetime.
+    pub cache_control: CloudflareCacheConfig,
+    
+    /// AVIF encoder speed used when the request omits `speed` (0-10).
+    /// Lower is slower with better compression; see `transform::encode_image_with`.
+    pub avif_speed: u8,
+    
+    /// AVIF color signaling used when the request omits `colorspace`.
+    pub avif_colorspace: AvifColorSpace,
+    
+    /// Longest an AVIF encode may run on the blocking pool before the
+    /// request fails with 504 Gateway Timeout.
+    pub avif_encode_timeout: Duration,
+    
+    /// Longest a source fetch may take, from connecting to the last body
+    /// byte, before the request fails with 504 Gateway Timeout.
+    pub fetch_timeout: Duration,
+    
+    /// Formats tried in order when an encoder fails: a failed encode retries
+    /// with the formats after it here. Translucent output skips JPEG.
+    pub encode_fallback_chain: Vec<ImageFormat>,
+    
+    /// Mix a hash of `secret` into every cache key.
+    /// Rotating the secret then logically invalidates the whole cache, so
+    /// outputs produced under a retired secret can never be served again.
+    pub bind_cache_to_secret: bool,
+    
+    /// Generation of the transform logic, mixed into every cache key.
+    /// Bump it after changing how outputs are produced (a resize filter,
+    /// an encoder setting) to logically invalidate the whole cache without
+    /// wiping it. `0` leaves keys as they were before versioning existed.
+    pub cache_version: u32,
+    
+    /// Optional hooks invoked during `/img` processing (fetch, transform, cache hit/miss).
+    /// `None` behaves like `observer::NoopObserver`.
+    pub observer: Option<Arc<dyn TransformObserver>>,
+    
+    /// Optional cap on concurrent cache-miss transforms for `/img`.
+    /// When saturated, requests get 503 with a latency-based `Retry-After`.
+    pub transform_limiter: Option<TransformLimiter>,
+    
+    /// Optional cap on concurrent outbound source fetches (`max_concurrent_fetches`).
+    /// Fetches queue for a slot; one that can't get it in time gets 503.
+    pub fetch_limiter: Option<FetchLimiter>,
+    
+    /// Optional monthly `/img` request quotas per tenant (`tenant_quotas`).
+    /// A tenant over its quota gets 429 until the month turns over;
+    /// tenants without one are unlimited.
+    pub quotas: Option<QuotaTracker>,
+    
+    /// Keep fetched source bytes under `cache_dir/originals` for this long.
+    /// Serves `f=original` passthrough and lets transforms of a known URL skip
+    /// the origin fetch. `None` disables the originals store.
+    pub original_cache_ttl: Option<Duration>,
+    
+    /// HTTP clients reused across requests. `router()` builds them from the
+    /// TLS settings when unset; see
+    /// [`ImageKitConfig::build_http_clients`].
+    pub http_clients: Option<HttpClients>,
+    
+    /// Short-lived in-memory cache of fetched sources, so several variants
+    /// of one image requested together share a single download.
+    /// `None` fetches per cache miss (unless the originals store hits).
+    pub source_cache: Option<SourceCache>,
+    
+    /// Origins allowed to load images cross-origin (e.g. `<img crossorigin>`
+    /// drawn to a canvas). `"*"` allows any origin; empty disables CORS.
+    pub cors_allowed_origins: Vec<String>,
+    
+    /// Reject `/img` requests carrying any parameter that wasn't signed.
+    /// Unknown parameters (e.g. `utm_source`) are otherwise ignored, so they
+    /// can be appended to a signed URL; see `signature::SIGNED_PARAMS`.
+    pub strict_params: bool,
+    
+    /// Add `x-imagekit-cache: HIT|MISS` and `x-imagekit-transform` (the
+    /// effective size, format, quality and fit) to `/img` responses, for
+    /// checking cache behavior through a CDN. Off by default.
+    pub expose_transform_headers: bool,
+    
+    /// Add `x-imagekit-blurhash` (a BlurHash of the output) to `/img`
+    /// responses. Computed from the resized image before encoding and
+    /// stored with the cache entry, so hits don't decode. Off by default.
+    pub blurhash_header: bool,
+    
+    /// Route diagnostics such as `GET /debug/cache-key`. Off by default:
+    /// they reveal cache internals, so keep them out of production.
+    pub enable_debug_endpoints: bool,
+    
+    /// Named transforms selectable with `preset=<name>`, so clients never
+    /// pass raw dimensions. The signature covers the name; the cache key
+    /// covers the expanded params, so redefining a preset takes effect.
+    pub presets: HashMap<String, PresetParams>,
+    
+    /// URL schemes sources may be fetched over (case-insensitive). Defaults
+    /// to `https` only; add `http` for plaintext origins, e.g. in development.
+    pub allowed_schemes: Vec<String>,
+    
+    /// Lowest TLS version negotiated with origins (default 1.2).
+    pub min_tls_version: TlsVersion,
+    
+    /// **Dangerous:** skip certificate validation on source fetches, so
+    /// self-signed internal origins work. Any certificate, including an
+    /// attacker's, is then trusted; only enable it for origins reached over
+    /// a network you control. Off by default, and logged at startup.
+    pub accept_invalid_certs: bool,
+    
+    /// Extra headers sent when fetching sources from a host, keyed by
+    /// lowercase host name, e.g. an `Authorization` bearer token for a
+    /// protected origin. Values stay server-side: clients never see or sign them.
+    pub origin_headers: HashMap<String, HashMap<String, String>>,
+    
+    /// Hosts an `/img` `callback_url` may point at (lowercase host names).
+    /// Empty, the default, disables callbacks.
+    pub callback_hosts: Vec<String>,
+    
+    /// Source formats `/upload` accepts; others get 415 before decoding.
+    /// None accepts anything the decoder understands.
+    pub allowed_upload_formats: Option<Vec<InputFormat>>,
+    
+    /// Filter for axes being shrunk; `/img?downscale_filter=` overrides it.
+    pub downscale_filter: ResizeFilter,
+    
+    /// Filter for axes being enlarged; `/img?upscale_filter=` overrides it.
+    /// `nearest` keeps pixel art crisp.
+    pub upscale_filter: ResizeFilter,
+    
+    /// Shrink factor from which downscales run in two stages: a fast box
+    /// pre-pass to twice the target, then `downscale_filter`. Much faster
+    /// for e.g. 8000px → 200px at nearly the same quality. None always
+    /// uses a single pass; see `transform::ResizeFilters`.
+    pub staged_downscale_factor: Option<u32>,
+    
+    /// Disable Nagle's algorithm on accepted connections, so small
+    /// responses (cache hits, `/health`) aren't held back. On by default.
+    pub tcp_nodelay: bool,
+    
+    /// Keep HTTP/1.1 connections open between requests. On by default.
+    pub http1_keep_alive: bool,
+    
+    /// Also serve HTTP/2 over cleartext (h2c, prior knowledge) on the same
+    /// port, for a proxy in front that speaks h2c upstream. Off by default,
+    /// serving HTTP/1.1 only: h2c has no TLS and, exposed directly, lets any
+    /// client open many multiplexed streams per connection. TLS (and so
+    /// ALPN `h2`) is left to the proxy.
+    pub http2: bool,
+    
+    /// Interval of HTTP/2 keep-alive pings; idle connections that stop
+    /// answering are closed. None (the default) sends no pings.
+    pub http2_keep_alive_interval: Option<Duration>,
 }
 
 impl Default for ImageKitConfig {
     fn default() -> Self {
         Self {
             secret: String::new(),
+            tenants: HashMap::new(),
             cache_dir: PathBuf::from("./cache"),
             max_input_size: 8 * 1024 * 1024,              // 8MB prevents DOS via large uploads
+            max_input_pixels: DEFAULT_MAX_INPUT_PIXELS,
+            max_decode_alloc: Some(DEFAULT_MAX_DECODE_ALLOC),
+            max_image_width: None,
+            max_image_height: None,
+            max_url_length: DEFAULT_MAX_URL_LENGTH,
             max_cache_size: Some(10 * 1024 * 1024 * 1024), // 10GB reasonable for most deployments
-            allowed_formats: vec![ImageFormat::jpeg, ImageFormat::webp, ImageFormat::avif],
+            max_cache_entries: None,
+            min_free_cache_bytes: DEFAULT_MIN_FREE_CACHE_BYTES,
+            allowed_formats: vec![ImageFormat::jpeg, ImageFormat::webp, ImageFormat::avif, ImageFormat::png],
             default_format: Some(ImageFormat::webp),       // Best compression/compatibility balance
+            default_quality: HashMap::from([
+                (ImageFormat::jpeg, 82),
+                (ImageFormat::webp, 80),
+                (ImageFormat::avif, 55),
+            ]),
+            preserve_source_format: false,
+            cache_control: CloudflareCacheConfig::for_images(),
+            avif_speed: DEFAULT_AVIF_SPEED,
+            avif_colorspace: AvifColorSpace::Srgb,
+            avif_encode_timeout: DEFAULT_AVIF_ENCODE_TIMEOUT,
+            fetch_timeout: DEFAULT_FETCH_TIMEOUT,
+            encode_fallback_chain: vec![ImageFormat::avif, ImageFormat::webp, ImageFormat::jpeg],
+            bind_cache_to_secret: false,
+            cache_version: 0,
+            observer: None,
+            transform_limiter: None,
+            fetch_limiter: None,
+            quotas: None,
+            original_cache_ttl: None,
+            http_clients: None,
+            source_cache: None,
+            cors_allowed_origins: Vec::new(),
+            strict_params: false,
+            expose_transform_headers: false,
+            blurhash_header: false,
+            enable_debug_endpoints: false,
+            presets: HashMap::new(),
+            allowed_schemes: DEFAULT_ALLOWED_SCHEMES.iter().map(|s| s.to_string()).collect(),
+            min_tls_version: TlsVersion::default(),
+            accept_invalid_certs: false,
+            origin_headers: HashMap::new(),
+            callback_hosts: Vec::new(),
+            allowed_upload_formats: None,
+            downscale_filter: ResizeFilter::Lanczos3,
+            upscale_filter: ResizeFilter::Lanczos3,
+            staged_downscale_factor: Some(crate::transform::DEFAULT_STAGED_DOWNSCALE_FACTOR),
+            tcp_nodelay: true,
+            http1_keep_alive: true,
+            http2: false,
+            http2_keep_alive_interval: None,
         }
     }
 }
 
+/// Fluent builder for [`ImageKitConfig`].
+///
+/// Starts from `ImageKitConfig::default()`; only `secret` has no usable
+/// default. `build()` runs `validate()`, so a built config is always ready
+/// to hand to `router`.
+///
+/// ```no_run
+/// use imagekit::config::{ImageFormat, ImageKitConfig};
+///
+/// let config = ImageKitConfig::builder()
+///     .secret("change-me")
+///     .cache_dir("/var/cache/imagekit")
+///     .default_format(ImageFormat::avif)
+///     .build()
+///     .expect("valid config");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ImageKitConfigBuilder {
+    config: ImageKitConfig,
+}
+
+impl ImageKitConfigBuilder {
+    pub fn secret(mut self, secret: impl Into<String>) -> Self {
+        self.config.secret = secret.into();
+        self
+    }
+    
+    /// Registers (or replaces) the signing secret of tenant `id`.
+    pub fn tenant(mut self, id: impl Into<String>, secret: impl Into<String>) -> Self {
+        self.config.tenants.insert(id.into(), secret.into());
+        self
+    }
+    
+    pub fn cache_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.config.cache_dir = dir.into();
+        self
+    }
+    
+    pub fn max_input_size(mut self, bytes: usize) -> Self {
+        self.config.max_input_size = bytes;
+        self
+    }
+    
+    pub fn max_input_pixels(mut self, pixels: u64) -> Self {
+        self.config.max_input_pixels = pixels;
+        self
+    }
+    
+    pub fn max_decode_alloc(mut self, bytes: Option<u64>) -> Self {
+        self.config.max_decode_alloc = bytes;
+        self
+    }
+    
+    pub fn max_image_dimensions(mut self, width: Option<u32>, height: Option<u32>) -> Self {
+        self.config.max_image_width = width;
+        self.config.max_image_height = height;
+        self
+    }
+    
+    pub fn max_url_length(mut self, bytes: usize) -> Self {
+        self.config.max_url_length = bytes;
+        self
+    }
+    
+    /// `None` allows unbounded cache growth.
+    pub fn max_cache_size(mut self, bytes: Option<u64>) -> Self {
+        self.config.max_cache_size = bytes;
+        self
+    }
+    
+    pub fn min_free_cache_bytes(mut self, bytes: u64) -> Self {
+        self.config.min_free_cache_bytes = bytes;
+        self
+    }
+    
+    /// `None` leaves the entry count unbounded.
+    pub fn max_cache_entries(mut self, entries: Option<usize>) -> Self {
+        self.config.max_cache_entries = entries;
+        self
+    }
+    
+    pub fn allowed_formats(mut self, formats: impl IntoIterator<Item = ImageFormat>) -> Self {
+        self.config.allowed_formats = formats.into_iter().collect();
+        self
+    }
+    
+    pub fn default_format(mut self, format: ImageFormat) -> Self {
+        self.config.default_format = Some(format);
+        self
+    }
+    
+    /// Sets the quality used for `format` when the request omits `q`.
+    pub fn default_quality(mut self, format: ImageFormat, quality: u8) -> Self {
+        self.config.default_quality.insert(format, quality);
+        self
+    }
+    
+    pub fn preserve_source_format(mut self, preserve: bool) -> Self {
+        self.config.preserve_source_format = preserve;
+        self
+    }
+    
+    pub fn cache_control(mut self, cache_control: CloudflareCacheConfig) -> Self {
+        self.config.cache_control = cache_control;
+        self
+    }
+    
+    pub fn avif_speed(mut self, speed: u8) -> Self {
+        self.config.avif_speed = speed;
+        self
+    }
+    
+    pub fn avif_colorspace(mut self, colorspace: AvifColorSpace) -> Self {
+        self.config.avif_colorspace = colorspace;
+        self
+    }
+    
+    pub fn avif_encode_timeout(mut self, timeout: Duration) -> Self {
+        self.config.avif_encode_timeout = timeout;
+        self
+    }
+    
+    pub fn fetch_timeout(mut self, timeout: Duration) -> Self {
+        self.config.fetch_timeout = timeout;
+        self
+    }
+    
+    pub fn encode_fallback_chain(mut self, formats: impl IntoIterator<Item = ImageFormat>) -> Self {
+        self.config.encode_fallback_chain = formats.into_iter().collect();
+        self
+    }
+    
+    pub fn bind_cache_to_secret(mut self, bind: bool) -> Self {
+        self.config.bind_cache_to_secret = bind;
+        self
+    }
+    
+    pub fn cache_version(mut self, version: u32) -> Self {
+        self.config.cache_version = version;
+        self
+    }
+    
+    pub fn observer(mut self, observer: Arc<dyn TransformObserver>) -> Self {
+        self.config.observer = Some(observer);
+        self
+    }
+    
+    pub fn transform_limiter(mut self, limiter: TransformLimiter) -> Self {
+        self.config.transform_limiter = Some(limiter);
+        self
+    }
+    
+    /// Allows at most `max` source fetches at once, each queueing up to
+    /// `max_wait` for a slot; see [`FetchLimiter`].
+    pub fn max_concurrent_fetches(mut self, max: usize, max_wait: Duration) -> Self {
+        self.config.fetch_limiter = Some(FetchLimiter::new(max, max_wait));
+        self
+    }
+    
+    /// Allows tenant `id` at most `monthly_limit` `/img` requests per calendar
+    /// month (UTC); see [`QuotaTracker`].
+    pub fn tenant_quota(mut self, id: impl Into<String>, monthly_limit: u64) -> Self {
+        let quotas = self.config.quotas.take().unwrap_or_default();
+        self.config.quotas = Some(quotas.with_limit(id, monthly_limit));
+        self
+    }
+    
+    pub fn source_cache(mut self, cache: SourceCache) -> Self {
+        self.config.source_cache = Some(cache);
+        self
+    }
+    
+    pub fn original_cache_ttl(mut self, ttl: Duration) -> Self {
+        self.config.original_cache_ttl = Some(ttl);
+        self
+    }
+    
+    pub fn cors_allowed_origins<S: Into<String>>(mut self, origins: impl IntoIterator<Item = S>) -> Self {
+        self.config.cors_allowed_origins = origins.into_iter().map(Into::into).collect();
+        self
+    }
+    
+    pub fn strict_params(mut self, strict: bool) -> Self {
+        self.config.strict_params = strict;
+        self
+    }
+    
+    pub fn expose_transform_headers(mut self, expose: bool) -> Self {
+        self.config.expose_transform_headers = expose;
+        self
+    }
+    
+    pub fn blurhash_header(mut self, enable: bool) -> Self {
+        self.config.blurhash_header = enable;
+        self
+    }
+    
+    pub fn enable_debug_endpoints(mut self, enable: bool) -> Self {
+        self.config.enable_debug_endpoints = enable;
+        self
+    }
+    
+    /// Registers (or replaces) the preset `name`.
+    pub fn preset(mut self, name: impl Into<String>, params: PresetParams) -> Self {
+        self.config.presets.insert(name.into(), params);
+        self
+    }
+    
+    pub fn allowed_schemes<S: Into<String>>(mut self, schemes: impl IntoIterator<Item = S>) -> Self {
+        self.config.allowed_schemes = schemes.into_iter().map(Into::into).collect();
+        self
+    }
+    
+    pub fn min_tls_version(mut self, version: TlsVersion) -> Self {
+        self.config.min_tls_version = version;
+        self
+    }
+    
+    /// See [`ImageKitConfig::accept_invalid_certs`] before enabling this.
+    pub fn accept_invalid_certs(mut self, accept: bool) -> Self {
+        self.config.accept_invalid_certs = accept;
+        self
+    }
+    
+    /// Adds a header sent with every source fetch from `host`.
+    pub fn origin_header(mut self, host: impl Into<String>, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.config
+            .origin_headers
+            .entry(host.into().to_ascii_lowercase())
+            .or_default()
+            .insert(name.into(), value.into());
+        self
+    }
+    
+    /// Allows `callback_url`s on `host`.
+    pub fn callback_host(mut self, host: impl Into<String>) -> Self {
+        self.config.callback_hosts.push(host.into().to_ascii_lowercase());
+        self
+    }
+    
+    /// `None` accepts any decodable upload.
+    pub fn allowed_upload_formats(mut self, formats: Option<Vec<InputFormat>>) -> Self {
+        self.config.allowed_upload_formats = formats;
+        self
+    }
+    
+    pub fn downscale_filter(mut self, filter: ResizeFilter) -> Self {
+        self.config.downscale_filter = filter;
+        self
+    }
+    
+    pub fn upscale_filter(mut self, filter: ResizeFilter) -> Self {
+        self.config.upscale_filter = filter;
+        self
+    }
+    
+    pub fn staged_downscale_factor(mut self, factor: Option<u32>) -> Self {
+        self.config.staged_downscale_factor = factor;
+        self
+    }
+    
+    pub fn tcp_nodelay(mut self, enabled: bool) -> Self {
+        self.config.tcp_nodelay = enabled;
+        self
+    }
+    
+    pub fn http1_keep_alive(mut self, enabled: bool) -> Self {
+        self.config.http1_keep_alive = enabled;
+        self
+    }
+    
+    pub fn http2(mut self, enabled: bool) -> Self {
+        self.config.http2 = enabled;
+        self
+    }
+    
+    pub fn http2_keep_alive_interval(mut self, interval: Duration) -> Self {
+        self.config.http2_keep_alive_interval = Some(interval);
+        self
+    }
+    
+    /// Validates and returns the configuration.
+    ///
+    /// # Errors
+    /// Returns `ConfigError` from [`ImageKitConfig::validate`].
+    pub fn build(self) -> Result<ImageKitConfig, ConfigError> {
+        self.config.validate()?;
+        Ok(self.config)
+    }
+}
+
 /// Configuration validation errors.
 ///
 /// These errors indicate invalid configuration state that must be
@@ -100,11 +813,218 @@ pub enum ConfigError {
     #[error("Secret cannot be empty")]
     EmptySecret,
     
+    #[error("Secret for tenant {0} cannot be empty")]
+    EmptyTenantSecret(String),
+    
+    #[error("Quota for unknown tenant {0}")]
+    UnknownQuotaTenant(String),
+    
     #[error("Max input size must be > 0")]
     InvalidMaxInput,
+    
+    #[error("Max input pixels must be > 0")]
+    InvalidMaxPixels,
+    
+    #[error("AVIF speed must be between 0 and 10")]
+    InvalidAvifSpeed,
+    
+    #[error("Default quality for {0} must be between 1 and 100")]
+    InvalidDefaultQuality(ImageFormat),
+    
+    #[error("Unsupported TLS settings: {0}")]
+    Tls(String),
+    
+    #[error("Failed to read config file: {0}")]
+    Read(#[from] std::io::Error),
+    
+    #[error("Invalid config file: {0}")]
+    Parse(String),
+    
+    #[error("Invalid value for {name}: {value}")]
+    InvalidEnv { name: &'static str, value: String },
+}
+
+/// On-disk form of [`ImageKitConfig`] (see `ImageKitConfig::from_file`).
+///
+/// Every field is optional and falls back to `ImageKitConfig::default()`.
+/// Runtime-only settings (observer, limiter, cache-control policy) can't be
+/// expressed in a file and keep their defaults.
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileConfig {
+    secret: Option<String>,
+    tenants: Option<HashMap<String, String>>,
+    /// Monthly `/img` requests per tenant id; see `ImageKitConfig::quotas`
+    tenant_quotas: Option<HashMap<String, u64>>,
+    cache_dir: Option<PathBuf>,
+    max_input_size: Option<usize>,
+    max_input_pixels: Option<u64>,
+    max_decode_alloc: Option<u64>,
+    max_image_width: Option<u32>,
+    max_image_height: Option<u32>,
+    max_url_length: Option<usize>,
+    max_cache_size: Option<u64>,
+    max_cache_entries: Option<usize>,
+    min_free_cache_bytes: Option<u64>,
+    allowed_formats: Option<Vec<ImageFormat>>,
+    default_format: Option<ImageFormat>,
+    /// Merged over the built-in per-format defaults
+    default_quality: Option<HashMap<ImageFormat, u8>>,
+    preserve_source_format: Option<bool>,
+    avif_speed: Option<u8>,
+    avif_colorspace: Option<AvifColorSpace>,
+    /// Milliseconds; see `ImageKitConfig::avif_encode_timeout`
+    avif_encode_timeout_ms: Option<u64>,
+    /// Milliseconds; see `ImageKitConfig::fetch_timeout`
+    fetch_timeout_ms: Option<u64>,
+    max_concurrent_fetches: Option<usize>,
+    /// Milliseconds a fetch may queue for a slot; see `FetchLimiter`
+    fetch_queue_timeout_ms: Option<u64>,
+    encode_fallback_chain: Option<Vec<ImageFormat>>,
+    bind_cache_to_secret: Option<bool>,
+    cache_version: Option<u32>,
+    /// Seconds; see `ImageKitConfig::original_cache_ttl`
+    original_cache_ttl_secs: Option<u64>,
+    cors_allowed_origins: Option<Vec<String>>,
+    strict_params: Option<bool>,
+    expose_transform_headers: Option<bool>,
+    blurhash_header: Option<bool>,
+    enable_debug_endpoints: Option<bool>,
+    presets: Option<HashMap<String, PresetParams>>,
+    allowed_schemes: Option<Vec<String>>,
+    min_tls_version: Option<TlsVersion>,
+    accept_invalid_certs: Option<bool>,
+    origin_headers: Option<HashMap<String, HashMap<String, String>>>,
+    callback_hosts: Option<Vec<String>>,
+    allowed_upload_formats: Option<Vec<InputFormat>>,
+    downscale_filter: Option<ResizeFilter>,
+    upscale_filter: Option<ResizeFilter>,
+    staged_downscale_factor: Option<u32>,
+    tcp_nodelay: Option<bool>,
+    http1_keep_alive: Option<bool>,
+    http2: Option<bool>,
+    http2_keep_alive_interval_secs: Option<u64>,
 }
 
 impl ImageKitConfig {
+    /// Starts an [`ImageKitConfigBuilder`] from the defaults.
+    pub fn builder() -> ImageKitConfigBuilder {
+        ImageKitConfigBuilder::default()
+    }
+    
+    /// Loads configuration from a TOML file, then applies env overrides.
+    ///
+    /// Missing keys keep their `Default` values and unknown keys are
+    /// rejected. After the file, these environment variables take precedence:
+    /// `IMAGEKIT_SECRET`, `IMAGEKIT_CACHE_DIR`, `IMAGEKIT_MAX_INPUT_SIZE`,
+    /// `IMAGEKIT_MAX_CACHE_SIZE`, `IMAGEKIT_DEFAULT_FORMAT`,
+    /// `IMAGEKIT_AVIF_SPEED`. The result is checked with [`validate`](Self::validate).
+    ///
+    /// # Errors
+    /// Returns `ConfigError` if the file can't be read or parsed, an env
+    /// override is malformed, or validation fails.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let text = std::fs::read_to_string(path)?;
+        let file: FileConfig = toml::from_str(&text).map_err(|e| ConfigError::Parse(e.to_string()))?;
+        
+        let defaults = Self::default();
+        let mut config = Self {
+            secret: file.secret.unwrap_or(defaults.secret),
+            tenants: file.tenants.unwrap_or(defaults.tenants),
+            cache_dir: file.cache_dir.unwrap_or(defaults.cache_dir),
+            max_input_size: file.max_input_size.unwrap_or(defaults.max_input_size),
+            max_input_pixels: file.max_input_pixels.unwrap_or(defaults.max_input_pixels),
+            max_decode_alloc: file.max_decode_alloc.or(defaults.max_decode_alloc),
+            max_image_width: file.max_image_width.or(defaults.max_image_width),
+            max_image_height: file.max_image_height.or(defaults.max_image_height),
+            max_url_length: file.max_url_length.unwrap_or(defaults.max_url_length),
+            max_cache_size: file.max_cache_size.or(defaults.max_cache_size),
+            max_cache_entries: file.max_cache_entries.or(defaults.max_cache_entries),
+            min_free_cache_bytes: file.min_free_cache_bytes.unwrap_or(defaults.min_free_cache_bytes),
+            allowed_formats: file.allowed_formats.unwrap_or(defaults.allowed_formats),
+            default_format: file.default_format.or(defaults.default_format),
+            default_quality: {
+                let mut qualities = defaults.default_quality;
+                qualities.extend(file.default_quality.unwrap_or_default());
+                qualities
+            },
+            preserve_source_format: file.preserve_source_format.unwrap_or(defaults.preserve_source_format),
+            avif_speed: file.avif_speed.unwrap_or(defaults.avif_speed),
+            avif_colorspace: file.avif_colorspace.unwrap_or(defaults.avif_colorspace),
+            avif_encode_timeout: file.avif_encode_timeout_ms.map(Duration::from_millis).unwrap_or(defaults.avif_encode_timeout),
+            fetch_timeout: file.fetch_timeout_ms.map(Duration::from_millis).unwrap_or(defaults.fetch_timeout),
+            fetch_limiter: file
+                .max_concurrent_fetches
+                .map(|max| {
+                    let max_wait = file.fetch_queue_timeout_ms.map(Duration::from_millis).unwrap_or(DEFAULT_FETCH_QUEUE_TIMEOUT);
+                    FetchLimiter::new(max, max_wait)
+                })
+                .or(defaults.fetch_limiter),
+            quotas: file.tenant_quotas.map(QuotaTracker::new).or(defaults.quotas),
+            encode_fallback_chain: file.encode_fallback_chain.unwrap_or(defaults.encode_fallback_chain),
+            bind_cache_to_secret: file.bind_cache_to_secret.unwrap_or(defaults.bind_cache_to_secret),
+            cache_version: file.cache_version.unwrap_or(defaults.cache_version),
+            original_cache_ttl: file.original_cache_ttl_secs.map(Duration::from_secs).or(defaults.original_cache_ttl),
+            cors_allowed_origins: file.cors_allowed_origins.unwrap_or(defaults.cors_allowed_origins),
+            strict_params: file.strict_params.unwrap_or(defaults.strict_params),
+            expose_transform_headers: file.expose_transform_headers.unwrap_or(defaults.expose_transform_headers),
+            blurhash_header: file.blurhash_header.unwrap_or(defaults.blurhash_header),
+            enable_debug_endpoints: file.enable_debug_endpoints.unwrap_or(defaults.enable_debug_endpoints),
+            presets: file.presets.unwrap_or(defaults.presets),
+            allowed_schemes: file.allowed_schemes.unwrap_or(defaults.allowed_schemes),
+            min_tls_version: file.min_tls_version.unwrap_or(defaults.min_tls_version),
+            accept_invalid_certs: file.accept_invalid_certs.unwrap_or(defaults.accept_invalid_certs),
+            origin_headers: file.origin_headers.unwrap_or(defaults.origin_headers),
+            callback_hosts: file.callback_hosts.unwrap_or(defaults.callback_hosts),
+            allowed_upload_formats: file.allowed_upload_formats.or(defaults.allowed_upload_formats),
+            downscale_filter: file.downscale_filter.unwrap_or(defaults.downscale_filter),
+            upscale_filter: file.upscale_filter.unwrap_or(defaults.upscale_filter),
+            staged_downscale_factor: file.staged_downscale_factor.or(defaults.staged_downscale_factor),
+            tcp_nodelay: file.tcp_nodelay.unwrap_or(defaults.tcp_nodelay),
+            http1_keep_alive: file.http1_keep_alive.unwrap_or(defaults.http1_keep_alive),
+            http2: file.http2.unwrap_or(defaults.http2),
+            http2_keep_alive_interval: file.http2_keep_alive_interval_secs.map(Duration::from_secs).or(defaults.http2_keep_alive_interval),
+            ..defaults
+        };
+        
+        config.apply_env_overrides()?;
+        config.validate()?;
+        Ok(config)
+    }
+    
+    /// Overrides file values with `IMAGEKIT_*` environment variables.
+    fn apply_env_overrides(&mut self) -> Result<(), ConfigError> {
+        fn parsed<T: std::str::FromStr>(name: &'static str) -> Result<Option<T>, ConfigError> {
+            match std::env::var(name) {
+                Ok(value) => value
+                    .parse()
+                    .map(Some)
+                    .map_err(|_| ConfigError::InvalidEnv { name, value }),
+                Err(_) => Ok(None),
+            }
+        }
+        
+        if let Ok(secret) = std::env::var("IMAGEKIT_SECRET") {
+            self.secret = secret;
+        }
+        if let Ok(dir) = std::env::var("IMAGEKIT_CACHE_DIR") {
+            self.cache_dir = PathBuf::from(dir);
+        }
+        if let Some(size) = parsed("IMAGEKIT_MAX_INPUT_SIZE")? {
+            self.max_input_size = size;
+        }
+        if let Some(size) = parsed("IMAGEKIT_MAX_CACHE_SIZE")? {
+            self.max_cache_size = Some(size);
+        }
+        if let Some(format) = parsed("IMAGEKIT_DEFAULT_FORMAT")? {
+            self.default_format = Some(format);
+        }
+        if let Some(speed) = parsed("IMAGEKIT_AVIF_SPEED")? {
+            self.avif_speed = speed;
+        }
+        Ok(())
+    }
+    
     /// Validates configuration for production readiness.
     ///
     /// Ensures critical security and resource limit settings are properly
@@ -116,9 +1036,174 @@ impl ImageKitConfig {
         if self.secret.trim().is_empty() {
             return Err(ConfigError::EmptySecret);
         }
+        if let Some((tenant, _)) = self.tenants.iter().find(|(_, secret)| secret.trim().is_empty()) {
+            return Err(ConfigError::EmptyTenantSecret(tenant.clone()));
+        }
+        // Likely a typo; the quota would never apply
+        if let Some(tenant) = self.quotas.iter().flat_map(|q| q.tenants()).find(|t| !self.tenants.contains_key(*t)) {
+            return Err(ConfigError::UnknownQuotaTenant(tenant.to_string()));
+        }
         if self.max_input_size == 0 {
             return Err(ConfigError::InvalidMaxInput);
         }
+        if self.max_input_pixels == 0 {
+            return Err(ConfigError::InvalidMaxPixels);
+        }
+        if self.avif_speed > MAX_AVIF_SPEED {
+            return Err(ConfigError::InvalidAvifSpeed);
+        }
+        if let Some((&format, _)) = self.default_quality.iter().find(|(_, &q)| q == 0 || q > 100) {
+            return Err(ConfigError::InvalidDefaultQuality(format));
+        }
+        self.fetch_client().map_err(|e| ConfigError::Tls(e.to_string()))?;
         Ok(())
     }
-}
\ No newline at end of file
+    
+    /// Decoder limits from `max_decode_alloc` and `max_image_width`/`height`;
+    /// see `transform::decode_image_with`.
+    pub fn decode_limits(&self) -> image::Limits {
+        let mut limits = image::Limits::no_limits();
+        limits.max_alloc = self.max_decode_alloc;
+        limits.max_image_width = self.max_image_width;
+        limits.max_image_height = self.max_image_height;
+        limits
+    }
+    
+    /// Connection settings for [`crate::server::serve`].
+    pub fn server_tuning(&self) -> ServerTuning {
+        ServerTuning {
+            tcp_nodelay: self.tcp_nodelay,
+            http1_keep_alive: self.http1_keep_alive,
+            http2: self.http2,
+            http2_keep_alive_interval: self.http2_keep_alive_interval,
+        }
+    }
+    
+    /// Output format for requests that don't pick one: `default_format`
+    /// when it's allowed, else WebP, else the first of `allowed_formats`.
+    pub fn default_output_format(&self) -> ImageFormat {
+        let preferred = self.default_format.unwrap_or(ImageFormat::webp);
+        [preferred, ImageFormat::webp]
+            .into_iter()
+            .chain(self.allowed_formats.iter().copied())
+            .find(|format| self.allowed_formats.contains(format))
+            .unwrap_or(preferred)
+    }
+    
+    /// Quality for `format` when the request omits `q`.
+    pub fn quality_for(&self, format: ImageFormat) -> u8 {
+        self.default_quality.get(&format).copied().unwrap_or(DEFAULT_QUALITY)
+    }
+    
+    /// HTTP client for source fetches, honoring `min_tls_version`,
+    /// `accept_invalid_certs`, `fetch_timeout` and, on every redirect,
+    /// `allowed_schemes`: the shared one from `http_clients` when set,
+    /// otherwise a new one.
+    ///
+    /// # Errors
+    /// Returns `ImageKitError::InternalError` if the TLS backend can't
+    /// satisfy the settings.
+    pub fn fetch_client(&self) -> Result<reqwest::Client, crate::ImageKitError> {
+        match &self.http_clients {
+            Some(clients) => Ok(clients.fetch.clone()),
+            None => crate::fetch::build_client(self.min_tls_version, self.accept_invalid_certs, self.fetch_timeout, &self.allowed_schemes),
+        }
+    }
+    
+    /// Builds the clients for `http_clients` from the current settings.
+    ///
+    /// # Errors
+    /// As for [`ImageKitConfig::fetch_client`].
+    pub fn build_http_clients(&self) -> Result<HttpClients, crate::ImageKitError> {
+        let fetch = crate::fetch::build_client(self.min_tls_version, self.accept_invalid_certs, self.fetch_timeout, &self.allowed_schemes)?;
+        let callback = crate::fetch::build_callback_client(self.min_tls_version, self.accept_invalid_certs)?;
+        Ok(HttpClients { fetch, callback })
+    }
+    
+    /// HTTP client for completion callbacks, which never follows redirects:
+    /// the shared one from `http_clients` when set, otherwise a new one.
+    ///
+    /// # Errors
+    /// As for [`ImageKitConfig::fetch_client`].
+    pub fn callback_client(&self) -> Result<reqwest::Client, crate::ImageKitError> {
+        match &self.http_clients {
+            Some(clients) => Ok(clients.callback.clone()),
+            None => crate::fetch::build_callback_client(self.min_tls_version, self.accept_invalid_certs),
+        }
+    }
+    
+    /// Headers configured in `origin_headers` for the host of `url`, if any.
+    pub fn origin_headers_for(&self, url: &str) -> Option<&HashMap<String, String>> {
+        let parsed = reqwest::Url::parse(url).ok()?;
+        self.origin_headers.get(parsed.host_str()?)
+    }
+    
+    /// Cache-key namespace derived from this configuration.
+    ///
+    /// Holds `cache_version` when non-zero and, if `bind_cache_to_secret`
+    /// is set, a truncated SHA-256 of the secret (never the secret itself).
+    /// Empty by default, so unversioned keys stay what they always were.
+    pub fn cache_namespace(&self) -> String {
+        let mut parts = Vec::new();
+        if self.cache_version != 0 {
+            parts.push(format!("v:{}", self.cache_version));
+        }
+        if self.bind_cache_to_secret {
+            let digest = Sha256::digest(self.secret.as_bytes());
+            parts.push(format!("secret:{}", &hex::encode(digest)[..16]));
+        }
+        parts.join(";")
+    }
+    
+    /// Self-tests `cache_dir` and warns loudly if it looks unsafe.
+    ///
+    /// Meant to run once at startup; see `cache::fs_check` for what is
+    /// verified. Network filesystems are the usual culprit, so the warning
+    /// suggests moving the cache to local disk.
+    pub fn check_cache_dir(&self) -> FsCheck {
+        let check = self_test(&self.cache_dir);
+        if let FsCheck::Unsafe(reason) = &check {
+            tracing::warn!(
+                "Cache directory {} failed its self-test ({}); it may be on a network filesystem. \
+                 Cached entries could be corrupted silently - use a local disk for cache_dir.",
+                self.cache_dir.display(),
+                reason
+            );
+        }
+        check
+    }
+    
+    /// Readiness checks for `cache_dir`: a write/rename/read round-trip
+    /// (see `cache::fs_check`) and at least `min_free_cache_bytes` free.
+    ///
+    /// Blocking; the `/health/ready` endpoint runs it off the async workers.
+    ///
+    /// # Errors
+    /// Returns a description of the first check that failed.
+    pub fn check_ready(&self) -> Result<u64, String> {
+        if let FsCheck::Unsafe(reason) = self_test(&self.cache_dir) {
+            return Err(format!("cache dir {} not writable: {}", self.cache_dir.display(), reason));
+        }
+        let free = fs2::available_space(&self.cache_dir)
+            .map_err(|e| format!("cannot read free space for {}: {}", self.cache_dir.display(), e))?;
+        if free < self.min_free_cache_bytes {
+            return Err(format!(
+                "only {} bytes free on cache volume, need {}", free, self.min_free_cache_bytes
+            ));
+        }
+        Ok(free)
+    }
+    
+    /// Reads statistics for the cache backing `cache_dir`.
+    ///
+    /// Programmatic equivalent of the `/stats/cache` endpoint for embedders
+    /// holding the router's config.
+    ///
+    /// # Errors
+    /// Returns the backend error if the cache can't be opened.
+    pub async fn cache_stats(&self) -> Result<CacheStats, String> {
+        let cache = SledCache::new(&self.cache_dir, self.max_cache_size)?
+            .with_max_entries(self.max_cache_entries);
+        Ok(cache.stats().await)
+    }
+}
@@ -1,52 +1,236 @@
+use crate::config::{TlsVersion, DEFAULT_FETCH_TIMEOUT};
 use crate::ImageKitError;
 use reqwest::Client;
 use bytes::BytesMut;
 use mime::Mime;
 use futures::StreamExt;
-use image::GenericImageView;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::time::Duration;
 
-/// Fetches and validates source image from remote URL.
+/// Source URL schemes allowed by default: plaintext `http` is refused.
+pub const DEFAULT_ALLOWED_SCHEMES: &[&str] = &["https"];
+
+/// Rejects `url` unless its scheme is one of `allowed` (case-insensitive).
+///
+/// Runs before any request is made. Unparseable URLs are rejected too.
+pub fn check_scheme(url: &str, allowed: &[String]) -> Result<(), ImageKitError> {
+    let parsed = reqwest::Url::parse(url)
+        .map_err(|e| ImageKitError::InvalidArgument(format!("Invalid source URL: {}", e)))?;
+    if allowed.iter().any(|s| s.eq_ignore_ascii_case(parsed.scheme())) {
+        Ok(())
+    } else {
+        Err(ImageKitError::InvalidArgument(format!(
+            "Source URL scheme not allowed: {}",
+            parsed.scheme()
+        )))
+    }
+}
+
+/// Redirects followed per fetch, matching reqwest's default policy.
+const MAX_REDIRECTS: usize = 10;
+
+/// Builds the client used for source fetches.
+///
+/// Certificates are verified unless `accept_invalid_certs` is set; see
+/// `ImageKitConfig::accept_invalid_certs` for why that is dangerous. A
+/// fetch running past `timeout` fails with `ImageKitError::Timeout`, and a
+/// redirect to a scheme outside `allowed_schemes` with
+/// `ImageKitError::InvalidArgument`.
+///
+/// # Errors
+/// Returns `ImageKitError::InternalError` if the TLS backend rejects the
+/// settings (native-tls can't require TLS 1.3).
+pub fn build_client(
+    min_tls_version: TlsVersion,
+    accept_invalid_certs: bool,
+    timeout: Duration,
+    allowed_schemes: &[String],
+) -> Result<Client, ImageKitError> {
+    // Every hop is held to `allowed_schemes`, not just the URL we were
+    // given, so an https origin can't bounce a fetch to plain http
+    let schemes = allowed_schemes.to_vec();
+    let redirects = reqwest::redirect::Policy::custom(move |attempt| {
+        if attempt.previous().len() >= MAX_REDIRECTS {
+            attempt.error("too many redirects")
+        } else if let Err(e) = check_scheme(attempt.url().as_str(), &schemes) {
+            attempt.error(e.to_string())
+        } else {
+            attempt.follow()
+        }
+    });
+    client_builder(min_tls_version, accept_invalid_certs)
+        .timeout(timeout)
+        .redirect(redirects)
+        .build()
+        .map_err(|e| ImageKitError::InternalError(format!("Failed to build fetch client: {}", e)))
+}
+
+/// Builds the client used for completion callbacks: the same TLS settings
+/// as [`build_client`], but redirects are never followed, so an allowed
+/// callback host can't forward the signed payload elsewhere.
+///
+/// # Errors
+/// As for [`build_client`].
+pub fn build_callback_client(min_tls_version: TlsVersion, accept_invalid_certs: bool) -> Result<Client, ImageKitError> {
+    client_builder(min_tls_version, accept_invalid_certs)
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .map_err(|e| ImageKitError::InternalError(format!("Failed to build callback client: {}", e)))
+}
+
+fn client_builder(min_tls_version: TlsVersion, accept_invalid_certs: bool) -> reqwest::ClientBuilder {
+    let version = match min_tls_version {
+        TlsVersion::Tls1_2 => reqwest::tls::Version::TLS_1_2,
+        TlsVersion::Tls1_3 => reqwest::tls::Version::TLS_1_3,
+    };
+    Client::builder()
+        .min_tls_version(version)
+        .danger_accept_invalid_certs(accept_invalid_certs)
+}
+
+/// HTTP clients shared by every request of a router, so connections are
+/// pooled and the TLS settings are applied once.
+#[derive(Debug, Clone)]
+pub struct HttpClients {
+    /// Source fetches; see [`build_client`]
+    pub fetch: Client,
+    /// Completion callbacks; see [`build_callback_client`]
+    pub callback: Client,
+}
+
+/// Upstream cache validators, replayed on refresh as
+/// `If-None-Match`/`If-Modified-Since`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl Validators {
+    /// True when the origin sent neither validator, so a conditional
+    /// request isn't possible.
+    pub fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+}
+
+/// Outcome of [`fetch_source_conditional`].
+#[derive(Debug)]
+pub enum Fetched {
+    /// Full body, with the validators the origin sent alongside it
+    Modified {
+        bytes: Vec<u8>,
+        content_type: String,
+        validators: Validators,
+    },
+    /// `304 Not Modified`: the caller's copy is still current
+    NotModified,
+}
+
+/// Fetches and validates source image from remote URL, with a client
+/// built from default (secure) settings.
 ///
 /// Implements defense-in-depth validation strategy:
 /// 1. HTTP status code verification
 /// 2. Content-Type validation  
 /// 3. Content-Length size limits
 /// 4. Streaming size enforcement (prevents size header spoofing)
-/// 5. Image format validation via decoding
-/// 6. Dimension sanity checks
+/// 5. Image format validation via magic bytes
+/// 6. Header-only dimension checks (see [`validate_dimensions`])
 ///
 /// # Parameters
 /// * `url` - Source image URL (must be publicly accessible)
 /// * `max_size` - Maximum allowed content size in bytes
+/// * `max_pixels` - Maximum declared `width * height` of the source image
 /// * `_allowed_formats` - Reserved for future format filtering
+/// * `allowed_schemes` - URL schemes that may be fetched (see [`check_scheme`])
 ///
 /// # Security
 /// - Prevents memory exhaustion via size limits
 /// - Validates actual image data (not just Content-Type)
 /// - Streaming download prevents holding large buffers
-/// - Rejects malformed or zero-dimension images
+/// - Rejects zero-dimension or oversized images from the header alone,
+///   so decompression bombs are refused before any pixels are decoded
 ///
 /// # Returns
 /// Tuple of (image_bytes, content_type) on success
 ///
 /// # Errors
 /// Returns `ImageKitError` if:
+/// - The URL's scheme isn't in `allowed_schemes`
 /// - Network request fails or returns non-2xx status
 /// - Content-Type is not image/* (when parseable)
 /// - Content size exceeds `max_size` limit
-/// - Image cannot be decoded or has invalid dimensions
+/// - Image format cannot be detected or declares invalid/oversized dimensions
 pub async fn fetch_source(
     url: &str,
     max_size: usize,
+    max_pixels: u64,
     _allowed_formats: &[crate::config::ImageFormat],
+    allowed_schemes: &[String],
 ) -> Result<(Vec<u8>, String), ImageKitError> {
-    let client = Client::new();
-    let resp = client
-        .get(url)
-        .send()
-        .await
-        .map_err(|e| ImageKitError::NetworkError(e.to_string()))?;
-        
+    let client = build_client(TlsVersion::default(), false, DEFAULT_FETCH_TIMEOUT, allowed_schemes)?;
+    fetch_source_with_headers(&client, url, &HashMap::new(), max_size, max_pixels, _allowed_formats, allowed_schemes).await
+}
+
+/// [`fetch_source`] through `client`, sending `headers` with the request.
+///
+/// Used for origins that require credentials (see
+/// `ImageKitConfig::origin_headers`); `client` carries the TLS settings
+/// (see `ImageKitConfig::fetch_client`). Validation is identical.
+pub async fn fetch_source_with_headers(
+    client: &Client,
+    url: &str,
+    headers: &HashMap<String, String>,
+    max_size: usize,
+    max_pixels: u64,
+    _allowed_formats: &[crate::config::ImageFormat],
+    allowed_schemes: &[String],
+) -> Result<(Vec<u8>, String), ImageKitError> {
+    match fetch_source_conditional(client, url, headers, None, max_size, max_pixels, allowed_schemes).await? {
+        Fetched::Modified { bytes, content_type, .. } => Ok((bytes, content_type)),
+        Fetched::NotModified => Err(ImageKitError::NetworkError(
+            "Upstream answered 304 to an unconditional request".into(),
+        )),
+    }
+}
+
+/// Revalidating fetch: sends `validators` as `If-None-Match` /
+/// `If-Modified-Since` and returns [`Fetched::NotModified`] on a `304`.
+///
+/// A modified response goes through the same checks as [`fetch_source`]
+/// and carries the origin's new `ETag`/`Last-Modified`. `client` carries
+/// the TLS settings; see [`build_client`].
+pub async fn fetch_source_conditional(
+    client: &Client,
+    url: &str,
+    headers: &HashMap<String, String>,
+    validators: Option<&Validators>,
+    max_size: usize,
+    max_pixels: u64,
+    allowed_schemes: &[String],
+) -> Result<Fetched, ImageKitError> {
+    check_scheme(url, allowed_schemes)?;
+
+    let mut request = client.get(url);
+    for (name, value) in headers {
+        request = request.header(name.as_str(), value.as_str());
+    }
+    if let Some(validators) = validators {
+        if let Some(etag) = &validators.etag {
+            request = request.header(reqwest::header::IF_NONE_MATCH, etag.as_str());
+        }
+        if let Some(last_modified) = &validators.last_modified {
+            request = request.header(reqwest::header::IF_MODIFIED_SINCE, last_modified.as_str());
+        }
+    }
+    let resp = request.send().await.map_err(request_error)?;
+
+    if resp.status() == reqwest::StatusCode::NOT_MODIFIED && validators.is_some() {
+        return Ok(Fetched::NotModified);
+    }
     if !resp.status().is_success() {
         return Err(ImageKitError::NetworkError(format!(
             "Upstream status: {}",
@@ -71,6 +255,12 @@ pub async fn fetch_source(
     }
     // Unknown MIME types continue - will be validated during decode
 
+    let header = |name: reqwest::header::HeaderName| resp.headers().get(name).and_then(|v| v.to_str().ok()).map(str::to_string);
+    let validators = Validators {
+        etag: header(reqwest::header::ETAG),
+        last_modified: header(reqwest::header::LAST_MODIFIED),
+    };
+
     // Pre-flight size check based on Content-Length header
     if let Some(len) = resp.content_length() {
         if len as usize > max_size {
@@ -88,7 +278,7 @@ pub async fn fetch_source(
         .next()
         .await
         .transpose()
-        .map_err(|e| ImageKitError::NetworkError(e.to_string()))?
+        .map_err(request_error)?
     {
         if buf.len() + chunk.len() > max_size {
             return Err(ImageKitError::InvalidArgument(
@@ -100,25 +290,70 @@ pub async fn fetch_source(
     
     let bytes = buf.to_vec();
 
-    // Validate image integrity by attempting decode and dimension check
-    match image::guess_format(&bytes)
-        .ok()
-        .and_then(|fmt| image::load_from_memory_with_format(&bytes, fmt).ok())
-    {
-        Some(img) => {
-            let (w, h) = img.dimensions();
-            if w == 0 || h == 0 {
-                return Err(ImageKitError::InvalidArgument(
-                    "Invalid image dimensions".into(),
-                ));
-            }
-        }
-        None => {
-            return Err(ImageKitError::InvalidArgument(
-                "Unable to decode image for validation".into(),
-            ))
-        }
+    // Validate format and declared dimensions without decoding pixels
+    validate_dimensions(&bytes, max_pixels)?;
+
+    Ok(Fetched::Modified { bytes, content_type: ct, validators })
+}
+
+/// Timeouts (the client's overall `timeout` covers the body too) become
+/// `ImageKitError::Timeout`, anything else `NetworkError`.
+fn request_error(e: reqwest::Error) -> ImageKitError {
+    if e.is_timeout() {
+        ImageKitError::Timeout(e.to_string())
+    } else if e.is_redirect() {
+        // The policy's reason is the source; reqwest's own message only
+        // names the URL
+        let reason = std::error::Error::source(&e).map(ToString::to_string).unwrap_or_else(|| e.to_string());
+        ImageKitError::InvalidArgument(format!("Source redirect rejected: {}", reason))
+    } else {
+        ImageKitError::NetworkError(e.to_string())
     }
+}
 
-    Ok((bytes, ct))
-}
\ No newline at end of file
+/// Reads image dimensions from the encoded header and checks them.
+///
+/// Only the container/codec header is parsed, so an image declaring huge
+/// dimensions is rejected without allocating or decompressing its pixels.
+///
+/// # Returns
+/// Declared `(width, height)` of the image.
+///
+/// # Errors
+/// Returns `ImageKitError::InvalidArgument` if the format is unrecognized,
+/// the header is unreadable, either dimension is zero, or `width * height`
+/// exceeds `max_pixels`.
+pub fn validate_dimensions(bytes: &[u8], max_pixels: u64) -> Result<(u32, u32), ImageKitError> {
+    let (w, h) = read_dimensions(bytes)
+        .ok_or_else(|| ImageKitError::InvalidArgument(
+            "Unable to read image header for validation".into(),
+        ))?;
+
+    if w == 0 || h == 0 {
+        return Err(ImageKitError::InvalidArgument(
+            "Invalid image dimensions".into(),
+        ));
+    }
+
+    if w as u64 * h as u64 > max_pixels {
+        return Err(ImageKitError::InvalidArgument(format!(
+            "Image dimensions {}x{} exceed pixel limit of {}",
+            w, h, max_pixels
+        )));
+    }
+
+    Ok((w, h))
+}
+
+/// Reads declared dimensions from the image header.
+fn read_dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    #[cfg(feature = "heif")]
+    if crate::transform::is_heif(bytes) {
+        return crate::transform::heif_dimensions(bytes);
+    }
+
+    image::ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .ok()
+        .and_then(|reader| reader.into_dimensions().ok())
+}
@@ -1,20 +1,26 @@
 use axum::{
     extract::Query,
     http::{HeaderMap, HeaderValue, StatusCode},
-    response::IntoResponse,
+    response::{IntoResponse, Response},
     routing::get,
     Router,
     body::Body,
     Json,
 };
-use axum::extract::Multipart;
+use axum::extract::{Multipart, RawQuery};
 use serde::{Deserialize, Serialize};
-use std::{collections::BTreeMap, sync::Arc};
+use utoipa::{IntoParams, OpenApi, ToSchema};
+use std::{collections::{BTreeMap, HashMap}, sync::Arc};
 use thiserror::Error;
 use hmac::Hmac;
 use hmac::Mac;
 use sha2::Sha256;
+use image::GenericImageView;
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+use tower_http::limit::RequestBodyLimitLayer;
 use tower_http::services::ServeDir;
+use tower_http::set_header::SetResponseHeaderLayer;
+use axum::http::{HeaderName, Method};
 use tower_governor::{governor::GovernorConfigBuilder, GovernorLayer};
 
 pub mod config;
@@ -22,16 +28,30 @@ pub mod signature;
 pub mod cache;
 pub mod transform;
 pub mod fetch;
+pub mod observer;
+pub mod backpressure;
+pub mod coalesce;
+pub mod logging;
 #[cfg(feature = "prometheus")]
 pub mod metrics;
+pub mod placeholder;
+pub mod compare;
+pub mod quota;
+pub mod server;
 
-use crate::cache::{Cache, DiskCache};
-use crate::config::{ImageFormat, ImageKitConfig, DEFAULT_QUALITY, DEFAULT_CACHE_CONTROL, NO_CACHE_CONTROL};
-use crate::fetch::fetch_source;
-use crate::signature::verify_signature;
-use crate::transform::{encode_image, resize_image, decode_image};
+use crate::cache::{content_type_from_format, etag_for_content, format_from_extension, Cache, CloudflareCacheConfig, Computed, DiskCache, Lookup, OriginalStore};
+use crate::config::{AvifColorSpace, ImageFormat, ImageKitConfig, PresetParams, MAX_AVIF_SPEED, NO_CACHE_CONTROL};
+use crate::fetch::{check_scheme, fetch_source_conditional, validate_dimensions, Fetched};
+use crate::observer::{NoopObserver, TransformObserver};
+use crate::signature::{tenant_secret, verify_signature, verify_tenant_signature};
+use crate::coalesce::InFlight;
+use crate::compare::compare_images;
+use crate::quota::{QuotaPeriod, QuotaUsage};
+use crate::placeholder::{compute_blurhash, lqip_data_uri, DEFAULT_BLURHASH_COMPONENTS};
+use crate::transform::{auto_quality, avif_bit_depth, crop_image, crop_with_gravity, decode_image_with, fits_within, encode_image_with, probe_image, encode_to_budget, encode_with_fallback, resize_image_with, resize_cover, resize_fill, detect_input_format, sniff_output_format, source_icc_profile, draw_badge, draw_ring, draw_text, embed_icc_profile, encoded_dimensions, gamma_image, invert_image, pad_to_canvas, parse_crop, parse_hex_color, set_opacity, parse_ring, pixelate_image, rotate_arbitrary, sanitize_text, sepia_image, tint_image, Crop, EncodeOptions, ResizeFilters, DEFAULT_TEXT_SIZE, MAX_ANGLE, MAX_GAMMA, MAX_PIXELATE_BLOCK, MAX_TEXT_SIZE, MIN_GAMMA, MIN_PIXELATE_BLOCK, MIN_TEXT_SIZE};
+use crate::transform::params::{ChromaSubsampling, FitMode, FormatParam, Gravity, QualityParam, ResizeFilter, TextPosition, Wrap};
 
-#[derive(Error, Debug)]
+#[derive(Error, Debug, Clone)]
 pub enum ImageKitError {
     #[error("Cache error: {0}")]
     CacheError(String),
@@ -39,6 +59,12 @@ pub enum ImageKitError {
     TransformError(String),
     #[error("Network error: {0}")]
     NetworkError(String),
+    /// The origin didn't answer within `fetch_timeout`
+    #[error("Timeout: {0}")]
+    Timeout(String),
+    /// Out of capacity, e.g. no `max_concurrent_fetches` slot freed up in time
+    #[error("Unavailable: {0}")]
+    Unavailable(String),
     #[error("Invalid argument: {0}")]
     InvalidArgument(String),
     #[error("Not found: {0}")]
@@ -54,26 +80,341 @@ pub enum ImageKitError {
 pub type Result<T> = std::result::Result<T, ImageKitError>;
 
 /// Public query parameters for image transformation
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct ImageQuery {
+    /// Source image URL
     pub url: String,
     #[serde(default)]
     pub w: Option<u32>,
     #[serde(default)]
     pub h: Option<u32>,
+    /// Output format, `original`, or `auto`
     #[serde(default)]
-    pub f: Option<ImageFormat>,
+    #[param(value_type = Option<String>)]
+    pub f: Option<FormatParam>,
+    /// Quality 1-100, or `auto`
     #[serde(default)]
-    pub q: Option<u8>,
+    #[param(value_type = Option<String>)]
+    pub q: Option<QualityParam>,
+    /// Expiry as a Unix timestamp
     #[serde(default)]
     pub t: Option<i64>,
-    pub sig: String,
+    #[serde(default)]
+    pub fit: Option<FitMode>,
+    /// Canvas color for `fit=pad`, as hex (default white)
+    #[serde(default)]
+    pub bg: Option<String>,
+    #[serde(default)]
+    pub fp_x: Option<f32>,
+    #[serde(default)]
+    pub fp_y: Option<f32>,
+    #[serde(default)]
+    pub gravity: Option<Gravity>,
+    #[serde(default)]
+    pub speed: Option<u8>,
+    #[serde(default)]
+    pub lossless: Option<bool>,
+    #[serde(default)]
+    pub colorspace: Option<AvifColorSpace>,
+    /// JPEG chroma subsampling: `420`, `422` or `444`
+    #[serde(default)]
+    pub chroma: Option<ChromaSubsampling>,
+    /// AVIF bits per channel: `8` (default) or `10`
+    #[serde(default)]
+    pub depth: Option<u8>,
+    #[serde(default)]
+    pub max_bytes: Option<usize>,
+    #[serde(default)]
+    pub wrap: Option<Wrap>,
+    #[serde(default)]
+    pub pixelate: Option<u32>,
+    #[serde(default)]
+    pub tint: Option<String>,
+    #[serde(default)]
+    pub ring: Option<String>,
+    /// Region cut from the source before resizing: `x,y,w,h`, or `WxH[,gravity]` (e.g. `400x400,north`)
+    #[serde(default)]
+    pub crop: Option<String>,
+    #[serde(default)]
+    pub badge: Option<String>,
+    #[serde(default)]
+    pub invert: Option<bool>,
+    #[serde(default)]
+    pub sepia: Option<bool>,
+    #[serde(default)]
+    pub opacity: Option<f32>,
+    #[serde(default)]
+    pub gamma: Option<f32>,
+    /// Clockwise rotation in degrees (-360 to 360); the canvas grows to fit
+    #[serde(default)]
+    pub angle: Option<f32>,
+    /// `false` leaves sources that already fit `w`×`h` at their own size
+    #[serde(default)]
+    pub enlarge: Option<bool>,
+    /// Notified with a signed POST once the output is cached; the host must
+    /// be in `callback_hosts`
+    #[serde(default)]
+    pub callback_url: Option<String>,
+    /// Filename for `Content-Disposition: attachment`; the extension is
+    /// set from the output format
+    #[serde(default)]
+    pub download: Option<String>,
+    /// Carry the source's ICC color profile into JPEG/WebP output
+    #[serde(default)]
+    pub keep_icc: Option<bool>,
+    /// Named transform from `ImageKitConfig.presets`
+    #[serde(default)]
+    pub preset: Option<String>,
+    /// Tenant whose secret signs the request; see `ImageKitConfig.tenants`
+    #[serde(default)]
+    pub tenant: Option<String>,
+    /// Text stamped over the output, styled by `text_pos`/`text_size`/`text_color`
+    #[serde(default)]
+    pub text: Option<String>,
+    #[serde(default)]
+    pub text_pos: Option<TextPosition>,
+    #[serde(default)]
+    pub text_size: Option<u32>,
+    #[serde(default)]
+    pub text_color: Option<String>,
+    #[serde(default)]
+    pub downscale_filter: Option<ResizeFilter>,
+    #[serde(default)]
+    pub upscale_filter: Option<ResizeFilter>,
+    /// May instead be sent as `Authorization: Signature <hex>`
+    #[serde(default)]
+    pub sig: Option<String>,
+}
+
+impl ImageQuery {
+    /// Transformation parameters covered by the signature (everything except `sig`).
+    ///
+    /// Unknown query params never get here, which is what lets tracking
+    /// params ride along unsigned; see [`signature::SIGNED_PARAMS`].
+    fn to_params(&self) -> BTreeMap<String, String> {
+        let mut map = BTreeMap::new();
+        map.insert("url".into(), self.url.clone());
+        if let Some(w) = self.w { map.insert("w".into(), w.to_string()); }
+        if let Some(h) = self.h { map.insert("h".into(), h.to_string()); }
+        if let Some(f) = self.f { map.insert("f".into(), f.to_string()); }
+        if let Some(q) = self.q { map.insert("q".into(), q.to_string()); }
+        if let Some(t) = self.t { map.insert("t".into(), t.to_string()); }
+        if let Some(fit) = &self.fit { map.insert("fit".into(), fit.to_string()); }
+        if let Some(bg) = &self.bg { map.insert("bg".into(), bg.clone()); }
+        if let Some(x) = self.fp_x { map.insert("fp_x".into(), x.to_string()); }
+        if let Some(y) = self.fp_y { map.insert("fp_y".into(), y.to_string()); }
+        if let Some(g) = self.gravity { map.insert("gravity".into(), g.to_string()); }
+        if let Some(speed) = self.speed { map.insert("speed".into(), speed.to_string()); }
+        if let Some(lossless) = self.lossless { map.insert("lossless".into(), lossless.to_string()); }
+        if let Some(cs) = self.colorspace { map.insert("colorspace".into(), cs.to_string()); }
+        if let Some(depth) = self.depth { map.insert("depth".into(), depth.to_string()); }
+        if let Some(chroma) = self.chroma { map.insert("chroma".into(), chroma.to_string()); }
+        if let Some(max) = self.max_bytes { map.insert("max_bytes".into(), max.to_string()); }
+        if let Some(wrap) = self.wrap { map.insert("wrap".into(), wrap.to_string()); }
+        if let Some(block) = self.pixelate { map.insert("pixelate".into(), block.to_string()); }
+        if let Some(tint) = &self.tint { map.insert("tint".into(), tint.clone()); }
+        if let Some(ring) = &self.ring { map.insert("ring".into(), ring.clone()); }
+        if let Some(crop) = &self.crop { map.insert("crop".into(), crop.clone()); }
+        if let Some(badge) = &self.badge { map.insert("badge".into(), badge.clone()); }
+        if let Some(invert) = self.invert { map.insert("invert".into(), invert.to_string()); }
+        if let Some(sepia) = self.sepia { map.insert("sepia".into(), sepia.to_string()); }
+        if let Some(opacity) = self.opacity { map.insert("opacity".into(), opacity.to_string()); }
+        if let Some(gamma) = self.gamma { map.insert("gamma".into(), gamma.to_string()); }
+        if let Some(angle) = self.angle { map.insert("angle".into(), angle.to_string()); }
+        if let Some(enlarge) = self.enlarge { map.insert("enlarge".into(), enlarge.to_string()); }
+        if let Some(callback_url) = &self.callback_url { map.insert("callback_url".into(), callback_url.clone()); }
+        if let Some(download) = &self.download { map.insert("download".into(), download.clone()); }
+        if let Some(keep) = self.keep_icc { map.insert("keep_icc".into(), keep.to_string()); }
+        if let Some(preset) = &self.preset { map.insert("preset".into(), preset.clone()); }
+        if let Some(tenant) = &self.tenant { map.insert("tenant".into(), tenant.clone()); }
+        if let Some(text) = &self.text { map.insert("text".into(), text.clone()); }
+        if let Some(pos) = self.text_pos { map.insert("text_pos".into(), pos.to_string()); }
+        if let Some(size) = self.text_size { map.insert("text_size".into(), size.to_string()); }
+        if let Some(color) = &self.text_color { map.insert("text_color".into(), color.clone()); }
+        if let Some(filter) = self.downscale_filter { map.insert("downscale_filter".into(), filter.to_string()); }
+        if let Some(filter) = self.upscale_filter { map.insert("upscale_filter".into(), filter.to_string()); }
+        debug_assert!(map.keys().all(|k| signature::is_signed_param(k)), "param missing from SIGNED_PARAMS");
+        map
+    }
 }
 
 // Signing query without `sig`
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct SignQuery {
-    pub url: String,
+    /// Source image URL for `/img`; omit it to sign `POST /transform` params
+    #[serde(default)]
+    pub url: Option<String>,
+    #[serde(default)]
+    pub w: Option<u32>,
+    #[serde(default)]
+    pub h: Option<u32>,
+    /// Output format, `original`, or `auto`
+    #[serde(default)]
+    #[param(value_type = Option<String>)]
+    pub f: Option<FormatParam>,
+    /// Quality 1-100, or `auto`
+    #[serde(default)]
+    #[param(value_type = Option<String>)]
+    pub q: Option<QualityParam>,
+    /// Expiry as a Unix timestamp
+    #[serde(default)]
+    pub t: Option<i64>,
+    #[serde(default)]
+    pub fit: Option<FitMode>,
+    /// Canvas color for `fit=pad`, as hex (default white)
+    #[serde(default)]
+    pub bg: Option<String>,
+    #[serde(default)]
+    pub fp_x: Option<f32>,
+    #[serde(default)]
+    pub fp_y: Option<f32>,
+    #[serde(default)]
+    pub gravity: Option<Gravity>,
+    #[serde(default)]
+    pub speed: Option<u8>,
+    #[serde(default)]
+    pub lossless: Option<bool>,
+    #[serde(default)]
+    pub colorspace: Option<AvifColorSpace>,
+    /// JPEG chroma subsampling: `420`, `422` or `444`
+    #[serde(default)]
+    pub chroma: Option<ChromaSubsampling>,
+    /// AVIF bits per channel: `8` (default) or `10`
+    #[serde(default)]
+    pub depth: Option<u8>,
+    #[serde(default)]
+    pub max_bytes: Option<usize>,
+    #[serde(default)]
+    pub wrap: Option<Wrap>,
+    #[serde(default)]
+    pub pixelate: Option<u32>,
+    #[serde(default)]
+    pub tint: Option<String>,
+    #[serde(default)]
+    pub ring: Option<String>,
+    /// Region cut from the source before resizing: `x,y,w,h`, or `WxH[,gravity]` (e.g. `400x400,north`)
+    #[serde(default)]
+    pub crop: Option<String>,
+    #[serde(default)]
+    pub badge: Option<String>,
+    #[serde(default)]
+    pub invert: Option<bool>,
+    #[serde(default)]
+    pub sepia: Option<bool>,
+    #[serde(default)]
+    pub opacity: Option<f32>,
+    #[serde(default)]
+    pub gamma: Option<f32>,
+    /// Clockwise rotation in degrees (-360 to 360); the canvas grows to fit
+    #[serde(default)]
+    pub angle: Option<f32>,
+    /// `false` leaves sources that already fit `w`×`h` at their own size
+    #[serde(default)]
+    pub enlarge: Option<bool>,
+    /// Notified with a signed POST once the output is cached; the host must
+    /// be in `callback_hosts`
+    #[serde(default)]
+    pub callback_url: Option<String>,
+    /// Filename for `Content-Disposition: attachment`; the extension is
+    /// set from the output format
+    #[serde(default)]
+    pub download: Option<String>,
+    /// Carry the source's ICC color profile into JPEG/WebP output
+    #[serde(default)]
+    pub keep_icc: Option<bool>,
+    /// Named transform from `ImageKitConfig.presets`
+    #[serde(default)]
+    pub preset: Option<String>,
+    /// Tenant whose secret signs the request; see `ImageKitConfig.tenants`
+    #[serde(default)]
+    pub tenant: Option<String>,
+    /// Text stamped over the output, styled by `text_pos`/`text_size`/`text_color`
+    #[serde(default)]
+    pub text: Option<String>,
+    #[serde(default)]
+    pub text_pos: Option<TextPosition>,
+    #[serde(default)]
+    pub text_size: Option<u32>,
+    #[serde(default)]
+    pub text_color: Option<String>,
+    #[serde(default)]
+    pub downscale_filter: Option<ResizeFilter>,
+    #[serde(default)]
+    pub upscale_filter: Option<ResizeFilter>,
+}
+
+impl SignQuery {
+    /// Transformation parameters to be signed.
+    fn to_params(&self) -> BTreeMap<String, String> {
+        let mut map = BTreeMap::new();
+        if let Some(url) = &self.url { map.insert("url".into(), url.clone()); }
+        if let Some(w) = self.w { map.insert("w".into(), w.to_string()); }
+        if let Some(h) = self.h { map.insert("h".into(), h.to_string()); }
+        if let Some(f) = self.f { map.insert("f".into(), f.to_string()); }
+        if let Some(q) = self.q { map.insert("q".into(), q.to_string()); }
+        if let Some(t) = self.t { map.insert("t".into(), t.to_string()); }
+        if let Some(fit) = &self.fit { map.insert("fit".into(), fit.to_string()); }
+        if let Some(bg) = &self.bg { map.insert("bg".into(), bg.clone()); }
+        if let Some(x) = self.fp_x { map.insert("fp_x".into(), x.to_string()); }
+        if let Some(y) = self.fp_y { map.insert("fp_y".into(), y.to_string()); }
+        if let Some(g) = self.gravity { map.insert("gravity".into(), g.to_string()); }
+        if let Some(speed) = self.speed { map.insert("speed".into(), speed.to_string()); }
+        if let Some(lossless) = self.lossless { map.insert("lossless".into(), lossless.to_string()); }
+        if let Some(cs) = self.colorspace { map.insert("colorspace".into(), cs.to_string()); }
+        if let Some(depth) = self.depth { map.insert("depth".into(), depth.to_string()); }
+        if let Some(chroma) = self.chroma { map.insert("chroma".into(), chroma.to_string()); }
+        if let Some(max) = self.max_bytes { map.insert("max_bytes".into(), max.to_string()); }
+        if let Some(wrap) = self.wrap { map.insert("wrap".into(), wrap.to_string()); }
+        if let Some(block) = self.pixelate { map.insert("pixelate".into(), block.to_string()); }
+        if let Some(tint) = &self.tint { map.insert("tint".into(), tint.clone()); }
+        if let Some(ring) = &self.ring { map.insert("ring".into(), ring.clone()); }
+        if let Some(crop) = &self.crop { map.insert("crop".into(), crop.clone()); }
+        if let Some(badge) = &self.badge { map.insert("badge".into(), badge.clone()); }
+        if let Some(invert) = self.invert { map.insert("invert".into(), invert.to_string()); }
+        if let Some(sepia) = self.sepia { map.insert("sepia".into(), sepia.to_string()); }
+        if let Some(opacity) = self.opacity { map.insert("opacity".into(), opacity.to_string()); }
+        if let Some(gamma) = self.gamma { map.insert("gamma".into(), gamma.to_string()); }
+        if let Some(angle) = self.angle { map.insert("angle".into(), angle.to_string()); }
+        if let Some(enlarge) = self.enlarge { map.insert("enlarge".into(), enlarge.to_string()); }
+        if let Some(callback_url) = &self.callback_url { map.insert("callback_url".into(), callback_url.clone()); }
+        if let Some(download) = &self.download { map.insert("download".into(), download.clone()); }
+        if let Some(keep) = self.keep_icc { map.insert("keep_icc".into(), keep.to_string()); }
+        if let Some(preset) = &self.preset { map.insert("preset".into(), preset.clone()); }
+        if let Some(tenant) = &self.tenant { map.insert("tenant".into(), tenant.clone()); }
+        if let Some(text) = &self.text { map.insert("text".into(), text.clone()); }
+        if let Some(pos) = self.text_pos { map.insert("text_pos".into(), pos.to_string()); }
+        if let Some(size) = self.text_size { map.insert("text_size".into(), size.to_string()); }
+        if let Some(color) = &self.text_color { map.insert("text_color".into(), color.clone()); }
+        if let Some(filter) = self.downscale_filter { map.insert("downscale_filter".into(), filter.to_string()); }
+        if let Some(filter) = self.upscale_filter { map.insert("upscale_filter".into(), filter.to_string()); }
+        map
+    }
+
+    /// Checks that a query without `url` only uses what `POST /transform`
+    /// accepts, so the signature it gets is one `/transform` can verify.
+    fn check_transform_params(&self) -> std::result::Result<(), String> {
+        const TRANSFORM_PARAMS: [&str; 5] = ["w", "h", "f", "q", "t"];
+        if let Some(key) = self.to_params().into_keys().find(|k| !TRANSFORM_PARAMS.contains(&k.as_str())) {
+            return Err(format!("`{}` requires `url`; /transform only takes w, h, f, q and t", key));
+        }
+        if let Some(f @ (FormatParam::Original | FormatParam::Auto)) = self.f {
+            return Err(format!("`f={}` requires `url`", f));
+        }
+        if let Some(QualityParam::Auto) = self.q {
+            return Err("`q=auto` requires `url`".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Query for `POST /transform`.
+///
+/// The source image is the request body, so there is no `url`; the
+/// remaining parameters are signed exactly like `/img` parameters.
+#[derive(Debug, Deserialize)]
+pub struct TransformQuery {
     #[serde(default)]
     pub w: Option<u32>,
     #[serde(default)]
@@ -84,162 +425,1623 @@ pub struct SignQuery {
     pub q: Option<u8>,
     #[serde(default)]
     pub t: Option<i64>,
+    /// May instead be sent as `Authorization: Signature <hex>`
+    #[serde(default)]
+    pub sig: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
+impl TransformQuery {
+    /// Transformation parameters covered by the signature (everything except `sig`).
+    fn to_params(&self) -> BTreeMap<String, String> {
+        let mut map = BTreeMap::new();
+        if let Some(w) = self.w { map.insert("w".into(), w.to_string()); }
+        if let Some(h) = self.h { map.insert("h".into(), h.to_string()); }
+        if let Some(f) = self.f { map.insert("f".into(), f.to_string()); }
+        if let Some(q) = self.q { map.insert("q".into(), q.to_string()); }
+        if let Some(t) = self.t { map.insert("t".into(), t.to_string()); }
+        map
+    }
+}
+
+/// Query parameters for `POST /metrics/reset`.
+#[derive(Debug, Deserialize)]
+pub struct MetricsResetQuery {
+    #[serde(default)]
+    pub t: Option<i64>,
+    /// May instead be sent as `Authorization: Signature <hex>`
+    #[serde(default)]
+    pub sig: Option<String>,
+}
+
+impl MetricsResetQuery {
+    /// Signed parameters: a fixed `action` plus optional expiry.
+    ///
+    /// `action` isn't accepted by `/sign`, so its output can never
+    /// authorize a reset.
+    fn to_params(&self) -> BTreeMap<String, String> {
+        let mut map = BTreeMap::new();
+        map.insert("action".into(), "metrics_reset".into());
+        if let Some(t) = self.t { map.insert("t".into(), t.to_string()); }
+        map
+    }
+}
+
+/// Query parameters for `POST /warm`.
+#[derive(Debug, Deserialize)]
+pub struct WarmQuery {
+    #[serde(default)]
+    pub t: Option<i64>,
+    /// May instead be sent as `Authorization: Signature <hex>`
+    #[serde(default)]
+    pub sig: Option<String>,
+}
+
+/// Query for `GET /blurhash`, `GET /lqip` and `GET /info`.
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PlaceholderQuery {
+    /// Source image URL
+    pub url: String,
+    /// Expiry as a unix timestamp
+    #[serde(default)]
+    pub t: Option<i64>,
+    /// Tenant whose secret signed the request
+    #[serde(default)]
+    pub tenant: Option<String>,
+    /// May instead be sent as `Authorization: Signature <hex>`
+    #[serde(default)]
+    pub sig: Option<String>,
+}
+
+impl PlaceholderQuery {
+    /// Signed parameters; the same as an untransformed `/img` URL, so a
+    /// `/sign` result for just `url` works here too.
+    fn to_params(&self) -> BTreeMap<String, String> {
+        let mut map = BTreeMap::new();
+        map.insert("url".into(), self.url.clone());
+        if let Some(t) = self.t { map.insert("t".into(), t.to_string()); }
+        if let Some(tenant) = &self.tenant { map.insert("tenant".into(), tenant.clone()); }
+        map
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BlurhashResponse {
+    pub blurhash: String,
+    /// Source dimensions, for sizing the placeholder
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct LqipResponse {
+    /// `data:image/jpeg;base64,...`
+    pub data_uri: String,
+    /// Source dimensions, for sizing the placeholder
+    pub width: u32,
+    pub height: u32,
+}
+
+impl WarmQuery {
+    /// Signed parameters: a fixed `action` plus optional expiry, as for
+    /// [`MetricsResetQuery`].
+    fn to_params(&self) -> BTreeMap<String, String> {
+        let mut map = BTreeMap::new();
+        map.insert("action".into(), "warm".into());
+        if let Some(t) = self.t { map.insert("t".into(), t.to_string()); }
+        map
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
 pub struct SignResponse {
     pub canonical: String,
     pub sig: String,
     pub signed_url: String,
 }
 
+/// Output settings an `/img` request resolves to before the source is seen.
+#[derive(Debug, Clone, Copy)]
+struct OutputPlan {
+    /// `f=auto`: keep the source's format, which is only known after
+    /// decoding; until then `format` is the fallback for unencodable sources
+    auto: bool,
+    format: ImageFormat,
+    /// `q`, or the configured default for `format`
+    quality: QualityParam,
+    filters: ResizeFilters,
+}
+
+impl ImageQuery {
+    /// Replaces `preset` by the params it stands for.
+    ///
+    /// Explicit params that a preset may set are rejected alongside one,
+    /// rather than overriding it.
+    fn expand_preset(&mut self, presets: &HashMap<String, PresetParams>) -> std::result::Result<(), String> {
+        let Some(name) = &self.preset else {
+            return Ok(());
+        };
+        let preset = presets.get(name).ok_or_else(|| format!("Unknown preset: {}", name))?;
+        let explicit = [
+            ("w", self.w.is_some()),
+            ("h", self.h.is_some()),
+            ("fit", self.fit.is_some()),
+            ("f", self.f.is_some()),
+            ("q", self.q.is_some()),
+            ("gravity", self.gravity.is_some()),
+        ];
+        if let Some((param, _)) = explicit.iter().find(|(_, set)| *set) {
+            return Err(format!("Cannot combine preset with {}", param));
+        }
+
+        let preset = preset.clone();
+        self.w = preset.w;
+        self.h = preset.h;
+        self.fit = preset.fit;
+        self.f = preset.f;
+        self.q = preset.q.map(QualityParam::Value);
+        self.gravity = preset.gravity;
+        Ok(())
+    }
+
+    fn plan(&self, state: &ImageKitConfig) -> OutputPlan {
+        let format = match self.f {
+            Some(FormatParam::Encoded(f)) => f,
+            _ => state.default_output_format(),
+        };
+        let format = keep_alpha(state, format, self.opacity.is_some_and(|o| o < 1.0));
+        OutputPlan {
+            auto: self.f == Some(FormatParam::Auto) || (self.f.is_none() && state.preserve_source_format),
+            format,
+            quality: self.q.unwrap_or_else(|| QualityParam::Value(state.quality_for(format))),
+            filters: ResizeFilters {
+                downscale: self.downscale_filter.unwrap_or(state.downscale_filter),
+                upscale: self.upscale_filter.unwrap_or(state.upscale_filter),
+                staged_downscale: state.staged_downscale_factor,
+            },
+        }
+    }
+}
+
+/// The `/img` output cache for `state`.
+fn image_cache(state: &ImageKitConfig) -> DiskCache {
+    DiskCache::new(state.cache_dir.clone())
+        .with_namespace(state.cache_namespace())
+        .with_max_size(state.max_cache_size)
+        .with_max_entries(state.max_cache_entries)
+}
+
+/// Params the `/img` cache key is hashed from.
+///
+/// The envelope (`wrap`) and `download` header are applied after caching
+/// and `callback_url` only affects who is notified, so none of them split
+/// entries. The format, quality and filters actually used are keyed even
+/// when omitted, so changing their config defaults doesn't serve entries
+/// made under the old ones.
+fn cache_key_params(signed: &BTreeMap<String, String>, plan: &OutputPlan) -> BTreeMap<String, String> {
+    let mut params = signed.clone();
+    params.remove("wrap");
+    params.remove("callback_url");
+    params.remove("download");
+    let format = if plan.auto { "auto".to_string() } else { plan.format.to_string() };
+    params.insert("f".into(), format);
+    params.insert("q".into(), plan.quality.to_string());
+    params.insert("downscale_filter".into(), plan.filters.downscale.to_string());
+    params.insert("upscale_filter".into(), plan.filters.upscale.to_string());
+    // The box pre-pass changes the pixels, so the staging factor is part of the output
+    let staged = plan.filters.staged_downscale.map_or_else(|| "off".to_string(), |factor| factor.to_string());
+    params.insert("staged_downscale".into(), staged);
+    params
+}
+
+#[derive(Debug, Serialize)]
+struct CacheKeyResponse {
+    /// Signed canonical string of the request
+    canonical: String,
+    /// Canonical string of the params the key is hashed from
+    key_canonical: String,
+    key: String,
+}
+
+/// `GET /debug/cache-key`: the cache key a signed `/img` query maps to.
+///
+/// Takes the same query (and signature) as `/img`, for diagnosing
+/// unexpected misses. Only routed with `enable_debug_endpoints`.
+async fn debug_cache_key_handler(
+    axum::extract::State(state): axum::extract::State<Arc<ImageKitConfig>>,
+    Query(mut query): Query<ImageQuery>,
+    request_headers: HeaderMap,
+) -> Response {
+    let map = query.to_params();
+    let sig = match resolve_signature(query.sig.as_deref(), &request_headers) {
+        Ok(sig) => sig,
+        Err(msg) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+    if let Err(e) = verify_tenant_signature(&map, sig, &state.secret, &state.tenants) {
+        let status = match e {
+            crate::signature::SignatureError::Expired => StatusCode::GONE,
+            _ => StatusCode::UNAUTHORIZED,
+        };
+        return (status, e.to_string()).into_response();
+    }
+    if let Err(msg) = query.expand_preset(&state.presets) {
+        return (StatusCode::BAD_REQUEST, msg).into_response();
+    }
+    let map = query.to_params();
+
+    let key_params = cache_key_params(&map, &query.plan(&state));
+    Json(CacheKeyResponse {
+        canonical: canonical_params(&map),
+        key_canonical: canonical_params(&key_params),
+        key: image_cache(&state).key_for(&key_params),
+    })
+    .into_response()
+}
+
+/// Canonical signing string; see [`signature::canonical_string`].
 fn canonical_params(query_map: &BTreeMap<String, String>) -> String {
-    let mut parts = Vec::new();
-    for (k, v) in query_map {
-        if k != "sig" { parts.push(format!("{}={}", k, v)); }
+    signature::canonical_string(query_map)
+}
+
+/// Picks the request signature from the `sig` query param or the
+/// `Authorization: Signature <hex>` header.
+///
+/// The header keeps signatures out of URLs (logs, referrers). If both are
+/// present they must be identical; a mismatch is rejected rather than
+/// silently preferring one. Other `Authorization` schemes are ignored.
+fn resolve_signature<'a>(query_sig: Option<&'a str>, headers: &'a HeaderMap) -> std::result::Result<&'a str, &'static str> {
+    let header_sig = headers
+        .get(axum::http::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split_once(' '))
+        .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("Signature"))
+        .map(|(_, sig)| sig.trim());
+
+    match (query_sig, header_sig) {
+        (Some(q), Some(h)) if q != h => Err("Conflicting signatures in query and Authorization header"),
+        (Some(sig), _) | (None, Some(sig)) => Ok(sig),
+        (None, None) => Err("Missing signature"),
+    }
+}
+
+/// Finds a query parameter that isn't covered by the signature.
+///
+/// `signed` is the map the signature was verified against; anything else
+/// in the raw query (other than `sig`) would have been silently ignored.
+fn unsigned_param(raw_query: &str, signed: &BTreeMap<String, String>) -> Option<String> {
+    let pairs: Vec<(String, String)> = serde_urlencoded::from_str(raw_query).unwrap_or_default();
+    pairs
+        .into_iter()
+        .map(|(k, _)| k)
+        .find(|k| k != "sig" && !signed.contains_key(k))
+}
+
+/// Resolves the caching policy for a signed image response.
+///
+/// Starts from the configured directive and, when the URL carries an expiry
+/// (`t`), bounds it by the remaining lifetime so no cache holds the response
+/// longer than the URL itself is valid.
+fn cache_policy(config: &ImageKitConfig, expires_at: Option<i64>) -> CloudflareCacheConfig {
+    match expires_at {
+        Some(t) => {
+            let now = time::OffsetDateTime::now_utc().unix_timestamp();
+            let remaining = t.saturating_sub(now).clamp(0, u32::MAX as i64) as u32;
+            config.cache_control.clone().bounded_by(remaining)
+        }
+        None => config.cache_control.clone(),
     }
-    parts.join("&")
 }
 
+/// `GET /img`: fetches, transforms and serves a source image.
+///
+/// Requires a signature over the params (from `/sign`), as `sig` or an
+/// `Authorization: Signature <hex>` header.
+#[utoipa::path(
+    get,
+    path = "/img",
+    params(ImageQuery),
+    responses(
+        (status = 200, description = "Transformed image, or a JSON envelope with `wrap=json`"),
+        (status = 304, description = "`If-None-Match` matches the current ETag"),
+        (status = 400, description = "Invalid parameter or missing signature"),
+        (status = 401, description = "Invalid signature"),
+        (status = 410, description = "Signature expired (`t` is in the past)"),
+        (status = 429, description = "The tenant's monthly quota is used up"),
+    )
+)]
 async fn handler(
     Query(query): Query<ImageQuery>,
+    RawQuery(raw_query): RawQuery,
+    request_headers: HeaderMap,
     state: axum::extract::State<Arc<ImageKitConfig>>,
-) -> impl IntoResponse {
+) -> Response {
+    serve_image(query, raw_query, request_headers, state, None).await
+}
+
+/// Extensions accepted by `/img.<ext>`; see [`extension_handler`].
+const PATH_EXTENSIONS: &[&str] = &[
+    "webp",
+    "jpeg",
+    "jpg",
+    "avif",
+    "png",
+    "ico",
+    #[cfg(feature = "extra_formats")]
+    "tiff",
+    #[cfg(feature = "extra_formats")]
+    "tif",
+    #[cfg(feature = "extra_formats")]
+    "bmp",
+];
+
+/// `GET /img.<ext>`: `/img` with the output format taken from the path.
+///
+/// The extension overrides `f` (CDNs and clients often key on it). It isn't
+/// part of the signature; the query params are verified as for `/img`.
+async fn extension_handler(
+    uri: axum::http::Uri,
+    Query(query): Query<ImageQuery>,
+    RawQuery(raw_query): RawQuery,
+    request_headers: HeaderMap,
+    state: axum::extract::State<Arc<ImageKitConfig>>,
+) -> Response {
+    let format = uri.path().rsplit_once('.').and_then(|(_, ext)| format_from_extension(ext));
+    let Some(format) = format else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    serve_image(query, raw_query, request_headers, state, Some(format)).await
+}
+
+/// Checks an `/img` query's signature (`sig` or the `Authorization`
+/// header) and, with `strict_params`, that `raw_query` carries nothing
+/// unsigned; then expands its preset.
+fn authorize(
+    state: &ImageKitConfig,
+    query: &mut ImageQuery,
+    request_headers: &HeaderMap,
+    raw_query: Option<&str>,
+) -> std::result::Result<(), Response> {
+    // Checked first, so a huge `url` is never copied, hashed or logged
+    if query.url.len() > state.max_url_length {
+        return Err((StatusCode::BAD_REQUEST, "URL too long").into_response());
+    }
     tracing::debug!("Processing image request: url={}, w={:?}, h={:?}, f={:?}, q={:?}", 
                     query.url, query.w, query.h, query.f, query.q);
-    
+
     // Validate and verify signature
-    let mut map = BTreeMap::new();
-    map.insert("url".into(), query.url.clone());
-    if let Some(w) = query.w { map.insert("w".into(), w.to_string()); }
-    if let Some(h) = query.h { map.insert("h".into(), h.to_string()); }
-    if let Some(f) = query.f { map.insert("f".into(), f.to_string()); }
-    if let Some(q) = query.q { map.insert("q".into(), q.to_string()); }
-    if let Some(t) = query.t { map.insert("t".into(), t.to_string()); }
-
-    if let Err(e) = verify_signature(&map, &query.sig, &state.secret) {
+    let map = query.to_params();
+    let sig = match resolve_signature(query.sig.as_deref(), request_headers) {
+        Ok(sig) => sig,
+        Err(msg) => return Err((StatusCode::BAD_REQUEST, msg).into_response()),
+    };
+
+    if let Err(e) = verify_tenant_signature(&map, sig, &state.secret, &state.tenants) {
         tracing::warn!("Signature verification failed for url={}: {:?}", query.url, e);
         let status = match e {
             crate::signature::SignatureError::Expired => StatusCode::GONE,
             _ => StatusCode::UNAUTHORIZED,
         };
-        return (status, e.to_string()).into_response();
+        return Err((status, e.to_string()).into_response());
     }
 
-    // Quality bounds
-    if let Some(q) = query.q {
-        if q == 0 || q > 100 { return (StatusCode::BAD_REQUEST, "Invalid quality").into_response(); }
+    if state.strict_params {
+        if let Some(name) = unsigned_param(raw_query.unwrap_or(""), &map) {
+            tracing::warn!("Rejecting unsigned parameter {} for url={}", name, query.url);
+            return Err((StatusCode::BAD_REQUEST, format!("Unsigned parameter: {}", name)).into_response());
+        }
+    }
+
+    // The signature covers the preset name; from here on (validation, cache
+    // key) the expanded params are used, so redefining a preset takes effect
+    query.expand_preset(&state.presets).map_err(|msg| (StatusCode::BAD_REQUEST, msg).into_response())
+}
+
+/// Shared body of [`handler`] and [`extension_handler`]; `path_format`
+/// replaces `f` once the signature has been checked.
+async fn serve_image(
+    mut query: ImageQuery,
+    raw_query: Option<String>,
+    request_headers: HeaderMap,
+    state: axum::extract::State<Arc<ImageKitConfig>>,
+    path_format: Option<ImageFormat>,
+) -> Response {
+    let started = std::time::Instant::now();
+    if let Err(response) = authorize(&state, &mut query, &request_headers, raw_query.as_deref()) {
+        return response;
+    }
+    if let Some(format) = path_format {
+        query.f = Some(FormatParam::Encoded(format));
+    }
+    let effects = match validate_image_query(&state, &query) {
+        Ok(effects) => effects,
+        Err(response) => return response,
+    };
+    // Counted once the signature holds and the params are valid, so forged
+    // URLs and bad requests can't drain a quota
+    if let (Some(quotas), Some(tenant)) = (&state.quotas, query.tenant.as_deref()) {
+        if let Err(usage) = quotas.consume(tenant) {
+            // Once per tenant and month; a client retrying in a loop would flood the log
+            if usage.rejected == 1 {
+                tracing::warn!("Tenant {} exceeded its monthly quota ({} requests)", tenant, usage.used);
+            } else {
+                tracing::debug!("Tenant {} over its monthly quota ({} rejected)", tenant, usage.rejected);
+            }
+            let body = format!(
+                "Monthly quota exceeded: tenant {} used {} of {} requests in {}",
+                tenant, usage.used, usage.limit.unwrap_or(0), usage.period
+            );
+            return (StatusCode::TOO_MANY_REQUESTS, body).into_response();
+        }
+    }
+
+    // Passthrough: serve the source bytes untouched, transformation params are ignored
+    if query.f == Some(FormatParam::Original) {
+        let observer: &dyn TransformObserver = state.observer.as_deref().unwrap_or(&NoopObserver);
+        let source = match load_source(&state, &query.url, observer).await {
+            Ok(v) => v,
+            Err(e) => {
+                tracing::error!("Failed to fetch {}: {}", query.url, e);
+                return (fetch_error_status(&e), e.to_string()).into_response();
+            }
+        };
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            axum::http::header::CONTENT_TYPE,
+            HeaderValue::from_str(&source.content_type).unwrap_or(HeaderValue::from_static("application/octet-stream")),
+        );
+        headers.insert(axum::http::header::X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+        cache_policy(&state, query.t).apply_headers(&mut headers);
+        if source.stale {
+            mark_stale(&mut headers);
+        }
+        let bytes = Arc::try_unwrap(source.bytes).unwrap_or_else(|bytes| bytes.to_vec());
+        return (headers, Body::from(bytes)).into_response();
+    }
+    let Output { bytes, format, etag, stale, hit, phases, blurhash, .. } = match transform_and_cache(&state, &query, effects).await {
+        Ok(output) => output,
+        Err(response) => return response,
+    };
+
+    let mut headers = image_headers(format);
+    cache_policy(&state, query.t).apply_headers(&mut headers);
+    if let Ok(value) = HeaderValue::from_str(&server_timing(phases.as_ref(), started.elapsed())) {
+        headers.insert(HeaderName::from_static("server-timing"), value);
+    }
+    // With CORS configured, the router sets it to the allowed origins instead
+    if state.cors_allowed_origins.is_empty() {
+        headers.insert(HeaderName::from_static("timing-allow-origin"), HeaderValue::from_static("*"));
+    }
+    if state.expose_transform_headers {
+        headers.insert(HeaderName::from_static(CACHE_STATUS_HEADER), HeaderValue::from_static(if hit { "HIT" } else { "MISS" }));
+        if let Ok(value) = HeaderValue::from_str(&transform_summary(&state, &query, format, &bytes)) {
+            headers.insert(HeaderName::from_static(TRANSFORM_HEADER), value);
+        }
+    }
+    if let Some(value) = query.download.as_deref().and_then(|name| content_disposition(name, format)) {
+        headers.insert(axum::http::header::CONTENT_DISPOSITION, value);
+    }
+    // Read back from the bytes, so cache hits and format fallbacks say so too
+    if query.depth == Some(10) && avif_bit_depth(&bytes) != Some(10) {
+        headers.insert(HeaderName::from_static(WARNING_HEADER), HeaderValue::from_static("10-bit output unavailable; served 8-bit"));
+    }
+    // Computed at transform time and kept with the entry; see `Output::blurhash`
+    if let Some(hash) = blurhash {
+        match HeaderValue::from_str(&hash) {
+            Ok(value) => {
+                headers.insert(HeaderName::from_static(BLURHASH_HEADER), value);
+            }
+            Err(e) => tracing::warn!("Invalid blurhash header: {}", e),
+        }
+    }
+    // The envelope is another representation of the same bytes, so it
+    // mustn't validate against (or as) the raw image
+    let etag = match query.wrap {
+        Some(Wrap::Json) => representation_etag(&etag, "json"),
+        None => etag,
+    };
+    headers.insert("ETag", HeaderValue::from_str(&etag).unwrap_or(HeaderValue::from_static("")));
+    if stale {
+        mark_stale(&mut headers);
+    }
+    if etag_matches(&request_headers, &etag) {
+        return (StatusCode::NOT_MODIFIED, headers).into_response();
+    }
+    if query.wrap == Some(Wrap::Json) {
+        return json_envelope(headers, format, &bytes);
+    }
+    // Only copied when a coalesced request still holds the bytes
+    let bytes = Arc::try_unwrap(bytes).unwrap_or_else(|bytes| bytes.to_vec());
+    (headers, Body::from(bytes)).into_response()
+}
+
+/// Response header saying whether `/img` output came from the cache
+/// (`HIT`) or was just transformed (`MISS`); see `expose_transform_headers`.
+pub const CACHE_STATUS_HEADER: &str = "x-imagekit-cache";
+
+/// Response header summarizing the transform behind `/img` output; see
+/// [`transform_summary`].
+pub const TRANSFORM_HEADER: &str = "x-imagekit-transform";
+
+/// Response header explaining where `/img` output falls short of the
+/// request, e.g. `depth=10` served as 8-bit.
+pub const WARNING_HEADER: &str = "x-imagekit-warning";
+
+/// Response header carrying a BlurHash of `/img` output; see
+/// `ImageKitConfig::blurhash_header`.
+pub const BLURHASH_HEADER: &str = "x-imagekit-blurhash";
+
+/// Effective output settings, e.g. `w=640,h=360,f=webp,q=80,fit=contain`.
+///
+/// Dimensions and format are read from the output itself, so encoder
+/// fallbacks and `f=auto` show what was actually served; `q` is the
+/// request's or the configured default for that format.
+fn transform_summary(state: &ImageKitConfig, query: &ImageQuery, format: ImageFormat, bytes: &[u8]) -> String {
+    let (width, height) = encoded_dimensions(bytes).unwrap_or((0, 0));
+    let quality = query.q.unwrap_or_else(|| QualityParam::Value(state.quality_for(format)));
+    let fit = query.fit.as_ref().unwrap_or(&FitMode::Contain);
+    format!("w={},h={},f={},q={},fit={}", width, height, format, quality, fit)
+}
+
+/// Colors, overlays and opacity parsed from validated `/img` params.
+struct Effects {
+    /// Canvas for `fit=pad`
+    bg: [u8; 3],
+    tint: Option<[u8; 3]>,
+    ring: Option<([u8; 3], u32)>,
+    badge: Option<[u8; 3]>,
+    text: Option<(String, TextPosition, u32, [u8; 3])>,
+    crop: Option<Crop>,
+    /// `opacity` below 1, so the output needs an alpha channel
+    translucent: bool,
+}
+
+impl Effects {
+    /// Validates the `/img` params, rejecting out-of-range values with a 400.
+    fn parse(query: &ImageQuery) -> std::result::Result<Self, Response> {
+        // Quality bounds
+        if let Some(QualityParam::Value(q)) = query.q {
+            if q == 0 || q > 100 { return Err((StatusCode::BAD_REQUEST, "Invalid quality").into_response()); }
+        }
+
+        // Focal point is expressed as a fraction of the source dimensions
+        for fp in [query.fp_x, query.fp_y].into_iter().flatten() {
+            if !(0.0..=1.0).contains(&fp) { return Err((StatusCode::BAD_REQUEST, "Invalid focal point").into_response()); }
+        }
+
+        if let Some(speed) = query.speed {
+            if speed > MAX_AVIF_SPEED { return Err((StatusCode::BAD_REQUEST, "Invalid speed").into_response()); }
+        }
+
+        if let Some(depth) = query.depth {
+            if depth != 8 && depth != 10 { return Err((StatusCode::BAD_REQUEST, "Invalid depth").into_response()); }
+        }
+
+        if query.max_bytes == Some(0) {
+            return Err((StatusCode::BAD_REQUEST, "Invalid max_bytes").into_response());
+        }
+
+        if let Some(block) = query.pixelate {
+            if !(MIN_PIXELATE_BLOCK..=MAX_PIXELATE_BLOCK).contains(&block) {
+                return Err((StatusCode::BAD_REQUEST, "Invalid pixelate").into_response());
+            }
+        }
+
+        let bg = match query.bg.as_deref() {
+            Some(hex) => match parse_hex_color(hex) {
+                Some(color) => color,
+                None => return Err((StatusCode::BAD_REQUEST, "Invalid bg").into_response()),
+            },
+            None => [255, 255, 255],
+        };
+
+        let tint = match query.tint.as_deref() {
+            Some(hex) => match parse_hex_color(hex) {
+                Some(color) => Some(color),
+                None => return Err((StatusCode::BAD_REQUEST, "Invalid tint").into_response()),
+            },
+            None => None,
+        };
+
+        let ring = match query.ring.as_deref() {
+            Some(spec) => match parse_ring(spec) {
+                Some(ring) => Some(ring),
+                None => return Err((StatusCode::BAD_REQUEST, "Invalid ring").into_response()),
+            },
+            None => None,
+        };
+
+        let badge = match query.badge.as_deref() {
+            Some(hex) => match parse_hex_color(hex) {
+                Some(color) => Some(color),
+                None => return Err((StatusCode::BAD_REQUEST, "Invalid badge").into_response()),
+            },
+            None => None,
+        };
+
+        let text = match query.text.as_deref() {
+            Some(raw) => {
+                let Some(text) = sanitize_text(raw) else {
+                    return Err((StatusCode::BAD_REQUEST, "Invalid text").into_response());
+                };
+                let size = query.text_size.unwrap_or(DEFAULT_TEXT_SIZE);
+                if !(MIN_TEXT_SIZE..=MAX_TEXT_SIZE).contains(&size) {
+                    return Err((StatusCode::BAD_REQUEST, "Invalid text_size").into_response());
+                }
+                let color = match query.text_color.as_deref() {
+                    Some(hex) => match parse_hex_color(hex) {
+                        Some(color) => color,
+                        None => return Err((StatusCode::BAD_REQUEST, "Invalid text_color").into_response()),
+                    },
+                    None => [255, 255, 255],
+                };
+                Some((text, query.text_pos.unwrap_or_default(), size, color))
+            }
+            None => None,
+        };
+
+        if let Some(opacity) = query.opacity {
+            if !(0.0..=1.0).contains(&opacity) {
+                return Err((StatusCode::BAD_REQUEST, "Invalid opacity").into_response());
+            }
+        }
+        let translucent = query.opacity.is_some_and(|o| o < 1.0);
+
+        if let Some(gamma) = query.gamma {
+            if !(MIN_GAMMA..=MAX_GAMMA).contains(&gamma) {
+                return Err((StatusCode::BAD_REQUEST, "Invalid gamma").into_response());
+            }
+        }
+
+        if let Some(angle) = query.angle {
+            if !(-MAX_ANGLE..=MAX_ANGLE).contains(&angle) {
+                return Err((StatusCode::BAD_REQUEST, "Invalid angle").into_response());
+            }
+        }
+
+        if query.download.as_deref().is_some_and(|name| sanitize_filename(name).is_none()) {
+            return Err((StatusCode::BAD_REQUEST, "Invalid download").into_response());
+        }
+
+        let crop = match query.crop.as_deref() {
+            Some(spec) => match parse_crop(spec) {
+                Some(crop) => Some(crop),
+                None => return Err((StatusCode::BAD_REQUEST, "Invalid crop").into_response()),
+            },
+            None => None,
+        };
+
+        Ok(Effects { bg, tint, ring, badge, text, crop, translucent })
+    }
+}
+
+/// The `/img` pipeline after the signature check: serves the cached output
+/// or fetches, transforms and caches it.
+///
+/// `query` must already have its preset expanded and have passed
+/// [`validate_image_query`], which produced `effects`. Shared by `/img`,
+/// `/warm` and `/diff`; errors come back as the response to send.
+async fn transform_and_cache(state: &Arc<ImageKitConfig>, query: &ImageQuery, effects: Effects) -> std::result::Result<Output, Response> {
+    let started = std::time::Instant::now();
+    let Effects { bg, tint, ring, badge, text, crop, translucent } = effects;
+    let observer: &dyn TransformObserver = state.observer.as_deref().unwrap_or(&NoopObserver);
+    let map = query.to_params();
+    let plan = query.plan(state);
+    let OutputPlan { auto, format: target_format, filters, .. } = plan;
+
+    // Build cache and key
+    let cache = image_cache(state);
+    let canonical_params = canonical_params(&map);
+    let key = cache.key_for(&cache_key_params(&map, &plan));
+
+    // Identical concurrent requests share one lookup, fetch, encode and
+    // store; the rest wait for its bytes. Keyed by cache dir too, as routers
+    // may share a process.
+    let flight_key = format!("{}|{}", state.cache_dir.display(), key);
+    let work = async {
+        METRICS.transforms.fetch_add(1, Ordering::Relaxed);     // Track transformation
+
+        // Held until the encode is done; saturation means a stampede of misses
+        let permit = match &state.transform_limiter {
+            Some(limiter) => match limiter.try_acquire() {
+                Some(permit) => Some(permit),
+                None => {
+                    let retry_after = limiter.retry_after_secs(target_format);
+                    tracing::warn!("Transform capacity exhausted, asking client to retry in {}s", retry_after);
+                    return Err((
+                        StatusCode::SERVICE_UNAVAILABLE,
+                        [(axum::http::header::RETRY_AFTER, retry_after.to_string())],
+                        "Transform capacity exhausted",
+                    ).into_response());
+                }
+            },
+            None => None,
+        };
+        let fetch_start = std::time::Instant::now();
+        let Source { bytes, stale, .. } = match load_source(&state, &query.url, observer).await {
+            Ok(v) => v,
+            Err(e) => {
+                tracing::error!("Failed to fetch {}: {}", query.url, e);
+                return Err((fetch_error_status(&e), e.to_string()).into_response());
+            }
+        };
+        let fetch_time = fetch_start.elapsed();
+
+        let transform_start = std::time::Instant::now();
+        let (img, source_format) = match decode_image_with(&bytes, state.decode_limits()) {
+            Ok(d) => d,
+            Err(e) => return Err((StatusCode::BAD_REQUEST, format!("Decode error: {}", e)).into_response()),
+        };
+        let decode_time = transform_start.elapsed();
+        let target_format = match source_format {
+            Some(format) if auto && state.allowed_formats.contains(&format) => keep_alpha(state, format, translucent),
+            _ => target_format,
+        };
+        // Stripped by default, for privacy and size
+        let icc = if query.keep_icc == Some(true) { source_icc_profile(&bytes) } else { None };
+        let src_dims = img.dimensions();
+        // Cropped first, so `w`/`h` and `fit` apply to the region
+        let img = match crop {
+            Some(crop) => match crop_image(img, crop) {
+                Ok(img) => img,
+                Err(e) => return Err((StatusCode::BAD_REQUEST, e.to_string()).into_response()),
+            },
+            None => img,
+        };
+
+        // `enlarge=false`: a source already inside the box keeps its size
+        // in every fit mode; `pad` still centers it on the full canvas
+        let keep_size = query.enlarge == Some(false) && fits_within(&img, query.w, query.h);
+        let resized = match (&query.fit, query.w, query.h) {
+            (Some(FitMode::Pad), Some(w), Some(h)) if keep_size => Ok(pad_to_canvas(img, w, h, bg)),
+            _ if keep_size => Ok(img),
+            (Some(FitMode::Cover), Some(w), Some(h)) => {
+                // Without enlarging, a side shorter than the box is kept and
+                // only the other is cropped; clamping the box to the source
+                // caps the cover scale at 1
+                let (w, h) = if query.enlarge == Some(false) {
+                    (w.min(img.width()), h.min(img.height()))
+                } else {
+                    (w, h)
+                };
+                // An explicit focal point takes precedence over gravity
+                if query.fp_x.is_some() || query.fp_y.is_some() {
+                    let focal = (query.fp_x.unwrap_or(0.5), query.fp_y.unwrap_or(0.5));
+                    resize_cover(img, w, h, focal)
+                } else {
+                    crop_with_gravity(img, w, h, query.gravity.unwrap_or(Gravity::Center))
+                }
+            }
+            (Some(FitMode::Fill), Some(w), Some(h)) => resize_fill(img, w, h, filters),
+            (Some(FitMode::Pad), Some(w), Some(h)) => {
+                resize_image_with(img, Some(w), Some(h), filters).map(|img| pad_to_canvas(img, w, h, bg))
+            }
+            // Contain (the default); cover/fill/pad with one dimension scale proportionally
+            _ => resize_image_with(img, query.w, query.h, filters),
+        };
+        let resized = match resized {
+            Ok(i) => i,
+            Err(e) => return Err((StatusCode::BAD_REQUEST, format!("Resize error: {}", e)).into_response()),
+        };
+        // New corners stay transparent unless `bg` asks for a fill or the
+        // output can't carry alpha
+        let resized = match query.angle {
+            Some(angle) => {
+                let fill = (query.bg.is_some() || target_format == ImageFormat::jpeg).then_some(bg);
+                rotate_arbitrary(resized, angle, fill)
+            }
+            None => resized,
+        };
+        let resized = match query.gamma {
+            Some(gamma) => gamma_image(resized, gamma),
+            None => resized,
+        };
+        let resized = match query.pixelate {
+            Some(block) => pixelate_image(resized, block),
+            None => resized,
+        };
+        let resized = match tint {
+            Some(color) => tint_image(resized, color),
+            None => resized,
+        };
+        let resized = if query.invert == Some(true) { invert_image(resized) } else { resized };
+        let resized = if query.sepia == Some(true) { sepia_image(resized) } else { resized };
+        // Overlays go last so they aren't pixelated or tinted
+        let resized = match ring {
+            Some((color, width)) => draw_ring(resized, color, width),
+            None => resized,
+        };
+        let resized = match badge {
+            Some(color) => draw_badge(resized, color),
+            None => resized,
+        };
+        let resized = match &text {
+            Some((text, position, size, color)) => draw_text(resized, text, *position, *size, *color),
+            None => resized,
+        };
+        let resized = match query.opacity {
+            Some(opacity) if translucent => set_opacity(resized, opacity),
+            _ => resized,
+        };
+
+        let resize_time = transform_start.elapsed() - decode_time;
+        let encode_start = std::time::Instant::now();
+
+        let quality = quality_fn(&state, query.q);
+
+        let options = EncodeOptions {
+            avif_speed: query.speed.unwrap_or(state.avif_speed),
+            webp_lossless: query.lossless.unwrap_or(false),
+            avif_colorspace: query.colorspace.unwrap_or(state.avif_colorspace),
+            jpeg_chroma: query.chroma,
+            avif_depth: query.depth.unwrap_or(8),
+        };
+
+        // Hashed from the pre-encode image while the encode runs, so the
+        // output never has to be decoded for it (AVIF included)
+        let blurhash_task = state.blurhash_header.then(|| {
+            let img = resized.clone();
+            let (x_comp, y_comp) = DEFAULT_BLURHASH_COMPONENTS;
+            tokio::task::spawn_blocking(move || compute_blurhash(&img, x_comp, y_comp))
+        });
+
+        // With a byte budget, `q` becomes the upper bound of the quality search
+        let out_dims = resized.dimensions();
+        let max_bytes = query.max_bytes;
+        let chain = fallback_chain(&state, translucent);
+        let requested = target_format;
+        let (encoded, target_format) = run_encode(&state, target_format, permit, move || {
+            encode_with_fallback(target_format, &chain, |format| match max_bytes {
+                Some(max_bytes) => encode_to_budget(&resized, format, quality(&resized, format), max_bytes, &options).map(|(b, _)| b),
+                None => encode_image_with(&resized, format, quality(&resized, format), &options),
+            })
+        })
+        .await?;
+        let fallback = target_format != requested;
+        if fallback {
+            tracing::warn!(url = %query.url, "{} encode failed, fell back to {}, not caching", requested, target_format);
+        }
+        let encoded = match &icc {
+            Some(icc) => embed_icc_profile(encoded, target_format, icc),
+            None => encoded,
+        };
+        let transform_time = transform_start.elapsed();
+        observer.on_transform(src_dims, out_dims, target_format, transform_time);
+        if let Some(limiter) = &state.transform_limiter {
+            limiter.record(target_format, transform_time);
+        }
+
+        if stale {
+            tracing::warn!("Serving {} from a stale original, not caching", query.url);
+        }
+        let etag = etag_for_content(&encoded);
+        let phases = Phases { fetch: fetch_time, decode: decode_time, resize: resize_time, encode: encode_start.elapsed() };
+        let blurhash = match blurhash_task {
+            Some(task) => joined_blurhash(task.await, &query.url),
+            None => None,
+        };
+        Ok(Output { bytes: Arc::new(encoded), format: target_format, etag, stale, fallback, hit: false, phases: Some(phases), blurhash })
+    };
+    // The whole lookup runs in the flight, so waiters never look up the
+    // cache while the leader is still storing, and a leader that's dropped
+    // mid-way hands over to a waiter rather than losing the entry
+    let output = IN_FLIGHT.run(&flight_key, || async {
+        let lookup = cache.get_or_compute(&key, &canonical_params, || async {
+            tracing::info!(cache_key = %key, url = %query.url, "Cache miss, fetching");
+            let output = work.await?;
+            // Output built from a stale source isn't kept, or it would
+            // outlive the origin outage. Nor is a fallback encode: the key
+            // names the requested format, which should be retried
+            let store_as = (!output.stale && !output.fallback).then_some(output.format);
+            Ok::<_, Response>(Computed { value: output, store_as })
+        }).await;
+        match lookup {
+            Ok(Lookup::Hit(data)) => {
+                tracing::info!(cache_key = %key, "Cache hit");
+                // Sniffed rather than taken from the plan: `auto` and encoder
+                // fallbacks can both store a format other than the requested one
+                let format = sniff_output_format(&data).unwrap_or(target_format);
+                let etag = etag_for_content(&data);
+                let bytes = Arc::new(data);
+                let blurhash = match state.blurhash_header {
+                    true => cached_blurhash(state, &cache, &key, &bytes, &query.url).await,
+                    false => None,
+                };
+                Ok(Output { bytes, format, etag, stale: false, fallback: false, hit: true, phases: None, blurhash })
+            }
+            Ok(Lookup::Computed { value, stored }) => {
+                if stored {
+                    if let Some(hash) = &value.blurhash {
+                        if let Err(e) = cache.put_blurhash(&key, hash).await {
+                            tracing::warn!("Failed to cache blurhash for {}: {}", key, e);
+                        }
+                    }
+                    // Only the leader gets here, and only once the output is
+                    // cached, so a callback fires once per stored entry
+                    notify_callback(state, query, &key, &value, started.elapsed());
+                }
+                Ok(value)
+            }
+            Err(response) => Err(SharedResponse::buffer(response).await),
+        }
+    }).await.map_err(IntoResponse::into_response)?;
+
+    // Counted per request, including those that waited on another
+    if output.hit {
+        METRICS.cache_hits.fetch_add(1, Ordering::Relaxed);  // Track cache hit
+        observer.on_cache_hit(&key);
+    } else {
+        METRICS.cache_misses.fetch_add(1, Ordering::Relaxed);  // Track cache miss
+        observer.on_cache_miss(&key);
+    }
+    Ok(output)
+}
+
+/// Validates an authorized `/img` query: its params, output format and
+/// callback URL. Returns the parsed effects.
+fn validate_image_query(state: &ImageKitConfig, query: &ImageQuery) -> std::result::Result<Effects, Response> {
+    let effects = Effects::parse(query)?;
+    check_allowed_format(state, requested_format(query))?;
+    check_callback_url(state, query.callback_url.as_deref())?;
+    Ok(effects)
+}
+
+/// Header carrying the hex HMAC-SHA256 of a callback body, keyed with
+/// `secret`, so receivers can check the notification came from us.
+pub const CALLBACK_SIGNATURE_HEADER: &str = "x-imagekit-signature";
+
+/// How long a callback POST may take before it's abandoned.
+const CALLBACK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Body of a `callback_url` notification.
+#[derive(Debug, Serialize)]
+struct CallbackPayload<'a> {
+    url: &'a str,
+    /// Output cache key
+    key: &'a str,
+    format: ImageFormat,
+    /// Encoded size of the cached output
+    bytes: usize,
+    /// Time to serve the output, transform included on a miss
+    duration_ms: u64,
+}
+
+/// Rejects a `callback_url` that isn't http(s) or whose host isn't in
+/// `callback_hosts`, with a 400.
+fn check_callback_url(state: &ImageKitConfig, callback_url: Option<&str>) -> std::result::Result<(), Response> {
+    let Some(callback_url) = callback_url else {
+        return Ok(());
+    };
+    let allowed = reqwest::Url::parse(callback_url).ok().is_some_and(|url| {
+        matches!(url.scheme(), "http" | "https")
+            && url.host_str().is_some_and(|host| state.callback_hosts.iter().any(|h| h.eq_ignore_ascii_case(host)))
+    });
+    if allowed {
+        Ok(())
+    } else {
+        Err((StatusCode::BAD_REQUEST, "Callback URL not allowed").into_response())
+    }
+}
+
+/// POSTs a [`CallbackPayload`] to the query's `callback_url` in the
+/// background, signed with [`CALLBACK_SIGNATURE_HEADER`] under the secret
+/// that signed the request (the tenant's, if any). Failures are logged,
+/// never retried, and redirects aren't followed.
+fn notify_callback(state: &Arc<ImageKitConfig>, query: &ImageQuery, key: &str, output: &Output, duration: std::time::Duration) {
+    let Some(callback_url) = query.callback_url.clone() else {
+        return;
+    };
+    let payload = CallbackPayload {
+        url: &query.url,
+        key,
+        format: output.format,
+        bytes: output.bytes.len(),
+        duration_ms: duration.as_millis() as u64,
+    };
+    let body = match serde_json::to_vec(&payload) {
+        Ok(body) => body,
+        Err(e) => {
+            tracing::warn!("Failed to encode callback: {}", e);
+            return;
+        }
+    };
+    // The request was verified under this secret, so it resolves
+    let secret = match tenant_secret(&query.to_params(), &state.secret, &state.tenants) {
+        Ok(secret) => secret,
+        Err(e) => {
+            tracing::warn!("Failed to sign callback: {}", e);
+            return;
+        }
+    };
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC key");
+    mac.update(&body);
+    let sig = hex::encode(mac.finalize().into_bytes());
+
+    let state = state.clone();
+    tokio::spawn(async move {
+        let client = match state.callback_client() {
+            Ok(client) => client,
+            Err(e) => {
+                tracing::warn!("Failed to build callback client: {}", e);
+                return;
+            }
+        };
+        let sent = client
+            .post(&callback_url)
+            .timeout(CALLBACK_TIMEOUT)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(CALLBACK_SIGNATURE_HEADER, sig)
+            .body(body)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        if let Err(e) = sent {
+            tracing::warn!(callback_url = %callback_url, "Callback failed: {}", e);
+        }
+    });
+}
+
+/// Runs an encode, moving AVIF onto the blocking pool under `avif_encode_timeout`.
+///
+/// Exceeding the timeout yields a 504. The encode itself can't be cancelled:
+/// its thread finishes in the background and the result is dropped. A
+/// `transform_limiter` permit goes with it, so capacity is only released
+/// once that thread is done.
+async fn run_encode<F, T>(
+    state: &ImageKitConfig,
+    format: ImageFormat,
+    permit: Option<tokio::sync::OwnedSemaphorePermit>,
+    encode: F,
+) -> std::result::Result<T, Response>
+where
+    F: FnOnce() -> Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    let encode = move || {
+        let _permit = permit;
+        encode()
+    };
+    let encoded = if format == ImageFormat::avif {
+        match tokio::time::timeout(state.avif_encode_timeout, tokio::task::spawn_blocking(encode)).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(e)) => Err(ImageKitError::TransformError(e.to_string())),
+            Err(_) => {
+                tracing::warn!("AVIF encode exceeded {:?}", state.avif_encode_timeout);
+                return Err((StatusCode::GATEWAY_TIMEOUT, "AVIF encode timed out").into_response());
+            }
+        }
+    } else {
+        encode()
+    };
+    encoded.map_err(|e| (StatusCode::BAD_REQUEST, format!("Encode error: {}", e)).into_response())
+}
+
+/// An error response buffered so every coalesced request can send a copy.
+#[derive(Clone)]
+struct SharedResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: bytes::Bytes,
+}
+
+impl SharedResponse {
+    async fn buffer(response: Response) -> Self {
+        let (parts, body) = response.into_parts();
+        // Error bodies are short messages
+        let body = axum::body::to_bytes(body, usize::MAX).await.unwrap_or_default();
+        Self { status: parts.status, headers: parts.headers, body }
+    }
+}
+
+impl IntoResponse for SharedResponse {
+    fn into_response(self) -> Response {
+        (self.status, self.headers, self.body).into_response()
+    }
+}
+
+/// Encoded result of a `/img` miss, shared with coalesced requests.
+#[derive(Clone)]
+struct Output {
+    bytes: Arc<Vec<u8>>,
+    format: ImageFormat,
+    /// See [`etag_for_content`]
+    etag: String,
+    /// Built from a stale original; see [`load_source`]
+    stale: bool,
+    /// Encoded in a fallback format after the requested encoder failed
+    fallback: bool,
+    /// Served from the output cache rather than transformed
+    hit: bool,
+    /// Per-phase durations of the transform; `None` on a cache hit
+    phases: Option<Phases>,
+    /// For [`BLURHASH_HEADER`], when enabled: hashed from the pre-encode
+    /// image on a miss and read back from the entry's sidecar on a hit
+    blurhash: Option<String>,
+}
+
+/// A finished blurhash task's hash; failures are logged and leave the
+/// header off.
+fn joined_blurhash(joined: std::result::Result<Result<String>, tokio::task::JoinError>, url: &str) -> Option<String> {
+    match joined {
+        Ok(Ok(hash)) => Some(hash),
+        Ok(Err(e)) => {
+            tracing::warn!("Failed to compute blurhash for {}: {}", url, e);
+            None
+        }
+        Err(e) => {
+            tracing::warn!("Blurhash task for {} failed: {}", url, e);
+            None
+        }
+    }
+}
+
+/// The blurhash stored with a cached entry. Entries cached before
+/// `blurhash_header` was enabled have none; those are decoded once on the
+/// blocking pool and the hash stored for later hits. AVIF entries can't be
+/// decoded, so they go without the header rather than retry every hit.
+async fn cached_blurhash(state: &ImageKitConfig, cache: &DiskCache, key: &str, bytes: &Arc<Vec<u8>>, url: &str) -> Option<String> {
+    if let Some(hash) = cache.blurhash(key).await {
+        return Some(hash);
+    }
+    if matches!(sniff_output_format(bytes), None | Some(ImageFormat::avif)) {
+        return None;
+    }
+    let (bytes, limits) = (bytes.clone(), state.decode_limits());
+    let (x_comp, y_comp) = DEFAULT_BLURHASH_COMPONENTS;
+    let joined = tokio::task::spawn_blocking(move || {
+        decode_image_with(&bytes, limits).and_then(|(img, _)| compute_blurhash(&img, x_comp, y_comp))
+    })
+    .await;
+    let hash = joined_blurhash(joined, url)?;
+    if let Err(e) = cache.put_blurhash(key, &hash).await {
+        tracing::warn!("Failed to cache blurhash for {}: {}", key, e);
+    }
+    Some(hash)
+}
+
+/// The encoded bytes, as stored by [`Cache::get_or_compute`].
+impl AsRef<[u8]> for Output {
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+/// How long each step of an `/img` miss took, for `Server-Timing`.
+#[derive(Debug, Clone, Copy)]
+struct Phases {
+    fetch: std::time::Duration,
+    decode: std::time::Duration,
+    /// Resize plus every effect and overlay
+    resize: std::time::Duration,
+    encode: std::time::Duration,
+}
+
+/// `Server-Timing` value for an `/img` response, in milliseconds, e.g.
+/// `fetch;dur=12.1, decode;dur=3.4, resize;dur=8.0, encode;dur=20.5, total;dur=45.2`.
+///
+/// Cache hits have no phases and only report `total`.
+fn server_timing(phases: Option<&Phases>, total: std::time::Duration) -> String {
+    let ms = |d: std::time::Duration| format!("{:.1}", d.as_secs_f64() * 1000.0);
+    let mut metrics = Vec::new();
+    if let Some(p) = phases {
+        for (name, duration) in [("fetch", p.fetch), ("decode", p.decode), ("resize", p.resize), ("encode", p.encode)] {
+            metrics.push(format!("{};dur={}", name, ms(duration)));
+        }
+    }
+    metrics.push(format!("total;dur={}", ms(total)));
+    metrics.join(", ")
+}
+
+/// Whether the request's `If-None-Match` lists `etag` (or is `*`).
+///
+/// Uses the weak comparison `If-None-Match` calls for, so a `W/` prefix
+/// added by an intermediary still matches.
+fn etag_matches(request_headers: &HeaderMap, etag: &str) -> bool {
+    let Some(value) = request_headers.get(axum::http::header::IF_NONE_MATCH).and_then(|v| v.to_str().ok()) else {
+        return false;
+    };
+    value
+        .split(',')
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+/// Encode quality per format: the request's `q` (`auto` measures the image),
+/// else the configured default for whichever format is being encoded
+/// (fallbacks included).
+fn quality_fn(state: &Arc<ImageKitConfig>, q: Option<QualityParam>) -> impl Fn(&image::DynamicImage, ImageFormat) -> u8 + Send + 'static {
+    let state = Arc::clone(state);
+    move |img: &image::DynamicImage, format: ImageFormat| match q {
+        Some(QualityParam::Value(q)) => q,
+        Some(QualityParam::Auto) => auto_quality(img, format),
+        None => state.quality_for(format),
+    }
+}
+
+/// `encode_fallback_chain` limited to `allowed_formats`, minus JPEG when
+/// the output must keep alpha.
+fn fallback_chain(state: &ImageKitConfig, translucent: bool) -> Vec<ImageFormat> {
+    state
+        .encode_fallback_chain
+        .iter()
+        .copied()
+        .filter(|f| state.allowed_formats.contains(f))
+        .filter(|f| !(translucent && *f == ImageFormat::jpeg))
+        .collect()
+}
+
+/// JPEG has no alpha channel, so translucent output falls back to WebP
+/// (or PNG, then AVIF, if WebP isn't allowed). With none of them allowed
+/// it stays JPEG and the alpha is flattened.
+fn keep_alpha(state: &ImageKitConfig, format: ImageFormat, translucent: bool) -> ImageFormat {
+    if !(translucent && format == ImageFormat::jpeg) {
+        return format;
+    }
+    [ImageFormat::webp, ImageFormat::png, ImageFormat::avif]
+        .into_iter()
+        .find(|f| state.allowed_formats.contains(f))
+        .unwrap_or(format)
+}
+
+/// Rejects an explicitly requested output format missing from
+/// `allowed_formats` with a 400.
+fn check_allowed_format(state: &ImageKitConfig, format: Option<ImageFormat>) -> std::result::Result<(), Response> {
+    match format {
+        Some(format) if !state.allowed_formats.contains(&format) => {
+            Err((StatusCode::BAD_REQUEST, format!("Output format not allowed: {}", format)).into_response())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The explicit format of an `/img` query, if it names one.
+fn requested_format(query: &ImageQuery) -> Option<ImageFormat> {
+    match query.f {
+        Some(FormatParam::Encoded(format)) => Some(format),
+        _ => None,
+    }
+}
+
+/// Resize filters from config, for endpoints without per-request overrides.
+fn config_filters(state: &ImageKitConfig) -> ResizeFilters {
+    ResizeFilters {
+        downscale: state.downscale_filter,
+        upscale: state.upscale_filter,
+        staged_downscale: state.staged_downscale_factor,
+    }
+}
+
+/// Base headers for any response carrying encoded image bytes.
+///
+/// Source bytes are user-controlled, so `nosniff` is always set to stop
+/// clients from reinterpreting a polyglot payload as active content.
+fn image_headers(format: ImageFormat) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(axum::http::header::CONTENT_TYPE, HeaderValue::from_static(content_type_from_format(format)));
+    headers.insert(axum::http::header::X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+    headers
+}
+
+/// Longest `download` filename accepted, in characters, before the extension.
+const MAX_DOWNLOAD_NAME: usize = 200;
+
+/// Cleans a `download` filename: only the last path segment is kept,
+/// control characters and quotes are dropped, leading dots and
+/// surrounding whitespace trimmed, and an image extension removed (the
+/// output format's is added later). `None` if nothing usable is left or
+/// it exceeds [`MAX_DOWNLOAD_NAME`].
+fn sanitize_filename(name: &str) -> Option<String> {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or(name);
+    let cleaned: String = name.chars().filter(|c| !c.is_control() && *c != '"').collect();
+    let cleaned = cleaned.trim().trim_start_matches('.');
+    let stem = match cleaned.rsplit_once('.') {
+        Some((stem, ext)) if ["webp", "jpeg", "jpg", "avif", "png", "gif"].contains(&ext.to_ascii_lowercase().as_str()) => stem,
+        _ => cleaned,
+    };
+    let stem = stem.trim();
+    if stem.is_empty() || stem.chars().count() > MAX_DOWNLOAD_NAME {
+        return None;
+    }
+    Some(stem.to_string())
+}
+
+/// `Content-Disposition: attachment` naming the download `name`.`format`.
+///
+/// Non-ASCII names are sent as RFC 5987 `filename*`, with an ASCII
+/// `filename` fallback for old clients.
+fn content_disposition(name: &str, format: ImageFormat) -> Option<HeaderValue> {
+    let file = format!("{}.{}", sanitize_filename(name)?, format);
+    let value = if file.is_ascii() {
+        format!("attachment; filename=\"{}\"", file)
+    } else {
+        let fallback: String = file.chars().map(|c| if c.is_ascii() { c } else { '_' }).collect();
+        let encoded: String = file
+            .bytes()
+            .map(|b| match b {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'.' | b'-' | b'_' => (b as char).to_string(),
+                _ => format!("%{:02X}", b),
+            })
+            .collect();
+        format!("attachment; filename=\"{}\"; filename*=UTF-8''{}", fallback, encoded)
+    };
+    HeaderValue::from_str(&value).ok()
+}
+
+/// Marks a response built from a stale source (RFC 7234 `Warning: 110`).
+///
+/// It must not be cached downstream either, so the next request retries
+/// the origin.
+fn mark_stale(headers: &mut HeaderMap) {
+    headers.insert(axum::http::header::WARNING, HeaderValue::from_static("110 - \"Response is Stale\""));
+    headers.insert(axum::http::header::CACHE_CONTROL, HeaderValue::from_static(NO_CACHE_CONTROL));
+    headers.remove("cdn-cache-control");
+    headers.remove(axum::http::header::EXPIRES);
+}
+
+/// Source image as loaded by [`load_source`].
+#[derive(Clone)]
+struct Source {
+    /// Shared with the source cache and coalesced loads
+    bytes: Arc<Vec<u8>>,
+    content_type: String,
+    /// Served from an expired original because the origin fetch failed
+    stale: bool,
+}
+
+/// Status for a source that couldn't be loaded: `504` when the origin
+/// timed out (a gateway problem worth retrying), `503` when no fetch slot
+/// was free, else `400`.
+fn fetch_error_status(e: &ImageKitError) -> StatusCode {
+    match e {
+        ImageKitError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+        ImageKitError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+        _ => StatusCode::BAD_REQUEST,
+    }
+}
+
+/// Returns the source bytes and content type for `url`.
+///
+/// Consults the in-memory `source_cache`, then the originals store when
+/// `original_cache_ttl` is set, and populates both after a fetch.
+/// `observer.on_fetch` only fires for real fetches.
+///
+/// An expired copy is revalidated with the origin's `ETag`/`Last-Modified`;
+/// a `304` restarts its TTL without a download. If the fetch fails and the
+/// originals store still holds an expired copy, that copy is returned with
+/// `stale` set (stale-if-error).
+///
+/// Concurrent loads of one URL that miss the source cache share a single
+/// originals lookup and fetch, e.g. several variants of a new image
+/// requested at once.
+async fn load_source(
+    state: &ImageKitConfig,
+    url: &str,
+    observer: &dyn TransformObserver,
+) -> Result<Source> {
+    // Also checked by the fetch; doing it first keeps cached copies of a
+    // now-disallowed scheme from being served
+    check_scheme(url, &state.allowed_schemes)?;
+
+    if let Some((bytes, content_type)) = state.source_cache.as_ref().and_then(|c| c.get(url)) {
+        tracing::debug!("Source cache hit for {}", url);
+        return Ok(Source { bytes, content_type, stale: false });
+    }
+
+    // Keyed by cache dir too, like `IN_FLIGHT`: the originals store lives there
+    let flight_key = format!("{}|{}", state.cache_dir.display(), url);
+    SOURCE_FLIGHTS.run(&flight_key, || load_uncached_source(state, url, observer)).await
+}
+
+/// [`load_source`] past the source cache: the originals store, then the origin.
+async fn load_uncached_source(
+    state: &ImageKitConfig,
+    url: &str,
+    observer: &dyn TransformObserver,
+) -> Result<Source> {
+    let store = state
+        .original_cache_ttl
+        .map(|ttl| OriginalStore::new(state.cache_dir.join("originals"), ttl));
+
+    if let Some(store) = &store {
+        match store.get(url).await {
+            Ok(Some((bytes, content_type))) => {
+                tracing::debug!("Original cache hit for {}", url);
+                let bytes = Arc::new(bytes);
+                if let Some(cache) = &state.source_cache {
+                    cache.put(url, bytes.clone(), &content_type);
+                }
+                return Ok(Source { bytes, content_type, stale: false });
+            }
+            Ok(None) => {}
+            Err(e) => tracing::warn!("Failed to read cached original: {}", e),
+        }
     }
 
-    // Build cache and key
-    let cache = DiskCache::new(state.cache_dir.clone());
-    let canonical_params = canonical_params(&map);
-    let key = cache.key_for(&map);
-
-    if let Some(data) = cache.get(&key).await.map_err(|e| e.to_string()).ok().flatten() {
-        // Cache hit: return data directly
-        tracing::info!("Cache hit for key={}", key);
-        METRICS.cache_hits.fetch_add(1, Ordering::Relaxed);  // Track cache hit
-        
-        let etag = cache.etag_for(&key);
-        
-        // Determine format from query or default
-        let format = query.f.unwrap_or_else(|| state.default_format.unwrap_or(ImageFormat::webp));
-        let content_type = match format {
-            ImageFormat::webp => "image/webp",
-            ImageFormat::jpeg => "image/jpeg",
-            ImageFormat::avif => "image/avif",
-        };
-        
-        let mut headers = HeaderMap::new();
-        headers.insert("Cache-Control", HeaderValue::from_static(DEFAULT_CACHE_CONTROL));
-        headers.insert("ETag", HeaderValue::from_str(&etag).unwrap_or(HeaderValue::from_static("")));
-        headers.insert(axum::http::header::CONTENT_TYPE, HeaderValue::from_static(content_type));
-        return (headers, Body::from(data)).into_response();
-    }
-
-    // Cache miss: fetch, transform, cache, stream
-    tracing::info!("Cache miss for key={}, fetching from {}", key, query.url);
-    METRICS.cache_misses.fetch_add(1, Ordering::Relaxed);  // Track cache miss
-    METRICS.transforms.fetch_add(1, Ordering::Relaxed);     // Track transformation
-    let max_size = state.max_input_size;
-    let allowed = state.allowed_formats.clone();
-    let (bytes, _content_type) = match fetch_source(&query.url, max_size, &allowed).await {
-        Ok(v) => v,
-        Err(e) => {
-            tracing::error!("Failed to fetch {}: {}", query.url, e);
-            return (StatusCode::BAD_REQUEST, e.to_string()).into_response();
+    // An expired original is revalidated with the origin's validators, so
+    // an unchanged source costs a 304 instead of a full download
+    let validators = match &store {
+        Some(store) => store.validators(url).await,
+        None => None,
+    };
+    let no_headers = HashMap::new();
+    let headers = state.origin_headers_for(url).unwrap_or(&no_headers);
+    let client = state.fetch_client()?;
+    // Held for the whole download, so the bound covers open connections
+    let permit = match &state.fetch_limiter {
+        Some(limiter) => match limiter.acquire().await {
+            Some(permit) => Some(permit),
+            None => {
+                tracing::warn!("No fetch slot freed up in time for {}", url);
+                return Err(ImageKitError::Unavailable("Too many concurrent source fetches".into()));
+            }
+        },
+        None => None,
+    };
+    let fetched = fetch_source_conditional(
+        &client,
+        url,
+        headers,
+        validators.as_ref(),
+        state.max_input_size,
+        state.max_input_pixels,
+        &state.allowed_schemes,
+    )
+    .await;
+    drop(permit);
+    let (bytes, content_type, validators) = match (fetched, &store) {
+        (Ok(Fetched::Modified { bytes, content_type, validators }), _) => (bytes, content_type, validators),
+        (Ok(Fetched::NotModified), Some(store)) => match store.get_stale(url).await {
+            Ok(Some((bytes, content_type))) => {
+                tracing::debug!("Original for {} not modified, refreshing it", url);
+                if let Err(e) = store.touch(url).await {
+                    tracing::warn!("Failed to refresh cached original: {}", e);
+                }
+                let bytes = Arc::new(bytes);
+                if let Some(cache) = &state.source_cache {
+                    cache.put(url, bytes.clone(), &content_type);
+                }
+                return Ok(Source { bytes, content_type, stale: false });
+            }
+            _ => return Err(ImageKitError::NetworkError("Upstream answered 304 but the cached original is gone".into())),
+        },
+        (Ok(Fetched::NotModified), None) => {
+            return Err(ImageKitError::NetworkError("Upstream answered 304 to an unconditional request".into()))
         }
+        (Err(e), Some(store)) => match store.get_stale(url).await {
+            Ok(Some((bytes, content_type))) => {
+                tracing::warn!("Fetching {} failed ({}), serving stale original", url, e);
+                return Ok(Source { bytes: Arc::new(bytes), content_type, stale: true });
+            }
+            _ => return Err(e),
+        },
+        (Err(e), None) => return Err(e),
     };
+    observer.on_fetch(url, bytes.len());
 
-    let (img, _orig_format) = match decode_image(&bytes) {
-        Ok(d) => d,
-        Err(e) => return (StatusCode::BAD_REQUEST, format!("Decode error: {}", e)).into_response(),
-    };
+    let bytes = Arc::new(bytes);
+    if let Some(cache) = &state.source_cache {
+        cache.put(url, bytes.clone(), &content_type);
+    }
+    if let Some(store) = &store {
+        if let Err(e) = store.put(url, &bytes, &content_type, &validators).await {
+            tracing::warn!("Failed to cache original: {}", e);
+        }
+    }
+    Ok(Source { bytes, content_type, stale: false })
+}
 
-    let resized = match resize_image(img, query.w, query.h) {
-        Ok(i) => i,
-        Err(e) => return (StatusCode::BAD_REQUEST, format!("Resize error: {}", e)).into_response(),
-    };
+/// Largest encoded image returned inside a `wrap=json` envelope.
+/// Base64 inflates payloads by a third, so larger outputs get a 413.
+pub const MAX_JSON_ENVELOPE_BYTES: usize = 4 * 1024 * 1024;
 
-    let target_format = query.f.unwrap_or_else(|| state.default_format.unwrap_or(ImageFormat::webp));
-    let quality = query.q.unwrap_or(DEFAULT_QUALITY);
+#[derive(Serialize)]
+struct JsonEnvelope {
+    format: ImageFormat,
+    width: u32,
+    height: u32,
+    data: String,
+}
 
-    let encoded = match encode_image(&resized, target_format, quality) {
-        Ok(b) => b,
-        Err(e) => return (StatusCode::BAD_REQUEST, format!("Encode error: {}", e)).into_response(),
-    };
+/// `etag` for a `representation` of the output other than its raw bytes,
+/// e.g. `"abc"` becomes `"abc-json"`.
+fn representation_etag(etag: &str, representation: &str) -> String {
+    format!("{}-{}\"", etag.trim_end_matches('"'), representation)
+}
 
-    // Store in cache
-    if let Err(e) = cache.put(&key, &encoded, target_format, &canonical_params).await {
-        tracing::warn!("Failed to cache transformed image: {}", e);
-        // Continue anyway - we can still serve the image
-    }
+/// Wraps encoded image bytes in a JSON envelope, keeping the caching headers.
+fn json_envelope(mut headers: HeaderMap, format: ImageFormat, data: &[u8]) -> Response {
+    use base64::Engine;
 
-    // Return the encoded image directly
-    let etag = cache.etag_for(&key);
-    let mut headers = HeaderMap::new();
-    headers.insert("Cache-Control", HeaderValue::from_static(DEFAULT_CACHE_CONTROL));
-    headers.insert("ETag", HeaderValue::from_str(&etag).unwrap_or(HeaderValue::from_static("")));
-    let content_type = match target_format {
-        ImageFormat::webp => "image/webp",
-        ImageFormat::jpeg => "image/jpeg",
-        ImageFormat::avif => "image/avif",
+    if data.len() > MAX_JSON_ENVELOPE_BYTES {
+        return (StatusCode::PAYLOAD_TOO_LARGE, "Image too large for wrap=json").into_response();
+    }
+    let (width, height) = encoded_dimensions(data).unwrap_or((0, 0));
+    let envelope = JsonEnvelope {
+        format,
+        width,
+        height,
+        data: base64::engine::general_purpose::STANDARD.encode(data),
     };
-    headers.insert(axum::http::header::CONTENT_TYPE, HeaderValue::from_static(content_type));
-    (headers, Body::from(encoded)).into_response()
+
+    headers.insert(axum::http::header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    (headers, Json(envelope)).into_response()
 }
 
+/// Whether the request's `Authorization: Bearer` token is `secret`,
+/// compared in constant time.
+fn bearer_matches(headers: &HeaderMap, secret: &str) -> bool {
+    use subtle::ConstantTimeEq;
+
+    headers
+        .get(axum::http::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .is_some_and(|bearer| bool::from(bearer.as_bytes().ct_eq(secret.as_bytes())))
+}
+
+/// `GET /sign`: signs a set of `/img` params, or `POST /transform` params
+/// when `url` is absent.
+#[utoipa::path(
+    get,
+    path = "/sign",
+    params(SignQuery),
+    responses(
+        (status = 200, description = "Signature and the signed `/img` URL (`/transform` without `url`)", body = SignResponse),
+        (status = 400, description = "Invalid parameter, URL too long, or a `/img`-only parameter without `url`"),
+        (status = 401, description = "`tenant` without the admin secret, or an unknown tenant"),
+    )
+)]
 async fn sign_handler(
     Query(query): Query<SignQuery>,
+    request_headers: HeaderMap,
     state: axum::extract::State<Arc<ImageKitConfig>>,
-) -> Json<SignResponse> {
-    let mut map = BTreeMap::new();
-    map.insert("url".into(), query.url.clone());
-    if let Some(w) = query.w { map.insert("w".into(), w.to_string()); }
-    if let Some(h) = query.h { map.insert("h".into(), h.to_string()); }
-    if let Some(f) = query.f { map.insert("f".into(), f.to_string()); }
-    if let Some(q) = query.q { map.insert("q".into(), q.to_string()); }
-    if let Some(t) = query.t { map.insert("t".into(), t.to_string()); }
+) -> Response {
+    let endpoint = match &query.url {
+        Some(url) if url.len() > state.max_url_length => {
+            return (StatusCode::BAD_REQUEST, "URL too long").into_response();
+        }
+        Some(_) => "/img",
+        None => {
+            if let Err(msg) = query.check_transform_params() {
+                return (StatusCode::BAD_REQUEST, msg).into_response();
+            }
+            "/transform"
+        }
+    };
+    let map = query.to_params();
+
+    // Tenant signatures are only handed out to the operator, who proves it
+    // with the main secret; otherwise this would sign for any tenant
+    if map.contains_key("tenant") {
+        if !bearer_matches(&request_headers, &state.secret) {
+            return (StatusCode::UNAUTHORIZED, "Signing for a tenant requires the admin secret").into_response();
+        }
+    }
+    let secret = match tenant_secret(&map, &state.secret, &state.tenants) {
+        Ok(secret) => secret,
+        Err(e) => return (StatusCode::UNAUTHORIZED, e.to_string()).into_response(),
+    };
 
     let canonical = canonical_params(&map);
-    let mut mac = Hmac::<Sha256>::new_from_slice(state.secret.as_bytes()).expect("HMAC key");
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC key");
     mac.update(canonical.as_bytes());
     let sig = hex::encode(mac.finalize().into_bytes());
 
-    let mut signed_url = String::from("/img?");
-    signed_url.push_str(&canonical);
-    signed_url.push_str("&sig=");
+    let mut signed_url = format!("{}?", endpoint);
+    if !canonical.is_empty() {
+        signed_url.push_str(&canonical);
+        signed_url.push('&');
+    }
+    signed_url.push_str("sig=");
     signed_url.push_str(&sig);
 
-    Json(SignResponse { canonical, sig, signed_url })
+    Json(SignResponse { canonical, sig, signed_url }).into_response()
 }
 
 /// Provide an Axum route handler for image transformations.
 /// Usage: `app.route("/img", imagekit::route(config))`
 pub fn route(config: ImageKitConfig) -> axum::routing::MethodRouter {
-    let state = Arc::new(config);
-    get(handler).with_state(state)
+    let state = shared_state(config);
+    get(handler).with_state(state).layer(axum::middleware::map_response(count_errors))
 }
 
 /// Convenience to build a Router with the image route and optional metrics.
@@ -270,7 +2072,7 @@ async fn upload_handler(
             if let Ok(text) = field.text().await { h = text.parse::<u32>().ok(); }
         } else if name == "f" {
             if let Ok(text) = field.text().await {
-                f = match text.as_str() { "jpeg" => Some(ImageFormat::jpeg), "webp" => Some(ImageFormat::webp), "avif" => Some(ImageFormat::avif), _ => None };
+                f = text.parse::<ImageFormat>().ok();
             }
         } else if name == "q" {
             if let Ok(text) = field.text().await { q = text.parse::<u8>().ok(); }
@@ -278,32 +2080,114 @@ async fn upload_handler(
     }
 
     let bytes = match file_bytes { Some(b) => b, None => return (StatusCode::BAD_REQUEST, "Missing file").into_response() };
-    let (img, _orig_format) = match decode_image(&bytes) {
+    if let Err(response) = check_allowed_format(&state, f) {
+        return response;
+    }
+    if let Some(allowed) = &state.allowed_upload_formats {
+        match detect_input_format(&bytes) {
+            Some(format) if allowed.contains(&format) => {}
+            Some(format) => {
+                return (StatusCode::UNSUPPORTED_MEDIA_TYPE, format!("Upload format not allowed: {}", format)).into_response();
+            }
+            None => return (StatusCode::UNSUPPORTED_MEDIA_TYPE, "Unrecognized upload format").into_response(),
+        }
+    }
+    let (img, _orig_format) = match decode_image_with(&bytes, state.decode_limits()) {
         Ok(d) => d,
         Err(e) => return (StatusCode::BAD_REQUEST, format!("Decode error: {}", e)).into_response(),
     };
 
-    let resized = match resize_image(img, w, h) {
+    let resized = match resize_image_with(img, w, h, config_filters(&state)) {
         Ok(i) => i,
         Err(e) => return (StatusCode::BAD_REQUEST, format!("Resize error: {}", e)).into_response(),
     };
 
-    let target_format = f.unwrap_or_else(|| state.default_format.unwrap_or(ImageFormat::webp));
-    let quality = q.unwrap_or(DEFAULT_QUALITY);
+    let target_format = f.unwrap_or_else(|| state.default_output_format());
+    let quality = quality_fn(&state, q.map(QualityParam::Value));
+
+    let options = EncodeOptions {
+        avif_speed: state.avif_speed,
+        avif_colorspace: state.avif_colorspace,
+        ..Default::default()
+    };
 
-    let encoded = match encode_image(&resized, target_format, quality) {
+    let chain = fallback_chain(&state, false);
+    let encode = move || encode_with_fallback(target_format, &chain, |format| encode_image_with(&resized, format, quality(&resized, format), &options));
+    let (encoded, target_format) = match run_encode(&state, target_format, None, encode).await {
         Ok(b) => b,
-        Err(e) => return (StatusCode::BAD_REQUEST, format!("Encode error: {}", e)).into_response(),
+        Err(response) => return response,
+    };
+
+    let mut headers = image_headers(target_format);
+    headers.insert("Cache-Control", HeaderValue::from_static(NO_CACHE_CONTROL));
+    (headers, Body::from(encoded)).into_response()
+}
+
+/// `POST /transform`: transforms raw image bytes sent as the request body.
+///
+/// Avoids multipart overhead for server-to-server callers. The body size is
+/// capped at `max_input_size` by the route's body limit.
+async fn transform_handler(
+    axum::extract::State(state): axum::extract::State<Arc<ImageKitConfig>>,
+    Query(query): Query<TransformQuery>,
+    request_headers: HeaderMap,
+    body: axum::body::Bytes,
+) -> impl IntoResponse {
+    let map = query.to_params();
+    let sig = match resolve_signature(query.sig.as_deref(), &request_headers) {
+        Ok(sig) => sig,
+        Err(msg) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+    if let Err(e) = verify_signature(&map, sig, &state.secret) {
+        tracing::warn!("Signature verification failed for /transform: {:?}", e);
+        let status = match e {
+            crate::signature::SignatureError::Expired => StatusCode::GONE,
+            _ => StatusCode::UNAUTHORIZED,
+        };
+        return (status, e.to_string()).into_response();
+    }
+
+    if let Some(q) = query.q {
+        if q == 0 || q > 100 { return (StatusCode::BAD_REQUEST, "Invalid quality").into_response(); }
+    }
+    if let Err(response) = check_allowed_format(&state, query.f) {
+        return response;
+    }
+
+    if body.is_empty() {
+        return (StatusCode::BAD_REQUEST, "Missing body").into_response();
+    }
+    if let Err(e) = validate_dimensions(&body, state.max_input_pixels) {
+        return (StatusCode::BAD_REQUEST, e.to_string()).into_response();
+    }
+
+    let (img, _orig_format) = match decode_image_with(&body, state.decode_limits()) {
+        Ok(d) => d,
+        Err(e) => return (StatusCode::BAD_REQUEST, format!("Decode error: {}", e)).into_response(),
     };
 
-    let ct = match target_format {
-        crate::config::ImageFormat::webp => "image/webp",
-        crate::config::ImageFormat::jpeg => "image/jpeg",
-        crate::config::ImageFormat::avif => "image/avif",
+    let resized = match resize_image_with(img, query.w, query.h, config_filters(&state)) {
+        Ok(i) => i,
+        Err(e) => return (StatusCode::BAD_REQUEST, format!("Resize error: {}", e)).into_response(),
     };
 
-    let mut headers = HeaderMap::new();
-    headers.insert(axum::http::header::CONTENT_TYPE, HeaderValue::from_static(ct));
+    let target_format = query.f.unwrap_or_else(|| state.default_output_format());
+    let quality = quality_fn(&state, query.q.map(QualityParam::Value));
+
+    let options = EncodeOptions {
+        avif_speed: state.avif_speed,
+        avif_colorspace: state.avif_colorspace,
+        ..Default::default()
+    };
+
+    let chain = fallback_chain(&state, false);
+    let encode = move || encode_with_fallback(target_format, &chain, |format| encode_image_with(&resized, format, quality(&resized, format), &options));
+    let (encoded, target_format) = match run_encode(&state, target_format, None, encode).await {
+        Ok(b) => b,
+        Err(response) => return response,
+    };
+
+    let mut headers = image_headers(target_format);
     headers.insert("Cache-Control", HeaderValue::from_static(NO_CACHE_CONTROL));
     (headers, Body::from(encoded)).into_response()
 }
@@ -320,6 +2204,9 @@ pub struct Metrics {
     pub cache_misses: AtomicU64,
     pub transforms: AtomicU64,
     pub errors: AtomicU64,
+    /// Full Sled cache scans (stats, eviction) and their summed duration
+    pub cache_scans: AtomicU64,
+    pub cache_scan_micros: AtomicU64,
 }
 
 impl Metrics {
@@ -329,12 +2216,32 @@ impl Metrics {
             cache_misses: AtomicU64::new(0),
             transforms: AtomicU64::new(0),
             errors: AtomicU64::new(0),
+            cache_scans: AtomicU64::new(0),
+            cache_scan_micros: AtomicU64::new(0),
         }
     }
+    
+    /// Zeroes the request counters (hits, misses, transforms, errors).
+    ///
+    /// Cache scan totals are left alone; they describe the cache, not traffic.
+    pub fn reset(&self) {
+        self.cache_hits.store(0, Ordering::Relaxed);
+        self.cache_misses.store(0, Ordering::Relaxed);
+        self.transforms.store(0, Ordering::Relaxed);
+        self.errors.store(0, Ordering::Relaxed);
+    }
 }
 
 lazy_static::lazy_static! {
     static ref METRICS: Metrics = Metrics::new();
+    /// Cache misses currently being produced, keyed by cache dir and key
+    static ref IN_FLIGHT: InFlight<Output, SharedResponse> = InFlight::new();
+    /// Source loads currently running, keyed by cache dir and URL
+    static ref SOURCE_FLIGHTS: InFlight<Source, ImageKitError> = InFlight::new();
+    /// Serializes `/health/ready` cache probes
+    static ref READY_PROBE: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    /// Process start for `imagekit_uptime_seconds`; forced in `router()`
+    static ref START_TIME: std::time::Instant = std::time::Instant::now();
 }
 
 /// Health check endpoint
@@ -348,16 +2255,45 @@ async fn health_handler() -> impl IntoResponse {
     }))
 }
 
+/// Readiness probe: `/health` only says the process is up, this checks
+/// that the cache can actually take writes. Answers 503 with the reason
+/// when it can't, so a bad volume is pulled from rotation instead of
+/// failing every request.
+async fn ready_handler(
+    axum::extract::State(state): axum::extract::State<Arc<ImageKitConfig>>,
+) -> impl IntoResponse {
+    use serde_json::json;
+    
+    let result = tokio::task::spawn_blocking(move || {
+        // Concurrent probes would trample each other's probe files
+        let _guard = READY_PROBE.lock().unwrap_or_else(|e| e.into_inner());
+        state.check_ready()
+    })
+    .await
+    .unwrap_or_else(|e| Err(format!("readiness check panicked: {}", e)));
+    
+    match result {
+        Ok(free) => Json(json!({
+            "status": "ready",
+            "cache_writable": true,
+            "disk_free_bytes": free,
+        })).into_response(),
+        Err(reason) => {
+            tracing::warn!("Readiness check failed: {}", reason);
+            (StatusCode::SERVICE_UNAVAILABLE, Json(json!({
+                "status": "unavailable",
+                "reason": reason,
+            }))).into_response()
+        }
+    }
+}
+
 /// Cache statistics endpoint
 async fn cache_stats_handler(
     axum::extract::State(state): axum::extract::State<Arc<ImageKitConfig>>,
 ) -> impl IntoResponse {
-    use crate::cache::SledCache;
-    
-    match SledCache::new(&state.cache_dir, state.max_cache_size) {
-        Ok(cache) => {
-            let stats = cache.stats().await;
-            
+    match state.cache_stats().await {
+        Ok(stats) => {
             // Calculate hit rate
             let hits = METRICS.cache_hits.load(Ordering::Relaxed);
             let misses = METRICS.cache_misses.load(Ordering::Relaxed);
@@ -396,12 +2332,186 @@ async fn cache_stats_handler(
     }
 }
 
+/// Counts 4xx/5xx responses from the transform endpoints in `METRICS.errors`.
+///
+/// Applied as a response mapper so every early return (signature, fetch,
+/// decode, resize, encode, capacity) is counted without threading the
+/// counter through each branch.
+async fn count_errors(response: Response) -> Response {
+    let status = response.status();
+    if status.is_client_error() || status.is_server_error() {
+        METRICS.errors.fetch_add(1, Ordering::Relaxed);
+    }
+    response
+}
+
+/// `POST /metrics/reset`: zeroes the request counters for ad-hoc benchmarking.
+///
+/// Requires a signature over `action=metrics_reset` (plus `t` if given),
+/// made with the server secret like any other signed request.
+async fn metrics_reset_handler(
+    axum::extract::State(state): axum::extract::State<Arc<ImageKitConfig>>,
+    Query(query): Query<MetricsResetQuery>,
+    request_headers: HeaderMap,
+) -> impl IntoResponse {
+    let map = query.to_params();
+    let sig = match resolve_signature(query.sig.as_deref(), &request_headers) {
+        Ok(sig) => sig,
+        Err(msg) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+    if let Err(e) = verify_signature(&map, sig, &state.secret) {
+        tracing::warn!("Signature verification failed for /metrics/reset: {:?}", e);
+        let status = match e {
+            crate::signature::SignatureError::Expired => StatusCode::GONE,
+            _ => StatusCode::UNAUTHORIZED,
+        };
+        return (status, e.to_string()).into_response();
+    }
+    
+    METRICS.reset();
+    tracing::info!("Metrics counters reset");
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// Parses and authorizes a signed `/img` URL given as a string (a full
+/// URL, `/img.ext?...` or a bare query string), as for a request to it.
+fn signed_img_query(
+    state: &ImageKitConfig,
+    url: &str,
+    request_headers: &HeaderMap,
+) -> std::result::Result<ImageQuery, Response> {
+    let (path, raw_query) = url.split_once('?').unwrap_or(("", url));
+    let path_format = match path.rsplit_once("/img.") {
+        Some((_, ext)) => format_from_extension(ext),
+        None => None,
+    };
+    let mut query = serde_urlencoded::from_str::<ImageQuery>(raw_query)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()).into_response())?;
+    authorize(state, &mut query, request_headers, Some(raw_query))?;
+    if let Some(format) = path_format {
+        query.f = Some(FormatParam::Encoded(format));
+    }
+    Ok(query)
+}
+
+/// Most entries accepted by one `POST /warm`.
+pub const MAX_WARM_ENTRIES: usize = 1000;
+
+/// Entries a `/warm` job transforms at once.
+const WARM_CONCURRENCY: usize = 4;
+
+#[derive(Debug, Serialize)]
+struct WarmRejection {
+    /// Position of the entry in the request
+    index: usize,
+    status: u16,
+    error: String,
+}
+
+#[derive(Debug, Serialize)]
+struct WarmResponse {
+    /// Entries queued for warming
+    accepted: usize,
+    rejected: Vec<WarmRejection>,
+}
+
+/// `POST /warm`: pre-populates the image cache.
+///
+/// The body is a JSON array of signed `/img` URLs (as returned by `/sign`
+/// in `signed_url`, a bare query string works too). Each entry is checked
+/// like an `/img` request; valid ones are fetched, transformed and cached
+/// in the background, `WARM_CONCURRENCY` at a time, and the response only
+/// summarizes what was accepted. The request itself needs a signature over
+/// `action=warm` (optionally with `t`).
+async fn warm_handler(
+    axum::extract::State(state): axum::extract::State<Arc<ImageKitConfig>>,
+    Query(query): Query<WarmQuery>,
+    request_headers: HeaderMap,
+    Json(entries): Json<Vec<String>>,
+) -> Response {
+    use futures::StreamExt;
+
+    let map = query.to_params();
+    let sig = match resolve_signature(query.sig.as_deref(), &request_headers) {
+        Ok(sig) => sig,
+        Err(msg) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+    if let Err(e) = verify_signature(&map, sig, &state.secret) {
+        tracing::warn!("Signature verification failed for /warm: {:?}", e);
+        let status = match e {
+            crate::signature::SignatureError::Expired => StatusCode::GONE,
+            _ => StatusCode::UNAUTHORIZED,
+        };
+        return (status, e.to_string()).into_response();
+    }
+    if entries.len() > MAX_WARM_ENTRIES {
+        return (StatusCode::PAYLOAD_TOO_LARGE, format!("At most {} entries per request", MAX_WARM_ENTRIES)).into_response();
+    }
+
+    let no_headers = HeaderMap::new();
+    let mut queries = Vec::new();
+    let mut rejected = Vec::new();
+    for (index, entry) in entries.iter().enumerate() {
+        let checked = signed_img_query(&state, entry, &no_headers)
+            .and_then(|query| {
+                if query.f == Some(FormatParam::Original) {
+                    return Err((StatusCode::BAD_REQUEST, "f=original is never cached").into_response());
+                }
+                let effects = validate_image_query(&state, &query)?;
+                Ok((query, effects))
+            });
+        match checked {
+            Ok(checked) => queries.push(checked),
+            Err(response) => {
+                let status = response.status().as_u16();
+                let error = axum::body::to_bytes(response.into_body(), 1024)
+                    .await
+                    .map(|b| String::from_utf8_lossy(&b).into_owned())
+                    .unwrap_or_default();
+                rejected.push(WarmRejection { index, status, error });
+            }
+        }
+    }
+
+    let accepted = queries.len();
+    let job_state = state.clone();
+    tokio::spawn(async move {
+        let started = std::time::Instant::now();
+        let failed = futures::stream::iter(queries)
+            .map(|(query, effects)| {
+                let state = job_state.clone();
+                async move {
+                    let result = transform_and_cache(&state, &query, effects).await;
+                    if let Err(response) = &result {
+                        tracing::warn!(url = %query.url, status = response.status().as_u16(), "Failed to warm cache entry");
+                    }
+                    result.is_err()
+                }
+            })
+            .buffer_unordered(WARM_CONCURRENCY)
+            .filter(|failed| futures::future::ready(*failed))
+            .count()
+            .await;
+        tracing::info!(
+            warmed = accepted - failed,
+            failed,
+            duration_ms = started.elapsed().as_millis() as u64,
+            "Cache warm finished"
+        );
+    });
+
+    (StatusCode::ACCEPTED, Json(WarmResponse { accepted, rejected })).into_response()
+}
+
 /// Metrics endpoint (Prometheus-compatible plain text)
 async fn metrics_handler() -> impl IntoResponse {
     let hits = METRICS.cache_hits.load(Ordering::Relaxed);
     let misses = METRICS.cache_misses.load(Ordering::Relaxed);
     let transforms = METRICS.transforms.load(Ordering::Relaxed);
     let errors = METRICS.errors.load(Ordering::Relaxed);
+    let scans = METRICS.cache_scans.load(Ordering::Relaxed);
+    let scan_seconds = METRICS.cache_scan_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+    let uptime = START_TIME.elapsed().as_secs_f64();
     
     let metrics = format!(
         "# HELP imagekit_cache_hits_total Total number of cache hits\n\
@@ -415,8 +2525,20 @@ async fn metrics_handler() -> impl IntoResponse {
          imagekit_transforms_total {}\n\
          # HELP imagekit_errors_total Total number of errors\n\
          # TYPE imagekit_errors_total counter\n\
-         imagekit_errors_total {}\n",
-        hits, misses, transforms, errors
+         imagekit_errors_total {}\n\
+         # HELP imagekit_cache_scans_total Full cache database scans (stats, eviction)\n\
+         # TYPE imagekit_cache_scans_total counter\n\
+         imagekit_cache_scans_total {}\n\
+         # HELP imagekit_cache_scan_seconds_total Time spent in full cache scans\n\
+         # TYPE imagekit_cache_scan_seconds_total counter\n\
+         imagekit_cache_scan_seconds_total {}\n\
+         # HELP imagekit_uptime_seconds Seconds since the service started\n\
+         # TYPE imagekit_uptime_seconds gauge\n\
+         imagekit_uptime_seconds {:.3}\n\
+         # HELP imagekit_build_info Build information; value is always 1\n\
+         # TYPE imagekit_build_info gauge\n\
+         imagekit_build_info{{version=\"{}\"}} 1\n",
+        hits, misses, transforms, errors, scans, scan_seconds, uptime, env!("CARGO_PKG_VERSION")
     );
     
     (
@@ -426,26 +2548,486 @@ async fn metrics_handler() -> impl IntoResponse {
     )
 }
 
+/// Checks a `/blurhash`, `/lqip` or `/info` request's signature, then
+/// loads its source. Errors come back as the response to send.
+async fn signed_source(
+    state: &ImageKitConfig,
+    query: &PlaceholderQuery,
+    request_headers: &HeaderMap,
+) -> std::result::Result<Source, Response> {
+    if query.url.len() > state.max_url_length {
+        return Err((StatusCode::BAD_REQUEST, "URL too long").into_response());
+    }
+    let map = query.to_params();
+    let sig = match resolve_signature(query.sig.as_deref(), request_headers) {
+        Ok(sig) => sig,
+        Err(msg) => return Err((StatusCode::BAD_REQUEST, msg).into_response()),
+    };
+    if let Err(e) = verify_tenant_signature(&map, sig, &state.secret, &state.tenants) {
+        tracing::warn!("Signature verification failed for source url={}: {:?}", query.url, e);
+        let status = match e {
+            crate::signature::SignatureError::Expired => StatusCode::GONE,
+            _ => StatusCode::UNAUTHORIZED,
+        };
+        return Err((status, e.to_string()).into_response());
+    }
+
+    let observer: &dyn TransformObserver = state.observer.as_deref().unwrap_or(&NoopObserver);
+    match load_source(state, &query.url, observer).await {
+        Ok(source) => Ok(source),
+        Err(e) => {
+            tracing::error!("Failed to fetch {}: {}", query.url, e);
+            Err((fetch_error_status(&e), e.to_string()).into_response())
+        }
+    }
+}
+
+/// Decodes a source on the blocking pool under the configured limits.
+async fn decode_source(state: &ImageKitConfig, source: Source) -> std::result::Result<image::DynamicImage, Response> {
+    let limits = state.decode_limits();
+    match tokio::task::spawn_blocking(move || decode_image_with(&source.bytes, limits)).await {
+        Ok(Ok((img, _))) => Ok(img),
+        Ok(Err(e)) => Err((StatusCode::BAD_REQUEST, format!("Decode error: {}", e)).into_response()),
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()),
+    }
+}
+
+/// [`signed_source`], decoded; for `/blurhash` and `/lqip`.
+async fn placeholder_source(
+    state: &ImageKitConfig,
+    query: &PlaceholderQuery,
+    request_headers: &HeaderMap,
+) -> std::result::Result<image::DynamicImage, Response> {
+    let source = signed_source(state, query, request_headers).await?;
+    decode_source(state, source).await
+}
+
+/// `GET /blurhash`: a BlurHash placeholder string for a source image.
+///
+/// Signed like an untransformed `/img` URL (`url`, optional `t` and
+/// `tenant`). The source goes through the same fetch caches as `/img`.
+#[utoipa::path(
+    get,
+    path = "/blurhash",
+    params(PlaceholderQuery),
+    responses(
+        (status = 200, description = "BlurHash and source dimensions", body = BlurhashResponse),
+        (status = 400, description = "Missing signature, or the source can't be fetched or decoded"),
+        (status = 401, description = "Invalid signature"),
+        (status = 410, description = "Signature expired (`t` is in the past)"),
+    )
+)]
+async fn blurhash_handler(
+    Query(query): Query<PlaceholderQuery>,
+    request_headers: HeaderMap,
+    state: axum::extract::State<Arc<ImageKitConfig>>,
+) -> Response {
+    let img = match placeholder_source(&state, &query, &request_headers).await {
+        Ok(img) => img,
+        Err(response) => return response,
+    };
+    let (width, height) = img.dimensions();
+    let (x_comp, y_comp) = DEFAULT_BLURHASH_COMPONENTS;
+    match tokio::task::spawn_blocking(move || compute_blurhash(&img, x_comp, y_comp)).await {
+        Ok(Ok(blurhash)) => Json(BlurhashResponse { blurhash, width, height }).into_response(),
+        Ok(Err(e)) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+/// `GET /lqip`: a tiny, heavily compressed JPEG of a source image as a
+/// `data:` URI, for inlining into HTML as a placeholder.
+///
+/// Signed like `/blurhash`.
+#[utoipa::path(
+    get,
+    path = "/lqip",
+    params(PlaceholderQuery),
+    responses(
+        (status = 200, description = "`data:image/jpeg;base64,...` URI and source dimensions", body = LqipResponse),
+        (status = 400, description = "Missing signature, or the source can't be fetched or decoded"),
+        (status = 401, description = "Invalid signature"),
+        (status = 410, description = "Signature expired (`t` is in the past)"),
+    )
+)]
+async fn lqip_handler(
+    Query(query): Query<PlaceholderQuery>,
+    request_headers: HeaderMap,
+    state: axum::extract::State<Arc<ImageKitConfig>>,
+) -> Response {
+    let img = match placeholder_source(&state, &query, &request_headers).await {
+        Ok(img) => img,
+        Err(response) => return response,
+    };
+    let (width, height) = img.dimensions();
+    // Resize and encode stay off the async workers, like the decode
+    match tokio::task::spawn_blocking(move || lqip_data_uri(img)).await {
+        Ok(Ok(data_uri)) => Json(LqipResponse { data_uri, width, height }).into_response(),
+        Ok(Err(e)) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+/// Query for `GET /diff`.
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DiffQuery {
+    /// First signed `/img` URL (as `signed_url` from `/sign`), the reference
+    pub a: String,
+    /// Second signed `/img` URL, resized to `a`'s dimensions if they differ
+    pub b: String,
+    /// Minimum SSIM (0-1) for `within` to be true
+    #[serde(default)]
+    pub threshold: Option<f64>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DiffResponse {
+    /// Mean squared error over the RGBA channels (0-255 scale)
+    pub mse: f64,
+    /// Peak signal-to-noise ratio in dB; `null` when the images are identical
+    pub psnr: Option<f64>,
+    /// Structural similarity estimate, 1.0 for identical images
+    pub ssim: f64,
+    /// Whether `ssim` reaches `threshold`; only present when one was given
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub within: Option<bool>,
+}
+
+/// Produces the output of one `/diff` side through the image cache and
+/// decodes it. The URL must carry its own `sig`.
+async fn diff_side(state: &Arc<ImageKitConfig>, url: &str) -> std::result::Result<image::DynamicImage, Response> {
+    let query = signed_img_query(state, url, &HeaderMap::new())?;
+    if query.f == Some(FormatParam::Original) {
+        return Err((StatusCode::BAD_REQUEST, "f=original can't be compared").into_response());
+    }
+    // This build encodes AVIF but can't decode it, so such a side could
+    // only fail after its transform
+    let avif = || (StatusCode::BAD_REQUEST, "AVIF output can't be compared; diff another format").into_response();
+    let effects = validate_image_query(state, &query)?;
+    let plan = query.plan(state);
+    if !plan.auto && plan.format == ImageFormat::avif {
+        return Err(avif());
+    }
+    let output = transform_and_cache(state, &query, effects).await?;
+    if output.format == ImageFormat::avif {
+        return Err(avif());
+    }
+    let limits = state.decode_limits();
+    match tokio::task::spawn_blocking(move || decode_image_with(&output.bytes, limits)).await {
+        Ok(Ok((img, _))) => Ok(img),
+        Ok(Err(e)) => Err((StatusCode::INTERNAL_SERVER_ERROR, format!("Decode error: {}", e)).into_response()),
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()),
+    }
+}
+
+/// `GET /diff`: how close two `/img` outputs are, for QA checks.
+///
+/// `a` and `b` are each checked like a request to that URL, then produced
+/// (or read from the cache) and compared with [`compare_images`]. Sides
+/// producing AVIF are rejected, as the output can't be decoded.
+#[utoipa::path(
+    get,
+    path = "/diff",
+    params(DiffQuery),
+    responses(
+        (status = 200, description = "MSE, PSNR and SSIM of `b` against `a`", body = DiffResponse),
+        (status = 400, description = "Invalid inner URL or threshold, an AVIF side, or a source can't be fetched or decoded"),
+        (status = 401, description = "Invalid signature on an inner URL"),
+        (status = 410, description = "Inner URL signature expired"),
+    )
+)]
+async fn diff_handler(
+    Query(query): Query<DiffQuery>,
+    state: axum::extract::State<Arc<ImageKitConfig>>,
+) -> Response {
+    if let Some(threshold) = query.threshold {
+        if !(0.0..=1.0).contains(&threshold) {
+            return (StatusCode::BAD_REQUEST, "Invalid threshold").into_response();
+        }
+    }
+    let (a, b) = tokio::join!(
+        diff_side(&state, &query.a),
+        diff_side(&state, &query.b),
+    );
+    let (a, b) = match (a, b) {
+        (Ok(a), Ok(b)) => (a, b),
+        (Err(response), _) | (_, Err(response)) => return response,
+    };
+    let comparison = match tokio::task::spawn_blocking(move || compare_images(&a, &b)).await {
+        Ok(comparison) => comparison,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+    Json(DiffResponse {
+        mse: comparison.mse,
+        psnr: comparison.psnr.is_finite().then_some(comparison.psnr),
+        ssim: comparison.ssim,
+        within: query.threshold.map(|threshold| comparison.ssim >= threshold),
+    })
+    .into_response()
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct InfoResponse {
+    pub width: u32,
+    pub height: u32,
+    /// Source format sniffed from its bytes (`jpeg`, `png`, `heif`, ...)
+    pub format: Option<String>,
+    /// Pixel layout declared by the headers, e.g. `Rgb8` or `Rgba16`
+    pub color_type: String,
+    pub has_alpha: bool,
+    /// Size of the encoded source
+    pub bytes: usize,
+}
+
+/// `GET /info`: dimensions, format and size of a source image, without
+/// transforming it.
+///
+/// Signed like `/blurhash`. The source is fetched under the usual size
+/// and pixel limits, through the same caches as `/img`.
+#[utoipa::path(
+    get,
+    path = "/info",
+    params(PlaceholderQuery),
+    responses(
+        (status = 200, description = "Source metadata", body = InfoResponse),
+        (status = 400, description = "Missing signature, or the source can't be fetched or decoded"),
+        (status = 401, description = "Invalid signature"),
+        (status = 410, description = "Signature expired (`t` is in the past)"),
+    )
+)]
+async fn info_handler(
+    Query(query): Query<PlaceholderQuery>,
+    request_headers: HeaderMap,
+    state: axum::extract::State<Arc<ImageKitConfig>>,
+) -> Response {
+    let source = match signed_source(&state, &query, &request_headers).await {
+        Ok(source) => source,
+        Err(response) => return response,
+    };
+    let format = detect_input_format(&source.bytes).map(|f| f.to_string());
+    let bytes = source.bytes.len();
+    // Headers are enough for most formats; the rest are decoded
+    let ((width, height), color) = match probe_image(&source.bytes) {
+        Some(probed) => probed,
+        None => match decode_source(&state, source).await {
+            Ok(img) => (img.dimensions(), img.color()),
+            Err(response) => return response,
+        },
+    };
+    Json(InfoResponse {
+        width,
+        height,
+        format,
+        color_type: format!("{:?}", color),
+        has_alpha: color.has_alpha(),
+        bytes,
+    })
+    .into_response()
+}
+
+/// Query for `GET /quota`.
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QuotaQuery {
+    /// Tenant id, as in `ImageKitConfig.tenants`
+    pub tenant: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct QuotaResponse {
+    pub tenant: String,
+    /// Calendar month (UTC) the usage counts towards, e.g. `2026-10`
+    pub period: String,
+    /// `/img` requests this month
+    pub used: u64,
+    /// Monthly quota; absent for unlimited tenants
+    pub limit: Option<u64>,
+    /// Requests left this month; absent for unlimited tenants
+    pub remaining: Option<u64>,
+}
+
+/// `GET /quota`: a tenant's `/img` usage this month.
+///
+/// Requires `Authorization: Bearer <secret>` carrying the tenant's own
+/// secret or the main `secret`.
+#[utoipa::path(
+    get,
+    path = "/quota",
+    params(QuotaQuery),
+    responses(
+        (status = 200, description = "Usage for the current month", body = QuotaResponse),
+        (status = 401, description = "Unknown tenant, or the bearer secret matches neither it nor the main secret"),
+    )
+)]
+async fn quota_handler(
+    Query(query): Query<QuotaQuery>,
+    request_headers: HeaderMap,
+    state: axum::extract::State<Arc<ImageKitConfig>>,
+) -> Response {
+    let Some(tenant_secret) = state.tenants.get(&query.tenant) else {
+        return (StatusCode::UNAUTHORIZED, "Unknown tenant").into_response();
+    };
+    // Check both secrets so the timing doesn't reveal which one matched
+    let authorized = bearer_matches(&request_headers, tenant_secret) | bearer_matches(&request_headers, &state.secret);
+    if !authorized {
+        return (StatusCode::UNAUTHORIZED, "Quota usage requires the tenant's or the admin secret").into_response();
+    }
+
+    let usage = match &state.quotas {
+        Some(quotas) => quotas.usage(&query.tenant),
+        None => QuotaUsage { period: QuotaPeriod::at(std::time::SystemTime::now()), used: 0, limit: None, rejected: 0 },
+    };
+    Json(QuotaResponse {
+        tenant: query.tenant,
+        period: usage.period.to_string(),
+        used: usage.used,
+        limit: usage.limit,
+        remaining: usage.limit.map(|limit| limit.saturating_sub(usage.used)),
+    })
+    .into_response()
+}
+
+/// OpenAPI description of the public endpoints, served at `GET /openapi.json`.
+#[derive(OpenApi)]
+#[openapi(
+    paths(handler, sign_handler, blurhash_handler, lqip_handler, info_handler, diff_handler, quota_handler),
+    components(schemas(SignResponse, BlurhashResponse, LqipResponse, InfoResponse, DiffResponse, QuotaResponse, ChromaSubsampling, FitMode, Gravity, AvifColorSpace, Wrap, TextPosition, ResizeFilter))
+)]
+pub struct ApiDoc;
+
+async fn openapi_handler() -> impl IntoResponse {
+    Json(ApiDoc::openapi())
+}
+
+/// Builds the CORS layer for the image/sign routes.
+///
+/// Returns `None` when no origins are configured. A `*` entry allows any
+/// origin; unparseable origins are skipped with a warning.
+fn cors_layer(origins: &[String]) -> Option<CorsLayer> {
+    if origins.is_empty() {
+        return None;
+    }
+    
+    let allow_origin = if origins.iter().any(|o| o == "*") {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(origins.iter().filter_map(|o| match HeaderValue::from_str(o) {
+            Ok(v) => Some(v),
+            Err(_) => {
+                tracing::warn!("Ignoring invalid CORS origin: {}", o);
+                None
+            }
+        }))
+    };
+    
+    Some(
+        CorsLayer::new()
+            .allow_origin(allow_origin)
+            .allow_methods([Method::GET, Method::POST, Method::OPTIONS])
+            .allow_headers(Any)
+            .expose_headers([axum::http::header::ETAG, axum::http::header::RETRY_AFTER]),
+    )
+}
+
+/// Room for multipart framing and the small form fields on `/upload`,
+/// on top of `max_input_size` for the file itself.
+const UPLOAD_FORM_OVERHEAD: usize = 64 * 1024;
+
+/// Wraps `config` for the handlers, building its HTTP clients once so
+/// every request reuses their connection pools.
+fn shared_state(mut config: ImageKitConfig) -> Arc<ImageKitConfig> {
+    if config.http_clients.is_none() {
+        match config.build_http_clients() {
+            Ok(clients) => config.http_clients = Some(clients),
+            // Left unset, each fetch reports the error
+            Err(e) => tracing::error!("Failed to build HTTP clients: {}", e),
+        }
+    }
+    Arc::new(config)
+}
+
 pub fn router(config: ImageKitConfig) -> Router {
     use crate::cache::cloudflare_cache_middleware;
     use axum::middleware;
     
-    let state = Arc::new(config);
+    let state = shared_state(config);
+    lazy_static::initialize(&START_TIME);
+    if state.accept_invalid_certs {
+        tracing::warn!("accept_invalid_certs is set: source fetches trust any TLS certificate");
+    }
     
     // Observability endpoints - NO rate limiting, NO caching
     let observability_routes = Router::new()
         .route("/health", get(health_handler))
+        .route("/health/ready", get(ready_handler).with_state(state.clone()))
         .route("/stats/cache", get(cache_stats_handler).with_state(state.clone()))
-        .route("/metrics", get(metrics_handler));
+        .route("/metrics", get(metrics_handler))
+        .route("/openapi.json", get(openapi_handler))
+        .route("/metrics/reset", axum::routing::post(metrics_reset_handler).with_state(state.clone()))
+        .route("/warm", axum::routing::post(warm_handler).with_state(state.clone()))
+        .route("/quota", get(quota_handler).with_state(state.clone()));
+    let observability_routes = if state.enable_debug_endpoints {
+        observability_routes.route("/debug/cache-key", get(debug_cache_key_handler).with_state(state.clone()))
+    } else {
+        observability_routes
+    };
     
+    // `/img.webp`, `/img.avif`, ...: one static route per extension
+    let extension_routes = PATH_EXTENSIONS.iter().fold(Router::new(), |routes, ext| {
+        routes.route(
+            &format!("/img.{}", ext),
+            get(extension_handler).with_state(state.clone()).layer(middleware::map_response(count_errors)),
+        )
+    });
+
     // Transformation endpoints - WITH rate limiting AND Cloudflare caching
     let mut transform_routes = Router::new()
-        .route("/img", get(handler).with_state(state.clone()))
-        .route("/upload", axum::routing::post(upload_handler).with_state(state.clone()))
+        .route("/img", get(handler).with_state(state.clone()).layer(middleware::map_response(count_errors)))
+        .merge(extension_routes)
+        // Bodies over the limit get a 413 before they're buffered; axum's own
+        // 2MB default is replaced rather than stacked
+        .route(
+            "/upload",
+            axum::routing::post(upload_handler)
+                .layer(axum::extract::DefaultBodyLimit::disable())
+                .layer(RequestBodyLimitLayer::new(state.max_input_size + UPLOAD_FORM_OVERHEAD))
+                .with_state(state.clone())
+                .layer(middleware::map_response(count_errors)),
+        )
+        .route(
+            "/transform",
+            axum::routing::post(transform_handler)
+                .layer(axum::extract::DefaultBodyLimit::disable())
+                .layer(RequestBodyLimitLayer::new(state.max_input_size))
+                .with_state(state.clone())
+                .layer(middleware::map_response(count_errors)),
+        )
         .route("/sign", get(sign_handler).with_state(state.clone()))
+        .route("/blurhash", get(blurhash_handler).with_state(state.clone()).layer(middleware::map_response(count_errors)))
+        .route("/lqip", get(lqip_handler).with_state(state.clone()).layer(middleware::map_response(count_errors)))
+        .route("/info", get(info_handler).with_state(state.clone()).layer(middleware::map_response(count_errors)))
+        .route("/diff", get(diff_handler).with_state(state.clone()).layer(middleware::map_response(count_errors)))
         // Add Cloudflare caching middleware to all transformation endpoints
         .layer(middleware::from_fn(cloudflare_cache_middleware));
     
+    // CORS sits outside the caching middleware so preflights short-circuit
+    if let Some(cors) = cors_layer(&state.cors_allowed_origins) {
+        // Lets cross-origin pages read Resource Timing for the images
+        let timing = if state.cors_allowed_origins.iter().any(|o| o == "*") {
+            "*".to_string()
+        } else {
+            state.cors_allowed_origins.join(", ")
+        };
+        transform_routes = transform_routes.layer(cors);
+        if let Ok(timing) = HeaderValue::from_str(&timing) {
+            transform_routes = transform_routes.layer(SetResponseHeaderLayer::if_not_present(
+                HeaderName::from_static("timing-allow-origin"),
+                timing,
+            ));
+        }
+    }
+    
     // Only add rate limiting to transformation endpoints if not disabled
     if std::env::var("DISABLE_RATE_LIMIT").is_err() {
         // Configure rate limiting: 10 req/sec per IP, burst of 30
@@ -473,4 +3055,5 @@ pub fn router(config: ImageKitConfig) -> Router {
         .merge(observability_routes)
         .merge(transform_routes)
         .nest_service("/", ServeDir::new("frontend"))
+        .layer(middleware::from_fn(logging::request_span))
 }
@@ -0,0 +1,105 @@
+use axum::extract::Request;
+use axum::http::{HeaderName, HeaderValue};
+use axum::middleware::Next;
+use axum::response::Response;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tracing::Instrument;
+use tracing_subscriber::EnvFilter;
+
+/// Filter used when `RUST_LOG` is unset.
+pub const DEFAULT_LOG_FILTER: &str = "imagekit=debug,tower_http=debug";
+
+/// Header carrying the request id, read from the client or proxy when present.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Output format of the standalone server's logs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    /// Human-readable lines (the default)
+    #[default]
+    Pretty,
+    /// One JSON object per event, with span fields such as `request_id`,
+    /// for log aggregators (Render, Fly, Railway, ...)
+    Json,
+}
+
+impl LogFormat {
+    /// Reads `LOG_FORMAT`; `json` selects [`LogFormat::Json`], anything else pretty.
+    pub fn from_env() -> Self {
+        match std::env::var("LOG_FORMAT") {
+            Ok(v) if v.eq_ignore_ascii_case("json") => LogFormat::Json,
+            _ => LogFormat::Pretty,
+        }
+    }
+}
+
+/// Builds the subscriber for `format`, filtered by `RUST_LOG`.
+pub fn subscriber(format: LogFormat) -> Box<dyn tracing::Subscriber + Send + Sync> {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| DEFAULT_LOG_FILTER.into());
+    match format {
+        LogFormat::Pretty => Box::new(tracing_subscriber::fmt().with_env_filter(filter).finish()),
+        LogFormat::Json => Box::new(
+            tracing_subscriber::fmt()
+                .json()
+                .with_current_span(true)
+                .with_span_list(false)
+                .with_env_filter(filter)
+                .finish(),
+        ),
+    }
+}
+
+/// Installs the subscriber for `format` as the global default.
+///
+/// # Panics
+/// If a global subscriber is already set.
+pub fn init(format: LogFormat) {
+    tracing::subscriber::set_global_default(subscriber(format)).expect("global subscriber already set");
+}
+
+/// Runs each request inside a `request` span carrying `request_id`, and logs
+/// its status and `duration_ms` when it finishes.
+///
+/// The id comes from an incoming `x-request-id` (so it matches the proxy's
+/// logs) or is generated, and is echoed on the response.
+pub async fn request_span(req: Request, next: Next) -> Response {
+    let request_id = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+        .unwrap_or_else(next_request_id);
+    let span = tracing::info_span!(
+        "request",
+        request_id = %request_id,
+        method = %req.method(),
+        path = %req.uri().path(),
+    );
+
+    let start = std::time::Instant::now();
+    let mut response = next.run(req).instrument(span.clone()).await;
+    span.in_scope(|| {
+        tracing::info!(
+            status = response.status().as_u16(),
+            duration_ms = start.elapsed().as_millis() as u64,
+            "request finished"
+        );
+    });
+
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+    }
+    response
+}
+
+/// Process-unique id: start time plus a counter, both hex.
+fn next_request_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    lazy_static::lazy_static! {
+        static ref EPOCH: u64 = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+    }
+    format!("{:x}-{:x}", *EPOCH, COUNTER.fetch_add(1, Ordering::Relaxed))
+}
@@ -1,6 +1,6 @@
 use axum::Router;
 use std::net::SocketAddr;
-use imagekit::{config::{ImageKitConfig, ImageFormat}, router};
+use imagekit::{config::{ImageKitConfig, ImageFormat}, logging::{self, LogFormat}, router, server};
 
 /// ImageKit standalone server entry point.
 ///
@@ -10,37 +10,45 @@ use imagekit::{config::{ImageKitConfig, ImageFormat}, router};
 ///
 /// # Configuration
 /// Environment variables:
+/// - `IMAGEKIT_CONFIG`: Optional TOML config file (see `ImageKitConfig::from_file`);
+///   `IMAGEKIT_*` variables override values from the file
 /// - `IMAGEKIT_SECRET`: HMAC secret for URL signing (required in production)
 /// - `PORT`: HTTP listen port (default: 8080)
 /// - `RUST_LOG`: Logging verbosity (default: "imagekit=debug,tower_http=debug")
+/// - `LOG_FORMAT`: `json` for structured JSON logs (default: human-readable)
 ///
 /// # Deployment
 /// Server binds to 0.0.0.0 to accept external connections, required for
-/// platforms like Render, Railway, Fly.io, etc.
+/// platforms like Render, Railway, Fly.io, etc. Connections are tuned per
+/// `tcp_nodelay`, `http1_keep_alive` and `http2` from the config. On Ctrl-C
+/// or SIGTERM, in-flight requests finish before the process exits.
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Initialize structured logging with environment-based filtering
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "imagekit=debug,tower_http=debug".into())
-        )
-        .init();
+    logging::init(LogFormat::from_env());
 
     tracing::info!("Starting ImageKit server");
 
-    // Load configuration from environment with fallback defaults
-    let cfg = ImageKitConfig {
-        secret: std::env::var("IMAGEKIT_SECRET")
-            .unwrap_or_else(|_| "local-dev-secret".into()),
-        cache_dir: std::path::PathBuf::from("./cache"),
-        max_input_size: 8 * 1024 * 1024,        // 8MB prevents DoS
-        max_cache_size: Some(10 * 1024 * 1024 * 1024), // 10GB cache limit
-        allowed_formats: vec![ImageFormat::jpeg, ImageFormat::webp, ImageFormat::avif],
-        default_format: Some(ImageFormat::webp), // Best compression/compatibility
+    // Load configuration from a file when given, else from environment with fallback defaults
+    let cfg = if let Ok(path) = std::env::var("IMAGEKIT_CONFIG") {
+        tracing::info!("Loading configuration from {}", path);
+        ImageKitConfig::from_file(path)?
+    } else {
+        ImageKitConfig {
+            secret: std::env::var("IMAGEKIT_SECRET")
+                .unwrap_or_else(|_| "local-dev-secret".into()),
+            cache_dir: std::path::PathBuf::from("./cache"),
+            max_input_size: 8 * 1024 * 1024,        // 8MB prevents DoS
+            max_cache_size: Some(10 * 1024 * 1024 * 1024), // 10GB cache limit
+            allowed_formats: vec![ImageFormat::jpeg, ImageFormat::webp, ImageFormat::avif, ImageFormat::png],
+            default_format: Some(ImageFormat::webp), // Best compression/compatibility
+            ..Default::default()
+        }
     };
     cfg.validate()?;
+    cfg.check_cache_dir();
 
+    let tuning = cfg.server_tuning();
     let app = Router::new().merge(router(cfg));
 
     // Cloud platforms inject PORT environment variable
@@ -54,6 +62,37 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     tracing::info!("Server listening on {}", addr);
     println!("Server listening on {}", addr);
     
-    axum::serve(tokio::net::TcpListener::bind(addr).await?, app).await?;
+    server::serve_with_shutdown(tokio::net::TcpListener::bind(addr).await?, app, tuning, shutdown_signal()).await;
     Ok(())
 }
+
+/// Resolves on Ctrl-C, or on SIGTERM, which platforms send before
+/// replacing an instance on deploys.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::warn!("Failed to listen for Ctrl-C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(e) => {
+                tracing::warn!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        () = ctrl_c => {}
+        () = terminate => {}
+    }
+    tracing::info!("Shutdown signal received");
+}
@@ -0,0 +1,41 @@
+use crate::config::ImageFormat;
+use std::time::Duration;
+
+/// Hooks into the `/img` transform pipeline for custom metrics and logging.
+///
+/// Embedders implement this to record business metrics (bytes saved,
+/// per-tenant counts, ...) without forking the handler. Every callback has
+/// a no-op default, so implementors only override what they need.
+///
+/// Callbacks run inline on the request path and should be cheap; offload
+/// any I/O (e.g. pushing to an external metrics system) to a channel or task.
+pub trait TransformObserver: Send + Sync + std::fmt::Debug {
+    /// Source image downloaded from `url` (`bytes` is the encoded size).
+    fn on_fetch(&self, _url: &str, _bytes: usize) {}
+
+    /// Decode → resize → encode finished.
+    ///
+    /// * `src_dims` / `dst_dims` - `(width, height)` before and after transformation
+    /// * `format` - Output format actually encoded
+    /// * `duration` - Wall time spent decoding, resizing and encoding
+    fn on_transform(
+        &self,
+        _src_dims: (u32, u32),
+        _dst_dims: (u32, u32),
+        _format: ImageFormat,
+        _duration: Duration,
+    ) {
+    }
+
+    /// Request served from cache.
+    fn on_cache_hit(&self, _key: &str) {}
+
+    /// Request missed the cache and will be transformed.
+    fn on_cache_miss(&self, _key: &str) {}
+}
+
+/// Observer that ignores every event; the default when none is configured.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoopObserver;
+
+impl TransformObserver for NoopObserver {}
@@ -0,0 +1,55 @@
+//! Compact placeholders shown while the real image loads.
+
+use image::DynamicImage;
+
+use crate::config::ImageFormat;
+use crate::transform::encode_image;
+use crate::ImageKitError;
+
+/// Components used by `GET /blurhash` and the `/img` header: 4 horizontal
+/// by 3 vertical, the usual choice for landscape photos.
+pub const DEFAULT_BLURHASH_COMPONENTS: (u32, u32) = (4, 3);
+
+/// Longest side the image is shrunk to before hashing. A BlurHash only
+/// keeps a few cosine components, so more pixels just cost time.
+const BLURHASH_SAMPLE_SIZE: u32 = 32;
+
+/// Encodes `img` as a [BlurHash](https://blurha.sh) with `x_comp` by
+/// `y_comp` components (each 1-9).
+///
+/// # Errors
+/// Returns `ImageKitError::InvalidArgument` for components out of range.
+pub fn compute_blurhash(img: &DynamicImage, x_comp: u32, y_comp: u32) -> Result<String, ImageKitError> {
+    if !(1..=9).contains(&x_comp) || !(1..=9).contains(&y_comp) {
+        return Err(ImageKitError::InvalidArgument(format!(
+            "blurhash components must be 1-9, got {}x{}", x_comp, y_comp
+        )));
+    }
+    let sample = if img.width().max(img.height()) > BLURHASH_SAMPLE_SIZE {
+        img.thumbnail(BLURHASH_SAMPLE_SIZE, BLURHASH_SAMPLE_SIZE)
+    } else {
+        img.clone()
+    };
+    let rgba = sample.to_rgba8();
+    blurhash::encode(x_comp, y_comp, rgba.width(), rgba.height(), rgba.as_raw())
+        .map_err(|e| ImageKitError::TransformError(format!("blurhash: {}", e)))
+}
+
+/// Longest side of an LQIP; the other follows the source's aspect ratio.
+pub const LQIP_SIZE: u32 = 20;
+
+/// JPEG quality of an LQIP. It's shown blurred and scaled up, so
+/// compression artifacts don't matter.
+pub const LQIP_QUALITY: u8 = 30;
+
+/// Shrinks `img` to fit [`LQIP_SIZE`] on its longest side (smaller sources
+/// keep their size) and encodes it as a low-quality JPEG `data:` URI.
+///
+/// Bounding both sides keeps a very tall source's placeholder tiny too.
+pub fn lqip_data_uri(img: DynamicImage) -> Result<String, ImageKitError> {
+    use base64::Engine;
+
+    let small = if img.width().max(img.height()) > LQIP_SIZE { img.thumbnail(LQIP_SIZE, LQIP_SIZE) } else { img };
+    let jpeg = encode_image(&small, ImageFormat::jpeg, LQIP_QUALITY)?;
+    Ok(format!("data:image/jpeg;base64,{}", base64::engine::general_purpose::STANDARD.encode(jpeg)))
+}
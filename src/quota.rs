@@ -0,0 +1,121 @@
+//! Per-tenant monthly `/img` quotas.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// Calendar month a usage count belongs to, in UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct QuotaPeriod {
+    pub year: i32,
+    pub month: u8,
+}
+
+impl QuotaPeriod {
+    /// The month `at` falls in.
+    pub fn at(at: SystemTime) -> Self {
+        let date = time::OffsetDateTime::from(at);
+        Self { year: date.year(), month: date.month() as u8 }
+    }
+}
+
+impl std::fmt::Display for QuotaPeriod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:04}-{:02}", self.year, self.month)
+    }
+}
+
+/// A tenant's usage in one period.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuotaUsage {
+    pub period: QuotaPeriod,
+    pub used: u64,
+    /// `None` for tenants without a quota
+    pub limit: Option<u64>,
+    /// Requests turned away over the limit, including this one
+    pub rejected: u64,
+}
+
+/// A tenant's counters for one period.
+#[derive(Debug, Clone, Copy)]
+struct Count {
+    period: QuotaPeriod,
+    used: u64,
+    rejected: u64,
+}
+
+/// Counts `/img` requests per tenant against monthly limits.
+///
+/// Counters live in memory and start from zero on restart; a count from an
+/// earlier month reads as zero, so usage resets when the month turns over.
+/// Tenants without a limit (and requests signed with the main secret) are
+/// never counted.
+///
+/// Cloning shares the same counters.
+#[derive(Debug, Clone, Default)]
+pub struct QuotaTracker {
+    limits: HashMap<String, u64>,
+    counts: Arc<Mutex<HashMap<String, Count>>>,
+}
+
+impl QuotaTracker {
+    /// Tracks the tenants in `limits`, each allowed that many requests a month.
+    pub fn new(limits: HashMap<String, u64>) -> Self {
+        Self { limits, counts: Arc::default() }
+    }
+
+    /// Sets `tenant`'s monthly limit, keeping its current count.
+    pub fn with_limit(mut self, tenant: impl Into<String>, monthly_limit: u64) -> Self {
+        self.limits.insert(tenant.into(), monthly_limit);
+        self
+    }
+
+    /// Tenants with a limit.
+    pub fn tenants(&self) -> impl Iterator<Item = &str> {
+        self.limits.keys().map(String::as_str)
+    }
+
+    /// Counts one request by `tenant` now; see [`QuotaTracker::consume_at`].
+    pub fn consume(&self, tenant: &str) -> std::result::Result<QuotaUsage, QuotaUsage> {
+        self.consume_at(tenant, SystemTime::now())
+    }
+
+    /// Counts one request by `tenant` at `at`.
+    ///
+    /// Returns the usage including this request, or the unchanged usage as
+    /// the error once the limit is reached; `rejected` then counts this
+    /// request too, so callers can tell the first rejection of a period.
+    pub fn consume_at(&self, tenant: &str, at: SystemTime) -> std::result::Result<QuotaUsage, QuotaUsage> {
+        let period = QuotaPeriod::at(at);
+        let Some(&limit) = self.limits.get(tenant) else {
+            return Ok(QuotaUsage { period, used: 0, limit: None, rejected: 0 });
+        };
+        let fresh = Count { period, used: 0, rejected: 0 };
+        let mut counts = self.counts.lock().unwrap();
+        let count = counts.entry(tenant.to_string()).or_insert(fresh);
+        if count.period != period {
+            *count = fresh;
+        }
+        if count.used >= limit {
+            count.rejected += 1;
+            return Err(QuotaUsage { period, used: count.used, limit: Some(limit), rejected: count.rejected });
+        }
+        count.used += 1;
+        Ok(QuotaUsage { period, used: count.used, limit: Some(limit), rejected: count.rejected })
+    }
+
+    /// `tenant`'s usage now; see [`QuotaTracker::usage_at`].
+    pub fn usage(&self, tenant: &str) -> QuotaUsage {
+        self.usage_at(tenant, SystemTime::now())
+    }
+
+    /// `tenant`'s usage in the month `at` falls in.
+    pub fn usage_at(&self, tenant: &str, at: SystemTime) -> QuotaUsage {
+        let period = QuotaPeriod::at(at);
+        let (used, rejected) = match self.counts.lock().unwrap().get(tenant) {
+            Some(count) if count.period == period => (count.used, count.rejected),
+            _ => (0, 0),
+        };
+        QuotaUsage { period, used, limit: self.limits.get(tenant).copied(), rejected }
+    }
+}
@@ -0,0 +1,91 @@
+//! Serving a router with connection-level tuning.
+
+use axum::Router;
+use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
+use hyper_util::server::conn::auto::Builder;
+use hyper_util::server::graceful::GracefulShutdown;
+use hyper_util::service::TowerToHyperService;
+use std::future::Future;
+use std::time::Duration;
+use tokio::net::TcpListener;
+
+/// How long [`serve_with_shutdown`] waits for open connections to finish
+/// once shutdown starts; stragglers are then dropped.
+pub const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(30);
+
+/// Connection settings for [`serve`]; see the matching fields of
+/// [`ImageKitConfig`](crate::config::ImageKitConfig).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServerTuning {
+    pub tcp_nodelay: bool,
+    pub http1_keep_alive: bool,
+    pub http2: bool,
+    pub http2_keep_alive_interval: Option<Duration>,
+}
+
+impl Default for ServerTuning {
+    fn default() -> Self {
+        Self { tcp_nodelay: true, http1_keep_alive: true, http2: false, http2_keep_alive_interval: None }
+    }
+}
+
+/// Serves `app` on `listener` until the process exits.
+///
+/// Like `axum::serve`, but applies `tuning` to every connection. With
+/// `http2`, each connection is sniffed for the HTTP/2 preface, so
+/// HTTP/1.1 and h2c clients share the port.
+pub async fn serve(listener: TcpListener, app: Router, tuning: ServerTuning) {
+    serve_with_shutdown(listener, app, tuning, std::future::pending()).await
+}
+
+/// Like [`serve`], until `signal` resolves.
+///
+/// The listener is then closed and open connections are asked to finish:
+/// requests in flight complete, idle keep-alive connections close. Returns
+/// once they're all done, or after [`SHUTDOWN_GRACE_PERIOD`].
+pub async fn serve_with_shutdown<F>(listener: TcpListener, app: Router, tuning: ServerTuning, signal: F)
+where
+    F: Future<Output = ()> + Send,
+{
+    let mut builder = Builder::new(TokioExecutor::new());
+    builder.http1().keep_alive(tuning.http1_keep_alive);
+    if let Some(interval) = tuning.http2_keep_alive_interval {
+        builder.http2().timer(TokioTimer::new()).keep_alive_interval(interval);
+    }
+    let builder = if tuning.http2 { builder } else { builder.http1_only() };
+    let graceful = GracefulShutdown::new();
+    tokio::pin!(signal);
+
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            () = &mut signal => break,
+        };
+        let (stream, peer) = match accepted {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                // Usually out of file descriptors; back off instead of spinning
+                tracing::warn!("Failed to accept connection: {}", e);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+        if let Err(e) = stream.set_nodelay(tuning.tcp_nodelay) {
+            tracing::debug!("Failed to set TCP_NODELAY for {}: {}", peer, e);
+        }
+        let service = TowerToHyperService::new(app.clone());
+        let connection = builder.serve_connection_with_upgrades(TokioIo::new(stream), service).into_owned();
+        let connection = graceful.watch(connection);
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                tracing::debug!("Connection from {} ended with error: {}", peer, e);
+            }
+        });
+    }
+
+    drop(listener);
+    tracing::info!("Shutting down; waiting for open connections");
+    if tokio::time::timeout(SHUTDOWN_GRACE_PERIOD, graceful.shutdown()).await.is_err() {
+        tracing::warn!("Connections still open after {:?}; dropping them", SHUTDOWN_GRACE_PERIOD);
+    }
+}
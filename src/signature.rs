@@ -1,6 +1,6 @@
 use hmac::{Hmac, Mac};
 use sha2::Sha256;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 
 /// Signature verification errors.
 ///
@@ -16,6 +16,27 @@ pub enum SignatureError {
     
     #[error("expired")]
     Expired,
+    
+    #[error("unknown tenant")]
+    UnknownTenant,
+}
+
+/// Query parameters covered by an `/img` signature.
+///
+/// Anything else (e.g. `utm_source`) is left out of signing, so tracking
+/// params can be appended to a signed URL without re-signing. That is only
+/// safe because the server ignores parameters it doesn't recognize: a new
+/// transform parameter must be listed here, or it could be set by anyone
+/// holding a signed URL.
+pub const SIGNED_PARAMS: &[&str] = &[
+    "angle", "badge", "bg", "callback_url", "chroma", "colorspace", "crop", "depth", "download", "downscale_filter", "enlarge", "f", "fit", "fp_x", "fp_y", "gamma", "gravity", "h",
+    "invert", "keep_icc", "lossless", "max_bytes", "opacity", "pixelate", "preset", "q", "ring", "sepia", "speed", "t", "tenant",
+    "text", "text_color", "text_pos", "text_size", "tint", "upscale_filter", "url", "w", "wrap",
+];
+
+/// Whether `name` is one of the [`SIGNED_PARAMS`].
+pub fn is_signed_param(name: &str) -> bool {
+    SIGNED_PARAMS.contains(&name)
 }
 
 /// Generates canonical parameter string for HMAC signature computation.
@@ -25,16 +46,22 @@ pub enum SignatureError {
 /// parameter itself is excluded from the canonical string to prevent
 /// circular dependencies.
 ///
+/// Keys and values are `application/x-www-form-urlencoded`, so a source URL
+/// containing `&`, `=` or `?` can't masquerade as extra parameters: without
+/// encoding, `url=https://a/x.jpg&w=400` reads the same whether `w` is a
+/// real parameter or part of the URL. The result is also a valid query
+/// string, which `/sign` returns as-is.
+///
 /// # Format
-/// Returns "key1=value1&key2=value2" sorted by key name.
-fn canonical_string(params: &BTreeMap<String, String>) -> String {
-    let mut pairs: Vec<String> = Vec::new();
-    for (k, v) in params.iter() {
-        if k != "sig" {
-            pairs.push(format!("{}={}", k, v));
-        }
-    }
-    pairs.join("&")
+/// Returns "key1=value1&key2=value2" sorted by key name, percent-encoded
+/// (spaces as `+`).
+pub fn canonical_string(params: &BTreeMap<String, String>) -> String {
+    let pairs: Vec<(&str, &str)> = params
+        .iter()
+        .filter(|(k, _)| k.as_str() != "sig")
+        .map(|(k, v)| (k.as_str(), v.as_str()))
+        .collect();
+    serde_urlencoded::to_string(pairs).expect("string pairs always encode")
 }
 
 /// Verifies HMAC-SHA256 signature for URL parameters.
@@ -88,4 +115,65 @@ pub fn verify_signature(
     } else {
         Err(SignatureError::Invalid)
     }
-}
\ No newline at end of file
+}
+
+/// Secret a request must be signed with: the secret of its `tenant` param,
+/// or `default` when it has none.
+///
+/// # Errors
+/// Returns `SignatureError::UnknownTenant` if `tenant` isn't in `tenants`.
+pub fn tenant_secret<'a>(
+    params: &BTreeMap<String, String>,
+    default: &'a str,
+    tenants: &'a HashMap<String, String>,
+) -> Result<&'a str, SignatureError> {
+    match params.get("tenant") {
+        Some(tenant) => tenants.get(tenant).map(String::as_str).ok_or(SignatureError::UnknownTenant),
+        None => Ok(default),
+    }
+}
+
+/// [`verify_signature`] under the secret [`tenant_secret`] selects, so a
+/// leaked tenant secret only signs that tenant's URLs.
+///
+/// # Errors
+/// As [`tenant_secret`] and [`verify_signature`].
+pub fn verify_tenant_signature(
+    params: &BTreeMap<String, String>,
+    sig: &str,
+    default: &str,
+    tenants: &HashMap<String, String>,
+) -> Result<(), SignatureError> {
+    verify_signature(params, sig, tenant_secret(params, default, tenants)?)
+}
+
+/// Verifies a complete signed URL, e.g. `https://host/img?url=...&w=400&sig=...`.
+///
+/// For embedders checking URLs outside the server (edge middleware, proxies).
+/// Accepts a full URL or just its query string; any `#fragment` is ignored.
+/// Query values are percent-decoded (`+` as space) before signing, so a
+/// percent-encoded `url` param verifies against the same signature as the
+/// decoded map passed to [`verify_signature`]. Parameters outside
+/// [`SIGNED_PARAMS`] are ignored, as the server ignores them.
+///
+/// # Errors
+/// Returns `SignatureError::Missing` if there is no `sig` parameter, and
+/// `SignatureError::Invalid` if the query can't be parsed, repeats a
+/// parameter, or doesn't match. Expiry is checked as in [`verify_signature`].
+pub fn verify_signed_url(url: &str, secret: &str) -> Result<(), SignatureError> {
+    let url = url.split('#').next().unwrap_or_default();
+    let query = url.split_once('?').map(|(_, q)| q).unwrap_or(url);
+
+    let pairs: Vec<(String, String)> =
+        serde_urlencoded::from_str(query).map_err(|_| SignatureError::Invalid)?;
+    let mut params = BTreeMap::new();
+    for (k, v) in pairs.into_iter().filter(|(k, _)| k == "sig" || is_signed_param(k)) {
+        // Duplicates would make the signed value ambiguous
+        if params.insert(k, v).is_some() {
+            return Err(SignatureError::Invalid);
+        }
+    }
+
+    let sig = params.remove("sig").ok_or(SignatureError::Missing)?;
+    verify_signature(&params, &sig, secret)
+}
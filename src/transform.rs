@@ -1,16 +1,22 @@
-use crate::config::ImageFormat;
+use crate::config::{AvifColorSpace, ImageFormat, InputFormat, DEFAULT_AVIF_SPEED, DEFAULT_QUALITY};
 use crate::ImageKitError;
 use image::codecs::avif::AvifEncoder;
 use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
 use image::{DynamicImage, ExtendedColorType};
 use image::GenericImageView;
 use image::ImageEncoder;
 
+pub mod params;
+
+use params::{ChromaSubsampling, Gravity, ResizeFilter, TextPosition};
+
 /// Decodes raw image bytes into memory-resident representation.
 ///
 /// Performs format detection and validation before decoding to prevent
 /// processing malformed images. Supports JPEG, WebP, AVIF, and other
-/// formats via the `image` crate.
+/// formats via the `image` crate, plus HEIC/HEIF when built with the
+/// `heif` feature.
 ///
 /// # Parameters
 /// * `bytes` - Raw encoded image data
@@ -24,28 +30,320 @@ use image::ImageEncoder;
 /// - Format cannot be detected from magic bytes
 /// - Image data is corrupted or malformed
 /// - Decoder encounters unsupported features
+/// - Input is HEIC/HEIF and the `heif` feature is disabled
 pub fn decode_image(bytes: &[u8]) -> Result<(DynamicImage, Option<ImageFormat>), ImageKitError> {
-    let guessed = image::guess_format(bytes)
-        .map_err(|e| ImageKitError::TransformError(e.to_string()))?;
+    decode_image_with(bytes, image::Limits::default())
+}
+
+/// Like [`decode_image`], under explicit decoder `limits`.
+///
+/// The decoder checks the declared dimensions and its planned allocations
+/// against `limits` before reading pixel data, so an oversized image fails
+/// with an error instead of exhausting memory. HEIF sources are decoded
+/// by libheif and aren't covered.
+pub fn decode_image_with(bytes: &[u8], limits: image::Limits) -> Result<(DynamicImage, Option<ImageFormat>), ImageKitError> {
+    // HEIF must be checked first: `image` can't decode it
+    if is_heif(bytes) {
+        return decode_heif(bytes).map(|img| (img, None));
+    }
     
-    let img = image::load_from_memory_with_format(bytes, guessed)
-        .map_err(|e| ImageKitError::TransformError(e.to_string()))?;
+    // Known formats we deliberately don't rasterize get a clear message
+    if is_svg(bytes) {
+        return Err(ImageKitError::TransformError("unsupported format: svg".into()));
+    }
+    
+    // `image` would pick an entry by its own rules; favicons want the largest
+    if is_ico(bytes) {
+        return decode_ico(bytes, limits);
+    }
     
-    // Map detected format to our supported transformation formats
-    let fmt = match guessed {
+    let guessed = image::guess_format(bytes).map_err(|e| {
+        ImageKitError::TransformError(format!(
+            "unrecognized image format (first bytes: {}): {}", leading_hex(bytes), e
+        ))
+    })?;
+    
+    let mut reader = image::ImageReader::with_format(std::io::Cursor::new(bytes), guessed);
+    reader.limits(limits);
+    let img = reader.decode().map_err(|e| {
+        ImageKitError::TransformError(format!(
+            "failed to decode {:?} image (first bytes: {}): {}", guessed, leading_hex(bytes), e
+        ))
+    })?;
+    
+    Ok((img, output_format(guessed)))
+}
+
+/// Maps a detected `image` format to the output format that re-encodes it.
+fn output_format(format: image::ImageFormat) -> Option<ImageFormat> {
+    match format {
         image::ImageFormat::WebP => Some(ImageFormat::webp),
         image::ImageFormat::Jpeg => Some(ImageFormat::jpeg),
         image::ImageFormat::Avif => Some(ImageFormat::avif),
+        image::ImageFormat::Png => Some(ImageFormat::png),
+        image::ImageFormat::Ico => Some(ImageFormat::ico),
+        #[cfg(feature = "extra_formats")]
+        image::ImageFormat::Tiff => Some(ImageFormat::tiff),
+        #[cfg(feature = "extra_formats")]
+        image::ImageFormat::Bmp => Some(ImageFormat::bmp),
         _ => None,
-    };
+    }
+}
+
+/// Identifies already-encoded output (e.g. a cache entry) from magic bytes.
+pub fn sniff_output_format(bytes: &[u8]) -> Option<ImageFormat> {
+    output_format(image::guess_format(bytes).ok()?)
+}
+
+/// Reads the ICC color profile embedded in an encoded source, if any.
+///
+/// Only parses headers. Available wherever the `image` decoder exposes
+/// profiles (JPEG, PNG, WebP).
+pub fn source_icc_profile(bytes: &[u8]) -> Option<Vec<u8>> {
+    use image::ImageDecoder;
+
+    let reader = image::ImageReader::new(std::io::Cursor::new(bytes)).with_guessed_format().ok()?;
+    let mut decoder = reader.into_decoder().ok()?;
+    decoder.icc_profile().ok().flatten()
+}
+
+/// Reads the dimensions and pixel layout of an encoded image from its
+/// headers, without decoding pixels.
+///
+/// Returns `None` if the headers can't be read, and for sources only a full
+/// decode describes faithfully: HEIF (decoded by libheif) and ICO (where
+/// [`decode_image_with`] picks the largest entry, not `image`'s choice).
+pub fn probe_image(bytes: &[u8]) -> Option<((u32, u32), image::ColorType)> {
+    use image::ImageDecoder;
+
+    if is_heif(bytes) || is_ico(bytes) {
+        return None;
+    }
+    let reader = image::ImageReader::new(std::io::Cursor::new(bytes)).with_guessed_format().ok()?;
+    let decoder = reader.into_decoder().ok()?;
+    Some((decoder.dimensions(), decoder.color_type()))
+}
+
+/// Reads the dimensions of an encoded image without decoding pixels.
+///
+/// Falls back to the AVIF `ispe` property since `image` is built without
+/// an AVIF decoder.
+pub fn encoded_dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    let from_header = image::ImageReader::new(std::io::Cursor::new(bytes))
+        .with_guessed_format()
+        .ok()
+        .and_then(|reader| reader.into_dimensions().ok());
+    if from_header.is_some() {
+        return from_header;
+    }
+    
+    // ispe: 4-byte tag, 4-byte version/flags, then big-endian width and height
+    let pos = bytes.windows(4).position(|w| w == b"ispe")?;
+    let field = |at: usize| bytes.get(at..at + 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]));
+    Some((field(pos + 8)?, field(pos + 12)?))
+}
+
+/// Number of leading bytes included in decode error messages.
+const ERROR_PREVIEW_BYTES: usize = 16;
+
+/// Hex dump of the first few bytes, for diagnosing undecodable sources.
+fn leading_hex(bytes: &[u8]) -> String {
+    hex::encode(&bytes[..bytes.len().min(ERROR_PREVIEW_BYTES)])
+}
+
+/// Detects SVG documents, optionally preceded by a BOM, whitespace,
+/// an XML declaration, or comments.
+fn is_svg(bytes: &[u8]) -> bool {
+    let head = &bytes[..bytes.len().min(1024)];
+    let text = String::from_utf8_lossy(head);
+    let text = text.trim_start_matches('\u{feff}').trim_start();
+    (text.starts_with("<?xml") || text.starts_with("<!--") || text.starts_with("<svg") || text.starts_with("<!DOCTYPE svg"))
+        && text.contains("<svg")
+}
+
+/// Detects HEIC/HEIF containers from the ISO-BMFF `ftyp` box.
+///
+/// AVIF shares the same container, so files whose brands mention `avif`
+/// are left to the regular decoder.
+pub fn is_heif(bytes: &[u8]) -> bool {
+    if bytes.len() < 12 || &bytes[4..8] != b"ftyp" {
+        return false;
+    }
+    
+    let box_size = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+    let brands_end = box_size.clamp(12, bytes.len());
+    let major = &bytes[8..12];
+    // Compatible brands follow the 4-byte minor version
+    let compatible = bytes.get(16..brands_end).unwrap_or(&[]);
+    
+    let is_avif = major == b"avif"
+        || major == b"avis"
+        || compatible.chunks_exact(4).any(|b| b == b"avif" || b == b"avis");
     
-    Ok((img, fmt))
+    let heif_brands: [&[u8]; 10] = [
+        b"heic", b"heix", b"hevc", b"hevx", b"heim",
+        b"heis", b"hevm", b"hevs", b"mif1", b"msf1",
+    ];
+    
+    !is_avif && heif_brands.contains(&major)
+}
+
+/// Identifies the source format from magic bytes without decoding.
+///
+/// Works for formats this build can't decode (e.g. GIF), so callers can
+/// reject them by policy rather than with a decode error.
+pub fn detect_input_format(bytes: &[u8]) -> Option<InputFormat> {
+    if is_heif(bytes) {
+        return Some(InputFormat::heif);
+    }
+    match image::guess_format(bytes).ok()? {
+        image::ImageFormat::Jpeg => Some(InputFormat::jpeg),
+        image::ImageFormat::Png => Some(InputFormat::png),
+        image::ImageFormat::Gif => Some(InputFormat::gif),
+        image::ImageFormat::WebP => Some(InputFormat::webp),
+        image::ImageFormat::Avif => Some(InputFormat::avif),
+        image::ImageFormat::Bmp => Some(InputFormat::bmp),
+        image::ImageFormat::Tiff => Some(InputFormat::tiff),
+        image::ImageFormat::Ico => Some(InputFormat::ico),
+        _ => None,
+    }
+}
+
+/// Size of the ICO header preceding the entry directory.
+const ICO_HEADER_LEN: usize = 6;
+
+/// Size of one ICO directory entry.
+const ICO_ENTRY_LEN: usize = 16;
+
+/// Largest side an ICO entry can declare (stored as 0).
+pub const ICO_MAX_SIZE: u32 = 256;
+
+/// Detects ICO files: reserved 0, type 1 (icon), at least one entry and
+/// room for the whole directory.
+pub fn is_ico(bytes: &[u8]) -> bool {
+    if bytes.len() < ICO_HEADER_LEN || bytes[..4] != [0, 0, 1, 0] {
+        return false;
+    }
+    let count = u16::from_le_bytes([bytes[4], bytes[5]]) as usize;
+    count > 0 && bytes.len() >= ICO_HEADER_LEN + count * ICO_ENTRY_LEN
+}
+
+/// Directory entry of the largest image in an ICO file: most pixels, then
+/// the deepest color. Entries pointing outside the file are skipped.
+fn largest_ico_entry(bytes: &[u8]) -> Option<&[u8]> {
+    let count = u16::from_le_bytes([bytes[4], bytes[5]]) as usize;
+    let side = |b: u8| if b == 0 { ICO_MAX_SIZE } else { b as u32 };
+    (0..count)
+        .filter_map(|i| bytes.get(ICO_HEADER_LEN + i * ICO_ENTRY_LEN..ICO_HEADER_LEN + (i + 1) * ICO_ENTRY_LEN))
+        .filter(|entry| {
+            let size = u32::from_le_bytes([entry[8], entry[9], entry[10], entry[11]]) as usize;
+            let offset = u32::from_le_bytes([entry[12], entry[13], entry[14], entry[15]]) as usize;
+            offset.checked_add(size).is_some_and(|end| end <= bytes.len())
+        })
+        .max_by_key(|entry| (side(entry[0]) * side(entry[1]), u16::from_le_bytes([entry[6], entry[7]])))
+}
+
+/// Decodes the largest image of a multi-resolution ICO.
+///
+/// The chosen entry is repackaged as a single-entry ICO, so its PNG or BMP
+/// payload is decoded by `image` under `limits` like any other source.
+fn decode_ico(bytes: &[u8], limits: image::Limits) -> Result<(DynamicImage, Option<ImageFormat>), ImageKitError> {
+    let entry = largest_ico_entry(bytes)
+        .ok_or_else(|| ImageKitError::TransformError("ICO has no readable entries".into()))?;
+    let size = u32::from_le_bytes([entry[8], entry[9], entry[10], entry[11]]) as usize;
+    let offset = u32::from_le_bytes([entry[12], entry[13], entry[14], entry[15]]) as usize;
+    
+    let mut single = Vec::with_capacity(ICO_HEADER_LEN + ICO_ENTRY_LEN + size);
+    single.extend_from_slice(&[0, 0, 1, 0, 1, 0]);
+    single.extend_from_slice(&entry[..12]);
+    single.extend_from_slice(&((ICO_HEADER_LEN + ICO_ENTRY_LEN) as u32).to_le_bytes());
+    single.extend_from_slice(&bytes[offset..offset + size]);
+    
+    let mut reader = image::ImageReader::with_format(std::io::Cursor::new(single), image::ImageFormat::Ico);
+    reader.limits(limits);
+    let img = reader.decode().map_err(|e| {
+        ImageKitError::TransformError(format!("failed to decode ICO entry: {}", e))
+    })?;
+    Ok((img, Some(ImageFormat::ico)))
+}
+
+/// Decodes the primary image of a HEIC/HEIF file to RGBA.
+#[cfg(feature = "heif")]
+fn decode_heif(bytes: &[u8]) -> Result<DynamicImage, ImageKitError> {
+    use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+    
+    let err = |e: libheif_rs::HeifError| ImageKitError::TransformError(format!("HEIF decode: {}", e));
+    
+    let lib_heif = LibHeif::new();
+    let ctx = HeifContext::read_from_bytes(bytes).map_err(err)?;
+    let handle = ctx.primary_image_handle().map_err(err)?;
+    let decoded = lib_heif
+        .decode(&handle, ColorSpace::Rgb(RgbChroma::Rgba), None)
+        .map_err(err)?;
+    
+    let planes = decoded.planes();
+    let plane = planes.interleaved.ok_or_else(|| {
+        ImageKitError::TransformError("HEIF decode: missing interleaved plane".into())
+    })?;
+    
+    // Rows may be padded; copy each row without its stride padding
+    let (w, h) = (plane.width, plane.height);
+    let row_bytes = w as usize * 4;
+    let mut pixels = Vec::with_capacity(row_bytes * h as usize);
+    for row in plane.data.chunks(plane.stride).take(h as usize) {
+        pixels.extend_from_slice(&row[..row_bytes]);
+    }
+    
+    image::RgbaImage::from_raw(w, h, pixels)
+        .map(DynamicImage::ImageRgba8)
+        .ok_or_else(|| ImageKitError::TransformError("HEIF decode: invalid buffer size".into()))
+}
+
+#[cfg(not(feature = "heif"))]
+fn decode_heif(_bytes: &[u8]) -> Result<DynamicImage, ImageKitError> {
+    Err(ImageKitError::TransformError(
+        "HEIC/HEIF input requires the `heif` feature".into(),
+    ))
+}
+
+/// Reads HEIC/HEIF dimensions from the container without decoding pixels.
+#[cfg(feature = "heif")]
+pub fn heif_dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    let ctx = libheif_rs::HeifContext::read_from_bytes(bytes).ok()?;
+    let handle = ctx.primary_image_handle().ok()?;
+    Some((handle.width(), handle.height()))
+}
+
+/// Shrink factor from which [`ResizeFilters::default`] stages a downscale.
+pub const DEFAULT_STAGED_DOWNSCALE_FACTOR: u32 = 8;
+
+/// Resampling filters used by [`resize_image_with`], chosen per axis by
+/// whether that axis is being enlarged or shrunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResizeFilters {
+    pub downscale: ResizeFilter,
+    pub upscale: ResizeFilter,
+    /// When both axes shrink by at least this factor, a fast box pre-pass
+    /// first brings the image to twice the target, so `downscale` only
+    /// runs over a small image. None always resamples in one pass.
+    pub staged_downscale: Option<u32>,
+}
+
+impl Default for ResizeFilters {
+    fn default() -> Self {
+        Self {
+            downscale: ResizeFilter::default(),
+            upscale: ResizeFilter::default(),
+            staged_downscale: Some(DEFAULT_STAGED_DOWNSCALE_FACTOR),
+        }
+    }
 }
 
 /// Resizes image maintaining aspect ratio when only one dimension specified.
 ///
 /// Uses Lanczos3 resampling for high-quality output with minimal aliasing.
 /// When both dimensions omitted, returns original image unchanged.
+/// See [`resize_image_with`] to pick different up/downscaling filters.
 ///
 /// # Parameters
 /// * `img` - Source image to resize
@@ -53,7 +351,8 @@ pub fn decode_image(bytes: &[u8]) -> Result<(DynamicImage, Option<ImageFormat>),
 /// * `h` - Target height (optional)
 ///
 /// # Behavior
-/// - Both specified: Resize to exact dimensions (may distort aspect ratio)
+/// - Both specified: Fit within `w`×`h`, preserving aspect ratio
+///   ("contain"; see [`resize_fill`] for exact dimensions)
 /// - Only width: Scale height proportionally
 /// - Only height: Scale width proportionally
 /// - Neither: Return original
@@ -63,6 +362,21 @@ pub fn resize_image(
     img: DynamicImage,
     w: Option<u32>,
     h: Option<u32>,
+) -> Result<DynamicImage, ImageKitError> {
+    resize_image_with(img, w, h, ResizeFilters::default())
+}
+
+/// Like [`resize_image`], with separate filters for enlarging and shrinking.
+///
+/// Each axis uses `filters.upscale` when it grows and `filters.downscale`
+/// when it shrinks. When both axes move the same way this is one pass;
+/// otherwise width and height are resampled in two passes so each gets
+/// its own filter.
+pub fn resize_image_with(
+    img: DynamicImage,
+    w: Option<u32>,
+    h: Option<u32>,
+    filters: ResizeFilters,
 ) -> Result<DynamicImage, ImageKitError> {
     if w.is_none() && h.is_none() {
         return Ok(img);
@@ -81,20 +395,689 @@ pub fn resize_image(
         (orig_h as f32 * ratio).round() as u32
     });
     
-    // Lanczos3 provides best quality for downsampling
-    Ok(img.resize(
-        target_w.max(1),
-        target_h.max(1),
-        image::imageops::FilterType::Lanczos3,
-    ))
+    // Fit within the box like `DynamicImage::resize`
+    let ratio = (target_w.max(1) as f64 / orig_w as f64).min(target_h.max(1) as f64 / orig_h as f64);
+    let new_w = ((orig_w as f64 * ratio).round() as u32).max(1);
+    let new_h = ((orig_h as f64 * ratio).round() as u32).max(1);
+    
+    Ok(resize_exact_with(img, new_w, new_h, filters))
+}
+
+/// Whether `img` already fits inside the `w`×`h` box; an omitted side
+/// doesn't constrain.
+pub fn fits_within(img: &DynamicImage, w: Option<u32>, h: Option<u32>) -> bool {
+    w.map_or(true, |w| img.width() <= w) && h.map_or(true, |h| img.height() <= h)
+}
+
+/// Stretches to exactly `w`×`h` (`fit=fill`), ignoring aspect ratio.
+///
+/// Filters are picked per axis as in [`resize_image_with`]. Dimensions
+/// are clamped to at least 1 pixel.
+pub fn resize_fill(
+    img: DynamicImage,
+    w: u32,
+    h: u32,
+    filters: ResizeFilters,
+) -> Result<DynamicImage, ImageKitError> {
+    Ok(resize_exact_with(img, w.max(1), h.max(1), filters))
+}
+
+/// Resamples to exactly `new_w`×`new_h`, choosing a filter per axis.
+fn resize_exact_with(img: DynamicImage, new_w: u32, new_h: u32, filters: ResizeFilters) -> DynamicImage {
+    let (orig_w, orig_h) = img.dimensions();
+    if let Some(factor) = filters.staged_downscale.filter(|f| *f >= 2) {
+        if orig_w / new_w >= factor && orig_h / new_h >= factor {
+            // Area-averaging thumbnail pass; twice the target leaves the
+            // final filter enough pixels to shape the result
+            let img = img.thumbnail_exact(new_w * 2, new_h * 2);
+            return resize_exact_with(img, new_w, new_h, ResizeFilters { staged_downscale: None, ..filters });
+        }
+    }
+    let filter_for = |from: u32, to: u32| {
+        if to > from { filters.upscale } else { filters.downscale }
+    };
+    let filter_w = filter_for(orig_w, new_w);
+    let filter_h = filter_for(orig_h, new_h);
+    
+    if filter_w == filter_h {
+        return img.resize_exact(new_w, new_h, filter_w.into());
+    }
+    img.resize_exact(new_w, orig_h, filter_w.into())
+        .resize_exact(new_w, new_h, filter_h.into())
+}
+
+/// Centers `img` on an opaque `w`×`h` canvas filled with `color` (`fit=pad`).
+///
+/// `img` is expected to fit already (see [`resize_image_with`]); anything
+/// larger is clipped. Translucent pixels are composited over `color`, and
+/// the result keeps an alpha channel only if `img` had one.
+pub fn pad_to_canvas(img: DynamicImage, w: u32, h: u32, color: [u8; 3]) -> DynamicImage {
+    let (w, h) = (w.max(1), h.max(1));
+    let has_alpha = img.color().has_alpha();
+    let mut canvas = image::RgbaImage::from_pixel(w, h, image::Rgba([color[0], color[1], color[2], 255]));
+    let x = (w as i64 - img.width() as i64) / 2;
+    let y = (h as i64 - img.height() as i64) / 2;
+    image::imageops::overlay(&mut canvas, &img.to_rgba8(), x, y);
+
+    let padded = DynamicImage::ImageRgba8(canvas);
+    if has_alpha { padded } else { DynamicImage::ImageRgb8(padded.to_rgb8()) }
+}
+
+/// Largest `angle` magnitude accepted by `/img`, in degrees.
+pub const MAX_ANGLE: f32 = 360.0;
+
+/// Rotates `img` clockwise by `angle` degrees about its center.
+///
+/// The canvas grows to the rotated bounds, so nothing is clipped. The
+/// uncovered corners are transparent, or filled with `bg` when given;
+/// with a fill the result keeps an alpha channel only if `img` had one.
+pub fn rotate_arbitrary(img: DynamicImage, angle: f32, bg: Option<[u8; 3]>) -> DynamicImage {
+    if angle % 360.0 == 0.0 {
+        return img;
+    }
+    let has_alpha = img.color().has_alpha();
+    let (w, h) = img.dimensions();
+    let (sin, cos) = angle.to_radians().sin_cos();
+    // Shaved slightly so float error at right angles doesn't add a pixel
+    let fit = |extent: f32| ((extent - 1e-3).ceil() as u32).max(1);
+    let new_w = fit(w as f32 * cos.abs() + h as f32 * sin.abs());
+    let new_h = fit(w as f32 * sin.abs() + h as f32 * cos.abs());
+
+    // Rotated on a canvas holding both the source and the rotated bounds,
+    // then cropped to the latter
+    let (cw, ch) = (w.max(new_w), h.max(new_h));
+    let mut canvas = image::RgbaImage::new(cw, ch);
+    image::imageops::overlay(&mut canvas, &img.to_rgba8(), ((cw - w) / 2) as i64, ((ch - h) / 2) as i64);
+    let rotated = imageproc::geometric_transformations::rotate_about_center(
+        &canvas,
+        angle.to_radians(),
+        imageproc::geometric_transformations::Interpolation::Bilinear,
+        image::Rgba([0, 0, 0, 0]),
+    );
+    let rotated = image::imageops::crop_imm(&rotated, (cw - new_w) / 2, (ch - new_h) / 2, new_w, new_h).to_image();
+
+    match bg {
+        Some(color) => {
+            let mut filled = image::RgbaImage::from_pixel(new_w, new_h, image::Rgba([color[0], color[1], color[2], 255]));
+            image::imageops::overlay(&mut filled, &rotated, 0, 0);
+            let filled = DynamicImage::ImageRgba8(filled);
+            if has_alpha { filled } else { DynamicImage::ImageRgb8(filled.to_rgb8()) }
+        }
+        None => DynamicImage::ImageRgba8(rotated),
+    }
+}
+
+/// Allowed `pixelate` block sizes in pixels.
+pub const MIN_PIXELATE_BLOCK: u32 = 2;
+pub const MAX_PIXELATE_BLOCK: u32 = 256;
+
+/// Pixelates (mosaics) an image with square blocks of `block` pixels.
+///
+/// Downscales by the block factor with nearest-neighbor sampling, then
+/// scales back up to the original dimensions, so every block collapses to
+/// a single color. Used for privacy redaction; output dimensions are
+/// unchanged.
+pub fn pixelate_image(img: DynamicImage, block: u32) -> DynamicImage {
+    let block = block.max(1);
+    let (w, h) = img.dimensions();
+    let small_w = w.div_ceil(block).max(1);
+    let small_h = h.div_ceil(block).max(1);
+    
+    img.resize_exact(small_w, small_h, image::imageops::FilterType::Nearest)
+        .resize_exact(w, h, image::imageops::FilterType::Nearest)
+}
+
+/// Produces a color negative. Alpha is left untouched.
+pub fn invert_image(mut img: DynamicImage) -> DynamicImage {
+    // `image` inverts color channels only, never alpha
+    img.invert();
+    img
+}
+
+/// Applies the standard sepia color matrix for a warm, vintage tone.
+/// Alpha is left untouched.
+pub fn sepia_image(img: DynamicImage) -> DynamicImage {
+    const MATRIX: [[f32; 3]; 3] = [
+        [0.393, 0.769, 0.189],
+        [0.349, 0.686, 0.168],
+        [0.272, 0.534, 0.131],
+    ];
+    let sepia = |rgb: [u8; 3]| -> [u8; 3] {
+        let [r, g, b] = rgb.map(|v| v as f32);
+        MATRIX.map(|row| (row[0] * r + row[1] * g + row[2] * b).round().min(255.0) as u8)
+    };
+    if img.color().has_alpha() {
+        let mut rgba = img.to_rgba8();
+        for px in rgba.pixels_mut() {
+            let [r, g, b] = sepia([px[0], px[1], px[2]]);
+            px.0 = [r, g, b, px[3]];
+        }
+        DynamicImage::ImageRgba8(rgba)
+    } else {
+        let mut rgb = img.to_rgb8();
+        for px in rgb.pixels_mut() {
+            px.0 = sepia(px.0);
+        }
+        DynamicImage::ImageRgb8(rgb)
+    }
+}
+
+/// Smallest `gamma` accepted by `/img`.
+pub const MIN_GAMMA: f32 = 0.1;
+/// Largest `gamma` accepted by `/img`.
+pub const MAX_GAMMA: f32 = 3.0;
+
+/// Applies the power curve `out = in^gamma` to each color channel.
+///
+/// Values below 1 brighten midtones and above 1 darken them; black and
+/// white stay fixed. Uses a 256-entry lookup table, so the result is 8-bit.
+/// Alpha is left untouched.
+pub fn gamma_image(img: DynamicImage, gamma: f32) -> DynamicImage {
+    let lut: [u8; 256] = std::array::from_fn(|i| ((i as f32 / 255.0).powf(gamma) * 255.0).round() as u8);
+    if img.color().has_alpha() {
+        let mut rgba = img.to_rgba8();
+        for px in rgba.pixels_mut() {
+            for v in &mut px.0[..3] {
+                *v = lut[*v as usize];
+            }
+        }
+        DynamicImage::ImageRgba8(rgba)
+    } else {
+        let mut rgb = img.to_rgb8();
+        for px in rgb.pixels_mut() {
+            px.0 = px.0.map(|v| lut[v as usize]);
+        }
+        DynamicImage::ImageRgb8(rgb)
+    }
+}
+
+/// Multiplies alpha by `factor` (clamped to 0.0..=1.0), returning RGBA.
+///
+/// JPEG can't carry the result; `/img` switches such requests to WebP.
+pub fn set_opacity(img: DynamicImage, factor: f32) -> DynamicImage {
+    let factor = factor.clamp(0.0, 1.0);
+    let mut rgba = img.to_rgba8();
+    for px in rgba.pixels_mut() {
+        px[3] = (px[3] as f32 * factor).round() as u8;
+    }
+    DynamicImage::ImageRgba8(rgba)
+}
+
+/// Parses a `RRGGBB` hex color, with or without a leading `#`.
+pub fn parse_hex_color(s: &str) -> Option<[u8; 3]> {
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+/// Recolors an image as a monochrome ramp through `color`.
+///
+/// Each pixel's luminance (Rec. 709) is mapped onto black → `color` →
+/// white, with `color` itself placed at its own luminance. Shadows and
+/// highlights therefore stay black and white, and pure white or pure black
+/// tints degrade to plain grayscale. Alpha is preserved.
+pub fn tint_image(img: DynamicImage, color: [u8; 3]) -> DynamicImage {
+    let tint = color.map(|c| c as f32 / 255.0);
+    let tint_luma = luminance(tint[0], tint[1], tint[2]);
+    
+    let mut rgba = img.to_rgba8();
+    for px in rgba.pixels_mut() {
+        let l = luminance(px[0] as f32 / 255.0, px[1] as f32 / 255.0, px[2] as f32 / 255.0);
+        for (c, t) in tint.iter().enumerate() {
+            let v = if l <= tint_luma {
+                // Black → tint; a black tint has no lower segment
+                if tint_luma > 0.0 { t * l / tint_luma } else { 0.0 }
+            } else {
+                // Tint → white
+                t + (1.0 - t) * (l - tint_luma) / (1.0 - tint_luma)
+            };
+            px[c] = (v * 255.0).round().clamp(0.0, 255.0) as u8;
+        }
+    }
+    DynamicImage::ImageRgba8(rgba)
+}
+
+/// Ring thickness used when `ring` omits it, and the accepted range.
+pub const DEFAULT_RING_WIDTH: u32 = 4;
+pub const MAX_RING_WIDTH: u32 = 64;
+
+/// Parses a `ring` value: `RRGGBB` or `RRGGBB,THICKNESS`.
+pub fn parse_ring(s: &str) -> Option<([u8; 3], u32)> {
+    let (color, width) = match s.split_once(',') {
+        Some((color, width)) => (color, width.parse().ok()?),
+        None => (s, DEFAULT_RING_WIDTH),
+    };
+    if !(1..=MAX_RING_WIDTH).contains(&width) {
+        return None;
+    }
+    Some((parse_hex_color(color)?, width))
+}
+
+/// Draws an anti-aliased ring of `width` pixels just inside the circle
+/// inscribed in the image, for circular avatars.
+///
+/// Coverage is computed from each pixel center's distance to the ring's
+/// edges, so the ring blends smoothly into the image underneath.
+pub fn draw_ring(img: DynamicImage, color: [u8; 3], width: u32) -> DynamicImage {
+    let mut rgba = img.to_rgba8();
+    let (w, h) = rgba.dimensions();
+    let (cx, cy) = (w as f32 / 2.0, h as f32 / 2.0);
+    let outer = w.min(h) as f32 / 2.0;
+    let inner = outer - width as f32;
+    let ring = image::Rgba([color[0], color[1], color[2], 255]);
+    
+    for (x, y, px) in rgba.enumerate_pixels_mut() {
+        let d = ((x as f32 + 0.5 - cx).powi(2) + (y as f32 + 0.5 - cy).powi(2)).sqrt();
+        let coverage = ((outer - d).min(d - inner) + 0.5).clamp(0.0, 1.0);
+        if coverage > 0.0 {
+            *px = imageproc::pixelops::interpolate(ring, *px, coverage);
+        }
+    }
+    DynamicImage::ImageRgba8(rgba)
+}
+
+/// Draws a filled circular badge on the inscribed circle's lower-right edge
+/// (the usual "online" / notification dot position).
+pub fn draw_badge(img: DynamicImage, color: [u8; 3]) -> DynamicImage {
+    let mut rgba = img.to_rgba8();
+    let (w, h) = rgba.dimensions();
+    let outer = w.min(h) as f32 / 2.0;
+    let radius = (outer / 4.0).max(1.0);
+    // 45° down-right from the center, pulled in so the badge stays in frame
+    let offset = (outer - radius) * std::f32::consts::FRAC_1_SQRT_2;
+    let center = (
+        (w as f32 / 2.0 + offset).round() as i32,
+        (h as f32 / 2.0 + offset).round() as i32,
+    );
+    
+    imageproc::drawing::draw_filled_circle_mut(
+        &mut rgba,
+        center,
+        radius.round() as i32,
+        image::Rgba([color[0], color[1], color[2], 255]),
+    );
+    DynamicImage::ImageRgba8(rgba)
+}
+
+/// Font used for `text`: DejaVu Sans, bundled so output doesn't depend on
+/// the host's fonts (license in `assets/fonts`).
+const TEXT_FONT_BYTES: &[u8] = include_bytes!("../assets/fonts/DejaVuSans.ttf");
+
+/// Quality range `auto_quality` maps complexity onto, per format: flat
+/// graphics get the low end, busy photos the high end.
+fn auto_quality_range(format: ImageFormat) -> Option<(f32, f32)> {
+    match format {
+        ImageFormat::jpeg => Some((60.0, 90.0)),
+        ImageFormat::webp => Some((55.0, 88.0)),
+        ImageFormat::avif => Some((35.0, 70.0)),
+        // Lossless; quality is ignored
+        ImageFormat::png | ImageFormat::ico => None,
+        #[cfg(feature = "extra_formats")]
+        ImageFormat::tiff | ImageFormat::bmp => None,
+    }
+}
+
+/// Longest side of the thumbnail `auto_quality` measures.
+const COMPLEXITY_SAMPLE_SIZE: u32 = 256;
+
+/// Mean absolute luma gradient at which an image counts as fully complex.
+/// Smooth photos sit around a third of this; noise and fine texture reach it.
+const COMPLEXITY_SATURATION: f32 = 24.0;
+
+/// Picks an encode quality for `img` from its visual complexity (`q=auto`).
+///
+/// Complexity is the mean absolute luma difference between neighboring
+/// pixels of a small thumbnail: near zero for flat graphics, whose
+/// artifacts hide at low quality, and high for detailed photos, which
+/// need more bits. The result is mapped onto a per-format range, since
+/// encoders' quality scales differ.
+pub fn auto_quality(img: &DynamicImage, format: ImageFormat) -> u8 {
+    let Some((low, high)) = auto_quality_range(format) else {
+        return DEFAULT_QUALITY;
+    };
+    let sample = if img.width().max(img.height()) > COMPLEXITY_SAMPLE_SIZE {
+        img.thumbnail(COMPLEXITY_SAMPLE_SIZE, COMPLEXITY_SAMPLE_SIZE).to_luma8()
+    } else {
+        img.to_luma8()
+    };
+    let (w, h) = sample.dimensions();
+    let mut total = 0u64;
+    let mut count = 0u64;
+    for y in 0..h {
+        for x in 0..w {
+            let v = sample.get_pixel(x, y)[0] as i32;
+            if x + 1 < w {
+                total += (v - sample.get_pixel(x + 1, y)[0] as i32).unsigned_abs() as u64;
+                count += 1;
+            }
+            if y + 1 < h {
+                total += (v - sample.get_pixel(x, y + 1)[0] as i32).unsigned_abs() as u64;
+                count += 1;
+            }
+        }
+    }
+    let gradient = if count == 0 { 0.0 } else { total as f32 / count as f32 };
+    let complexity = (gradient / COMPLEXITY_SATURATION).min(1.0);
+    (low + (high - low) * complexity).round() as u8
+}
+
+/// Longest `text` accepted, in characters.
+pub const MAX_TEXT_LEN: usize = 100;
+/// Text height in pixels used when `text_size` is omitted, and the accepted range.
+pub const DEFAULT_TEXT_SIZE: u32 = 24;
+pub const MIN_TEXT_SIZE: u32 = 6;
+pub const MAX_TEXT_SIZE: u32 = 256;
+
+lazy_static::lazy_static! {
+    static ref TEXT_FONT: ab_glyph::FontRef<'static> =
+        ab_glyph::FontRef::try_from_slice(TEXT_FONT_BYTES).expect("bundled font is valid");
+}
+
+/// Cleans a `text` value for drawing: control characters are dropped and
+/// whitespace trimmed. `None` if nothing is left or it exceeds [`MAX_TEXT_LEN`].
+pub fn sanitize_text(text: &str) -> Option<String> {
+    let cleaned: String = text.chars().filter(|c| !c.is_control()).collect();
+    let cleaned = cleaned.trim();
+    if cleaned.is_empty() || cleaned.chars().count() > MAX_TEXT_LEN {
+        return None;
+    }
+    Some(cleaned.to_string())
+}
+
+/// Stamps `text` at `position`, `size` pixels tall, in the bundled font.
+///
+/// Glyphs are anti-aliased over the image; text that doesn't fit is clipped
+/// at the edges rather than wrapped.
+pub fn draw_text(img: DynamicImage, text: &str, position: TextPosition, size: u32, color: [u8; 3]) -> DynamicImage {
+    let mut rgba = img.to_rgba8();
+    let (w, h) = rgba.dimensions();
+    let scale = ab_glyph::PxScale::from(size as f32);
+    let (text_w, text_h) = imageproc::drawing::text_size(scale, &*TEXT_FONT, text);
+    let (w, h, text_w, text_h) = (w as i32, h as i32, text_w as i32, text_h as i32);
+    let margin = size as i32 / 2;
+
+    let left = margin;
+    let right = w - text_w - margin;
+    let h_center = (w - text_w) / 2;
+    let top = margin;
+    let bottom = h - text_h - margin;
+    let v_center = (h - text_h) / 2;
+    let (x, y) = match position {
+        TextPosition::TopLeft => (left, top),
+        TextPosition::Top => (h_center, top),
+        TextPosition::TopRight => (right, top),
+        TextPosition::Center => (h_center, v_center),
+        TextPosition::BottomLeft => (left, bottom),
+        TextPosition::Bottom => (h_center, bottom),
+        TextPosition::BottomRight => (right, bottom),
+    };
+
+    imageproc::drawing::draw_text_mut(
+        &mut rgba,
+        image::Rgba([color[0], color[1], color[2], 255]),
+        x,
+        y,
+        scale,
+        &*TEXT_FONT,
+        text,
+    );
+    DynamicImage::ImageRgba8(rgba)
+}
+
+fn luminance(r: f32, g: f32, b: f32) -> f32 {
+    0.2126 * r + 0.7152 * g + 0.0722 * b
+}
+
+/// Scales and crops an image so it exactly covers a `w`×`h` box.
+///
+/// The image is scaled (Lanczos3) until both dimensions cover the target
+/// box, then the overflowing axis is cropped. The crop window is positioned
+/// so the focal point stays as close to the window center as the image
+/// bounds allow; `(0.5, 0.5)` is a plain center crop.
+///
+/// # Parameters
+/// * `img` - Source image
+/// * `w`, `h` - Exact output dimensions
+/// * `focal` - Focal point as fractions `(x, y)` of the source, each in `0.0..=1.0`
+pub fn resize_cover(
+    img: DynamicImage,
+    w: u32,
+    h: u32,
+    focal: (f32, f32),
+) -> Result<DynamicImage, ImageKitError> {
+    let (w, h) = (w.max(1), h.max(1));
+    let scaled = scale_to_cover(img, w, h);
+    let (x, y) = focal_crop_origin(scaled.dimensions(), (w, h), focal);
+
+    Ok(scaled.crop_imm(x, y, w, h))
+}
+
+/// Scales and crops an image to exactly `w`×`h`, anchoring the crop by gravity.
+///
+/// Compass gravities pin the window to that edge; `Smart` slides the window
+/// along the cropped axis and keeps the position with the highest edge
+/// energy (sum of luma gradients), which tends to keep the detailed subject
+/// rather than flat background.
+pub fn crop_with_gravity(
+    img: DynamicImage,
+    w: u32,
+    h: u32,
+    gravity: Gravity,
+) -> Result<DynamicImage, ImageKitError> {
+    match gravity_focal(gravity) {
+        Some(focal) => resize_cover(img, w, h, focal),
+        None => {
+            let (w, h) = (w.max(1), h.max(1));
+            let scaled = scale_to_cover(img, w, h);
+            let (x, y) = smart_crop_origin(&scaled, w, h);
+            Ok(scaled.crop_imm(x, y, w, h))
+        }
+    }
+}
+
+/// Focal point a compass gravity pins the crop window to; `None` for
+/// `Smart`, which depends on the pixels.
+fn gravity_focal(gravity: Gravity) -> Option<(f32, f32)> {
+    match gravity {
+        Gravity::Center => Some((0.5, 0.5)),
+        Gravity::North => Some((0.5, 0.0)),
+        Gravity::South => Some((0.5, 1.0)),
+        Gravity::East => Some((1.0, 0.5)),
+        Gravity::West => Some((0.0, 0.5)),
+        Gravity::Smart => None,
+    }
+}
+
+/// Scales an image (Lanczos3) so it fully covers a `w`×`h` box.
+fn scale_to_cover(img: DynamicImage, w: u32, h: u32) -> DynamicImage {
+    let (orig_w, orig_h) = img.dimensions();
+
+    // Scale by the larger ratio so the box is fully covered
+    let scale = (w as f32 / orig_w as f32).max(h as f32 / orig_h as f32);
+    let scaled_w = ((orig_w as f32 * scale).round() as u32).max(w);
+    let scaled_h = ((orig_h as f32 * scale).round() as u32).max(h);
+
+    img.resize_exact(scaled_w, scaled_h, image::imageops::FilterType::Lanczos3)
+}
+
+/// Finds the crop window origin with the most edge energy.
+///
+/// Cover scaling leaves at most one axis overflowing, so only windows along
+/// that axis are candidates. Energy is summed per column (or row) once and
+/// windows are scored with a sliding sum.
+fn smart_crop_origin(img: &DynamicImage, w: u32, h: u32) -> (u32, u32) {
+    let luma = img.to_luma8();
+    let (img_w, img_h) = luma.dimensions();
+    let horizontal = img_w > w;
+    let lines = if horizontal { img_w } else { img_h };
+    let window = if horizontal { w } else { h };
+
+    if lines <= window {
+        return (0, 0);
+    }
+
+    // Edge energy per column (horizontal overflow) or row (vertical overflow)
+    let mut energy = vec![0u64; lines as usize];
+    for y in 0..img_h {
+        for x in 0..img_w {
+            let p = luma.get_pixel(x, y)[0] as i32;
+            let dx = if x + 1 < img_w { (luma.get_pixel(x + 1, y)[0] as i32 - p).unsigned_abs() } else { 0 };
+            let dy = if y + 1 < img_h { (luma.get_pixel(x, y + 1)[0] as i32 - p).unsigned_abs() } else { 0 };
+            let line = if horizontal { x } else { y };
+            energy[line as usize] += (dx + dy) as u64;
+        }
+    }
+
+    // Sliding window sum; ties keep the earliest (closest to origin) window
+    let window = window as usize;
+    let mut sum: u64 = energy[..window].iter().sum();
+    let (mut best, mut best_offset) = (sum, 0usize);
+    for offset in 1..=(energy.len() - window) {
+        sum = sum + energy[offset + window - 1] - energy[offset - 1];
+        if sum > best {
+            best = sum;
+            best_offset = offset;
+        }
+    }
+
+    if horizontal { (best_offset as u32, 0) } else { (0, best_offset as u32) }
+}
+
+/// Computes the top-left corner of a crop window centered on a focal point.
+///
+/// The window is clamped to the image bounds, so a focal point near an edge
+/// shifts the window toward that edge rather than outside the image.
+pub fn focal_crop_origin(image: (u32, u32), window: (u32, u32), focal: (f32, f32)) -> (u32, u32) {
+    let axis = |size: u32, win: u32, f: f32| -> u32 {
+        let max_offset = size.saturating_sub(win);
+        let centered = f.clamp(0.0, 1.0) * size as f32 - win as f32 / 2.0;
+        (centered.round().max(0.0) as u32).min(max_offset)
+    };
+    (
+        axis(image.0, window.0, focal.0),
+        axis(image.1, window.1, focal.1),
+    )
+}
+
+/// A `crop` region, cut from the source before any resizing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Crop {
+    /// `x,y,w,h`: an explicit rectangle in source pixels
+    Rect { x: u32, y: u32, w: u32, h: u32 },
+    /// `WxH[,gravity]`: a `w`×`h` window placed like a `fit=cover` crop
+    Anchored { w: u32, h: u32, gravity: Gravity },
+}
+
+/// Parses a `crop` value: `x,y,w,h`, or `WxH` with an optional gravity
+/// (default `center`), e.g. `400x400,north`. Sizes must be non-zero.
+pub fn parse_crop(s: &str) -> Option<Crop> {
+    let parts: Vec<&str> = s.split(',').map(str::trim).collect();
+    match parts.as_slice() {
+        [x, y, w, h] => {
+            let (x, y, w, h) = (x.parse().ok()?, y.parse().ok()?, w.parse().ok()?, h.parse().ok()?);
+            (w > 0 && h > 0).then_some(Crop::Rect { x, y, w, h })
+        }
+        [size] | [size, _] => {
+            let (w, h) = size.split_once(['x', 'X'])?;
+            let (w, h): (u32, u32) = (w.parse().ok()?, h.parse().ok()?);
+            let gravity = match parts.get(1) {
+                Some(gravity) => gravity.parse().ok()?,
+                None => Gravity::Center,
+            };
+            (w > 0 && h > 0).then_some(Crop::Anchored { w, h, gravity })
+        }
+        _ => None,
+    }
+}
+
+/// The `(x, y, w, h)` rectangle `crop` selects from `img`, clipped to its
+/// bounds; `None` when it lies entirely outside the image.
+///
+/// An anchored window larger than the image shrinks to fit. Compass
+/// gravities position it like [`crop_with_gravity`], without scaling;
+/// `Smart` picks the column, then the row, with the most edge energy.
+pub fn crop_rect(img: &DynamicImage, crop: Crop) -> Option<(u32, u32, u32, u32)> {
+    let (img_w, img_h) = img.dimensions();
+    match crop {
+        Crop::Rect { x, y, w, h } => {
+            if x >= img_w || y >= img_h {
+                return None;
+            }
+            Some((x, y, w.min(img_w - x), h.min(img_h - y)))
+        }
+        Crop::Anchored { w, h, gravity } => {
+            let (w, h) = (w.min(img_w), h.min(img_h));
+            let (x, y) = match gravity_focal(gravity) {
+                Some(focal) => focal_crop_origin((img_w, img_h), (w, h), focal),
+                None => {
+                    let (x, _) = smart_crop_origin(img, w, img_h);
+                    let (_, y) = smart_crop_origin(&img.crop_imm(x, 0, w, img_h), w, h);
+                    (x, y)
+                }
+            };
+            Some((x, y, w, h))
+        }
+    }
+}
+
+/// Cuts the [`crop_rect`] of `crop` out of `img`.
+///
+/// # Errors
+/// Returns `ImageKitError::InvalidArgument` if the region lies outside the image.
+pub fn crop_image(img: DynamicImage, crop: Crop) -> Result<DynamicImage, ImageKitError> {
+    let (x, y, w, h) = crop_rect(&img, crop).ok_or_else(|| {
+        ImageKitError::InvalidArgument(format!("crop lies outside the {}x{} image", img.width(), img.height()))
+    })?;
+    Ok(img.crop_imm(x, y, w, h))
+}
+
+/// Format-specific encoder settings beyond quality.
+///
+/// `Default` reproduces the behavior of [`encode_image`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncodeOptions {
+    /// AVIF encoder speed, 0 (slowest, smallest output) to 10 (fastest).
+    /// The encoder treats 0 as its slowest supported setting.
+    pub avif_speed: u8,
+    
+    /// Encode WebP losslessly from RGBA (quality is ignored, alpha is kept).
+    pub webp_lossless: bool,
+    
+    /// CICP color signaling for AVIF output.
+    pub avif_colorspace: AvifColorSpace,
+    
+    /// Chroma subsampling for JPEG output; None keeps the `image` encoder.
+    pub jpeg_chroma: Option<ChromaSubsampling>,
+    
+    /// Bits per channel of AVIF output, 8 or 10. 10 keeps the gradations
+    /// of HDR/wide-gamut sources; if the encoder can't produce it, 8-bit
+    /// is written instead (see [`avif_bit_depth`]).
+    pub avif_depth: u8,
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        Self {
+            avif_speed: DEFAULT_AVIF_SPEED,
+            webp_lossless: false,
+            avif_colorspace: AvifColorSpace::Srgb,
+            jpeg_chroma: None,
+            avif_depth: 8,
+        }
+    }
 }
 
 /// Encodes image to specified format with quality control.
 ///
 /// Format-specific encoding strategies:
 /// - **JPEG**: RGB color space, DCT-based lossy compression
-/// - **WebP**: RGB lossy encoding via libwebp
+/// - **WebP**: RGB lossy encoding via libwebp (RGBA lossless via [`encode_image_with`])
 /// - **AVIF**: RGBA with AV1 compression (slowest, best compression)
+/// - **PNG**: lossless, RGBA only when the source has alpha; `quality` is ignored
+/// - **TIFF**, **BMP** (`extra_formats` feature): uncompressed, RGBA only when the source has alpha; `quality` is ignored
 ///
 /// # Parameters
 /// * `img` - Image to encode
@@ -114,10 +1097,44 @@ pub fn encode_image(
     img: &DynamicImage,
     fmt: ImageFormat,
     quality: u8,
+) -> Result<Vec<u8>, ImageKitError> {
+    encode_image_with(img, fmt, quality, &EncodeOptions::default())
+}
+
+/// Encodes image like [`encode_image`], with explicit encoder settings.
+///
+/// # Performance
+/// AVIF speed dominates AVIF latency: speed 10 is typically an order of
+/// magnitude faster than speed 0-2 at the cost of noticeably larger files.
+/// Use low speeds for offline/batch encodes and high speeds on the request path.
+pub fn encode_image_with(
+    img: &DynamicImage,
+    fmt: ImageFormat,
+    quality: u8,
+    options: &EncodeOptions,
 ) -> Result<Vec<u8>, ImageKitError> {
     let mut out = Vec::new();
     
     match fmt {
+        ImageFormat::jpeg if options.jpeg_chroma.is_some() => {
+            let q = quality.clamp(1, 100);
+            let rgb = img.to_rgb8();
+            let (w, h) = rgb.dimensions();
+            let (w, h) = match (u16::try_from(w), u16::try_from(h)) {
+                (Ok(w), Ok(h)) => (w, h),
+                _ => return Err(ImageKitError::TransformError(format!("{}x{} is too large for JPEG", w, h))),
+            };
+            // Sampling factors are luma samples per chroma sample, horizontally then vertically
+            let sampling = match options.jpeg_chroma {
+                Some(ChromaSubsampling::Cs444) => jpeg_encoder::SamplingFactor::F_1_1,
+                Some(ChromaSubsampling::Cs422) => jpeg_encoder::SamplingFactor::F_2_1,
+                _ => jpeg_encoder::SamplingFactor::F_2_2,
+            };
+            let mut enc = jpeg_encoder::Encoder::new(&mut out, q);
+            enc.set_sampling_factor(sampling);
+            enc.encode(rgb.as_raw(), w, h, jpeg_encoder::ColorType::Rgb)
+                .map_err(|e| ImageKitError::TransformError(e.to_string()))?;
+        }
         ImageFormat::jpeg => {
             let q = quality.clamp(1, 100);
             let rgb = img.to_rgb8();
@@ -126,25 +1143,468 @@ pub fn encode_image(
             enc.write_image(rgb.as_raw(), w, h, ExtendedColorType::Rgb8)
                 .map_err(|e| ImageKitError::TransformError(e.to_string()))?;
         }
+        ImageFormat::webp if options.webp_lossless => {
+            // Lossless path keeps alpha, so encode from RGBA
+            let rgba = img.to_rgba8();
+            let (w, h) = rgba.dimensions();
+            
+            let encoder = webp::Encoder::from_rgba(rgba.as_raw(), w, h);
+            let encoded_webp = encoder.encode_lossless();
+            out.extend_from_slice(&encoded_webp);
+        }
         ImageFormat::webp => {
             let q = quality.clamp(1, 100) as f32;
-            let rgb = img.to_rgb8();
-            let (w, h) = rgb.dimensions();
-            
-            let encoder = webp::Encoder::from_rgb(rgb.as_raw(), w, h);
-            let encoded_webp = encoder.encode(q);
+            // Keep alpha only when something is actually transparent
+            let rgba = img.color().has_alpha().then(|| img.to_rgba8())
+                .filter(|rgba| rgba.pixels().any(|px| px[3] < u8::MAX));
+            let encoded_webp = match rgba {
+                Some(rgba) => webp::Encoder::from_rgba(rgba.as_raw(), rgba.width(), rgba.height()).encode(q),
+                None => {
+                    let rgb = img.to_rgb8();
+                    webp::Encoder::from_rgb(rgb.as_raw(), rgb.width(), rgb.height()).encode(q)
+                }
+            };
             out.extend_from_slice(&encoded_webp);
         }
+        ImageFormat::avif if options.avif_colorspace != AvifColorSpace::Srgb || options.avif_depth == 10 => {
+            out = encode_avif_cicp(img, quality, options)?;
+        }
         ImageFormat::avif => {
             let q = quality.clamp(1, 100);
             let rgba = img.to_rgba8();
             let (w, h) = rgba.dimensions();
-            // Speed 4 balances encoding time and compression ratio
-            let enc = AvifEncoder::new_with_speed_quality(&mut out, 4, q);
+            // Encoder accepts speeds 1-10; 0 is treated as the slowest setting
+            let speed = options.avif_speed.clamp(1, 10);
+            let enc = AvifEncoder::new_with_speed_quality(&mut out, speed, q);
             enc.write_image(rgba.as_raw(), w, h, ExtendedColorType::Rgba8)
                 .map_err(|e| ImageKitError::TransformError(e.to_string()))?;
         }
+        ImageFormat::png => {
+            let enc = PngEncoder::new(&mut out);
+            let written = if img.color().has_alpha() {
+                let rgba = img.to_rgba8();
+                enc.write_image(rgba.as_raw(), rgba.width(), rgba.height(), ExtendedColorType::Rgba8)
+            } else {
+                let rgb = img.to_rgb8();
+                enc.write_image(rgb.as_raw(), rgb.width(), rgb.height(), ExtendedColorType::Rgb8)
+            };
+            written.map_err(|e| ImageKitError::TransformError(e.to_string()))?;
+        }
+        // One PNG-compressed entry; larger images are scaled down to fit
+        ImageFormat::ico => {
+            let scaled;
+            let img = if img.width() > ICO_MAX_SIZE || img.height() > ICO_MAX_SIZE {
+                scaled = img.resize(ICO_MAX_SIZE, ICO_MAX_SIZE, image::imageops::FilterType::Lanczos3);
+                &scaled
+            } else {
+                img
+            };
+            let rgba = img.to_rgba8();
+            image::codecs::ico::IcoEncoder::new(&mut out)
+                .write_image(rgba.as_raw(), rgba.width(), rgba.height(), ExtendedColorType::Rgba8)
+                .map_err(|e| ImageKitError::TransformError(e.to_string()))?;
+        }
+        // Uncompressed, so quality is ignored; alpha is kept when present
+        #[cfg(feature = "extra_formats")]
+        ImageFormat::tiff => {
+            // The TIFF writer seeks back to patch offsets
+            let mut cursor = std::io::Cursor::new(Vec::new());
+            let enc = image::codecs::tiff::TiffEncoder::new(&mut cursor);
+            let written = if img.color().has_alpha() {
+                let rgba = img.to_rgba8();
+                enc.write_image(rgba.as_raw(), rgba.width(), rgba.height(), ExtendedColorType::Rgba8)
+            } else {
+                let rgb = img.to_rgb8();
+                enc.write_image(rgb.as_raw(), rgb.width(), rgb.height(), ExtendedColorType::Rgb8)
+            };
+            written.map_err(|e| ImageKitError::TransformError(e.to_string()))?;
+            out = cursor.into_inner();
+        }
+        #[cfg(feature = "extra_formats")]
+        ImageFormat::bmp => {
+            let enc = image::codecs::bmp::BmpEncoder::new(&mut out);
+            let written = if img.color().has_alpha() {
+                let rgba = img.to_rgba8();
+                enc.write_image(rgba.as_raw(), rgba.width(), rgba.height(), ExtendedColorType::Rgba8)
+            } else {
+                let rgb = img.to_rgb8();
+                enc.write_image(rgb.as_raw(), rgb.width(), rgb.height(), ExtendedColorType::Rgb8)
+            };
+            written.map_err(|e| ImageKitError::TransformError(e.to_string()))?;
+        }
     }
     
     Ok(out)
-}
\ No newline at end of file
+}
+
+/// Encodes as `format`, falling back along `chain` when the encoder fails.
+///
+/// `encode` is called with `format` first, then with each format after
+/// `format`'s position in `chain`; a format absent from `chain` gets no
+/// fallback. Returns the bytes and the format actually produced, or the
+/// first error if every attempt fails.
+pub fn encode_with_fallback<F>(
+    format: ImageFormat,
+    chain: &[ImageFormat],
+    mut encode: F,
+) -> Result<(Vec<u8>, ImageFormat), ImageKitError>
+where
+    F: FnMut(ImageFormat) -> Result<Vec<u8>, ImageKitError>,
+{
+    let fallbacks = chain.iter().skip_while(|f| **f != format).skip(1).copied();
+    let mut first_error = None;
+    for candidate in std::iter::once(format).chain(fallbacks) {
+        match encode(candidate) {
+            Ok(bytes) => return Ok((bytes, candidate)),
+            Err(e) => {
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+    Err(first_error.expect("the preferred format is always attempted"))
+}
+
+/// Largest ICC payload in one JPEG APP2 segment: 65535 minus the length
+/// field and the 14-byte `ICC_PROFILE` header.
+const JPEG_ICC_CHUNK: usize = 65_519;
+
+/// VP8X feature flags.
+const VP8X_ICC: u8 = 0x20;
+const VP8X_ALPHA: u8 = 0x10;
+
+/// Embeds an ICC color profile into encoded JPEG or WebP output.
+///
+/// Encoders work from pixels, so output carries no profile unless one is
+/// spliced in here. Other formats, and bytes that don't parse as the
+/// expected container, are returned unchanged.
+pub fn embed_icc_profile(encoded: Vec<u8>, format: ImageFormat, icc: &[u8]) -> Vec<u8> {
+    if icc.is_empty() {
+        return encoded;
+    }
+    match format {
+        ImageFormat::jpeg => embed_jpeg_icc(encoded, icc),
+        ImageFormat::webp => embed_webp_icc(encoded, icc),
+        _ => encoded,
+    }
+}
+
+/// Inserts `APP2 ICC_PROFILE` segments after SOI (and JFIF APP0, if present).
+fn embed_jpeg_icc(encoded: Vec<u8>, icc: &[u8]) -> Vec<u8> {
+    let chunks: Vec<&[u8]> = icc.chunks(JPEG_ICC_CHUNK).collect();
+    if encoded.len() < 4 || encoded[..2] != [0xFF, 0xD8] || chunks.len() > u8::MAX as usize {
+        return encoded;
+    }
+    let at = if encoded.len() >= 6 && encoded[2..4] == [0xFF, 0xE0] {
+        4 + u16::from_be_bytes([encoded[4], encoded[5]]) as usize
+    } else {
+        2
+    };
+    if at > encoded.len() {
+        return encoded;
+    }
+
+    let mut out = Vec::with_capacity(encoded.len() + icc.len() + chunks.len() * 18);
+    out.extend_from_slice(&encoded[..at]);
+    for (i, chunk) in chunks.iter().enumerate() {
+        out.extend_from_slice(&[0xFF, 0xE2]);
+        out.extend_from_slice(&((2 + 14 + chunk.len()) as u16).to_be_bytes());
+        out.extend_from_slice(b"ICC_PROFILE\0");
+        out.push(i as u8 + 1);
+        out.push(chunks.len() as u8);
+        out.extend_from_slice(chunk);
+    }
+    out.extend_from_slice(&encoded[at..]);
+    out
+}
+
+/// Adds an `ICCP` chunk, converting simple (VP8/VP8L) files to VP8X first.
+fn embed_webp_icc(encoded: Vec<u8>, icc: &[u8]) -> Vec<u8> {
+    if encoded.len() < 20 || &encoded[..4] != b"RIFF" || &encoded[8..12] != b"WEBP" {
+        return encoded;
+    }
+    let size = u32::from_le_bytes([encoded[16], encoded[17], encoded[18], encoded[19]]) as usize;
+    let first_end = 20 + size + (size & 1);
+    if first_end > encoded.len() {
+        return encoded;
+    }
+
+    // Chunks following the `WEBP` fourcc; ICCP must directly follow VP8X
+    let mut body = Vec::with_capacity(encoded.len() + icc.len() + 32);
+    match &encoded[12..16] {
+        b"VP8X" => {
+            body.extend_from_slice(&encoded[12..first_end]);
+            body[8] |= VP8X_ICC;
+            push_riff_chunk(&mut body, b"ICCP", icc);
+            body.extend_from_slice(&encoded[first_end..]);
+        }
+        fourcc @ (b"VP8 " | b"VP8L") => {
+            let Some((width, height, alpha)) = simple_webp_info(fourcc, &encoded[20..first_end]) else {
+                return encoded;
+            };
+            let mut vp8x = [0u8; 10];
+            vp8x[0] = VP8X_ICC | if alpha { VP8X_ALPHA } else { 0 };
+            vp8x[4..7].copy_from_slice(&(width - 1).to_le_bytes()[..3]);
+            vp8x[7..10].copy_from_slice(&(height - 1).to_le_bytes()[..3]);
+            push_riff_chunk(&mut body, b"VP8X", &vp8x);
+            push_riff_chunk(&mut body, b"ICCP", icc);
+            body.extend_from_slice(&encoded[12..]);
+        }
+        _ => return encoded,
+    }
+
+    let mut out = Vec::with_capacity(12 + body.len());
+    out.extend_from_slice(b"RIFF");
+    out.extend_from_slice(&((4 + body.len()) as u32).to_le_bytes());
+    out.extend_from_slice(b"WEBP");
+    out.extend_from_slice(&body);
+    out
+}
+
+/// Canvas size and alpha of a simple-format WebP bitstream.
+fn simple_webp_info(fourcc: &[u8], data: &[u8]) -> Option<(u32, u32, bool)> {
+    if fourcc == b"VP8L" {
+        // Signature byte, then 14-bit width-1, 14-bit height-1, alpha bit
+        if data.len() < 5 || data[0] != 0x2F {
+            return None;
+        }
+        let bits = u32::from_le_bytes([data[1], data[2], data[3], data[4]]);
+        Some(((bits & 0x3FFF) + 1, ((bits >> 14) & 0x3FFF) + 1, (bits >> 28) & 1 == 1))
+    } else {
+        // Frame tag, start code, then 14-bit width and height
+        if data.len() < 10 || data[3..6] != [0x9D, 0x01, 0x2A] {
+            return None;
+        }
+        let width = u16::from_le_bytes([data[6], data[7]]) as u32 & 0x3FFF;
+        let height = u16::from_le_bytes([data[8], data[9]]) as u32 & 0x3FFF;
+        (width > 0 && height > 0).then_some((width, height, false))
+    }
+}
+
+fn push_riff_chunk(out: &mut Vec<u8>, fourcc: &[u8; 4], payload: &[u8]) {
+    out.extend_from_slice(fourcc);
+    out.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    out.extend_from_slice(payload);
+    if payload.len() & 1 == 1 {
+        out.push(0);
+    }
+}
+
+/// Lowest quality `encode_to_budget` will go down to.
+pub const BUDGET_MIN_QUALITY: u8 = 10;
+
+/// Maximum number of trial encodes `encode_to_budget` performs.
+/// Seven halvings cover the whole 1-100 quality range.
+pub const BUDGET_MAX_ITERATIONS: u32 = 7;
+
+/// Encodes at the highest quality whose output fits within `max_bytes`.
+///
+/// Binary-searches quality between [`BUDGET_MIN_QUALITY`] and `max_quality`,
+/// with at most [`BUDGET_MAX_ITERATIONS`] trial encodes. If even the floor
+/// quality exceeds the budget, the floor-quality encode is returned as the
+/// best effort. Formats that ignore quality (lossless WebP, PNG, ICO, TIFF,
+/// BMP) are encoded once.
+///
+/// # Returns
+/// Tuple of `(encoded_bytes, quality_used)`.
+///
+/// # Errors
+/// Returns `ImageKitError::TransformError` on encoder failures.
+pub fn encode_to_budget(
+    img: &DynamicImage,
+    fmt: ImageFormat,
+    max_quality: u8,
+    max_bytes: usize,
+    options: &EncodeOptions,
+) -> Result<(Vec<u8>, u8), ImageKitError> {
+    let max_quality = max_quality.clamp(1, 100);
+    
+    // Fast path: already within budget at the requested quality
+    let first = encode_image_with(img, fmt, max_quality, options)?;
+    if first.len() <= max_bytes || ignores_quality(fmt, options) {
+        return Ok((first, max_quality));
+    }
+    
+    let floor = BUDGET_MIN_QUALITY.min(max_quality);
+    let (mut lo, mut hi) = (floor, max_quality.saturating_sub(1));
+    let mut best: Option<(Vec<u8>, u8)> = None;
+    let mut floor_output: Option<Vec<u8>> = None;
+    
+    for _ in 0..BUDGET_MAX_ITERATIONS {
+        if lo > hi {
+            break;
+        }
+        let q = lo + (hi - lo) / 2;
+        let out = encode_image_with(img, fmt, q, options)?;
+        
+        if out.len() <= max_bytes {
+            best = Some((out, q));
+            lo = q + 1;
+        } else {
+            if q == floor {
+                floor_output = Some(out);
+                break;
+            }
+            hi = q - 1;
+        }
+    }
+    
+    if let Some(found) = best {
+        return Ok(found);
+    }
+    
+    // Nothing fit: best effort at the floor quality
+    let out = match floor_output {
+        Some(out) => out,
+        None => encode_image_with(img, fmt, floor, options)?,
+    };
+    Ok((out, floor))
+}
+
+/// Whether `fmt` encodes the same bytes at every quality.
+fn ignores_quality(fmt: ImageFormat, options: &EncodeOptions) -> bool {
+    match fmt {
+        ImageFormat::png | ImageFormat::ico => true,
+        #[cfg(feature = "extra_formats")]
+        ImageFormat::tiff | ImageFormat::bmp => true,
+        ImageFormat::webp => options.webp_lossless,
+        _ => false,
+    }
+}
+
+/// Encodes AVIF with explicit video color signaling, or at 10 bits per
+/// channel, via `ravif`.
+///
+/// Pixels are converted to full-range YCbCr with the matrix matching the
+/// requested colorspace, and the primaries/transfer in the `colr` (nclx)
+/// box are rewritten to match, since `ravif` always declares BT.709/sRGB.
+/// `srgb` keeps that declaration, with the BT.601 matrix.
+fn encode_avif_cicp(
+    img: &DynamicImage,
+    quality: u8,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, ImageKitError> {
+    // (Kr, Kb, CICP code shared by primaries/transfer/matrix)
+    let (kr, kb, cicp, matrix) = match options.avif_colorspace {
+        AvifColorSpace::Bt709 => (0.2126f32, 0.0722f32, Some(1u16), ravif::MatrixCoefficients::Bt709),
+        AvifColorSpace::Bt601 => (0.299f32, 0.114f32, Some(6u16), ravif::MatrixCoefficients::Bt601),
+        AvifColorSpace::Srgb => (0.299f32, 0.114f32, None, ravif::MatrixCoefficients::Bt601),
+    };
+    let q = quality.clamp(1, 100) as f32;
+    let encoder = ravif::Encoder::new()
+        .with_quality(q)
+        .with_alpha_quality(q)
+        .with_speed(options.avif_speed.clamp(1, 10));
+    let (w, h) = img.dimensions();
+    
+    let ten_bit = if options.avif_depth == 10 {
+        // From float pixels, so 16-bit and HDR sources keep their precision
+        let rgba = img.to_rgba32f();
+        let planes: Vec<[u16; 3]> = rgba
+            .pixels()
+            .map(|p| {
+                let (r, g, b) = (p[0].clamp(0.0, 1.0), p[1].clamp(0.0, 1.0), p[2].clamp(0.0, 1.0));
+                let y = kr * r + (1.0 - kr - kb) * g + kb * b;
+                let cb = (b - y) / (2.0 * (1.0 - kb)) + 0.5;
+                let cr = (r - y) / (2.0 * (1.0 - kr)) + 0.5;
+                [y, cb, cr].map(|v| (v * 1023.0).round().clamp(0.0, 1023.0) as u16)
+            })
+            .collect();
+        let alpha = img
+            .color()
+            .has_alpha()
+            .then(|| rgba.pixels().map(|p| (p[3].clamp(0.0, 1.0) * 1023.0).round() as u16).collect::<Vec<u16>>());
+        match encoder.encode_raw_planes_10_bit(w as usize, h as usize, planes, alpha, ravif::PixelRange::Full, matrix) {
+            Ok(encoded) => Some(encoded),
+            Err(e) => {
+                tracing::warn!("10-bit AVIF encode failed ({}), encoding 8-bit", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let encoded = match ten_bit {
+        Some(encoded) => encoded,
+        None => encode_avif_planes_8_bit(img, &encoder, kr, kb, matrix)?,
+    };
+    
+    let mut out = encoded.avif_file;
+    if let Some(cicp) = cicp {
+        set_avif_primaries_transfer(&mut out, cicp, cicp)?;
+    }
+    Ok(out)
+}
+
+/// 8-bit half of [`encode_avif_cicp`].
+fn encode_avif_planes_8_bit(
+    img: &DynamicImage,
+    encoder: &ravif::Encoder,
+    kr: f32,
+    kb: f32,
+    matrix: ravif::MatrixCoefficients,
+) -> Result<ravif::EncodedImage, ImageKitError> {
+    let rgba = img.to_rgba8();
+    let (w, h) = rgba.dimensions();
+    
+    let planes: Vec<[u8; 3]> = rgba
+        .pixels()
+        .map(|p| {
+            let (r, g, b) = (p[0] as f32, p[1] as f32, p[2] as f32);
+            let y = kr * r + (1.0 - kr - kb) * g + kb * b;
+            let cb = (b - y) / (2.0 * (1.0 - kb)) + 128.0;
+            let cr = (r - y) / (2.0 * (1.0 - kr)) + 128.0;
+            [
+                y.round().clamp(0.0, 255.0) as u8,
+                cb.round().clamp(0.0, 255.0) as u8,
+                cr.round().clamp(0.0, 255.0) as u8,
+            ]
+        })
+        .collect();
+    
+    let alpha = if img.color().has_alpha() {
+        Some(rgba.pixels().map(|p| p[3]).collect::<Vec<u8>>())
+    } else {
+        None
+    };
+    
+    encoder
+        .encode_raw_planes_8_bit(
+            w as usize,
+            h as usize,
+            planes,
+            alpha,
+            ravif::PixelRange::Full,
+            matrix,
+        )
+        .map_err(|e| ImageKitError::TransformError(e.to_string()))
+}
+
+/// Bits per channel of encoded AVIF, from the `av1C` box: 8, 10 or 12.
+/// `None` for anything else, including other formats.
+pub fn avif_bit_depth(bytes: &[u8]) -> Option<u8> {
+    // av1C payload: marker/version, profile/level, then tier, high_bitdepth, twelve_bit, ...
+    let pos = bytes.windows(4).position(|w| w == b"av1C")? + 4;
+    let flags = *bytes.get(pos + 2)?;
+    Some(match (flags & 0x40 != 0, flags & 0x20 != 0) {
+        (false, _) => 8,
+        (true, false) => 10,
+        (true, true) => 12,
+    })
+}
+
+/// Rewrites colour primaries and transfer characteristics in an AVIF `colr` nclx box.
+fn set_avif_primaries_transfer(avif: &mut [u8], primaries: u16, transfer: u16) -> Result<(), ImageKitError> {
+    let pos = avif
+        .windows(8)
+        .position(|w| w == b"colrnclx")
+        .ok_or_else(|| ImageKitError::TransformError("AVIF output has no nclx colr box".into()))?;
+    
+    // Layout after the "colr" + "nclx" tags: primaries u16, transfer u16, matrix u16, range flag
+    let fields = pos + 8;
+    if avif.len() < fields + 4 {
+        return Err(ImageKitError::TransformError("Truncated AVIF colr box".into()));
+    }
+    avif[fields..fields + 2].copy_from_slice(&primaries.to_be_bytes());
+    avif[fields + 2..fields + 4].copy_from_slice(&transfer.to_be_bytes());
+    Ok(())
+}
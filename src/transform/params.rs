@@ -1,8 +1,11 @@
+use crate::config::ImageFormat;
 use serde::Deserialize;
+use utoipa::ToSchema;
 use std::fmt;
 use std::str::FromStr;
 
 /// Supported output image formats
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Deserialize, PartialEq, Clone)]
 #[serde(rename_all = "UPPERCASE")]
 pub enum Format {
@@ -35,11 +38,17 @@ impl FromStr for Format {
 }
 
 /// Fit modes for image transformation
-#[derive(Debug, Deserialize, PartialEq, Clone)]
+#[derive(Debug, Deserialize, PartialEq, Clone, ToSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum FitMode {
+    /// Fill `w`×`h` exactly, cropping the overflow
     Cover,
+    /// Fit within `w`×`h`, preserving aspect ratio (default)
     Contain,
+    /// Stretch to exactly `w`×`h`, ignoring aspect ratio
+    Fill,
+    /// Fit within `w`×`h`, then center on an exact `w`×`h` canvas of `bg`
+    Pad,
 }
 
 impl fmt::Display for FitMode {
@@ -47,6 +56,8 @@ impl fmt::Display for FitMode {
         match self {
             FitMode::Cover => write!(f, "cover"),
             FitMode::Contain => write!(f, "contain"),
+            FitMode::Fill => write!(f, "fill"),
+            FitMode::Pad => write!(f, "pad"),
         }
     }
 }
@@ -58,11 +69,297 @@ impl FromStr for FitMode {
         match s.to_lowercase().as_str() {
             "cover" => Ok(FitMode::Cover),
             "contain" => Ok(FitMode::Contain),
+            "fill" => Ok(FitMode::Fill),
+            "pad" => Ok(FitMode::Pad),
             _ => Err(format!("Invalid fit mode: {}", s)),
         }
     }
 }
 
+/// Anchor for the crop window when `fit=cover` has to discard pixels
+#[derive(Debug, Deserialize, PartialEq, Clone, Copy, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Gravity {
+    Center,
+    North,
+    South,
+    East,
+    West,
+    /// Pick the window with the most edge energy (detail)
+    Smart,
+}
+
+impl fmt::Display for Gravity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Gravity::Center => write!(f, "center"),
+            Gravity::North => write!(f, "north"),
+            Gravity::South => write!(f, "south"),
+            Gravity::East => write!(f, "east"),
+            Gravity::West => write!(f, "west"),
+            Gravity::Smart => write!(f, "smart"),
+        }
+    }
+}
+
+impl FromStr for Gravity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "center" => Ok(Gravity::Center),
+            "north" => Ok(Gravity::North),
+            "south" => Ok(Gravity::South),
+            "east" => Ok(Gravity::East),
+            "west" => Ok(Gravity::West),
+            "smart" => Ok(Gravity::Smart),
+            _ => Err(format!("Invalid gravity: {}", s)),
+        }
+    }
+}
+
+/// Where `text` is stamped, inset from the edges by half the text size
+#[derive(Debug, Deserialize, PartialEq, Clone, Copy, Default, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TextPosition {
+    TopLeft,
+    Top,
+    TopRight,
+    Center,
+    BottomLeft,
+    Bottom,
+    /// The usual watermark corner (default)
+    #[default]
+    BottomRight,
+}
+
+impl fmt::Display for TextPosition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TextPosition::TopLeft => write!(f, "top_left"),
+            TextPosition::Top => write!(f, "top"),
+            TextPosition::TopRight => write!(f, "top_right"),
+            TextPosition::Center => write!(f, "center"),
+            TextPosition::BottomLeft => write!(f, "bottom_left"),
+            TextPosition::Bottom => write!(f, "bottom"),
+            TextPosition::BottomRight => write!(f, "bottom_right"),
+        }
+    }
+}
+
+impl FromStr for TextPosition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "top_left" => Ok(TextPosition::TopLeft),
+            "top" => Ok(TextPosition::Top),
+            "top_right" => Ok(TextPosition::TopRight),
+            "center" => Ok(TextPosition::Center),
+            "bottom_left" => Ok(TextPosition::BottomLeft),
+            "bottom" => Ok(TextPosition::Bottom),
+            "bottom_right" => Ok(TextPosition::BottomRight),
+            _ => Err(format!("Invalid text position: {}", s)),
+        }
+    }
+}
+
+/// Value of the `f` query parameter: an output format to encode to,
+/// `original` to serve the source bytes untouched, or `auto` to re-encode
+/// in the source's own format.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum FormatParam {
+    Original,
+    Auto,
+    Encoded(ImageFormat),
+}
+
+impl fmt::Display for FormatParam {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormatParam::Original => write!(f, "original"),
+            FormatParam::Auto => write!(f, "auto"),
+            FormatParam::Encoded(format) => write!(f, "{}", format),
+        }
+    }
+}
+
+impl FromStr for FormatParam {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "original" => Ok(FormatParam::Original),
+            "auto" => Ok(FormatParam::Auto),
+            _ => s.parse().map(FormatParam::Encoded),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for FormatParam {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl From<ImageFormat> for FormatParam {
+    fn from(format: ImageFormat) -> Self {
+        FormatParam::Encoded(format)
+    }
+}
+
+/// Value of the `q` query parameter: a fixed quality (1-100), or `auto`
+/// to pick one from the image's complexity (see `transform::auto_quality`).
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum QualityParam {
+    Auto,
+    Value(u8),
+}
+
+impl fmt::Display for QualityParam {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QualityParam::Auto => write!(f, "auto"),
+            QualityParam::Value(q) => write!(f, "{}", q),
+        }
+    }
+}
+
+impl FromStr for QualityParam {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(QualityParam::Auto),
+            _ => s.parse().map(QualityParam::Value).map_err(|_| format!("Invalid quality: {}", s)),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for QualityParam {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Resampling filters selectable for resizing
+#[derive(Debug, Deserialize, PartialEq, Eq, Clone, Copy, Default, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ResizeFilter {
+    /// Hard edges; right for upscaling pixel art
+    Nearest,
+    Triangle,
+    /// Sharp, low-ringing cubic; a good upscaling default
+    CatmullRom,
+    Gaussian,
+    /// Best quality for downscaling
+    #[default]
+    Lanczos3,
+}
+
+impl fmt::Display for ResizeFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResizeFilter::Nearest => write!(f, "nearest"),
+            ResizeFilter::Triangle => write!(f, "triangle"),
+            ResizeFilter::CatmullRom => write!(f, "catmullrom"),
+            ResizeFilter::Gaussian => write!(f, "gaussian"),
+            ResizeFilter::Lanczos3 => write!(f, "lanczos3"),
+        }
+    }
+}
+
+impl FromStr for ResizeFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "nearest" => Ok(ResizeFilter::Nearest),
+            "triangle" => Ok(ResizeFilter::Triangle),
+            "catmullrom" => Ok(ResizeFilter::CatmullRom),
+            "gaussian" => Ok(ResizeFilter::Gaussian),
+            "lanczos3" => Ok(ResizeFilter::Lanczos3),
+            _ => Err(format!("Invalid filter: {}", s)),
+        }
+    }
+}
+
+impl From<ResizeFilter> for image::imageops::FilterType {
+    fn from(filter: ResizeFilter) -> Self {
+        match filter {
+            ResizeFilter::Nearest => image::imageops::FilterType::Nearest,
+            ResizeFilter::Triangle => image::imageops::FilterType::Triangle,
+            ResizeFilter::CatmullRom => image::imageops::FilterType::CatmullRom,
+            ResizeFilter::Gaussian => image::imageops::FilterType::Gaussian,
+            ResizeFilter::Lanczos3 => image::imageops::FilterType::Lanczos3,
+        }
+    }
+}
+
+/// JPEG chroma subsampling. 4:2:0 is smallest; 4:4:4 keeps sharp color
+/// edges (text, UI screenshots) free of fringing at a larger size.
+#[derive(Debug, Deserialize, PartialEq, Eq, Clone, Copy, ToSchema)]
+pub enum ChromaSubsampling {
+    #[serde(rename = "420")]
+    Cs420,
+    #[serde(rename = "422")]
+    Cs422,
+    #[serde(rename = "444")]
+    Cs444,
+}
+
+impl fmt::Display for ChromaSubsampling {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChromaSubsampling::Cs420 => write!(f, "420"),
+            ChromaSubsampling::Cs422 => write!(f, "422"),
+            ChromaSubsampling::Cs444 => write!(f, "444"),
+        }
+    }
+}
+
+impl FromStr for ChromaSubsampling {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "420" => Ok(ChromaSubsampling::Cs420),
+            "422" => Ok(ChromaSubsampling::Cs422),
+            "444" => Ok(ChromaSubsampling::Cs444),
+            _ => Err(format!("Invalid chroma: {}", s)),
+        }
+    }
+}
+
+/// Alternative response envelopes for clients that can't take raw bytes
+#[derive(Debug, Deserialize, PartialEq, Clone, Copy, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Wrap {
+    /// JSON object with the image base64-encoded in `data`
+    Json,
+}
+
+impl fmt::Display for Wrap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Wrap::Json => write!(f, "json"),
+        }
+    }
+}
+
+impl FromStr for Wrap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "json" => Ok(Wrap::Json),
+            _ => Err(format!("Invalid wrap: {}", s)),
+        }
+    }
+}
+
 /// Parameters for image transformation parsed from URL query parameters
 #[derive(Debug, Deserialize, Clone)]
 pub struct TransformParams {
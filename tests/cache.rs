@@ -0,0 +1,514 @@
+use imagekit::cache::fs_check::{self_test_with, CacheFs, StdFs};
+use imagekit::cache::{etag_for_content, self_test, Cache, Computed, DiskCache, FsCheck, Lookup, SledCache, SourceCache, ENCODER_VERSION};
+use imagekit::config::{ImageFormat, ImageKitConfig};
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Helper to get a fresh, process-unique cache directory
+fn temp_cache_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("imagekit-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+fn sample_params() -> BTreeMap<String, String> {
+    let mut params = BTreeMap::new();
+    params.insert("url".to_string(), "https://example.com/cat.jpg".to_string());
+    params.insert("w".to_string(), "400".to_string());
+    params.insert("f".to_string(), "webp".to_string());
+    params
+}
+
+// ====================================================================================
+// CACHE KEY TESTS
+// ====================================================================================
+
+#[test]
+fn test_encoder_version_changes_cache_key() {
+    let params = sample_params();
+
+    let old = DiskCache::new(PathBuf::from("./test-cache-keys")).with_encoder_version("image-0.24");
+    let new = DiskCache::new(PathBuf::from("./test-cache-keys")).with_encoder_version("image-0.25");
+
+    assert_ne!(old.key_for(&params), new.key_for(&params),
+               "Different encoder versions must not share cache keys");
+}
+
+#[test]
+fn test_default_encoder_version_is_stable() {
+    let params = sample_params();
+
+    let a = DiskCache::new(PathBuf::from("./test-cache-keys"));
+    let b = DiskCache::new(PathBuf::from("./test-cache-keys")).with_encoder_version(ENCODER_VERSION);
+
+    assert_eq!(a.key_for(&params), b.key_for(&params));
+}
+
+#[tokio::test]
+async fn test_secret_rotation_invalidates_cache_keys() {
+    let dir = temp_cache_dir("secret-ns");
+    let params = sample_params();
+
+    let old_config = ImageKitConfig {
+        secret: "old-secret".into(),
+        bind_cache_to_secret: true,
+        ..Default::default()
+    };
+    let new_config = ImageKitConfig {
+        secret: "new-secret".into(),
+        ..old_config.clone()
+    };
+
+    let old_cache = DiskCache::new(dir.clone()).with_namespace(old_config.cache_namespace());
+    let new_cache = DiskCache::new(dir.clone()).with_namespace(new_config.cache_namespace());
+
+    let old_key = old_cache.key_for(&params);
+    let new_key = new_cache.key_for(&params);
+    assert_ne!(old_key, new_key, "Rotating the secret must change cache keys");
+
+    // Entries written under the old secret are misses under the new one
+    old_cache.put(&old_key, b"old-output", ImageFormat::webp, "").await.unwrap();
+    assert_eq!(new_cache.get(&new_key).await.unwrap(), None);
+
+    // The namespace never embeds the secret itself
+    assert!(!old_config.cache_namespace().contains("old-secret"));
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_cache_namespace_disabled_by_default() {
+    let config = ImageKitConfig { secret: "s".into(), ..Default::default() };
+    assert_eq!(config.cache_namespace(), "");
+}
+
+#[test]
+fn test_cache_version_changes_keys_on_every_backend() {
+    let dir = temp_cache_dir("cache-version");
+    let params = sample_params();
+    let v1 = ImageKitConfig::builder().secret("s").cache_version(1).build().unwrap();
+    let v2 = ImageKitConfig::builder().secret("s").cache_version(2).build().unwrap();
+
+    let disk_v1 = DiskCache::new(dir.join("disk")).with_namespace(v1.cache_namespace());
+    let disk_v2 = DiskCache::new(dir.join("disk")).with_namespace(v2.cache_namespace());
+    assert_ne!(disk_v1.key_for(&params), disk_v2.key_for(&params),
+               "Bumping cache_version must change cache keys");
+
+    let sled_v1 = SledCache::new(&dir.join("sled-1"), None).unwrap().with_namespace(v1.cache_namespace());
+    let sled_v2 = SledCache::new(&dir.join("sled-2"), None).unwrap().with_namespace(v2.cache_namespace());
+    assert_ne!(sled_v1.key_for(&params), sled_v2.key_for(&params));
+
+    // Both backends hash the same way, and version 0 keeps unversioned keys
+    assert_eq!(disk_v1.key_for(&params), sled_v1.key_for(&params));
+    let unversioned = ImageKitConfig { secret: "s".into(), cache_version: 0, ..Default::default() };
+    assert_eq!(unversioned.cache_namespace(), "");
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+// ====================================================================================
+// EVICTION TESTS
+// ====================================================================================
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_put_over_limit_evicts_in_background() {
+    let dir = temp_cache_dir("evict");
+    let cache = SledCache::new(&dir, Some(10_000)).unwrap();
+    let chunk = vec![0u8; 1_000];
+
+    // Fill exactly up to the limit
+    for i in 0..10 {
+        cache.put(&format!("key-{}", i), &chunk, ImageFormat::webp, "").await.unwrap();
+    }
+
+    // Crossing the limit must not wait for the eviction pass
+    let start = Instant::now();
+    cache.put("key-10", &chunk, ImageFormat::webp, "").await.unwrap();
+    assert!(start.elapsed() < Duration::from_secs(1),
+            "put crossing the limit took {:?}", start.elapsed());
+
+    // Eviction eventually brings the cache back under its limit
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let stats = cache.stats().await;
+        if stats.total_size_bytes <= 10_000 && !cache.eviction_in_progress() {
+            break;
+        }
+        assert!(Instant::now() < deadline, "eviction did not complete: {:?}", stats);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_max_entries_caps_entry_count() {
+    let dir = temp_cache_dir("evict-entries");
+    // Byte limit far away; only the entry count can trigger eviction
+    let cache = SledCache::new(&dir, Some(100_000_000)).unwrap().with_max_entries(Some(10));
+
+    for i in 0..25 {
+        cache.put(&format!("key-{}", i), &[7u8; 16], ImageFormat::webp, "").await.unwrap();
+    }
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let stats = cache.stats().await;
+        if stats.entry_count <= 10 && !cache.eviction_in_progress() {
+            assert_eq!(stats.max_entries, Some(10));
+            assert!(stats.entry_count > 0, "eviction should stop at the target, not empty the cache");
+            break;
+        }
+        assert!(Instant::now() < deadline, "entry count not capped: {:?}", stats);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+// ====================================================================================
+// STATS TESTS
+// ====================================================================================
+
+#[tokio::test]
+async fn test_disk_cache_get_finds_what_put_wrote() {
+    let dir = temp_cache_dir("disk-roundtrip");
+    let cache = DiskCache::new(dir.clone());
+    let key = cache.key_for(&sample_params());
+
+    assert_eq!(cache.get(&key).await.unwrap(), None);
+    cache.put(&key, b"avif-output", ImageFormat::avif, "").await.unwrap();
+    assert_eq!(cache.get(&key).await.unwrap(), Some(b"avif-output".to_vec()));
+    assert!(dir.join(format!("{}.avif", key)).exists());
+    assert_eq!(cache.get("some-other-key").await.unwrap(), None);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_disk_cache_png_entry_round_trips_with_content_type() {
+    let dir = temp_cache_dir("disk-png");
+    let cache = DiskCache::new(dir.clone());
+    let key = cache.key_for(&sample_params());
+
+    cache.put(&key, b"png-output", ImageFormat::png, "").await.unwrap();
+    assert_eq!(cache.get(&key).await.unwrap(), Some(b"png-output".to_vec()));
+    let path = dir.join(format!("{}.png", key));
+    assert!(path.exists());
+    assert_eq!(cache.content_type_for_path(&path).as_deref(), Some("image/png"));
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_disk_cache_concurrent_puts_never_tear() {
+    let dir = temp_cache_dir("disk-atomic");
+    let cache = Arc::new(DiskCache::new(dir.clone()));
+    // Differently sized payloads, so a torn write would leave a truncated PNG
+    let payloads: Vec<Vec<u8>> = (1..=8u32)
+        .map(|i| {
+            let img = image::RgbaImage::from_pixel(16 * i, 16 * i, image::Rgba([i as u8, 0, 0, 255]));
+            let mut out = std::io::Cursor::new(Vec::new());
+            img.write_to(&mut out, image::ImageFormat::Png).unwrap();
+            out.into_inner()
+        })
+        .collect();
+    cache.put("k", &payloads[0], ImageFormat::png, "").await.unwrap();
+
+    let writers: Vec<_> = (0..16)
+        .map(|n| {
+            // A handle per writer, as `/img` creates one per request; they
+            // share the directory's write locks
+            let cache = DiskCache::new(dir.clone());
+            let bytes = payloads[n % payloads.len()].clone();
+            tokio::spawn(async move {
+                for _ in 0..10 {
+                    cache.put("k", &bytes, ImageFormat::png, "").await.unwrap();
+                }
+            })
+        })
+        .collect();
+    let reader = {
+        let cache = cache.clone();
+        tokio::spawn(async move {
+            for _ in 0..200 {
+                let bytes = cache.get("k").await.unwrap().expect("entry stays present");
+                image::load_from_memory(&bytes).expect("entry is always a complete image");
+                tokio::task::yield_now().await;
+            }
+        })
+    };
+    for w in writers {
+        w.await.unwrap();
+    }
+    reader.await.unwrap();
+
+    let bytes = cache.get("k").await.unwrap().unwrap();
+    assert!(payloads.contains(&bytes));
+    // No temp files are left behind
+    let leftovers: Vec<_> = std::fs::read_dir(&dir).unwrap().map(|e| e.unwrap().file_name()).collect();
+    assert_eq!(leftovers, vec![std::ffi::OsString::from("k.png")]);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_disk_cache_evicts_oldest_past_max_size() {
+    let dir = temp_cache_dir("disk-cap");
+    let cache = DiskCache::new(dir.clone()).with_max_size(Some(250));
+    // Unrelated files sharing the directory are neither counted nor evicted
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("notes.txt"), [0u8; 1000]).unwrap();
+
+    for key in ["a", "b", "c"] {
+        cache.put(key, &[0u8; 100], ImageFormat::webp, "").await.unwrap();
+        // Distinct modification times
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    assert!(!dir.join("a.webp").exists(), "oldest entry should be evicted");
+    assert!(dir.join("b.webp").exists());
+    assert!(dir.join("c.webp").exists());
+    assert!(dir.join("notes.txt").exists());
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_disk_cache_scans_only_once_over_the_cap() {
+    let dir = temp_cache_dir("disk-counter");
+    let cache = DiskCache::new(dir.clone()).with_max_size(Some(250));
+
+    cache.put("a", &[0u8; 100], ImageFormat::webp, "").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    // Written behind the cache's back, so the running total doesn't see it
+    std::fs::write(dir.join("x.webp"), [0u8; 1000]).unwrap();
+    tokio::time::sleep(Duration::from_millis(20)).await;
+
+    // Still under the cap by the running total: the directory isn't scanned
+    cache.put("b", &[0u8; 100], ImageFormat::webp, "").await.unwrap();
+    assert!(dir.join("a.webp").exists() && dir.join("x.webp").exists());
+    tokio::time::sleep(Duration::from_millis(20)).await;
+
+    // Crossing it runs a pass, which sees the real contents
+    cache.put("c", &[0u8; 100], ImageFormat::webp, "").await.unwrap();
+    assert!(!dir.join("a.webp").exists());
+    assert!(!dir.join("x.webp").exists());
+    assert!(dir.join("b.webp").exists() && dir.join("c.webp").exists());
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_stats_through_dyn_cache() {
+    let dir = temp_cache_dir("stats");
+    let cache: Box<dyn Cache> = Box::new(SledCache::new(&dir, Some(1_000_000)).unwrap());
+
+    cache.put("a", &[0u8; 100], ImageFormat::webp, "").await.unwrap();
+    cache.put("b", &[0u8; 250], ImageFormat::jpeg, "").await.unwrap();
+    cache.put("c", &[0u8; 50], ImageFormat::avif, "").await.unwrap();
+
+    let stats = cache.stats().await.expect("Sled reports stats");
+    assert_eq!(stats.total_size_bytes, 400);
+    assert_eq!(stats.entry_count, 3);
+    assert_eq!(stats.max_size_bytes, 1_000_000);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_sled_stores_content_etag() {
+    let dir = temp_cache_dir("etag");
+    let cache = SledCache::new(&dir, None).unwrap();
+
+    cache.put("a", b"same bytes", ImageFormat::webp, "").await.unwrap();
+    cache.put("b", b"same bytes", ImageFormat::webp, "").await.unwrap();
+    cache.put("c", b"other bytes", ImageFormat::webp, "").await.unwrap();
+
+    assert_eq!(cache.etag("a"), Some(etag_for_content(b"same bytes")));
+    assert_eq!(cache.etag("a"), cache.etag("b"), "identical content shares an ETag across keys");
+    assert_ne!(cache.etag("a"), cache.etag("c"));
+    assert_eq!(cache.etag("missing"), None);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_concurrent_stats_scans_are_serialized() {
+    let dir = temp_cache_dir("scan-guard");
+    let cache = Arc::new(SledCache::new(&dir, Some(100_000_000)).unwrap());
+    for i in 0..200 {
+        cache.put(&format!("key-{}", i), &[1u8; 64], ImageFormat::webp, "").await.unwrap();
+    }
+
+    let handles: Vec<_> = (0..8)
+        .map(|_| {
+            let cache = cache.clone();
+            tokio::spawn(async move { cache.stats().await })
+        })
+        .collect();
+
+    for handle in handles {
+        let stats = handle.await.unwrap();
+        assert_eq!(stats.entry_count, 200);
+        assert_eq!(stats.total_size_bytes, 200 * 64);
+    }
+
+    let metrics = cache.scan_metrics();
+    assert_eq!(metrics.scans, 8);
+    assert_eq!(metrics.peak_concurrent, 1, "full scans must not overlap");
+
+    drop(cache);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_stats_default_is_none() {
+    let cache: Box<dyn Cache> = Box::new(DiskCache::new(PathBuf::from("./test-cache-keys")));
+    assert!(cache.stats().await.is_none());
+}
+
+// ====================================================================================
+// GET-OR-COMPUTE TESTS
+// ====================================================================================
+
+/// In-memory stub backend, counting how often each method runs
+#[derive(Default)]
+struct MemoryCache {
+    entries: std::sync::Mutex<std::collections::HashMap<String, Vec<u8>>>,
+    puts: std::sync::atomic::AtomicUsize,
+}
+
+#[async_trait::async_trait]
+impl Cache for MemoryCache {
+    fn key_for(&self, params: &BTreeMap<String, String>) -> String {
+        imagekit::cache::hash_key(params, ENCODER_VERSION, "")
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, String> {
+        Ok(self.entries.lock().unwrap().get(key).cloned())
+    }
+
+    async fn put(&self, key: &str, data: &[u8], _format: ImageFormat, _params: &str) -> Result<(), String> {
+        self.puts.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        self.entries.lock().unwrap().insert(key.to_string(), data.to_vec());
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_get_or_compute_runs_compute_only_on_miss() {
+    let cache = MemoryCache::default();
+    let computed = std::sync::atomic::AtomicUsize::new(0);
+    let lookup = || cache.get_or_compute("k", "w=1", || async {
+        computed.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        Ok::<_, String>(Computed { value: b"output".to_vec(), store_as: Some(ImageFormat::webp) })
+    });
+
+    assert_eq!(lookup().await.unwrap(), Lookup::Computed { value: b"output".to_vec(), stored: true });
+    assert_eq!(lookup().await.unwrap(), Lookup::Hit(b"output".to_vec()));
+    assert_eq!(lookup().await.unwrap(), Lookup::Hit(b"output".to_vec()));
+    assert_eq!(computed.load(std::sync::atomic::Ordering::SeqCst), 1);
+    assert_eq!(cache.puts.load(std::sync::atomic::Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn test_get_or_compute_skips_store_on_opt_out_or_error() {
+    let cache = MemoryCache::default();
+
+    // `store_as: None` serves without caching, so the next call computes again
+    for _ in 0..2 {
+        let lookup = cache.get_or_compute("k", "", || async {
+            Ok::<_, String>(Computed { value: b"stale".to_vec(), store_as: None })
+        }).await.unwrap();
+        assert_eq!(lookup, Lookup::Computed { value: b"stale".to_vec(), stored: false });
+    }
+
+    let failed = cache.get_or_compute("k", "", || async { Err::<Computed<Vec<u8>>, _>("origin down".to_string()) }).await;
+    assert_eq!(failed, Err("origin down".to_string()));
+    assert_eq!(cache.puts.load(std::sync::atomic::Ordering::SeqCst), 0);
+    assert_eq!(cache.get("k").await.unwrap(), None);
+}
+
+// ====================================================================================
+// SOURCE CACHE TESTS
+// ====================================================================================
+
+#[test]
+fn test_source_cache_bounds_memory_and_expires() {
+    let cache = SourceCache::new(1_000, Duration::from_secs(60));
+
+    // Over a quarter of the budget: never stored
+    cache.put("huge", Arc::new(vec![0u8; 300]), "image/png");
+    assert!(cache.get("huge").is_none());
+
+    // The oldest entry goes once the budget is exceeded
+    for name in ["a", "b", "c", "d"] {
+        cache.put(name, Arc::new(vec![0u8; 250]), "image/png");
+        std::thread::sleep(Duration::from_millis(2));
+    }
+    cache.put("e", Arc::new(vec![0u8; 250]), "image/jpeg");
+    assert!(cache.get("a").is_none());
+    assert_eq!(cache.get("e").unwrap().1, "image/jpeg");
+    assert!(cache.size_bytes() <= 1_000);
+
+    let short = SourceCache::new(1_000, Duration::from_millis(10));
+    short.put("x", Arc::new(vec![1u8; 10]), "image/png");
+    assert!(short.get("x").is_some());
+    std::thread::sleep(Duration::from_millis(30));
+    assert!(short.get("x").is_none(), "expired entries are misses");
+    assert_eq!(short.size_bytes(), 0);
+}
+
+// ====================================================================================
+// FILESYSTEM SELF-TEST
+// ====================================================================================
+
+/// Filesystem whose rename copies only part of the file and keeps the source,
+/// like a non-atomic copy+delete that was interrupted
+struct TornRenameFs;
+
+impl CacheFs for TornRenameFs {
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        StdFs.create_dir_all(path)
+    }
+
+    fn write(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        StdFs.write(path, data)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let data = std::fs::read(from)?;
+        std::fs::write(to, &data[..data.len() / 2])
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        StdFs.read(path)
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        StdFs.remove(path)
+    }
+}
+
+#[test]
+fn test_cache_dir_self_test_passes_on_local_disk() {
+    let dir = temp_cache_dir("fs-ok");
+    assert_eq!(self_test(&dir), FsCheck::Ok);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_cache_dir_self_test_flags_non_atomic_rename() {
+    let dir = temp_cache_dir("fs-torn");
+    match self_test_with(&TornRenameFs, &dir) {
+        FsCheck::Unsafe(reason) => assert!(reason.contains("source"), "unexpected reason: {}", reason),
+        FsCheck::Ok => panic!("torn rename must not pass the self-test"),
+    }
+    // Probe files are cleaned up either way
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    let _ = std::fs::remove_dir_all(&dir);
+}
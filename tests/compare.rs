@@ -0,0 +1,55 @@
+use image::{DynamicImage, Rgba, RgbaImage};
+use imagekit::compare::compare_images;
+
+/// Smooth gradient, so SSIM has some structure to compare
+fn gradient(width: u32, height: u32) -> DynamicImage {
+    DynamicImage::ImageRgba8(RgbaImage::from_fn(width, height, |x, y| {
+        Rgba([(x * 255 / width) as u8, (y * 255 / height) as u8, 128, 255])
+    }))
+}
+
+/// `img` with deterministic noise of up to +-`amount` on each color channel
+fn with_noise(img: &DynamicImage, amount: i32) -> DynamicImage {
+    let mut state = 12345u32;
+    let mut rgba = img.to_rgba8();
+    for pixel in rgba.pixels_mut() {
+        for channel in pixel.0.iter_mut().take(3) {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let delta = (state >> 16) as i32 % (2 * amount + 1) - amount;
+            *channel = (*channel as i32 + delta).clamp(0, 255) as u8;
+        }
+    }
+    DynamicImage::ImageRgba8(rgba)
+}
+
+#[test]
+fn test_compare_identical_images() {
+    let img = gradient(64, 48);
+    let cmp = compare_images(&img, &img.clone());
+    assert_eq!(cmp.mse, 0.0);
+    assert!(cmp.psnr.is_infinite() && cmp.psnr > 0.0);
+    assert!((cmp.ssim - 1.0).abs() < 1e-9, "ssim {}", cmp.ssim);
+}
+
+#[test]
+fn test_compare_noisy_image() {
+    let img = gradient(64, 48);
+    let slight = compare_images(&img, &with_noise(&img, 4));
+    let heavy = compare_images(&img, &with_noise(&img, 60));
+
+    assert!(slight.mse > 0.0);
+    assert!(slight.psnr.is_finite() && slight.psnr > 35.0, "psnr {}", slight.psnr);
+    assert!(slight.ssim < 1.0 && slight.ssim > 0.9, "ssim {}", slight.ssim);
+    // More noise scores worse on every metric
+    assert!(heavy.mse > slight.mse);
+    assert!(heavy.psnr < slight.psnr);
+    assert!(heavy.ssim < slight.ssim);
+}
+
+#[test]
+fn test_compare_resizes_to_common_size() {
+    let img = gradient(64, 48);
+    let cmp = compare_images(&img, &gradient(128, 96));
+    assert!(cmp.psnr > 30.0, "psnr {}", cmp.psnr);
+    assert!(cmp.ssim > 0.9, "ssim {}", cmp.ssim);
+}
@@ -0,0 +1,206 @@
+use imagekit::config::{AvifColorSpace, ConfigError, ImageFormat, ImageKitConfig, PresetParams, TlsVersion};
+use imagekit::transform::params::FitMode;
+use std::path::PathBuf;
+use std::time::Duration;
+
+const SAMPLE_TOML: &str = r#"
+secret = "file-secret"
+cache_dir = "/var/cache/imagekit"
+max_input_size = 4194304
+max_cache_size = 1073741824
+allowed_formats = ["jpeg", "webp"]
+default_format = "jpeg"
+avif_speed = 6
+avif_colorspace = "bt709"
+original_cache_ttl_secs = 300
+cors_allowed_origins = ["https://app.example.com"]
+http2 = true
+http2_keep_alive_interval_secs = 20
+
+[default_quality]
+avif = 50
+
+[presets.thumb]
+w = 200
+h = 200
+fit = "cover"
+
+[origin_headers."private.example.com"]
+Authorization = "Bearer file-token"
+
+[tenants]
+acme = "acme-secret"
+"#;
+
+/// Helper to write `contents` to a process-unique config file
+fn write_config(name: &str, contents: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("imagekit-{}-{}.toml", name, std::process::id()));
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+// Env vars are process-global, so every from_file case lives in one test
+#[test]
+fn test_from_file_and_env_overrides() {
+    let path = write_config("sample", SAMPLE_TOML);
+
+    let config = ImageKitConfig::from_file(&path).unwrap();
+    assert_eq!(config.secret, "file-secret");
+    assert_eq!(config.cache_dir, PathBuf::from("/var/cache/imagekit"));
+    assert_eq!(config.max_input_size, 4 * 1024 * 1024);
+    assert_eq!(config.max_cache_size, Some(1024 * 1024 * 1024));
+    assert_eq!(config.allowed_formats, vec![ImageFormat::jpeg, ImageFormat::webp]);
+    assert_eq!(config.default_format, Some(ImageFormat::jpeg));
+    assert_eq!(config.avif_speed, 6);
+    assert_eq!(config.avif_colorspace, AvifColorSpace::Bt709);
+    assert_eq!(config.original_cache_ttl, Some(Duration::from_secs(300)));
+    assert_eq!(config.cors_allowed_origins, vec!["https://app.example.com".to_string()]);
+    // File qualities merge over the built-in per-format defaults
+    assert_eq!(config.quality_for(ImageFormat::avif), 50);
+    assert_eq!(config.quality_for(ImageFormat::jpeg), 82);
+    assert_eq!(
+        config.presets["thumb"],
+        PresetParams { w: Some(200), h: Some(200), fit: Some(FitMode::Cover), ..Default::default() }
+    );
+    assert_eq!(
+        config.origin_headers_for("https://private.example.com/a.jpg").unwrap()["Authorization"],
+        "Bearer file-token"
+    );
+    assert!(config.origin_headers_for("https://public.example.com/a.jpg").is_none());
+    assert_eq!(config.tenants["acme"], "acme-secret");
+    let tuning = config.server_tuning();
+    assert!(tuning.http2);
+    assert!(!ImageKitConfig::default().server_tuning().http2, "h2c is opt-in");
+    assert_eq!(tuning.http2_keep_alive_interval, Some(Duration::from_secs(20)));
+    assert!(tuning.tcp_nodelay && tuning.http1_keep_alive);
+    // Keys absent from the file keep their defaults
+    assert_eq!(config.max_input_pixels, ImageKitConfig::default().max_input_pixels);
+
+    std::env::set_var("IMAGEKIT_SECRET", "env-secret");
+    std::env::set_var("IMAGEKIT_AVIF_SPEED", "2");
+    std::env::set_var("IMAGEKIT_DEFAULT_FORMAT", "avif");
+    let overridden = ImageKitConfig::from_file(&path);
+    std::env::set_var("IMAGEKIT_AVIF_SPEED", "fast");
+    let malformed = ImageKitConfig::from_file(&path);
+    for var in ["IMAGEKIT_SECRET", "IMAGEKIT_AVIF_SPEED", "IMAGEKIT_DEFAULT_FORMAT"] {
+        std::env::remove_var(var);
+    }
+
+    let overridden = overridden.unwrap();
+    assert_eq!(overridden.secret, "env-secret");
+    assert_eq!(overridden.avif_speed, 2);
+    assert_eq!(overridden.default_format, Some(ImageFormat::avif));
+    assert_eq!(overridden.cache_dir, PathBuf::from("/var/cache/imagekit"), "non-overridden values come from the file");
+
+    assert!(matches!(malformed, Err(ConfigError::InvalidEnv { name: "IMAGEKIT_AVIF_SPEED", .. })));
+
+    let _ = std::fs::remove_file(&path);
+
+    // Unknown keys and values failing `validate()` are rejected
+    let unknown = write_config("unknown", "secret = \"s\"\nsecert = \"typo\"\n");
+    assert!(matches!(ImageKitConfig::from_file(&unknown), Err(ConfigError::Parse(_))));
+
+    let invalid = write_config("invalid", "secret = \"s\"\navif_speed = 42\n");
+    assert!(matches!(ImageKitConfig::from_file(&invalid), Err(ConfigError::InvalidAvifSpeed)));
+
+    let quota = write_config("quota", "secret = \"s\"\n[tenant_quotas]\nacme = 5\n");
+    assert!(matches!(ImageKitConfig::from_file(&quota), Err(ConfigError::UnknownQuotaTenant(_))));
+
+    let _ = std::fs::remove_file(&unknown);
+    let _ = std::fs::remove_file(&invalid);
+    let _ = std::fs::remove_file(&quota);
+}
+
+#[test]
+fn test_builder_happy_path() {
+    let config = ImageKitConfig::builder()
+        .secret("builder-secret")
+        .cache_dir("/tmp/imagekit-builder")
+        .max_cache_size(None)
+        .allowed_formats([ImageFormat::webp])
+        .default_format(ImageFormat::avif)
+        .avif_speed(8)
+        .original_cache_ttl(Duration::from_secs(60))
+        .cors_allowed_origins(["*"])
+        .strict_params(true)
+        .default_quality(ImageFormat::webp, 70)
+        .build()
+        .unwrap();
+
+    assert_eq!(config.secret, "builder-secret");
+    assert_eq!(config.cache_dir, PathBuf::from("/tmp/imagekit-builder"));
+    assert_eq!(config.max_cache_size, None);
+    assert_eq!(config.allowed_formats, vec![ImageFormat::webp]);
+    assert_eq!(config.default_format, Some(ImageFormat::avif));
+    assert_eq!(config.avif_speed, 8);
+    assert_eq!(config.original_cache_ttl, Some(Duration::from_secs(60)));
+    assert_eq!(config.cors_allowed_origins, vec!["*".to_string()]);
+    assert!(config.strict_params);
+    assert_eq!(config.quality_for(ImageFormat::webp), 70);
+    assert_eq!(config.quality_for(ImageFormat::avif), 55);
+    // Formats without a default use DEFAULT_QUALITY
+    assert_eq!(config.quality_for(ImageFormat::png), 80);
+    // Untouched settings keep their defaults
+    assert_eq!(config.max_input_size, ImageKitConfig::default().max_input_size);
+}
+
+#[test]
+fn test_fetch_tls_defaults_secure_and_client_builds() {
+    let defaults = ImageKitConfig::default();
+    assert_eq!(defaults.min_tls_version, TlsVersion::Tls1_2);
+    assert!(!defaults.accept_invalid_certs, "certificate checks must be on by default");
+    assert!(defaults.fetch_client().is_ok());
+
+    let insecure = ImageKitConfig::builder()
+        .secret("s")
+        .min_tls_version(TlsVersion::Tls1_2)
+        .accept_invalid_certs(true)
+        .build()
+        .unwrap();
+    assert!(insecure.accept_invalid_certs);
+    assert!(insecure.fetch_client().is_ok());
+}
+
+#[test]
+fn test_shared_http_clients_build_and_are_reused() {
+    let mut config = ImageKitConfig::default();
+    assert!(config.http_clients.is_none(), "clients are built by router(), not by default");
+    config.http_clients = Some(config.build_http_clients().unwrap());
+    assert!(config.fetch_client().is_ok());
+}
+
+#[test]
+fn test_default_output_format_stays_within_allowed_formats() {
+    let config = |allowed: Vec<ImageFormat>, default: Option<ImageFormat>| ImageKitConfig {
+        allowed_formats: allowed,
+        default_format: default,
+        ..Default::default()
+    };
+
+    assert_eq!(config(vec![ImageFormat::jpeg, ImageFormat::avif], Some(ImageFormat::avif)).default_output_format(), ImageFormat::avif);
+    // A disallowed default falls back to WebP, then the first allowed format
+    assert_eq!(config(vec![ImageFormat::jpeg, ImageFormat::webp], Some(ImageFormat::avif)).default_output_format(), ImageFormat::webp);
+    assert_eq!(config(vec![ImageFormat::jpeg], Some(ImageFormat::avif)).default_output_format(), ImageFormat::jpeg);
+    assert_eq!(config(vec![ImageFormat::png], None).default_output_format(), ImageFormat::png);
+}
+
+#[test]
+fn test_builder_surfaces_validation_errors() {
+    assert!(matches!(ImageKitConfig::builder().build(), Err(ConfigError::EmptySecret)));
+    assert!(matches!(
+        ImageKitConfig::builder().secret("s").avif_speed(11).build(),
+        Err(ConfigError::InvalidAvifSpeed)
+    ));
+    assert!(matches!(
+        ImageKitConfig::builder().secret("s").default_quality(ImageFormat::jpeg, 0).build(),
+        Err(ConfigError::InvalidDefaultQuality(ImageFormat::jpeg))
+    ));
+    assert!(matches!(
+        ImageKitConfig::builder().secret("s").tenant("acme", " ").build(),
+        Err(ConfigError::EmptyTenantSecret(tenant)) if tenant == "acme"
+    ));
+    assert!(matches!(
+        ImageKitConfig::builder().secret("s").tenant("acme", "a").tenant_quota("acmee", 10).build(),
+        Err(ConfigError::UnknownQuotaTenant(tenant)) if tenant == "acmee"
+    ));
+}
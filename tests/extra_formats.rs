@@ -0,0 +1,54 @@
+#![cfg(feature = "extra_formats")]
+
+use image::{DynamicImage, GenericImageView, Rgba, RgbaImage};
+use imagekit::cache::{content_type_from_format, format_from_extension};
+use imagekit::config::ImageFormat;
+use imagekit::transform::{decode_image, encode_image, encode_to_budget, sniff_output_format, EncodeOptions};
+
+/// Gradient with a translucent lower half, so alpha has to survive too
+fn fixture() -> DynamicImage {
+    DynamicImage::ImageRgba8(RgbaImage::from_fn(40, 30, |x, y| {
+        Rgba([(x * 6) as u8, (y * 8) as u8, 90, if y < 15 { 255 } else { 128 }])
+    }))
+}
+
+fn assert_round_trip(format: ImageFormat) {
+    let img = fixture();
+    let encoded = encode_image(&img, format, 80).unwrap();
+    assert_eq!(sniff_output_format(&encoded), Some(format));
+
+    let (decoded, detected) = decode_image(&encoded).unwrap();
+    assert_eq!(detected, Some(format));
+    assert_eq!(decoded.dimensions(), (40, 30));
+    // Uncompressed, so every pixel comes back exactly
+    assert_eq!(decoded.to_rgba8(), img.to_rgba8());
+}
+
+#[test]
+fn test_tiff_round_trip() {
+    assert_round_trip(ImageFormat::tiff);
+}
+
+#[test]
+fn test_bmp_round_trip() {
+    assert_round_trip(ImageFormat::bmp);
+}
+
+#[test]
+fn test_budget_encodes_uncompressed_formats_once() {
+    for format in [ImageFormat::tiff, ImageFormat::bmp] {
+        let (out, quality) = encode_to_budget(&fixture(), format, 80, 16, &EncodeOptions::default()).unwrap();
+        assert_eq!(out, encode_image(&fixture(), format, 80).unwrap());
+        assert_eq!(quality, 80);
+    }
+}
+
+#[test]
+fn test_extra_format_names_and_content_types() {
+    assert_eq!("tiff".parse::<ImageFormat>(), Ok(ImageFormat::tiff));
+    assert_eq!(ImageFormat::bmp.to_string(), "bmp");
+    assert_eq!(format_from_extension("tif"), Some(ImageFormat::tiff));
+    assert_eq!(format_from_extension("bmp"), Some(ImageFormat::bmp));
+    assert_eq!(content_type_from_format(ImageFormat::tiff), "image/tiff");
+    assert_eq!(content_type_from_format(ImageFormat::bmp), "image/bmp");
+}
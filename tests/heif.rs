@@ -0,0 +1,49 @@
+#![cfg(feature = "heif")]
+
+use imagekit::transform::{decode_image, is_heif, resize_image};
+use image::GenericImageView;
+use libheif_rs::{Channel, ColorSpace, CompressionFormat, EncoderQuality, HeifContext, Image, LibHeif, RgbChroma};
+
+/// Helper to build a small HEIC fixture (64x48) via libheif's HEVC encoder
+fn heic_fixture() -> Vec<u8> {
+    let lib_heif = LibHeif::new();
+    let mut image = Image::new(64, 48, ColorSpace::Rgb(RgbChroma::Rgb)).unwrap();
+    image.create_plane(Channel::Interleaved, 64, 48, 8).unwrap();
+
+    let planes = image.planes_mut();
+    let plane = planes.interleaved.unwrap();
+    let stride = plane.stride;
+    for y in 0..48usize {
+        for x in 0..64usize {
+            let i = y * stride + x * 3;
+            plane.data[i] = (x * 4) as u8;
+            plane.data[i + 1] = (y * 5) as u8;
+            plane.data[i + 2] = 128;
+        }
+    }
+
+    let mut context = HeifContext::new().unwrap();
+    let mut encoder = lib_heif.encoder_for_format(CompressionFormat::Hevc).unwrap();
+    encoder.set_quality(EncoderQuality::Lossy(80)).unwrap();
+    context.encode_image(&image, &mut encoder, None).unwrap();
+    context.write_to_bytes().unwrap()
+}
+
+#[test]
+fn test_heic_decodes_to_expected_dimensions() {
+    let heic = heic_fixture();
+    assert!(is_heif(&heic), "Fixture should be detected as HEIF");
+
+    let (img, format) = decode_image(&heic).unwrap();
+
+    assert_eq!(img.dimensions(), (64, 48));
+    assert_eq!(format, None, "HEIC is an input-only format");
+}
+
+#[test]
+fn test_heic_flows_through_resize() {
+    let (img, _) = decode_image(&heic_fixture()).unwrap();
+    let resized = resize_image(img, Some(32), None).unwrap();
+
+    assert_eq!(resized.dimensions(), (32, 24));
+}
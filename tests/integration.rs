@@ -1,7 +1,13 @@
 use axum::body::Body;
 use axum::http::{Request, StatusCode};
-use imagekit::config::{ImageFormat, ImageKitConfig};
+use axum::response::IntoResponse;
+use imagekit::backpressure::TransformLimiter;
+use imagekit::cache::SourceCache;
+use imagekit::config::{ImageFormat, ImageKitConfig, InputFormat};
+use imagekit::observer::TransformObserver;
+use imagekit::quota::QuotaTracker;
 use imagekit::router;
+use std::sync::{Arc, Mutex};
 use std::collections::BTreeMap;
 use tower::util::ServiceExt; // for `oneshot`
 use serde_json::Value;
@@ -15,27 +21,118 @@ fn test_config() -> ImageKitConfig {
         secret: "test-secret-key".to_string(),
         cache_dir: std::path::PathBuf::from("./test-cache"),
         max_input_size: 8 * 1024 * 1024,
-        allowed_formats: vec![ImageFormat::jpeg, ImageFormat::webp, ImageFormat::avif],
+        allowed_formats: vec![ImageFormat::jpeg, ImageFormat::webp, ImageFormat::avif, ImageFormat::png],
         default_format: Some(ImageFormat::webp),
+        // Mock origins are plain http
+        allowed_schemes: vec!["http".to_string(), "https".to_string()],
+        ..Default::default()
     }
 }
 
+/// Helper to encode a solid-color PNG fixture
+fn png_fixture(width: u32, height: u32) -> Vec<u8> {
+    let img = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+        width,
+        height,
+        image::Rgba([200, 80, 40, 255]),
+    ));
+    let mut png = Vec::new();
+    img.write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png).unwrap();
+    png
+}
+
+/// PNG header declaring 100000x100000 RGBA pixels with no real image data
+const PNG_BOMB: &[u8] = &[
+    0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, 0x00, 0x00, 0x00, 0x0D, 0x49, 0x48, 0x44, 0x52,
+    0x00, 0x01, 0x86, 0xA0, 0x00, 0x01, 0x86, 0xA0, 0x08, 0x06, 0x00, 0x00, 0x00, 0xA8, 0x52, 0x0B,
+    0xC8, 0x00, 0x00, 0x00, 0x09, 0x49, 0x44, 0x41, 0x54, 0x78, 0x9C, 0x63, 0x00, 0x00, 0x00, 0x01,
+    0x00, 0x01, 0x5E, 0xFF, 0x7D, 0xF9, 0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4E, 0x44, 0xAE, 0x42,
+    0x60, 0x82,
+];
+
+/// Helper to spawn a local origin serving `body` as a PNG; returns the image URL
+async fn spawn_origin(body: Vec<u8>) -> String {
+    let app = axum::Router::new().route(
+        "/image.png",
+        axum::routing::get(move || {
+            let body = body.clone();
+            async move { ([(axum::http::header::CONTENT_TYPE, "image/png")], body) }
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+    format!("http://{}/image.png", addr)
+}
+
+/// Like `spawn_origin`, also returning a counter of requests served
+async fn spawn_counting_origin(body: Vec<u8>) -> (String, Arc<std::sync::atomic::AtomicUsize>) {
+    let hits = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let counter = hits.clone();
+    let app = axum::Router::new().route(
+        "/image.png",
+        axum::routing::get(move || {
+            counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            let body = body.clone();
+            async move { ([(axum::http::header::CONTENT_TYPE, "image/png")], body) }
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+    (format!("http://{}/image.png", addr), hits)
+}
+
+/// Helper to build a signed `/img` URI from transformation params
+fn signed_img_uri(params: &BTreeMap<String, String>) -> String {
+    let sig = compute_signature(params, "test-secret-key");
+    let query = serde_urlencoded::to_string(params).unwrap();
+    format!("/img?{}&sig={}", query, sig)
+}
+
+/// Like `signed_img_uri`, for `/debug/cache-key`
+fn signed_debug_uri(params: &BTreeMap<String, String>) -> String {
+    signed_img_uri(params).replacen("/img", "/debug/cache-key", 1)
+}
+
 /// Helper to compute signature
 fn compute_signature(params: &BTreeMap<String, String>, secret: &str) -> String {
     use hmac::{Hmac, Mac};
     use sha2::Sha256;
     
-    let canonical: String = params
-        .iter()
-        .map(|(k, v)| format!("{}={}", k, v))
-        .collect::<Vec<_>>()
-        .join("&");
+    // Signing string: sorted, URL-encoded key=value pairs
+    let canonical = serde_urlencoded::to_string(params).unwrap();
     
     let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
     mac.update(canonical.as_bytes());
     hex::encode(mac.finalize().into_bytes())
 }
 
+/// Helper to build a `POST /upload` request carrying `file` as the only field
+fn upload_request(filename: &str, content_type: &str, file: &[u8]) -> Request<Body> {
+    let boundary = "imagekit-boundary";
+    let mut body = Vec::new();
+    body.extend_from_slice(format!(
+        "--{b}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{f}\"\r\n\
+         Content-Type: {t}\r\n\r\n",
+        b = boundary, f = filename, t = content_type
+    ).as_bytes());
+    body.extend_from_slice(file);
+    body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+
+    Request::builder()
+        .method("POST")
+        .uri("/upload")
+        .header("content-type", format!("multipart/form-data; boundary={}", boundary))
+        .header("content-length", body.len())
+        .body(Body::from(body))
+        .unwrap()
+}
+
 #[tokio::test]
 async fn test_sign_endpoint() {
     let app = router(test_config());
@@ -67,6 +164,816 @@ async fn test_sign_endpoint() {
     assert!(canonical.contains("w=400"));
 }
 
+#[tokio::test]
+async fn test_openapi_spec_describes_img_and_sign() {
+    let app = router(test_config());
+
+    let response = app
+        .oneshot(Request::builder().uri("/openapi.json").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert!(json["openapi"].is_string());
+    assert!(json["paths"]["/img"]["get"].is_object());
+    assert!(json["paths"]["/sign"]["get"].is_object());
+
+    let params = json["paths"]["/img"]["get"]["parameters"].as_array().unwrap();
+    assert!(params.iter().any(|p| p["name"] == "url" && p["required"] == true));
+    assert!(params.iter().any(|p| p["name"] == "sig"));
+}
+
+#[tokio::test]
+async fn test_blurhash_endpoint_and_img_header() {
+    let origin = spawn_origin(png_fixture(48, 32)).await;
+    let app = router(ImageKitConfig { blurhash_header: true, ..test_config() });
+
+    let mut params = BTreeMap::new();
+    params.insert("url".to_string(), origin);
+    let uri = signed_img_uri(&params).replacen("/img", "/blurhash", 1);
+    let response = app.clone().oneshot(Request::builder().uri(&uri).body(Body::empty()).unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    let hash = json["blurhash"].as_str().unwrap().to_string();
+    assert!(hash.starts_with('L') && hash.len() == 28, "unexpected blurhash {}", hash);
+    assert_eq!((json["width"].as_u64(), json["height"].as_u64()), (Some(48), Some(32)));
+
+    // Untransformed, so the /img output hashes like the source
+    params.insert("f".to_string(), "png".to_string());
+    let response = app.clone().oneshot(Request::builder().uri(signed_img_uri(&params)).body(Body::empty()).unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["x-imagekit-blurhash"], hash.as_str());
+    // A hit serves the hash stored with the entry
+    let response = app.clone().oneshot(Request::builder().uri(signed_img_uri(&params)).body(Body::empty()).unwrap()).await.unwrap();
+    assert_eq!(response.headers()["x-imagekit-blurhash"], hash.as_str());
+    // Hashed before encoding, so AVIF output gets one too
+    params.insert("f".to_string(), "avif".to_string());
+    let response = app.clone().oneshot(Request::builder().uri(signed_img_uri(&params)).body(Body::empty()).unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["x-imagekit-blurhash"], hash.as_str());
+
+    let unsigned = uri.split("&sig=").next().unwrap().to_string() + "&sig=00";
+    let response = app.oneshot(Request::builder().uri(unsigned).body(Body::empty()).unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_blurhash_header_on_entries_cached_without_it() {
+    let origin = spawn_origin(png_fixture(48, 32)).await;
+    let cache_dir = std::env::temp_dir().join(format!("imagekit-legacy-blurhash-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&cache_dir);
+    let config = || ImageKitConfig { cache_dir: cache_dir.clone(), ..test_config() };
+    let sidecars = || {
+        std::fs::read_dir(&cache_dir)
+            .unwrap()
+            .filter(|e| e.as_ref().unwrap().path().extension().is_some_and(|ext| ext == "blurhash"))
+            .count()
+    };
+
+    // Cached before the header was turned on, so no hash is stored
+    let legacy = router(config());
+    let mut uris = Vec::new();
+    for format in ["avif", "png"] {
+        let mut params = BTreeMap::new();
+        params.insert("url".to_string(), origin.clone());
+        params.insert("f".to_string(), format.to_string());
+        let uri = signed_img_uri(&params);
+        let response = legacy.clone().oneshot(Request::builder().uri(&uri).body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        uris.push(uri);
+    }
+    assert_eq!(sidecars(), 0);
+
+    let app = router(ImageKitConfig { blurhash_header: true, ..config() });
+    // AVIF entries can't be decoded: served without the header, nothing stored
+    for _ in 0..2 {
+        let response = app.clone().oneshot(Request::builder().uri(&uris[0]).body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().get("x-imagekit-blurhash").is_none());
+    }
+    assert_eq!(sidecars(), 0);
+    // Decodable entries are hashed once and the hash stored
+    let response = app.oneshot(Request::builder().uri(&uris[1]).body(Body::empty()).unwrap()).await.unwrap();
+    assert!(response.headers().get("x-imagekit-blurhash").is_some());
+    assert_eq!(sidecars(), 1);
+
+    let _ = std::fs::remove_dir_all(&cache_dir);
+}
+
+#[tokio::test]
+async fn test_lqip_returns_signed_data_uri() {
+    let origin = spawn_origin(png_fixture(400, 200)).await;
+    let app = router(test_config());
+
+    let mut params = BTreeMap::new();
+    params.insert("url".to_string(), origin);
+    let uri = signed_img_uri(&params).replacen("/img", "/lqip", 1);
+    let response = app.clone().oneshot(Request::builder().uri(&uri).body(Body::empty()).unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert!(json["data_uri"].as_str().unwrap().starts_with("data:image/jpeg;base64,"));
+    assert_eq!((json["width"].as_u64(), json["height"].as_u64()), (Some(400), Some(200)));
+
+    let unsigned = uri.split("&sig=").next().unwrap().to_string();
+    let response = app.oneshot(Request::builder().uri(unsigned).body(Body::empty()).unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_server_timing_reports_phases_on_miss() {
+    let origin = spawn_origin(png_fixture(64, 64)).await;
+    let app = router(test_config());
+
+    let mut params = BTreeMap::new();
+    params.insert("url".to_string(), origin);
+    params.insert("w".to_string(), "32".to_string());
+    let uri = signed_img_uri(&params);
+
+    let response = app.clone().oneshot(Request::builder().uri(&uri).body(Body::empty()).unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["timing-allow-origin"], "*");
+    let timing = response.headers()["server-timing"].to_str().unwrap().to_string();
+    let names: Vec<&str> = timing.split(", ").map(|m| m.split(';').next().unwrap()).collect();
+    assert_eq!(names, ["fetch", "decode", "resize", "encode", "total"], "got {}", timing);
+    assert!(timing.split(", ").all(|m| m.contains(";dur=")));
+
+    let response = app.oneshot(Request::builder().uri(&uri).body(Body::empty()).unwrap()).await.unwrap();
+    let timing = response.headers()["server-timing"].to_str().unwrap();
+    assert!(timing.starts_with("total;dur="), "cache hits only report total, got {}", timing);
+}
+
+#[tokio::test]
+async fn test_disallowed_output_format_is_rejected() {
+    let origin = spawn_origin(png_fixture(16, 16)).await;
+    let app = router(ImageKitConfig {
+        allowed_formats: vec![ImageFormat::jpeg, ImageFormat::webp],
+        default_format: Some(ImageFormat::avif),
+        ..test_config()
+    });
+
+    let mut params = BTreeMap::new();
+    params.insert("url".to_string(), origin);
+    let by_extension = signed_img_uri(&params).replacen("/img?", "/img.avif?", 1);
+    params.insert("f".to_string(), "avif".to_string());
+    for uri in [signed_img_uri(&params), by_extension] {
+        let response = app.clone().oneshot(Request::builder().uri(&uri).body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", uri);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"Output format not allowed: avif");
+    }
+
+    // Without `f`, the disallowed default gives way to WebP
+    params.remove("f");
+    let response = app.oneshot(Request::builder().uri(signed_img_uri(&params)).body(Body::empty()).unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["content-type"], "image/webp");
+}
+
+#[tokio::test]
+async fn test_enlarge_false_keeps_small_sources() {
+    let origin = spawn_origin(png_fixture(100, 100)).await;
+    let app = router(test_config());
+
+    for (enlarge, expected) in [(Some("false"), (100, 100)), (None, (500, 500))] {
+        let mut params = BTreeMap::new();
+        params.insert("url".to_string(), origin.clone());
+        params.insert("w".to_string(), "500".to_string());
+        params.insert("h".to_string(), "500".to_string());
+        params.insert("f".to_string(), "png".to_string());
+        if let Some(enlarge) = enlarge {
+            params.insert("enlarge".to_string(), enlarge.to_string());
+        }
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri(signed_img_uri(&params)).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let img = image::load_from_memory(&body).unwrap();
+        assert_eq!((img.width(), img.height()), expected, "enlarge={:?}", enlarge);
+    }
+
+    // Cover on a source narrower than the box only crops the long side
+    let origin = spawn_origin(png_fixture(100, 1000)).await;
+    let mut params = BTreeMap::new();
+    params.insert("url".to_string(), origin.clone());
+    params.insert("w".to_string(), "500".to_string());
+    params.insert("h".to_string(), "500".to_string());
+    params.insert("fit".to_string(), "cover".to_string());
+    params.insert("f".to_string(), "png".to_string());
+    params.insert("enlarge".to_string(), "false".to_string());
+    let response = app
+        .clone()
+        .oneshot(Request::builder().uri(signed_img_uri(&params)).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let img = image::load_from_memory(&body).unwrap();
+    assert_eq!((img.width(), img.height()), (100, 500));
+}
+
+#[tokio::test]
+async fn test_callback_url_is_notified_after_transform() {
+    use hmac::{Hmac, Mac};
+
+    let origin = spawn_origin(png_fixture(32, 32)).await;
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<(Option<String>, Vec<u8>)>();
+    let receiver = axum::Router::new().route(
+        "/done",
+        axum::routing::post(move |headers: axum::http::HeaderMap, body: axum::body::Bytes| {
+            let signature = headers.get("x-imagekit-signature").map(|v| v.to_str().unwrap().to_string());
+            tx.send((signature, body.to_vec())).unwrap();
+            async { StatusCode::NO_CONTENT }
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let callback_url = format!("http://{}/done", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        axum::serve(listener, receiver).await.unwrap();
+    });
+
+    let app = router(ImageKitConfig {
+        callback_hosts: vec!["127.0.0.1".to_string()],
+        tenants: std::collections::HashMap::from([("acme".to_string(), "acme-secret".to_string())]),
+        ..test_config()
+    });
+    let mut params = BTreeMap::new();
+    params.insert("url".to_string(), origin.clone());
+    params.insert("w".to_string(), "16".to_string());
+    params.insert("callback_url".to_string(), callback_url.clone());
+    let response = app
+        .clone()
+        .oneshot(Request::builder().uri(signed_img_uri(&params)).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let served = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+    let (signature, body) = tokio::time::timeout(std::time::Duration::from_secs(5), rx.recv())
+        .await
+        .expect("callback should arrive")
+        .unwrap();
+    let mut mac = Hmac::<sha2::Sha256>::new_from_slice(b"test-secret-key").unwrap();
+    mac.update(&body);
+    assert_eq!(signature, Some(hex::encode(mac.finalize().into_bytes())));
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["url"], origin.as_str());
+    assert_eq!(json["format"], "webp");
+    assert_eq!(json["bytes"].as_u64(), Some(served.len() as u64));
+    assert!(json["key"].is_string() && json["duration_ms"].is_u64());
+
+    // A cache hit stores nothing, so it announces nothing: the next callback
+    // to arrive is the tenant's, signed with the tenant's secret
+    let response = app
+        .clone()
+        .oneshot(Request::builder().uri(signed_img_uri(&params)).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let mut tenant_params = params.clone();
+    tenant_params.insert("w".to_string(), "8".to_string());
+    tenant_params.insert("tenant".to_string(), "acme".to_string());
+    let sig = compute_signature(&tenant_params, "acme-secret");
+    let uri = format!("/img?{}&sig={}", serde_urlencoded::to_string(&tenant_params).unwrap(), sig);
+    let response = app.clone().oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let served = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+    let (signature, body) = tokio::time::timeout(std::time::Duration::from_secs(5), rx.recv())
+        .await
+        .expect("tenant callback should arrive")
+        .unwrap();
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["bytes"].as_u64(), Some(served.len() as u64), "the hit must not have been announced");
+    let mut mac = Hmac::<sha2::Sha256>::new_from_slice(b"acme-secret").unwrap();
+    mac.update(&body);
+    assert_eq!(signature, Some(hex::encode(mac.finalize().into_bytes())));
+
+    // Hosts outside `callback_hosts` are refused before any work is done
+    params.insert("callback_url".to_string(), "http://example.com/done".to_string());
+    let response = app
+        .oneshot(Request::builder().uri(signed_img_uri(&params)).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_download_sets_sanitized_content_disposition() {
+    let origin = spawn_origin(png_fixture(16, 16)).await;
+    let app = router(test_config());
+
+    for (download, f, expected) in [
+        ("cat photo.png", "webp", "attachment; filename=\"cat photo.webp\""),
+        ("../../etc/pass\u{7}wd\"", "jpeg", "attachment; filename=\"passwd.jpeg\""),
+        ("café", "png", "attachment; filename=\"caf_.png\"; filename*=UTF-8''caf%C3%A9.png"),
+    ] {
+        let mut params = BTreeMap::new();
+        params.insert("url".to_string(), origin.clone());
+        params.insert("f".to_string(), f.to_string());
+        params.insert("download".to_string(), download.to_string());
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri(signed_img_uri(&params)).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-disposition"], expected, "download={:?}", download);
+    }
+
+    // Nothing usable left after sanitizing
+    let mut params = BTreeMap::new();
+    params.insert("url".to_string(), origin);
+    params.insert("download".to_string(), "../..".to_string());
+    let response = app
+        .oneshot(Request::builder().uri(signed_img_uri(&params)).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_info_reports_source_metadata() {
+    let png = png_fixture(48, 32);
+    let origin = spawn_origin(png.clone()).await;
+    let app = router(test_config());
+
+    let mut params = BTreeMap::new();
+    params.insert("url".to_string(), origin);
+    let uri = signed_img_uri(&params).replacen("/img", "/info", 1);
+    let response = app.clone().oneshot(Request::builder().uri(&uri).body(Body::empty()).unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!((json["width"].as_u64(), json["height"].as_u64()), (Some(48), Some(32)));
+    assert_eq!(json["format"], "png");
+    assert_eq!(json["color_type"], "Rgba8");
+    assert_eq!(json["has_alpha"], true);
+    assert_eq!(json["bytes"].as_u64(), Some(png.len() as u64));
+
+    let unsigned = uri.split("&sig=").next().unwrap().to_string();
+    let response = app.oneshot(Request::builder().uri(&unsigned).body(Body::empty()).unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_diff_compares_signed_outputs() {
+    let origin = spawn_origin(png_fixture(64, 64)).await;
+    let app = router(test_config());
+    let signed = |f: &str| {
+        let mut params = BTreeMap::new();
+        params.insert("url".to_string(), origin.clone());
+        params.insert("f".to_string(), f.to_string());
+        signed_img_uri(&params)
+    };
+    let diff = |a: &str, b: &str, extra: &str| {
+        format!(
+            "/diff?{}{}",
+            serde_urlencoded::to_string([("a", a), ("b", b)]).unwrap(),
+            extra
+        )
+    };
+
+    let uri = diff(&signed("png"), &signed("png"), "&threshold=0.99");
+    let response = app.clone().oneshot(Request::builder().uri(&uri).body(Body::empty()).unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["mse"], 0.0);
+    assert!(json["psnr"].is_null());
+    assert_eq!(json["within"], true);
+
+    // Lossy JPEG of a flat color is close to, but not exactly, the PNG
+    let uri = diff(&signed("png"), &signed("jpeg"), "");
+    let response = app.clone().oneshot(Request::builder().uri(&uri).body(Body::empty()).unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert!(json["psnr"].as_f64().unwrap() > 30.0, "{}", json);
+    assert!(json.get("within").is_none());
+
+    // Inner URLs must be signed
+    let unsigned = format!("/img?url={}", origin);
+    let uri = diff(&signed("png"), &unsigned, "");
+    let response = app.clone().oneshot(Request::builder().uri(&uri).body(Body::empty()).unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let uri = diff(&signed("png"), &signed("png").replace("sig=", "sig=00"), "");
+    let response = app.clone().oneshot(Request::builder().uri(&uri).body(Body::empty()).unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    // AVIF can't be decoded back, so it's refused rather than failing later
+    let uri = diff(&signed("png"), &signed("avif"), "");
+    let response = app.clone().oneshot(Request::builder().uri(&uri).body(Body::empty()).unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert!(String::from_utf8_lossy(&body).contains("AVIF"));
+}
+
+#[tokio::test]
+async fn test_tuned_server_serves_health_over_http1_and_h2c() {
+    let config = ImageKitConfig {
+        tcp_nodelay: true,
+        http1_keep_alive: true,
+        http2: true,
+        http2_keep_alive_interval: Some(std::time::Duration::from_secs(5)),
+        ..test_config()
+    };
+    let tuning = config.server_tuning();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(imagekit::server::serve(listener, router(config), tuning));
+    let url = format!("http://{}/health", addr);
+
+    // Reused keep-alive connection, then HTTP/2 with prior knowledge on the same port
+    let http1 = reqwest::Client::builder().http1_only().build().unwrap();
+    let h2c = reqwest::Client::builder().http2_prior_knowledge().build().unwrap();
+    for (client, version) in [(&http1, reqwest::Version::HTTP_11), (&http1, reqwest::Version::HTTP_11), (&h2c, reqwest::Version::HTTP_2)] {
+        let response = client.get(&url).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        assert_eq!(response.version(), version);
+        let json: Value = response.json().await.unwrap();
+        assert_eq!(json["status"], "healthy");
+    }
+}
+
+#[tokio::test]
+async fn test_server_shutdown_lets_in_flight_requests_finish() {
+    let app = axum::Router::new().route(
+        "/slow",
+        axum::routing::get(|| async {
+            tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+            "done"
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    let server = tokio::spawn(imagekit::server::serve_with_shutdown(
+        listener,
+        app,
+        Default::default(),
+        async move {
+            let _ = stopped.await;
+        },
+    ));
+
+    let request = tokio::spawn(reqwest::get(format!("http://{}/slow", addr)));
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    stop.send(()).unwrap();
+
+    let response = request.await.unwrap().unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    assert_eq!(response.text().await.unwrap(), "done");
+    tokio::time::timeout(std::time::Duration::from_secs(5), server)
+        .await
+        .expect("server should stop once the request is done")
+        .unwrap();
+    assert!(reqwest::get(format!("http://{}/slow", addr)).await.is_err(), "listener should be closed");
+}
+
+#[tokio::test]
+async fn test_slow_origin_times_out_with_504() {
+    let app = axum::Router::new().route(
+        "/slow.png",
+        axum::routing::get(|| async {
+            tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+            ([(axum::http::header::CONTENT_TYPE, "image/png")], png_fixture(8, 8))
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+
+    let app = router(ImageKitConfig { fetch_timeout: std::time::Duration::from_millis(200), ..test_config() });
+    let mut params = BTreeMap::new();
+    params.insert("url".to_string(), format!("http://{}/slow.png", addr));
+    let started = std::time::Instant::now();
+    let response = app
+        .oneshot(Request::builder().uri(signed_img_uri(&params)).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+    assert!(started.elapsed() < std::time::Duration::from_secs(3), "the fetch wasn't cut short");
+}
+
+/// Origin that takes `delay` per request, returning the peak number of
+/// requests it was serving at once
+async fn spawn_slow_origin(delay: std::time::Duration) -> (String, Arc<std::sync::atomic::AtomicUsize>) {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let in_flight = Arc::new(AtomicUsize::new(0));
+    let peak = Arc::new(AtomicUsize::new(0));
+    let (counter, max) = (in_flight.clone(), peak.clone());
+    let body = png_fixture(8, 8);
+    let app = axum::Router::new().route(
+        "/image.png",
+        axum::routing::get(move || {
+            let (counter, max, body) = (counter.clone(), max.clone(), body.clone());
+            async move {
+                let now = counter.fetch_add(1, Ordering::SeqCst) + 1;
+                max.fetch_max(now, Ordering::SeqCst);
+                tokio::time::sleep(delay).await;
+                counter.fetch_sub(1, Ordering::SeqCst);
+                ([(axum::http::header::CONTENT_TYPE, "image/png")], body)
+            }
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+    (format!("http://{}/image.png", addr), peak)
+}
+
+#[tokio::test]
+async fn test_max_concurrent_fetches_bounds_outbound_requests() {
+    let (origin, peak) = spawn_slow_origin(std::time::Duration::from_millis(100)).await;
+    let app = router(ImageKitConfig {
+        fetch_limiter: Some(imagekit::backpressure::FetchLimiter::new(2, std::time::Duration::from_secs(10))),
+        ..test_config()
+    });
+    // The disk cache outlives the test run; a nonce keeps these misses
+    let nonce = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos();
+
+    // Distinct source URLs, so neither the cache nor coalescing merges them
+    let requests = (0..8).map(|i| {
+        let mut params = BTreeMap::new();
+        params.insert("url".to_string(), format!("{}?n={}-{}", origin, nonce, i));
+        params.insert("w".to_string(), "4".to_string());
+        let uri = signed_img_uri(&params);
+        let app = app.clone();
+        async move { app.oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap()).await.unwrap().status() }
+    });
+    for status in futures::future::join_all(requests).await {
+        assert_eq!(status, StatusCode::OK);
+    }
+    let peak = peak.load(std::sync::atomic::Ordering::SeqCst);
+    assert!(peak <= 2, "{} fetches ran at once", peak);
+    assert_eq!(peak, 2, "the limiter shouldn't serialize below its bound");
+
+    // A fetch that can't get a slot in time is turned away
+    let (origin, _) = spawn_slow_origin(std::time::Duration::from_millis(500)).await;
+    let app = router(ImageKitConfig {
+        fetch_limiter: Some(imagekit::backpressure::FetchLimiter::new(1, std::time::Duration::from_millis(50))),
+        ..test_config()
+    });
+    let requests = (0..2).map(|i| {
+        let mut params = BTreeMap::new();
+        params.insert("url".to_string(), format!("{}?queued={}-{}", origin, nonce, i));
+        let uri = signed_img_uri(&params);
+        let app = app.clone();
+        async move { app.oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap()).await.unwrap().status() }
+    });
+    let mut statuses = futures::future::join_all(requests).await;
+    statuses.sort();
+    assert_eq!(statuses, vec![StatusCode::OK, StatusCode::SERVICE_UNAVAILABLE]);
+}
+
+#[tokio::test]
+async fn test_tenant_quota_returns_429_and_reports_usage() {
+    let origin = spawn_origin(png_fixture(16, 16)).await;
+    let app = router(ImageKitConfig {
+        tenants: std::collections::HashMap::from([
+            ("acme".to_string(), "acme-secret".to_string()),
+            ("globex".to_string(), "globex-secret".to_string()),
+        ]),
+        quotas: Some(QuotaTracker::default().with_limit("acme", 2)),
+        ..test_config()
+    });
+    let img = |tenant: Option<&str>, secret: &str| {
+        let mut params = BTreeMap::new();
+        params.insert("url".to_string(), origin.clone());
+        if let Some(tenant) = tenant {
+            params.insert("tenant".to_string(), tenant.to_string());
+        }
+        let sig = compute_signature(&params, secret);
+        let uri = format!("/img?{}&sig={}", serde_urlencoded::to_string(&params).unwrap(), sig);
+        let app = app.clone();
+        async move { app.oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap()).await.unwrap() }
+    };
+    let quota = |tenant: &str, auth: &str| {
+        let request = Request::builder()
+            .uri(format!("/quota?tenant={}", tenant))
+            .header("authorization", auth)
+            .body(Body::empty())
+            .unwrap();
+        app.clone().oneshot(request)
+    };
+
+    // A forged signature doesn't count against the quota
+    assert_eq!(img(Some("acme"), "wrong-secret").await.status(), StatusCode::UNAUTHORIZED);
+    // Nor does a signed request that fails validation
+    let mut params = BTreeMap::new();
+    params.insert("url".to_string(), origin.clone());
+    params.insert("tenant".to_string(), "acme".to_string());
+    params.insert("q".to_string(), "0".to_string());
+    let sig = compute_signature(&params, "acme-secret");
+    let uri = format!("/img?{}&sig={}", serde_urlencoded::to_string(&params).unwrap(), sig);
+    let response = app.clone().oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(img(Some("acme"), "acme-secret").await.status(), StatusCode::OK);
+    assert_eq!(img(Some("acme"), "acme-secret").await.status(), StatusCode::OK);
+    let response = img(Some("acme"), "acme-secret").await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert!(String::from_utf8_lossy(&body).contains("quota exceeded"));
+
+    // Other tenants and the main secret are unlimited
+    assert_eq!(img(Some("globex"), "globex-secret").await.status(), StatusCode::OK);
+    assert_eq!(img(None, "test-secret-key").await.status(), StatusCode::OK);
+
+    let response = quota("acme", "Bearer acme-secret").await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["used"], 2);
+    assert_eq!(json["limit"], 2);
+    assert_eq!(json["remaining"], 0);
+
+    let response = quota("globex", "Bearer test-secret-key").await.unwrap();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["limit"], Value::Null);
+
+    // A tenant can't read another tenant's usage
+    assert_eq!(quota("acme", "Bearer globex-secret").await.unwrap().status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(quota("initech", "Bearer test-secret-key").await.unwrap().status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_tenants_sign_with_their_own_secrets() {
+    let origin = spawn_origin(png_fixture(16, 16)).await;
+    let app = router(ImageKitConfig {
+        tenants: std::collections::HashMap::from([
+            ("acme".to_string(), "acme-secret".to_string()),
+            ("globex".to_string(), "globex-secret".to_string()),
+        ]),
+        ..test_config()
+    });
+    let uri = |tenant: &str, secret: &str| {
+        let mut params = BTreeMap::new();
+        params.insert("url".to_string(), origin.clone());
+        params.insert("tenant".to_string(), tenant.to_string());
+        let sig = compute_signature(&params, secret);
+        format!("/img?{}&sig={}", serde_urlencoded::to_string(&params).unwrap(), sig)
+    };
+    let status = |uri: String| {
+        let app = app.clone();
+        async move {
+            app.oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap()).await.unwrap().status()
+        }
+    };
+
+    assert_eq!(status(uri("acme", "acme-secret")).await, StatusCode::OK);
+    assert_eq!(status(uri("globex", "globex-secret")).await, StatusCode::OK);
+    assert_eq!(status(uri("globex", "acme-secret")).await, StatusCode::UNAUTHORIZED);
+    assert_eq!(status(uri("acme", "test-secret-key")).await, StatusCode::UNAUTHORIZED);
+    assert_eq!(status(uri("initech", "test-secret-key")).await, StatusCode::UNAUTHORIZED);
+
+    // /sign only signs for a tenant when given the admin secret
+    let sign = |auth: Option<&str>| {
+        let mut request = Request::builder().uri("/sign?url=https://example.com/a.jpg&tenant=acme");
+        if let Some(auth) = auth {
+            request = request.header("authorization", auth);
+        }
+        app.clone().oneshot(request.body(Body::empty()).unwrap())
+    };
+    assert_eq!(sign(None).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(sign(Some("Bearer acme-secret")).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+    let response = sign(Some("Bearer test-secret-key")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    let mut expected = BTreeMap::new();
+    expected.insert("tenant".to_string(), "acme".to_string());
+    expected.insert("url".to_string(), "https://example.com/a.jpg".to_string());
+    assert_eq!(json["sig"], compute_signature(&expected, "acme-secret"));
+}
+
+#[tokio::test]
+async fn test_focal_point_keeps_edge_in_cover_crop() {
+    // 200x100 source: blue, with the right-most 50 columns red
+    let mut src = image::RgbImage::from_pixel(200, 100, image::Rgb([0, 0, 255]));
+    for x in 150..200 {
+        for y in 0..100 {
+            src.put_pixel(x, y, image::Rgb([255, 0, 0]));
+        }
+    }
+    let mut png = Vec::new();
+    image::DynamicImage::ImageRgb8(src).write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png).unwrap();
+    let origin = spawn_origin(png).await;
+    let app = router(test_config());
+
+    let mut params = BTreeMap::new();
+    params.insert("url".to_string(), origin);
+    params.insert("w".to_string(), "100".to_string());
+    params.insert("h".to_string(), "100".to_string());
+    params.insert("fit".to_string(), "cover".to_string());
+    params.insert("fp_x".to_string(), "0.95".to_string());
+    params.insert("fp_y".to_string(), "0.5".to_string());
+    params.insert("f".to_string(), "png".to_string());
+    let uri = signed_img_uri(&params);
+
+    let response = app.clone()
+        .oneshot(Request::builder().uri(uri.clone()).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let img = image::load_from_memory(&body).unwrap().to_rgb8();
+
+    // The window is clamped to the right edge, so the red band fills its right half
+    assert_eq!(img.dimensions(), (100, 100));
+    assert_eq!(img.get_pixel(99, 50).0, [255, 0, 0]);
+    assert_eq!(img.get_pixel(60, 50).0, [255, 0, 0]);
+    assert_eq!(img.get_pixel(0, 50).0, [0, 0, 255]);
+
+    // The focal point is signed
+    let tampered = uri.replace("fp_x=0.95", "fp_x=0.05");
+    let response = app
+        .oneshot(Request::builder().uri(tampered).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_fit_pad_centers_on_exact_canvas() {
+    let origin = spawn_origin(png_fixture(160, 90)).await;
+    let app = router(test_config());
+
+    let mut params = BTreeMap::new();
+    params.insert("url".to_string(), origin);
+    params.insert("w".to_string(), "100".to_string());
+    params.insert("h".to_string(), "100".to_string());
+    params.insert("fit".to_string(), "pad".to_string());
+    params.insert("bg".to_string(), "0000ff".to_string());
+    params.insert("f".to_string(), "png".to_string());
+
+    let response = app
+        .oneshot(Request::builder().uri(signed_img_uri(&params)).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let img = image::load_from_memory(&body).unwrap().to_rgb8();
+
+    // 16:9 scales to 100x56, leaving 22px bars above and below
+    assert_eq!(img.dimensions(), (100, 100));
+    for (x, y) in [(0, 0), (50, 10), (99, 99), (50, 90)] {
+        assert_eq!(img.get_pixel(x, y).0, [0, 0, 255], "bar pixel at ({}, {})", x, y);
+    }
+    for (x, y) in [(0, 50), (50, 50), (99, 50)] {
+        assert_eq!(img.get_pixel(x, y).0, [200, 80, 40], "image pixel at ({}, {})", x, y);
+    }
+}
+
+#[tokio::test]
+async fn test_transform_headers_report_miss_then_hit() {
+    let origin = spawn_origin(png_fixture(64, 32)).await;
+    let cache_dir = std::env::temp_dir().join(format!("imagekit-xheaders-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&cache_dir);
+    let app = router(ImageKitConfig {
+        expose_transform_headers: true,
+        cache_dir: cache_dir.clone(),
+        ..test_config()
+    });
+
+    let mut params = BTreeMap::new();
+    params.insert("url".to_string(), origin.clone());
+    params.insert("w".to_string(), "32".to_string());
+    params.insert("f".to_string(), "webp".to_string());
+    params.insert("q".to_string(), "70".to_string());
+    let uri = signed_img_uri(&params);
+
+    let first = app.clone().oneshot(Request::builder().uri(&uri).body(Body::empty()).unwrap()).await.unwrap();
+    assert_eq!(first.status(), StatusCode::OK);
+    assert_eq!(first.headers()["x-imagekit-cache"], "MISS");
+    assert_eq!(first.headers()["x-imagekit-transform"], "w=32,h=16,f=webp,q=70,fit=contain");
+
+    let second = app.clone().oneshot(Request::builder().uri(&uri).body(Body::empty()).unwrap()).await.unwrap();
+    assert_eq!(second.status(), StatusCode::OK);
+    assert_eq!(second.headers()["x-imagekit-cache"], "HIT");
+    assert_eq!(second.headers()["x-imagekit-transform"], "w=32,h=16,f=webp,q=70,fit=contain");
+
+    // Off by default
+    let app = router(ImageKitConfig { cache_dir: cache_dir.clone(), ..test_config() });
+    let response = app.oneshot(Request::builder().uri(&uri).body(Body::empty()).unwrap()).await.unwrap();
+    assert!(response.headers().get("x-imagekit-cache").is_none());
+    assert!(response.headers().get("x-imagekit-transform").is_none());
+
+    let _ = std::fs::remove_dir_all(&cache_dir);
+}
+
 #[tokio::test]
 async fn test_img_without_signature_fails() {
     let app = router(test_config());
@@ -81,7 +988,7 @@ async fn test_img_without_signature_fails() {
         .await
         .unwrap();
 
-    // Missing sig causes deserialization failure = 400 Bad Request
+    // Missing sig (neither query nor Authorization header) = 400 Bad Request
     assert_eq!(response.status(), StatusCode::BAD_REQUEST);
 }
 
@@ -261,8 +1168,1647 @@ async fn test_cache_key_consistency() {
     assert_eq!(key1, key2);
 }
 
-// Cleanup test cache directory after tests
 #[tokio::test]
-async fn cleanup_test_cache() {
+async fn test_cache_control_bounded_by_expiry() {
+    let origin = spawn_origin(png_fixture(64, 64)).await;
+    let app = router(test_config());
+
+    let now = time::OffsetDateTime::now_utc().unix_timestamp();
+    let mut params = BTreeMap::new();
+    params.insert("url".to_string(), origin);
+    params.insert("t".to_string(), (now + 120).to_string());
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri(signed_img_uri(&params))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let cache_control = response.headers()["cache-control"].to_str().unwrap();
+    let max_age: i64 = cache_control
+        .split(", ")
+        .find_map(|d| d.strip_prefix("max-age="))
+        .unwrap()
+        .parse()
+        .unwrap();
+
+    assert!(max_age <= 120, "max-age {} outlives the signed URL", max_age);
+    assert!(!cache_control.contains("immutable"));
+}
+
+#[tokio::test]
+async fn test_http_source_rejected_when_only_https_allowed() {
+    let (origin, hits) = spawn_counting_origin(png_fixture(8, 8)).await;
+
+    let https_only = ["https".to_string()];
+    let result = imagekit::fetch::fetch_source(&origin, 8 * 1024 * 1024, 1_000_000, &[], &https_only).await;
+    assert!(matches!(result, Err(imagekit::ImageKitError::InvalidArgument(_))));
+
+    // The default config is https-only
+    let app = router(ImageKitConfig { allowed_schemes: ImageKitConfig::default().allowed_schemes, ..test_config() });
+    let mut params = BTreeMap::new();
+    params.insert("url".to_string(), origin);
+    let response = app
+        .oneshot(Request::builder().uri(signed_img_uri(&params)).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(hits.load(std::sync::atomic::Ordering::SeqCst), 0, "no request may reach the origin");
+}
+
+#[tokio::test]
+async fn test_redirect_to_disallowed_scheme_rejected() {
+    // An http-only fetch redirected to https must stop at the redirect
+    // rather than follow it
+    let app = axum::Router::new().route(
+        "/image.png",
+        axum::routing::get(|| async { axum::response::Redirect::temporary("https://127.0.0.1:9/image.png") }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+
+    let url = format!("http://{}/image.png", addr);
+    let result = imagekit::fetch::fetch_source(&url, 8 * 1024 * 1024, 1_000_000, &[], &["http".to_string()]).await;
+    match result {
+        Err(imagekit::ImageKitError::InvalidArgument(msg)) => assert!(msg.contains("scheme not allowed"), "unexpected error: {}", msg),
+        other => panic!("expected the redirect to be rejected, got {:?}", other.map(|(_, ct)| ct)),
+    }
+}
+
+#[tokio::test]
+async fn test_fetch_rejects_huge_dimensions_from_header() {
+    let origin = spawn_origin(PNG_BOMB.to_vec()).await;
+
+    let result = imagekit::fetch::fetch_source(&origin, 8 * 1024 * 1024, 1_000_000, &[], &["http".to_string()]).await;
+
+    // The pixel-limit error can only come from the header check; a full
+    // decode of this file would fail on the missing pixel data instead.
+    let err = result.unwrap_err().to_string();
+    assert!(err.contains("exceed pixel limit"), "unexpected error: {}", err);
+}
+
+/// Observer recording every callback as a readable event string
+#[derive(Debug, Default)]
+struct RecordingObserver {
+    events: Mutex<Vec<String>>,
+}
+
+impl TransformObserver for RecordingObserver {
+    fn on_fetch(&self, url: &str, bytes: usize) {
+        self.events.lock().unwrap().push(format!("fetch {} {}", url, bytes));
+    }
+
+    fn on_transform(
+        &self,
+        src_dims: (u32, u32),
+        dst_dims: (u32, u32),
+        format: ImageFormat,
+        _duration: std::time::Duration,
+    ) {
+        self.events.lock().unwrap().push(format!(
+            "transform {}x{} -> {}x{} {}",
+            src_dims.0, src_dims.1, dst_dims.0, dst_dims.1, format
+        ));
+    }
+
+    fn on_cache_hit(&self, _key: &str) {
+        self.events.lock().unwrap().push("hit".to_string());
+    }
+
+    fn on_cache_miss(&self, _key: &str) {
+        self.events.lock().unwrap().push("miss".to_string());
+    }
+}
+
+#[tokio::test]
+async fn test_observer_callbacks_fire_during_transform() {
+    let png = png_fixture(64, 64);
+    let png_len = png.len();
+    let origin = spawn_origin(png).await;
+
+    let observer = Arc::new(RecordingObserver::default());
+    let app = router(ImageKitConfig {
+        observer: Some(observer.clone()),
+        ..test_config()
+    });
+
+    let mut params = BTreeMap::new();
+    params.insert("url".to_string(), origin.clone());
+    params.insert("w".to_string(), "32".to_string());
+    params.insert("f".to_string(), "webp".to_string());
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri(signed_img_uri(&params))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let events = observer.events.lock().unwrap().clone();
+    assert_eq!(events, vec![
+        "miss".to_string(),
+        format!("fetch {} {}", origin, png_len),
+        "transform 64x64 -> 32x32 webp".to_string(),
+    ]);
+}
+
+#[tokio::test]
+async fn test_image_responses_carry_nosniff() {
+    let origin = spawn_origin(png_fixture(16, 16)).await;
+    let app = router(test_config());
+
+    let mut params = BTreeMap::new();
+    params.insert("url".to_string(), origin);
+    params.insert("f".to_string(), "jpeg".to_string());
+
+    // First request is a miss, second is served from cache
+    for _ in 0..2 {
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri(signed_img_uri(&params)).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["x-content-type-options"], "nosniff");
+        assert_eq!(response.headers()["content-type"], "image/jpeg");
+    }
+
+    let boundary = "imagekit-boundary";
+    let mut body = Vec::new();
+    body.extend_from_slice(format!(
+        "--{b}\r\nContent-Disposition: form-data; name=\"f\"\r\n\r\nwebp\r\n\
+         --{b}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.png\"\r\n\
+         Content-Type: image/png\r\n\r\n",
+        b = boundary
+    ).as_bytes());
+    body.extend_from_slice(&png_fixture(16, 16));
+    body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/upload")
+                .header("content-type", format!("multipart/form-data; boundary={}", boundary))
+                .body(Body::from(body))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["x-content-type-options"], "nosniff");
+    assert_eq!(response.headers()["content-type"], "image/webp");
+}
+
+#[tokio::test]
+async fn test_wrap_json_returns_base64_envelope() {
+    use base64::Engine;
+
+    let origin = spawn_origin(png_fixture(40, 20)).await;
+    let app = router(test_config());
+
+    let mut params = BTreeMap::new();
+    params.insert("url".to_string(), origin);
+    params.insert("w".to_string(), "20".to_string());
+    params.insert("f".to_string(), "jpeg".to_string());
+    params.insert("wrap".to_string(), "json".to_string());
+
+    let response = app
+        .clone()
+        .oneshot(Request::builder().uri(signed_img_uri(&params)).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["content-type"], "application/json");
+    let envelope_etag = response.headers()["etag"].to_str().unwrap().to_string();
+
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["format"], "jpeg");
+    assert_eq!(json["width"], 20);
+    assert_eq!(json["height"], 10);
+
+    let data = base64::engine::general_purpose::STANDARD
+        .decode(json["data"].as_str().unwrap())
+        .unwrap();
+    let img = image::load_from_memory(&data).expect("envelope data decodes");
+    assert_eq!((img.width(), img.height()), (20, 10));
+
+    // The envelope validates on its own, not as the raw image it wraps
+    let mut raw_params = params.clone();
+    raw_params.remove("wrap");
+    let response = app
+        .clone()
+        .oneshot(Request::builder().uri(signed_img_uri(&raw_params)).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let raw_etag = response.headers()["etag"].to_str().unwrap().to_string();
+    assert_ne!(envelope_etag, raw_etag);
+    assert!(envelope_etag.ends_with("-json\""), "unexpected envelope ETag {}", envelope_etag);
+
+    let conditional = |etag: &str| {
+        Request::builder()
+            .uri(signed_img_uri(&params))
+            .header("if-none-match", etag)
+            .body(Body::empty())
+            .unwrap()
+    };
+    let response = app.clone().oneshot(conditional(&raw_etag)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = app.oneshot(conditional(&envelope_etag)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+}
+
+#[tokio::test]
+async fn test_transform_raw_body() {
+    let app = router(test_config());
+
+    let mut params = BTreeMap::new();
+    params.insert("w".to_string(), "100".to_string());
+    params.insert("f".to_string(), "webp".to_string());
+    let sig = compute_signature(&params, "test-secret-key");
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri(format!("/transform?w=100&f=webp&sig={}", sig))
+                .header("content-type", "image/png")
+                .body(Body::from(png_fixture(200, 100)))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["content-type"], "image/webp");
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let img = image::load_from_memory(&body).unwrap();
+    assert_eq!((img.width(), img.height()), (100, 50));
+
+    // Tampered parameters are rejected
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri(format!("/transform?w=200&f=webp&sig={}", sig))
+                .body(Body::from(png_fixture(200, 100)))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_sign_without_url_signs_transform_params() {
+    let app = router(test_config());
+
+    let response = app
+        .clone()
+        .oneshot(Request::builder().uri("/sign?w=100&f=webp").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    let signed_url = json["signed_url"].as_str().unwrap();
+    assert!(signed_url.starts_with("/transform?"), "{}", signed_url);
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri(signed_url)
+                .body(Body::from(png_fixture(200, 100)))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK, "signed_url from /sign must verify");
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(image::load_from_memory(&body).unwrap().width(), 100);
+
+    // Params /transform doesn't take can't be signed without a `url`
+    for query in ["w=100&fit=cover", "f=auto", "q=auto", "tenant=acme"] {
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri(format!("/sign?{}", query)).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", query);
+    }
+}
+
+#[tokio::test]
+async fn test_overlong_url_is_rejected() {
+    let app = router(ImageKitConfig { max_url_length: 64, ..test_config() });
+    let url = format!("https://example.com/{}.png", "a".repeat(100));
+
+    let mut params = BTreeMap::new();
+    params.insert("url".to_string(), url.clone());
+    let response = app
+        .clone()
+        .oneshot(Request::builder().uri(signed_img_uri(&params)).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = app
+        .oneshot(Request::builder().uri(format!("/sign?url={}", url)).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_oversized_body_is_rejected() {
+    let app = router(ImageKitConfig { max_input_size: 1024, ..test_config() });
+    let body = vec![0u8; 128 * 1024];
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/transform?w=10")
+                .header("content-length", body.len())
+                .body(Body::from(body.clone()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    let response = app.oneshot(upload_request("big.png", "image/png", &body)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+#[tokio::test]
+async fn test_avif_encode_timeout_returns_504() {
+    let app = router(ImageKitConfig {
+        avif_speed: 1,
+        avif_encode_timeout: std::time::Duration::from_millis(1),
+        ..test_config()
+    });
+
+    let mut params = BTreeMap::new();
+    params.insert("f".to_string(), "avif".to_string());
+    let sig = compute_signature(&params, "test-secret-key");
+    let body = png_fixture(1024, 1024);
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri(format!("/transform?f=avif&sig={}", sig))
+                .header("content-length", body.len())
+                .body(Body::from(body))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+}
+
+#[tokio::test]
+async fn test_avif_timeout_keeps_transform_slot_until_encode_ends() {
+    let origin = spawn_origin(png_fixture(1024, 1024)).await;
+    let limiter = TransformLimiter::new(1);
+    let app = router(ImageKitConfig {
+        avif_speed: 1,
+        avif_encode_timeout: std::time::Duration::from_millis(1),
+        transform_limiter: Some(limiter.clone()),
+        ..test_config()
+    });
+
+    let mut params = BTreeMap::new();
+    params.insert("url".to_string(), origin);
+    params.insert("f".to_string(), "avif".to_string());
+    let response = app
+        .oneshot(Request::builder().uri(signed_img_uri(&params)).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+
+    // The abandoned encode still occupies the slot, then hands it back
+    assert!(limiter.try_acquire().is_none(), "slot freed while the encode still runs");
+    let mut freed = None;
+    for _ in 0..600 {
+        freed = limiter.try_acquire();
+        if freed.is_some() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    assert!(freed.is_some(), "slot never released");
+}
+
+#[tokio::test]
+async fn test_backpressure_returns_retry_after() {
+    let limiter = TransformLimiter::new(1);
+    limiter.record(ImageFormat::webp, std::time::Duration::from_millis(2_500));
+    let app = router(ImageKitConfig {
+        transform_limiter: Some(limiter.clone()),
+        ..test_config()
+    });
+
+    // Occupy the only transform slot
+    let _busy = limiter.try_acquire().unwrap();
+
+    let mut params = BTreeMap::new();
+    params.insert("url".to_string(), "http://127.0.0.1:9/backpressure.png".to_string());
+    params.insert("f".to_string(), "webp".to_string());
+
+    let response = app
+        .oneshot(Request::builder().uri(signed_img_uri(&params)).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    let retry_after: u64 = response.headers()["retry-after"].to_str().unwrap().parse().unwrap();
+    assert!((1..=60).contains(&retry_after), "Retry-After out of range: {}", retry_after);
+    assert_eq!(retry_after, 3, "Retry-After should round the p95 up");
+}
+
+#[tokio::test]
+async fn test_original_passthrough_reuses_cached_original() {
+    let png = png_fixture(24, 24);
+    let origin = spawn_origin(png.clone()).await;
+
+    let observer = Arc::new(RecordingObserver::default());
+    let app = router(ImageKitConfig {
+        observer: Some(observer.clone()),
+        original_cache_ttl: Some(std::time::Duration::from_secs(60)),
+        ..test_config()
+    });
+
+    let mut transform = BTreeMap::new();
+    transform.insert("url".to_string(), origin.clone());
+    transform.insert("w".to_string(), "12".to_string());
+    transform.insert("f".to_string(), "webp".to_string());
+
+    let response = app
+        .clone()
+        .oneshot(Request::builder().uri(signed_img_uri(&transform)).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let mut original = BTreeMap::new();
+    original.insert("url".to_string(), origin);
+    original.insert("f".to_string(), "original".to_string());
+
+    let response = app
+        .oneshot(Request::builder().uri(signed_img_uri(&original)).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["content-type"], "image/png");
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(body.as_ref(), png.as_slice(), "passthrough must return the source bytes untouched");
+
+    let fetches = observer.events.lock().unwrap().iter().filter(|e| e.starts_with("fetch")).count();
+    assert_eq!(fetches, 1, "original should be served from cache without a second fetch");
+}
+
+#[tokio::test]
+async fn test_origin_failure_serves_stale_original() {
+    let png = png_fixture(24, 24);
+    // Serves the image once, then fails every request
+    let served = Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let origin_app = axum::Router::new().route(
+        "/image.png",
+        axum::routing::get(move || {
+            let first = !served.swap(true, std::sync::atomic::Ordering::SeqCst);
+            let body = png.clone();
+            async move {
+                if first {
+                    ([(axum::http::header::CONTENT_TYPE, "image/png")], body).into_response()
+                } else {
+                    StatusCode::BAD_GATEWAY.into_response()
+                }
+            }
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let origin = format!("http://{}/image.png", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        axum::serve(listener, origin_app).await.unwrap();
+    });
+
+    let app = router(ImageKitConfig {
+        original_cache_ttl: Some(std::time::Duration::from_millis(1)),
+        ..test_config()
+    });
+
+    let mut params = BTreeMap::new();
+    params.insert("url".to_string(), origin);
+    params.insert("w".to_string(), "12".to_string());
+    params.insert("f".to_string(), "webp".to_string());
+    let response = app
+        .clone()
+        .oneshot(Request::builder().uri(signed_img_uri(&params)).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().get("warning").is_none());
+
+    // Let the original expire, then ask for a variant that isn't cached yet
+    tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    params.insert("w".to_string(), "8".to_string());
+    let response = app
+        .oneshot(Request::builder().uri(signed_img_uri(&params)).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK, "stale original should be served on origin failure");
+    assert_eq!(response.headers()["warning"], "110 - \"Response is Stale\"");
+    assert_eq!(response.headers()["cache-control"], "no-store");
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let img = image::load_from_memory(&body).unwrap();
+    assert_eq!(img.width(), 8);
+}
+
+#[tokio::test]
+async fn test_expired_original_revalidates_with_304() {
+    let png = png_fixture(24, 24);
+    // Full responses carry validators; a matching If-None-Match gets a bare
+    // 304. The origin records what each request sent and what it answered.
+    const LAST_MODIFIED: &str = "Wed, 21 Oct 2015 07:28:00 GMT";
+    let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let log = seen.clone();
+    let origin_app = axum::Router::new().route(
+        "/image.png",
+        axum::routing::get(move |headers: axum::http::HeaderMap| {
+            let body = png.clone();
+            let log = log.clone();
+            async move {
+                let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok()).map(str::to_string);
+                let (if_none_match, if_modified_since) = (header("if-none-match"), header("if-modified-since"));
+                let status = if if_none_match.as_deref() == Some("\"v1\"") {
+                    StatusCode::NOT_MODIFIED
+                } else {
+                    StatusCode::OK
+                };
+                log.lock().unwrap().push((if_none_match, if_modified_since, status));
+                if status == StatusCode::NOT_MODIFIED {
+                    return status.into_response();
+                }
+                (
+                    [
+                        (axum::http::header::CONTENT_TYPE, "image/png"),
+                        (axum::http::header::ETAG, "\"v1\""),
+                        (axum::http::header::LAST_MODIFIED, LAST_MODIFIED),
+                    ],
+                    body,
+                )
+                    .into_response()
+            }
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let origin = format!("http://{}/image.png", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        axum::serve(listener, origin_app).await.unwrap();
+    });
+
+    let observer = Arc::new(RecordingObserver::default());
+    let app = router(ImageKitConfig {
+        observer: Some(observer.clone()),
+        original_cache_ttl: Some(std::time::Duration::from_millis(1)),
+        ..test_config()
+    });
+
+    let mut params = BTreeMap::new();
+    params.insert("url".to_string(), origin);
+    params.insert("f".to_string(), "original".to_string());
+    let uri = signed_img_uri(&params);
+    for _ in 0..2 {
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri(&uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(image::load_from_memory(&body).unwrap().width(), 24);
+        // Let the original expire before the next request
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+
+    // First fetch is unconditional; the expired re-fetch carries both
+    // stored validators and is answered with 304
+    assert_eq!(
+        *seen.lock().unwrap(),
+        vec![
+            (None, None, StatusCode::OK),
+            (Some("\"v1\"".to_string()), Some(LAST_MODIFIED.to_string()), StatusCode::NOT_MODIFIED),
+        ]
+    );
+    let fetches = observer.events.lock().unwrap().iter().filter(|e| e.starts_with("fetch")).count();
+    assert_eq!(fetches, 1, "a 304 must not count as a fetch");
+}
+
+#[tokio::test]
+async fn test_auto_format_keeps_source_format() {
+    let png = png_fixture(24, 24);
+    let mut jpeg = Vec::new();
+    image::DynamicImage::ImageRgb8(image::RgbImage::from_pixel(24, 24, image::Rgb([200, 80, 40])))
+        .write_to(&mut std::io::Cursor::new(&mut jpeg), image::ImageFormat::Jpeg)
+        .unwrap();
+
+    let app = router(test_config());
+    for (source, content_type, format) in [
+        (jpeg, "image/jpeg", image::ImageFormat::Jpeg),
+        (png, "image/png", image::ImageFormat::Png),
+    ] {
+        let mut params = BTreeMap::new();
+        params.insert("url".to_string(), spawn_origin(source).await);
+        params.insert("w".to_string(), "12".to_string());
+        params.insert("f".to_string(), "auto".to_string());
+
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri(signed_img_uri(&params)).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], content_type);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(image::guess_format(&body).unwrap(), format);
+    }
+
+    // `preserve_source_format` makes a missing `f` behave like `f=auto`
+    let app = router(ImageKitConfig { preserve_source_format: true, ..test_config() });
+    let mut params = BTreeMap::new();
+    params.insert("url".to_string(), spawn_origin(png_fixture(24, 24)).await);
+    params.insert("w".to_string(), "12".to_string());
+    let response = app
+        .oneshot(Request::builder().uri(signed_img_uri(&params)).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["content-type"], "image/png");
+}
+
+#[tokio::test]
+async fn test_request_id_is_echoed_or_generated() {
+    let app = router(test_config());
+
+    let response = app
+        .clone()
+        .oneshot(Request::builder().uri("/health").header("x-request-id", "edge-42").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.headers()["x-request-id"], "edge-42");
+
+    let first = app
+        .clone()
+        .oneshot(Request::builder().uri("/health").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let second = app
+        .oneshot(Request::builder().uri("/health").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_ne!(first.headers()["x-request-id"], second.headers()["x-request-id"]);
+}
+
+#[tokio::test]
+async fn test_keep_icc_preserves_source_profile() {
+    use imagekit::transform::{embed_icc_profile, encode_image, source_icc_profile};
+
+    let icc = b"fake-icc-profile-for-testing".to_vec();
+    let img = image::DynamicImage::ImageRgb8(image::RgbImage::from_pixel(24, 24, image::Rgb([200, 80, 40])));
+    let source = embed_icc_profile(encode_image(&img, ImageFormat::jpeg, 90).unwrap(), ImageFormat::jpeg, &icc);
+    let origin = spawn_origin(source).await;
+    let app = router(test_config());
+
+    for (keep, f) in [(true, "jpeg"), (true, "webp"), (false, "webp")] {
+        let mut params = BTreeMap::new();
+        params.insert("url".to_string(), origin.clone());
+        params.insert("w".to_string(), "12".to_string());
+        params.insert("f".to_string(), f.to_string());
+        if keep {
+            params.insert("keep_icc".to_string(), "true".to_string());
+        }
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri(signed_img_uri(&params)).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let expected = keep.then(|| icc.clone());
+        assert_eq!(source_icc_profile(&body), expected, "keep_icc={} f={}", keep, f);
+    }
+}
+
+#[tokio::test]
+async fn test_debug_cache_key_matches_disk_cache() {
+    use imagekit::cache::{Cache, DiskCache};
+
+    let mut params = BTreeMap::new();
+    params.insert("url".to_string(), "https://example.com/a.jpg".to_string());
+    params.insert("w".to_string(), "300".to_string());
+    params.insert("wrap".to_string(), "json".to_string());
+
+    // Off unless enabled
+    let response = router(test_config())
+        .oneshot(Request::builder().uri(signed_debug_uri(&params)).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let config = ImageKitConfig { enable_debug_endpoints: true, ..test_config() };
+    let cache_dir = config.cache_dir.clone();
+    let app = router(config);
+
+    let response = app
+        .clone()
+        .oneshot(Request::builder().uri(signed_debug_uri(&params)).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let json: Value = serde_json::from_slice(&body).unwrap();
+
+    // `wrap` is dropped; the default format, quality and filters are filled in
+    let mut key_params = params.clone();
+    key_params.remove("wrap");
+    key_params.insert("f".to_string(), "webp".to_string());
+    key_params.insert("q".to_string(), "80".to_string());
+    key_params.insert("downscale_filter".to_string(), "lanczos3".to_string());
+    key_params.insert("upscale_filter".to_string(), "lanczos3".to_string());
+    key_params.insert("staged_downscale".to_string(), "8".to_string());
+    assert_eq!(json["key"], DiskCache::new(cache_dir).key_for(&key_params));
+    assert_eq!(json["canonical"], serde_urlencoded::to_string(&params).unwrap());
+
+    // Unsigned requests are refused
+    let response = app
+        .oneshot(Request::builder().uri("/debug/cache-key?url=https://example.com/a.jpg").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_preset_expands_to_stored_params() {
+    use imagekit::config::PresetParams;
+    use imagekit::transform::params::FitMode;
+
+    let origin = spawn_origin(png_fixture(64, 32)).await;
+    let mut config = test_config();
+    config.presets.insert(
+        "thumb".to_string(),
+        PresetParams { w: Some(20), h: Some(20), fit: Some(FitMode::Cover), ..Default::default() },
+    );
+    config.presets.insert("hero".to_string(), PresetParams { w: Some(40), ..Default::default() });
+    let app = router(config);
+
+    for (preset, dims) in [("thumb", (20, 20)), ("hero", (40, 20))] {
+        let mut params = BTreeMap::new();
+        params.insert("url".to_string(), origin.clone());
+        params.insert("preset".to_string(), preset.to_string());
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri(signed_img_uri(&params)).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK, "preset {}", preset);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let img = image::load_from_memory(&body).unwrap();
+        assert_eq!((img.width(), img.height()), dims, "preset {}", preset);
+    }
+
+    // Explicit params can't override a preset, and unknown presets are rejected
+    for extra in [Some(("w", "500")), None] {
+        let mut params = BTreeMap::new();
+        params.insert("url".to_string(), origin.clone());
+        match extra {
+            Some((k, v)) => {
+                params.insert("preset".to_string(), "thumb".to_string());
+                params.insert(k.to_string(), v.to_string());
+            }
+            None => {
+                params.insert("preset".to_string(), "banner".to_string());
+            }
+        }
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri(signed_img_uri(&params)).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
+
+#[tokio::test]
+async fn test_cors_headers_for_allowed_origin() {
+    let app = router(ImageKitConfig {
+        cors_allowed_origins: vec![
+            "https://app.example.com".to_string(),
+            "https://admin.example.com".to_string(),
+        ],
+        ..test_config()
+    });
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/sign?url=https://example.com/test.jpg&w=400")
+                .header("origin", "https://app.example.com")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["access-control-allow-origin"], "https://app.example.com");
+    assert_eq!(
+        response.headers()["timing-allow-origin"],
+        "https://app.example.com, https://admin.example.com"
+    );
+
+    // Preflight
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method("OPTIONS")
+                .uri("/img")
+                .header("origin", "https://app.example.com")
+                .header("access-control-request-method", "GET")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+    assert_eq!(response.headers()["access-control-allow-origin"], "https://app.example.com");
+    assert!(response.headers().contains_key("access-control-allow-methods"));
+}
+
+#[tokio::test]
+async fn test_signature_binds_every_param() {
+    let app = router(test_config());
+
+    let mut signed = BTreeMap::new();
+    signed.insert("url".to_string(), "https://example.com/test.jpg".to_string());
+    signed.insert("w".to_string(), "400".to_string());
+    signed.insert("h".to_string(), "300".to_string());
+    signed.insert("f".to_string(), "webp".to_string());
+    signed.insert("q".to_string(), "80".to_string());
+    signed.insert("fit".to_string(), "cover".to_string());
+    signed.insert("gravity".to_string(), "north".to_string());
+    signed.insert("pixelate".to_string(), "8".to_string());
+    let sig = compute_signature(&signed, "test-secret-key");
+
+    // Each row: (param, replacement value or None to drop it)
+    let tampered: &[(&str, Option<&str>)] = &[
+        ("url", Some("https://example.com/other.jpg")),
+        ("w", Some("800")),
+        ("h", Some("600")),
+        ("h", None),
+        ("f", Some("jpeg")),
+        ("q", Some("90")),
+        ("fit", Some("contain")),
+        ("gravity", Some("south")),
+        ("pixelate", Some("16")),
+        ("speed", Some("2")),
+        ("max_bytes", Some("1000")),
+    ];
+
+    for (param, value) in tampered {
+        let mut params = signed.clone();
+        match value {
+            Some(v) => { params.insert(param.to_string(), v.to_string()); }
+            None => { params.remove(*param); }
+        }
+        let query: String = params
+            .iter()
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect::<Vec<_>>()
+            .join("&");
+
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri(format!("/img?{}&sig={}", query, sig)).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED,
+                   "signature accepted with {}={:?}", param, value);
+    }
+}
+
+#[tokio::test]
+async fn test_tracking_params_do_not_break_signature() {
+    let origin = spawn_origin(png_fixture(32, 32)).await;
+    let app = router(test_config());
+
+    let mut params = BTreeMap::new();
+    params.insert("url".to_string(), origin);
+    params.insert("w".to_string(), "16".to_string());
+    let uri = format!("{}&utm_source=newsletter&utm_campaign=spring", signed_img_uri(&params));
+
+    let response = app
+        .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_strict_params_rejects_unsigned_params() {
+    let app = router(ImageKitConfig {
+        strict_params: true,
+        ..test_config()
+    });
+
+    let mut params = BTreeMap::new();
+    params.insert("url".to_string(), "https://example.com/test.jpg".to_string());
+    let uri = format!("{}&scope=admin", signed_img_uri(&params));
+
+    let response = app
+        .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(std::str::from_utf8(&body).unwrap(), "Unsigned parameter: scope");
+}
+
+#[tokio::test]
+async fn test_signature_via_authorization_header() {
+    let origin = spawn_origin(png_fixture(32, 32)).await;
+    let app = router(test_config());
+
+    let mut params = BTreeMap::new();
+    params.insert("url".to_string(), origin.clone());
+    params.insert("w".to_string(), "16".to_string());
+    params.insert("f".to_string(), "webp".to_string());
+    let sig = compute_signature(&params, "test-secret-key");
+    let query = format!("/img?url={}&w=16&f=webp", origin);
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri(&query)
+                .header("authorization", format!("Signature {}", sig))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["content-type"], "image/webp");
+
+    // Query and header agreeing is fine; disagreeing is rejected
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri(format!("{}&sig={}", query, sig))
+                .header("authorization", format!("Signature {}", sig))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri(format!("{}&sig={}", query, sig))
+                .header("authorization", format!("Signature {}", "0".repeat(64)))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_metrics_include_uptime_and_build_info() {
+    let app = router(test_config());
+
+    let response = app
+        .oneshot(Request::builder().uri("/metrics").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let text = std::str::from_utf8(&body).unwrap();
+
+    let uptime_line = text
+        .lines()
+        .find(|l| l.starts_with("imagekit_uptime_seconds "))
+        .expect("uptime gauge missing");
+    let uptime: f64 = uptime_line.split_whitespace().nth(1).unwrap().parse().unwrap();
+    assert!(uptime >= 0.0);
+
+    let build_info = format!("imagekit_build_info{{version=\"{}\"}} 1", env!("CARGO_PKG_VERSION"));
+    assert!(text.lines().any(|l| l == build_info), "missing {} in:\n{}", build_info, text);
+}
+
+#[tokio::test]
+async fn test_default_format_change_serves_new_format() {
+    let origin = spawn_origin(png_fixture(16, 16)).await;
+    let cache_dir = std::env::temp_dir().join(format!("imagekit-default-format-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&cache_dir);
+
+    // No `f`: the output format comes from the server default
+    let mut params = BTreeMap::new();
+    params.insert("url".to_string(), origin);
+    params.insert("w".to_string(), "8".to_string());
+    let uri = signed_img_uri(&params);
+
+    let fetch = |default_format| {
+        let app = router(ImageKitConfig {
+            cache_dir: cache_dir.clone(),
+            default_format: Some(default_format),
+            ..test_config()
+        });
+        let uri = uri.clone();
+        async move {
+            let response = app.oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap()).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let content_type = response.headers()["content-type"].to_str().unwrap().to_string();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            (content_type, body)
+        }
+    };
+
+    let (content_type, body) = fetch(ImageFormat::webp).await;
+    assert_eq!(content_type, "image/webp");
+    assert_eq!(image::guess_format(&body).unwrap(), image::ImageFormat::WebP);
+
+    // Same cache dir after the operator switches the default: the WebP entry
+    // must not be served, let alone labelled as JPEG
+    let (content_type, body) = fetch(ImageFormat::jpeg).await;
+    assert_eq!(content_type, "image/jpeg");
+    assert_eq!(image::guess_format(&body).unwrap(), image::ImageFormat::Jpeg);
+
+    let _ = std::fs::remove_dir_all(&cache_dir);
+}
+
+#[tokio::test]
+async fn test_sign_round_trip_with_reserved_characters_in_url() {
+    let origin = spawn_origin(png_fixture(16, 16)).await;
+    let app = router(test_config());
+
+    // The origin ignores the query, but `&`, `=` and spaces must survive signing
+    let source = format!("{}?a=1&b=x y", origin);
+    let sign_query = serde_urlencoded::to_string([("url", source.as_str()), ("w", "8"), ("f", "webp")]).unwrap();
+
+    let response = app
+        .clone()
+        .oneshot(Request::builder().uri(format!("/sign?{}", sign_query)).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    let signed_url = json["signed_url"].as_str().unwrap();
+
+    let mut params = BTreeMap::new();
+    params.insert("url".to_string(), source);
+    params.insert("w".to_string(), "8".to_string());
+    params.insert("f".to_string(), "webp".to_string());
+    assert_eq!(json["sig"].as_str().unwrap(), compute_signature(&params, "test-secret-key"));
+
+    let response = app
+        .oneshot(Request::builder().uri(signed_url).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK, "signed_url from /sign must verify");
+}
+
+#[tokio::test]
+async fn test_upload_rejects_disallowed_input_format() {
+    let app = router(ImageKitConfig {
+        allowed_upload_formats: Some(vec![InputFormat::jpeg, InputFormat::png]),
+        ..test_config()
+    });
+
+    // 1x1 transparent GIF; the declared content type is ignored, bytes decide
+    const GIF: &[u8] = b"GIF89a\x01\x00\x01\x00\x80\x00\x00\x00\x00\x00\xff\xff\xff\x21\xf9\x04\x01\x00\x00\x00\x00\x2c\x00\x00\x00\x00\x01\x00\x01\x00\x00\x02\x02\x44\x01\x00\x3b";
+    let response = app.clone().oneshot(upload_request("a.png", "image/png", GIF)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(std::str::from_utf8(&body).unwrap(), "Upload format not allowed: gif");
+
+    let response = app.oneshot(upload_request("a.png", "image/png", &png_fixture(8, 8))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[test]
+fn test_metrics_reset_zeroes_counters() {
+    use std::sync::atomic::Ordering;
+
+    let metrics = imagekit::Metrics::new();
+    metrics.cache_hits.fetch_add(3, Ordering::Relaxed);
+    metrics.transforms.fetch_add(2, Ordering::Relaxed);
+    metrics.errors.fetch_add(1, Ordering::Relaxed);
+
+    metrics.reset();
+    assert_eq!(metrics.cache_hits.load(Ordering::Relaxed), 0);
+    assert_eq!(metrics.cache_misses.load(Ordering::Relaxed), 0);
+    assert_eq!(metrics.transforms.load(Ordering::Relaxed), 0);
+    assert_eq!(metrics.errors.load(Ordering::Relaxed), 0);
+}
+
+#[tokio::test]
+async fn test_metrics_reset_requires_signature() {
+    let app = router(test_config());
+    let post = |uri: String| Request::builder().method("POST").uri(uri).body(Body::empty()).unwrap();
+
+    let response = app.clone().oneshot(post("/metrics/reset".to_string())).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // A signature for anything else (here: an /img request) doesn't authorize a reset
+    let mut img = BTreeMap::new();
+    img.insert("url".to_string(), "https://example.com/test.jpg".to_string());
+    let sig = compute_signature(&img, "test-secret-key");
+    let response = app.clone().oneshot(post(format!("/metrics/reset?sig={}", sig))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let mut reset = BTreeMap::new();
+    reset.insert("action".to_string(), "metrics_reset".to_string());
+    let sig = compute_signature(&reset, "test-secret-key");
+    let response = app.oneshot(post(format!("/metrics/reset?sig={}", sig))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+}
+
+#[tokio::test]
+async fn test_warm_populates_cache_for_each_entry() {
+    let origin = spawn_origin(png_fixture(48, 48)).await;
+    let cache_dir = std::env::temp_dir().join(format!("imagekit-warm-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&cache_dir);
+
+    let observer = Arc::new(RecordingObserver::default());
+    let app = router(ImageKitConfig {
+        observer: Some(observer.clone()),
+        cache_dir: cache_dir.clone(),
+        ..test_config()
+    });
+
+    let entries: Vec<String> = ["16", "24"]
+        .iter()
+        .map(|w| {
+            let mut params = BTreeMap::new();
+            params.insert("url".to_string(), origin.clone());
+            params.insert("w".to_string(), w.to_string());
+            params.insert("f".to_string(), "webp".to_string());
+            signed_img_uri(&params)
+        })
+        .chain(["/img?url=https://example.com/a.jpg&sig=bogus".to_string()])
+        .collect();
+
+    let mut warm = BTreeMap::new();
+    warm.insert("action".to_string(), "warm".to_string());
+    let sig = compute_signature(&warm, "test-secret-key");
+    let request = Request::builder()
+        .method("POST")
+        .uri(format!("/warm?sig={}", sig))
+        .header("content-type", "application/json")
+        .body(Body::from(serde_json::to_vec(&entries).unwrap()))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["accepted"], 2);
+    assert_eq!(json["rejected"][0]["index"], 2);
+    assert_eq!(json["rejected"][0]["status"], 401);
+
+    // The job runs in the background; wait for both entries to land
+    let cached = || {
+        std::fs::read_dir(&cache_dir)
+            .map(|dir| dir.flatten().filter(|e| e.path().extension().is_some_and(|x| x == "webp")).count())
+            .unwrap_or(0)
+    };
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+    while cached() < 2 && std::time::Instant::now() < deadline {
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    assert_eq!(cached(), 2);
+
+    observer.events.lock().unwrap().clear();
+    for entry in &entries[..2] {
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri(entry).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+    assert_eq!(*observer.events.lock().unwrap(), vec!["hit".to_string(), "hit".to_string()]);
+
+    let _ = std::fs::remove_dir_all(&cache_dir);
+}
+
+#[tokio::test]
+async fn test_source_cache_shares_download_across_variants() {
+    let (origin, downloads) = spawn_counting_origin(png_fixture(32, 32)).await;
+    let app = router(ImageKitConfig {
+        source_cache: Some(SourceCache::new(16 * 1024 * 1024, std::time::Duration::from_secs(30))),
+        ..test_config()
+    });
+
+    for (w, f) in [("16", "webp"), ("8", "jpeg")] {
+        let mut params = BTreeMap::new();
+        params.insert("url".to_string(), origin.clone());
+        params.insert("w".to_string(), w.to_string());
+        params.insert("f".to_string(), f.to_string());
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri(signed_img_uri(&params)).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    assert_eq!(downloads.load(std::sync::atomic::Ordering::SeqCst), 1,
+               "both variants should come from one origin download");
+}
+
+#[tokio::test]
+async fn test_concurrent_identical_misses_fetch_once() {
+    let (origin, downloads) = spawn_counting_origin(png_fixture(64, 64)).await;
+    let cache_dir = std::env::temp_dir().join(format!("imagekit-single-flight-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&cache_dir);
+    let app = router(ImageKitConfig { cache_dir: cache_dir.clone(), ..test_config() });
+
+    let mut params = BTreeMap::new();
+    params.insert("url".to_string(), origin);
+    params.insert("w".to_string(), "32".to_string());
+    params.insert("f".to_string(), "webp".to_string());
+    let uri = signed_img_uri(&params);
+
+    let requests = (0..8).map(|_| {
+        app.clone().oneshot(Request::builder().uri(uri.clone()).body(Body::empty()).unwrap())
+    });
+    let responses = futures::future::join_all(requests).await;
+
+    let mut bodies = Vec::new();
+    for response in responses {
+        let response = response.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        bodies.push(axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap());
+    }
+    assert!(bodies.windows(2).all(|w| w[0] == w[1]), "all waiters get the same bytes");
+    assert_eq!(downloads.load(std::sync::atomic::Ordering::SeqCst), 1,
+               "concurrent identical misses should share one fetch");
+    // One complete entry, and no temp files left by racing writers
+    let files: Vec<_> = std::fs::read_dir(&cache_dir).unwrap().map(|e| e.unwrap().file_name()).collect();
+    assert_eq!(files.len(), 1, "unexpected cache files: {:?}", files);
+    let stored = std::fs::read(cache_dir.join(&files[0])).unwrap();
+    assert_eq!(stored, bodies[0]);
+
+    let _ = std::fs::remove_dir_all(&cache_dir);
+}
+
+#[tokio::test]
+async fn test_max_cache_entries_caps_img_cache() {
+    let origin = spawn_origin(png_fixture(64, 64)).await;
+    let cache_dir = std::env::temp_dir().join(format!("imagekit-max-entries-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&cache_dir);
+    let app = router(ImageKitConfig { cache_dir: cache_dir.clone(), max_cache_entries: Some(2), ..test_config() });
+
+    // Each request builds its own cache handle, so the count has to carry
+    // across requests for the cap to hold
+    for w in [8, 16, 24, 32] {
+        let mut params = BTreeMap::new();
+        params.insert("url".to_string(), origin.clone());
+        params.insert("w".to_string(), w.to_string());
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri(signed_img_uri(&params)).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        // Distinct modification times
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    assert_eq!(std::fs::read_dir(&cache_dir).unwrap().count(), 2);
+
+    let _ = std::fs::remove_dir_all(&cache_dir);
+}
+
+#[tokio::test]
+async fn test_concurrent_variants_share_one_source_fetch() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // Slow enough that every variant misses while the first fetch runs
+    let downloads = Arc::new(AtomicUsize::new(0));
+    let counter = downloads.clone();
+    let body = png_fixture(64, 64);
+    let origin = axum::Router::new().route(
+        "/image.png",
+        axum::routing::get(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            let body = body.clone();
+            async move {
+                tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+                ([(axum::http::header::CONTENT_TYPE, "image/png")], body)
+            }
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/image.png", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        axum::serve(listener, origin).await.unwrap();
+    });
+    let cache_dir = std::env::temp_dir().join(format!("imagekit-source-flight-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&cache_dir);
+    // No source cache: the sharing comes from coalescing alone
+    let app = router(ImageKitConfig { cache_dir: cache_dir.clone(), ..test_config() });
+
+    let requests = [8, 16, 24, 32].map(|w| {
+        let mut params = BTreeMap::new();
+        params.insert("url".to_string(), url.clone());
+        params.insert("w".to_string(), w.to_string());
+        app.clone().oneshot(Request::builder().uri(signed_img_uri(&params)).body(Body::empty()).unwrap())
+    });
+    for response in futures::future::join_all(requests).await {
+        assert_eq!(response.unwrap().status(), StatusCode::OK);
+    }
+    assert_eq!(downloads.load(Ordering::SeqCst), 1, "concurrent variants should share one fetch");
+
+    let _ = std::fs::remove_dir_all(&cache_dir);
+}
+
+#[tokio::test]
+async fn test_concurrent_identical_misses_share_a_failure() {
+    let (origin, downloads) = spawn_counting_origin(b"not an image".to_vec()).await;
+    let app = router(test_config());
+
+    let mut params = BTreeMap::new();
+    params.insert("url".to_string(), origin);
+    params.insert("w".to_string(), "32".to_string());
+    let uri = signed_img_uri(&params);
+
+    let requests = (0..8).map(|_| {
+        app.clone().oneshot(Request::builder().uri(uri.clone()).body(Body::empty()).unwrap())
+    });
+    let responses = futures::future::join_all(requests).await;
+
+    let mut bodies = Vec::new();
+    for response in responses {
+        let response = response.unwrap();
+        assert!(response.status().is_client_error(), "got {}", response.status());
+        bodies.push(axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap());
+    }
+    assert!(bodies.windows(2).all(|w| w[0] == w[1]), "all waiters get the same error");
+    assert_eq!(downloads.load(std::sync::atomic::Ordering::SeqCst), 1,
+               "waiters shouldn't retry a failed fetch one after another");
+}
+
+#[tokio::test]
+async fn test_readiness_fails_for_unwritable_cache_dir() {
+    // A directory can't be created beneath a regular file, even as root
+    let blocker = std::env::temp_dir().join(format!("imagekit-not-a-dir-{}", std::process::id()));
+    std::fs::write(&blocker, b"file").unwrap();
+
+    let app = router(ImageKitConfig { cache_dir: blocker.join("cache"), ..test_config() });
+    let response = app
+        .oneshot(Request::builder().uri("/health/ready").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["status"], "unavailable");
+    assert!(json["reason"].as_str().unwrap().contains("not writable"), "{}", json);
+
+    // Liveness stays cheap and green
+    let app = router(ImageKitConfig { cache_dir: blocker.join("cache"), ..test_config() });
+    let response = app
+        .oneshot(Request::builder().uri("/health").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let _ = std::fs::remove_file(&blocker);
+}
+
+#[tokio::test]
+async fn test_readiness_passes_for_writable_cache_dir() {
+    let cache_dir = std::env::temp_dir().join(format!("imagekit-ready-{}", std::process::id()));
+    let app = router(ImageKitConfig { cache_dir: cache_dir.clone(), min_free_cache_bytes: 0, ..test_config() });
+    let response = app
+        .oneshot(Request::builder().uri("/health/ready").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["status"], "ready");
+
+    let _ = std::fs::remove_dir_all(&cache_dir);
+}
+
+// Cleanup test cache directory after tests
+#[tokio::test]
+async fn cleanup_test_cache() {
     let _ = tokio::fs::remove_dir_all("./test-cache").await;
 }
+
+#[tokio::test]
+async fn test_origin_headers_authenticate_source_fetch() {
+    let body = png_fixture(4, 4);
+    let app = axum::Router::new().route(
+        "/image.png",
+        axum::routing::get(move |headers: axum::http::HeaderMap| {
+            let body = body.clone();
+            async move {
+                if headers.get("authorization").and_then(|v| v.to_str().ok()) != Some("Bearer origin-token") {
+                    return StatusCode::UNAUTHORIZED.into_response();
+                }
+                ([(axum::http::header::CONTENT_TYPE, "image/png")], body).into_response()
+            }
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+    let origin = format!("http://{}/image.png", addr);
+
+    let mut params = BTreeMap::new();
+    params.insert("url".to_string(), origin);
+    params.insert("w".to_string(), "2".to_string());
+    let uri = signed_img_uri(&params);
+
+    let cache_dir = std::env::temp_dir().join(format!("imagekit-origin-headers-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&cache_dir);
+
+    // Without the configured header the origin refuses the fetch
+    let response = router(ImageKitConfig { cache_dir: cache_dir.clone(), ..test_config() })
+        .oneshot(Request::builder().uri(&uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_ne!(response.status(), StatusCode::OK);
+
+    let mut config = ImageKitConfig { cache_dir: cache_dir.clone(), ..test_config() };
+    config.origin_headers.insert(
+        "127.0.0.1".to_string(),
+        [("Authorization".to_string(), "Bearer origin-token".to_string())].into(),
+    );
+    let response = router(config)
+        .oneshot(Request::builder().uri(&uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let _ = std::fs::remove_dir_all(&cache_dir);
+}
+
+#[tokio::test]
+async fn test_omitted_quality_uses_per_format_default() {
+    let gradient = image::DynamicImage::ImageRgb8(image::RgbImage::from_fn(32, 32, |x, y| {
+        image::Rgb([(x * 8) as u8, (y * 8) as u8, ((x * y) % 256) as u8])
+    }));
+    let mut png = Vec::new();
+    gradient.write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png).unwrap();
+    let origin = spawn_origin(png).await;
+    let app = router(test_config());
+
+    let fetch = |q: Option<&str>| {
+        let mut params = BTreeMap::new();
+        params.insert("url".to_string(), origin.clone());
+        params.insert("f".to_string(), "avif".to_string());
+        params.insert("speed".to_string(), "10".to_string());
+        if let Some(q) = q {
+            params.insert("q".to_string(), q.to_string());
+        }
+        let request = Request::builder().uri(signed_img_uri(&params)).body(Body::empty()).unwrap();
+        let app = app.clone();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap()
+        }
+    };
+
+    let omitted = fetch(None).await;
+    assert_eq!(omitted, fetch(Some("55")).await, "AVIF should default to q=55");
+    assert_ne!(omitted, fetch(Some("80")).await, "AVIF must not use the generic default");
+}
+
+#[tokio::test]
+async fn test_etag_follows_content_not_params() {
+    let origin = spawn_origin(png_fixture(24, 24)).await;
+    let app = router(test_config());
+
+    // Different params (so different cache keys), identical 12x12 output
+    let mut width_only = BTreeMap::new();
+    width_only.insert("url".to_string(), origin.clone());
+    width_only.insert("w".to_string(), "12".to_string());
+    let mut both = width_only.clone();
+    both.insert("h".to_string(), "12".to_string());
+
+    let mut etags = Vec::new();
+    for params in [&width_only, &both] {
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri(signed_img_uri(params)).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let etag = response.headers()["etag"].to_str().unwrap().to_string();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(etag, imagekit::cache::etag_for_content(&body));
+        etags.push(etag);
+    }
+    assert_eq!(etags[0], etags[1]);
+
+    // The validator revalidates across keys too
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri(signed_img_uri(&both))
+                .header("if-none-match", &etags[0])
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+}
+
+#[tokio::test]
+async fn test_expires_matches_max_age() {
+    let origin = spawn_origin(png_fixture(16, 16)).await;
+    let config = test_config();
+    let max_age = config.cache_control.browser_max_age as u64;
+    let app = router(config);
+
+    let mut params = BTreeMap::new();
+    params.insert("url".to_string(), origin);
+    params.insert("w".to_string(), "8".to_string());
+    let response = app
+        .oneshot(Request::builder().uri(signed_img_uri(&params)).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let expires = httpdate::parse_http_date(response.headers()["expires"].to_str().unwrap()).unwrap();
+    let expected = std::time::SystemTime::now() + std::time::Duration::from_secs(max_age);
+    let skew = expected
+        .duration_since(expires)
+        .unwrap_or_else(|e| e.duration());
+    assert!(skew <= std::time::Duration::from_secs(5), "Expires off by {:?}", skew);
+}
+
+#[tokio::test]
+async fn test_path_extension_selects_format() {
+    let origin = spawn_origin(png_fixture(16, 16)).await;
+    let app = router(test_config());
+
+    // Signed without `f`; the path picks AVIF
+    let mut params = BTreeMap::new();
+    params.insert("url".to_string(), origin);
+    params.insert("w".to_string(), "8".to_string());
+    params.insert("speed".to_string(), "10".to_string());
+    let uri = signed_img_uri(&params).replacen("/img?", "/img.avif?", 1);
+
+    let response = app
+        .clone()
+        .oneshot(Request::builder().uri(&uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["content-type"], "image/avif");
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(&body[4..12], b"ftypavif");
+
+    let response = app
+        .oneshot(Request::builder().uri(uri.replacen("/img.avif?", "/img.gif?", 1)).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
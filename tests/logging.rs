@@ -0,0 +1,21 @@
+use imagekit::logging::{subscriber, LogFormat};
+
+#[test]
+fn subscriber_builds_in_both_formats() {
+    for format in [LogFormat::Pretty, LogFormat::Json] {
+        tracing::subscriber::with_default(subscriber(format), || {
+            let span = tracing::info_span!("request", request_id = "abc-1");
+            span.in_scope(|| tracing::info!(cache_key = "k", url = "https://example.com/a.jpg", duration_ms = 3u64, "done"));
+        });
+    }
+}
+
+#[test]
+fn log_format_reads_env() {
+    std::env::set_var("LOG_FORMAT", "JSON");
+    assert_eq!(LogFormat::from_env(), LogFormat::Json);
+    std::env::set_var("LOG_FORMAT", "pretty");
+    assert_eq!(LogFormat::from_env(), LogFormat::Pretty);
+    std::env::remove_var("LOG_FORMAT");
+    assert_eq!(LogFormat::from_env(), LogFormat::Pretty);
+}
@@ -0,0 +1,71 @@
+//! Counter tests live in their own binary: `METRICS` is process-global, and
+//! `tests/integration.rs` resets it via `POST /metrics/reset`.
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use imagekit::config::ImageKitConfig;
+use imagekit::router;
+use tower::util::ServiceExt; // for `oneshot`
+
+fn test_config() -> ImageKitConfig {
+    std::env::set_var("DISABLE_RATE_LIMIT", "1");
+
+    ImageKitConfig {
+        secret: "test-secret-key".to_string(),
+        cache_dir: std::path::PathBuf::from("./test-cache-metrics"),
+        ..Default::default()
+    }
+}
+
+/// Reads one unlabelled sample from `/metrics`
+async fn metric(app: &axum::Router, name: &str) -> f64 {
+    let response = app
+        .clone()
+        .oneshot(Request::builder().uri("/metrics").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let text = String::from_utf8(body.to_vec()).unwrap();
+    text.lines()
+        .find_map(|l| l.strip_prefix(name)?.strip_prefix(' ')?.parse().ok())
+        .unwrap_or_else(|| panic!("{} missing from /metrics", name))
+}
+
+#[tokio::test]
+async fn test_decode_error_increments_errors_metric() {
+    let app = router(test_config());
+    let before = metric(&app, "imagekit_errors_total").await;
+
+    let boundary = "imagekit-boundary";
+    let body = format!(
+        "--{b}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.png\"\r\n\
+         Content-Type: image/png\r\n\r\nthis is not an image\r\n--{b}--\r\n",
+        b = boundary
+    );
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/upload")
+                .header("content-type", format!("multipart/form-data; boundary={}", boundary))
+                .body(Body::from(body))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // An unsigned /img request is a signature failure
+    let response = app
+        .clone()
+        .oneshot(Request::builder().uri("/img?url=https://example.com/a.jpg").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let after = metric(&app, "imagekit_errors_total").await;
+    assert_eq!(after - before, 2.0, "each failed request counts once");
+
+    let _ = std::fs::remove_dir_all("./test-cache-metrics");
+}
@@ -0,0 +1,61 @@
+use imagekit::placeholder::{compute_blurhash, lqip_data_uri, LQIP_SIZE};
+
+const BASE83: &str = "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz#$%*+,-.:;=?@[\\]^_{|}~";
+
+fn decode83(s: &str) -> u32 {
+    s.chars().fold(0, |acc, c| acc * 83 + BASE83.find(c).unwrap() as u32)
+}
+
+fn solid(width: u32, height: u32, rgb: [u8; 3]) -> image::DynamicImage {
+    image::DynamicImage::ImageRgb8(image::ImageBuffer::from_pixel(width, height, image::Rgb(rgb)))
+}
+
+#[test]
+fn test_blurhash_is_stable_for_solid_color() {
+    let img = solid(24, 16, [200, 80, 40]);
+    let hash = compute_blurhash(&img, 4, 3).unwrap();
+
+    // Size flag (4x3 -> 'L'), max AC, 4-char DC, then 2 chars per AC component
+    assert_eq!(hash.len(), 2 + 4 + 2 * 11);
+    assert!(hash.starts_with('L'), "unexpected size flag in {}", hash);
+    let dc = decode83(&hash[2..6]);
+    let rgb = [(dc >> 16) as i32, ((dc >> 8) & 0xff) as i32, (dc & 0xff) as i32];
+    for (got, want) in rgb.iter().zip([200, 80, 40]) {
+        assert!((got - want).abs() <= 1, "DC {:?} should be the source color", rgb);
+    }
+
+    assert_eq!(compute_blurhash(&img, 4, 3).unwrap(), hash, "same image, same hash");
+    assert_ne!(compute_blurhash(&solid(24, 16, [40, 80, 200]), 4, 3).unwrap(), hash);
+}
+
+#[test]
+fn test_blurhash_downsamples_large_images_and_checks_components() {
+    let hash = compute_blurhash(&solid(1200, 800, [10, 120, 90]), 3, 3).unwrap();
+    assert_eq!(hash.len(), 2 + 4 + 2 * 8);
+
+    assert!(compute_blurhash(&solid(8, 8, [0, 0, 0]), 0, 3).is_err());
+    assert!(compute_blurhash(&solid(8, 8, [0, 0, 0]), 4, 10).is_err());
+}
+
+#[test]
+fn test_lqip_is_a_tiny_jpeg_data_uri() {
+    use base64::Engine;
+
+    let uri = lqip_data_uri(solid(400, 300, [200, 80, 40])).unwrap();
+    let data = uri.strip_prefix("data:image/jpeg;base64,").expect("JPEG data URI");
+    let jpeg = base64::engine::general_purpose::STANDARD.decode(data).unwrap();
+    assert!(jpeg.len() < 2048, "placeholder should be tiny, got {} bytes", jpeg.len());
+
+    let img = image::load_from_memory_with_format(&jpeg, image::ImageFormat::Jpeg).unwrap();
+    assert_eq!((img.width(), img.height()), (LQIP_SIZE, 15));
+
+    // Never enlarged
+    let uri = lqip_data_uri(solid(8, 8, [0, 0, 0])).unwrap();
+    let jpeg = base64::engine::general_purpose::STANDARD.decode(&uri["data:image/jpeg;base64,".len()..]).unwrap();
+    assert_eq!(image::load_from_memory(&jpeg).unwrap().width(), 8);
+
+    // Tall sources are bounded by their height
+    let uri = lqip_data_uri(solid(300, 6000, [0, 0, 0])).unwrap();
+    let jpeg = base64::engine::general_purpose::STANDARD.decode(&uri["data:image/jpeg;base64,".len()..]).unwrap();
+    assert_eq!(image::load_from_memory(&jpeg).unwrap().height(), LQIP_SIZE);
+}
@@ -0,0 +1,45 @@
+use imagekit::quota::{QuotaPeriod, QuotaTracker};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// 2026-10-31T23:00:00Z, an hour before the month turns over
+fn end_of_october() -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(1_793_487_600)
+}
+
+#[test]
+fn test_quota_period_is_the_utc_month() {
+    let period = QuotaPeriod::at(end_of_october());
+    assert_eq!(period, QuotaPeriod { year: 2026, month: 10 });
+    assert_eq!(period.to_string(), "2026-10");
+    assert_eq!(QuotaPeriod::at(end_of_october() + Duration::from_secs(3600)).to_string(), "2026-11");
+}
+
+#[test]
+fn test_quota_resets_when_the_month_turns_over() {
+    let quotas = QuotaTracker::default().with_limit("acme", 2);
+    let october = end_of_october();
+    let november = october + Duration::from_secs(3600);
+
+    assert_eq!(quotas.consume_at("acme", october).unwrap().used, 1);
+    assert_eq!(quotas.consume_at("acme", october).unwrap().used, 2);
+    let exceeded = quotas.consume_at("acme", october).unwrap_err();
+    assert_eq!((exceeded.used, exceeded.limit, exceeded.rejected), (2, Some(2), 1));
+    assert_eq!(quotas.consume_at("acme", october).unwrap_err().rejected, 2);
+    assert_eq!(quotas.usage_at("acme", october).used, 2);
+
+    // The old count doesn't carry into the next month
+    assert_eq!(quotas.usage_at("acme", november).used, 0);
+    let usage = quotas.consume_at("acme", november).unwrap();
+    assert_eq!((usage.used, usage.rejected), (1, 0));
+}
+
+#[test]
+fn test_tenants_without_a_quota_are_unlimited() {
+    let quotas = QuotaTracker::default().with_limit("acme", 0);
+    assert!(quotas.consume("acme").is_err());
+    for _ in 0..10 {
+        let usage = quotas.consume("globex").unwrap();
+        assert_eq!(usage.limit, None);
+    }
+    assert_eq!(quotas.usage("globex").used, 0);
+}
@@ -1,5 +1,5 @@
-use imagekit::signature::verify_signature;
-use std::collections::BTreeMap;
+use imagekit::signature::{canonical_string, is_signed_param, verify_signature, verify_signed_url, verify_tenant_signature, SignatureError};
+use std::collections::{BTreeMap, HashMap};
 use hmac::Mac;
 
 #[test]
@@ -9,7 +9,7 @@ fn signature_validates() {
     params.insert("w".to_string(), "400".to_string());
     let secret = "s";
     // compute expected
-    let canonical = params.iter().map(|(k,v)| format!("{}={}",k,v)).collect::<Vec<_>>().join("&");
+    let canonical = serde_urlencoded::to_string(&params).unwrap();
     let mut mac = hmac::Hmac::<sha2::Sha256>::new_from_slice(secret.as_bytes()).unwrap();
     mac.update(canonical.as_bytes());
     let sig = hex::encode(mac.finalize().into_bytes());
@@ -25,4 +25,129 @@ fn signature_rejects_tamper() {
     mac.update(b"bad=param");
     let sig = hex::encode(mac.finalize().into_bytes());
     assert!(verify_signature(&params, &sig, secret).is_err());
-}
\ No newline at end of file
+}
+
+/// Signs sorted, URL-encoded pairs the way a client would
+fn sign(pairs: &[(&str, &str)], secret: &str) -> String {
+    let canonical = serde_urlencoded::to_string(pairs).unwrap();
+    let mut mac = hmac::Hmac::<sha2::Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(canonical.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+#[test]
+fn signed_url_validates_with_encoded_url_param() {
+    // The signature covers the decoded source URL, query string and all
+    let sig = sign(&[("url", "https://example.com/a b.jpg?v=2"), ("w", "400")], "s");
+    let url = format!(
+        "https://imgs.example.com/img?url=https%3A%2F%2Fexample.com%2Fa%20b.jpg%3Fv%3D2&w=400&sig={}#top",
+        sig
+    );
+    assert!(verify_signed_url(&url, "s").is_ok());
+    // A bare query string works too
+    let query = url.split_once('?').unwrap().1;
+    assert!(verify_signed_url(query, "s").is_ok());
+}
+
+#[test]
+fn signed_url_rejects_tamper() {
+    let sig = sign(&[("url", "https://example.com/a.jpg"), ("w", "400")], "s");
+    let url = format!("/img?url=https%3A%2F%2Fexample.com%2Fa.jpg&w=800&sig={}", sig);
+    assert!(matches!(verify_signed_url(&url, "s"), Err(SignatureError::Invalid)));
+
+    // Repeating a signed param can't smuggle in a second value
+    let url = format!("/img?url=https%3A%2F%2Fexample.com%2Fa.jpg&w=400&w=800&sig={}", sig);
+    assert!(matches!(verify_signed_url(&url, "s"), Err(SignatureError::Invalid)));
+}
+
+#[test]
+fn signed_url_ignores_unsigned_tracking_params() {
+    let sig = sign(&[("url", "https://example.com/a.jpg"), ("w", "400")], "s");
+    let url = format!(
+        "/img?url=https%3A%2F%2Fexample.com%2Fa.jpg&w=400&utm_source=newsletter&utm_source=ads&sig={}",
+        sig
+    );
+    assert!(verify_signed_url(&url, "s").is_ok());
+    assert!(!is_signed_param("utm_source"));
+    assert!(is_signed_param("w"));
+}
+
+#[test]
+fn signed_url_without_sig_is_missing() {
+    let url = "/img?url=https%3A%2F%2Fexample.com%2Fa.jpg&w=400";
+    assert!(matches!(verify_signed_url(url, "s"), Err(SignatureError::Missing)));
+}
+
+#[test]
+fn signature_handles_reserved_characters_in_values() {
+    let mut params = BTreeMap::new();
+    params.insert("url".to_string(), "https://example.com/a b.jpg?x=1&y=2".to_string());
+    params.insert("w".to_string(), "400".to_string());
+
+    let canonical = canonical_string(&params);
+    assert_eq!(canonical, "url=https%3A%2F%2Fexample.com%2Fa+b.jpg%3Fx%3D1%26y%3D2&w=400");
+
+    let sig = sign(&[("url", "https://example.com/a b.jpg?x=1&y=2"), ("w", "400")], "s");
+    assert!(verify_signature(&params, &sig, "s").is_ok());
+    // The canonical string doubles as the query of a verifiable URL
+    assert!(verify_signed_url(&format!("/img?{}&sig={}", canonical, sig), "s").is_ok());
+}
+
+#[test]
+fn canonical_string_prevents_concatenation_collisions() {
+    // Naively, both of these canonicalize to "url=https://example.com/a.jpg&w=400"
+    let mut smuggled = BTreeMap::new();
+    smuggled.insert("url".to_string(), "https://example.com/a.jpg&w=400".to_string());
+
+    let mut real = BTreeMap::new();
+    real.insert("url".to_string(), "https://example.com/a.jpg".to_string());
+    real.insert("w".to_string(), "400".to_string());
+
+    assert_ne!(canonical_string(&smuggled), canonical_string(&real));
+
+    // A signature for one never verifies the other
+    let sig = sign(&[("url", "https://example.com/a.jpg"), ("w", "400")], "s");
+    assert!(verify_signature(&real, &sig, "s").is_ok());
+    assert!(matches!(verify_signature(&smuggled, &sig, "s"), Err(SignatureError::Invalid)));
+
+    // `=` inside a value can't shift the key/value boundary either
+    let mut shifted = BTreeMap::new();
+    shifted.insert("url".to_string(), "https://example.com/a.jpg".to_string());
+    shifted.insert("w".to_string(), "400&h=300".to_string());
+    let mut split = real.clone();
+    split.insert("h".to_string(), "300".to_string());
+    assert_ne!(canonical_string(&shifted), canonical_string(&split));
+}
+
+#[test]
+fn tenant_signatures_use_the_tenant_secret() {
+    let tenants = HashMap::from([
+        ("acme".to_string(), "acme-secret".to_string()),
+        ("globex".to_string(), "globex-secret".to_string()),
+    ]);
+    let params = |tenant: &str| {
+        BTreeMap::from([
+            ("tenant".to_string(), tenant.to_string()),
+            ("url".to_string(), "https://example.com/a.jpg".to_string()),
+        ])
+    };
+    let acme_sig = sign(&[("tenant", "acme"), ("url", "https://example.com/a.jpg")], "acme-secret");
+    let globex_sig = sign(&[("tenant", "globex"), ("url", "https://example.com/a.jpg")], "globex-secret");
+
+    assert!(verify_tenant_signature(&params("acme"), &acme_sig, "main", &tenants).is_ok());
+    assert!(verify_tenant_signature(&params("globex"), &globex_sig, "main", &tenants).is_ok());
+
+    // One tenant's secret can't sign for another, nor can the main secret
+    let forged = sign(&[("tenant", "globex"), ("url", "https://example.com/a.jpg")], "acme-secret");
+    assert!(matches!(verify_tenant_signature(&params("globex"), &forged, "main", &tenants), Err(SignatureError::Invalid)));
+    let main_sig = sign(&[("tenant", "acme"), ("url", "https://example.com/a.jpg")], "main");
+    assert!(matches!(verify_tenant_signature(&params("acme"), &main_sig, "main", &tenants), Err(SignatureError::Invalid)));
+
+    let sig = sign(&[("tenant", "initech"), ("url", "https://example.com/a.jpg")], "main");
+    assert!(matches!(verify_tenant_signature(&params("initech"), &sig, "main", &tenants), Err(SignatureError::UnknownTenant)));
+
+    // Without `tenant` the main secret applies
+    let untenanted = BTreeMap::from([("url".to_string(), "https://example.com/a.jpg".to_string())]);
+    let sig = sign(&[("url", "https://example.com/a.jpg")], "main");
+    assert!(verify_tenant_signature(&untenanted, &sig, "main", &tenants).is_ok());
+}
@@ -1,5 +1,6 @@
-use imagekit::transform::{encode_image, resize_image, decode_image};
-use imagekit::config::ImageFormat;
+use imagekit::transform::{auto_quality, encode_image, encode_image_with, resize_image, resize_image_with, fits_within, resize_fill, decode_image, decode_image_with, resize_cover, focal_crop_origin, crop_with_gravity, avif_bit_depth, sniff_output_format, is_ico, ICO_MAX_SIZE, crop_image, crop_rect, parse_crop, Crop, is_heif, encode_to_budget, encode_with_fallback, pixelate_image, rotate_arbitrary, invert_image, set_opacity, gamma_image, embed_icc_profile, source_icc_profile, probe_image, encoded_dimensions, draw_ring, draw_badge, draw_text, sanitize_text, sepia_image, parse_ring, tint_image, parse_hex_color, EncodeOptions, ResizeFilters, BUDGET_MIN_QUALITY};
+use imagekit::transform::params::{ChromaSubsampling, FitMode, Gravity, ResizeFilter, TextPosition};
+use imagekit::config::{AvifColorSpace, ImageFormat};
 use image::GenericImageView;
 
 
@@ -119,6 +120,25 @@ fn test_decode_empty_data() {
             "Should fail on empty data");
 }
 
+#[test]
+fn test_decode_with_limits_fails_cleanly() {
+    let img = image::DynamicImage::new_rgba8(64, 64);
+    let mut png = Vec::new();
+    img.write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png).unwrap();
+    
+    let mut narrow = image::Limits::default();
+    narrow.max_image_width = Some(32);
+    assert!(decode_image_with(&png, narrow).is_err(), "width over the limit should be rejected");
+    
+    // 64x64 RGBA needs 16KB, well over a 1KB allocation cap
+    let mut small = image::Limits::default();
+    small.max_alloc = Some(1024);
+    assert!(decode_image_with(&png, small).is_err(), "allocation over the limit should be rejected");
+    
+    let (decoded, _) = decode_image_with(&png, image::Limits::default()).unwrap();
+    assert_eq!((decoded.width(), decoded.height()), (64, 64));
+}
+
 #[test]
 fn decode_then_webp() {
     // Generate a simple PNG in memory to test decode path
@@ -130,6 +150,19 @@ fn decode_then_webp() {
     assert!(out.len() > 0);
 }
 
+#[test]
+fn test_heif_magic_detection() {
+    // ISO-BMFF ftyp box: size, "ftyp", major brand, minor version, compatible brands
+    let heic = b"\x00\x00\x00\x18ftypheic\x00\x00\x00\x00mif1heic";
+    let avif = b"\x00\x00\x00\x1cftypavif\x00\x00\x00\x00avifmif1miaf";
+    let avif_mif1 = b"\x00\x00\x00\x18ftypmif1\x00\x00\x00\x00avifmiaf";
+
+    assert!(is_heif(heic));
+    assert!(!is_heif(avif), "AVIF shares the container but is not HEIF");
+    assert!(!is_heif(avif_mif1), "mif1 files listing avif are AVIF");
+    assert!(!is_heif(&[0xFF, 0xD8, 0xFF, 0xE0]));
+}
+
 // ====================================================================================
 // FORMAT CONVERSION TESTS
 // ====================================================================================
@@ -256,6 +289,26 @@ fn test_full_pipeline_webp() {
     assert_eq!(format, Some(ImageFormat::webp));
 }
 
+#[test]
+fn test_fill_stretches_to_exact_dimensions() {
+    let img = image::DynamicImage::new_rgb8(1920, 1080); // 16:9 ratio
+
+    // Same request as above, but fit=fill honors both dimensions
+    let filled = resize_fill(img, 640, 480, ResizeFilters::default()).unwrap();
+    assert_eq!(filled.dimensions(), (640, 480));
+
+    let encoded = encode_image(&filled, ImageFormat::webp, 85).unwrap();
+    let (decoded, _) = decode_image(&encoded).unwrap();
+    assert_eq!(decoded.dimensions(), (640, 480));
+
+    // Enlarging one axis while shrinking the other still lands exactly
+    let mixed = resize_fill(image::DynamicImage::new_rgb8(100, 100), 300, 50, ResizeFilters::default()).unwrap();
+    assert_eq!(mixed.dimensions(), (300, 50));
+
+    assert_eq!("fill".parse::<FitMode>().unwrap(), FitMode::Fill);
+    assert_eq!(FitMode::Fill.to_string(), "fill");
+}
+
 #[test]
 fn test_full_pipeline_avif() {
     // Test complete pipeline with AVIF
@@ -285,4 +338,688 @@ fn test_resize_reduces_size() {
     assert!(resized_encoded.len() < original_encoded.len(),
             "Resized image should produce smaller file. Original: {} bytes, Resized: {} bytes",
             original_encoded.len(), resized_encoded.len());
-}
\ No newline at end of file
+}
+
+// ====================================================================================
+// COVER CROP / FOCAL POINT TESTS
+// ====================================================================================
+
+#[test]
+fn test_cover_produces_exact_dimensions() {
+    let img = image::DynamicImage::new_rgb8(1920, 1080);
+    let cropped = resize_cover(img, 400, 400, (0.5, 0.5)).unwrap();
+
+    assert_eq!(cropped.dimensions(), (400, 400));
+}
+
+#[test]
+fn test_focal_point_biases_cover_crop() {
+    // 200x100 source: right-most 50 columns are red, the rest blue
+    let mut src = image::RgbImage::from_pixel(200, 100, image::Rgb([0, 0, 255]));
+    for x in 150..200 {
+        for y in 0..100 {
+            src.put_pixel(x, y, image::Rgb([255, 0, 0]));
+        }
+    }
+    let img = image::DynamicImage::ImageRgb8(src);
+
+    // Top-right focal point pushes the window to the right edge
+    assert_eq!(focal_crop_origin((200, 100), (100, 100), (0.9, 0.1)), (100, 0));
+    assert_eq!(focal_crop_origin((200, 100), (100, 100), (0.5, 0.5)), (50, 0));
+
+    let biased = resize_cover(img.clone(), 100, 100, (0.9, 0.1)).unwrap().to_rgb8();
+    let centered = resize_cover(img, 100, 100, (0.5, 0.5)).unwrap().to_rgb8();
+
+    assert_eq!(biased.get_pixel(99, 0), &image::Rgb([255, 0, 0]),
+               "Focal crop should keep the top-right region in frame");
+    assert_eq!(centered.get_pixel(90, 0), &image::Rgb([0, 0, 255]),
+               "Center crop should cut the right edge");
+}
+
+// ====================================================================================
+// AVIF SPEED TESTS
+// ====================================================================================
+
+#[test]
+fn test_avif_speed_tradeoff() {
+    let img = image::DynamicImage::ImageRgb8(image::RgbImage::from_fn(128, 128, |x, y| {
+        image::Rgb([(x * 2) as u8, (y * 2) as u8, ((x * 7 + y * 13) % 256) as u8])
+    }));
+
+    // Encode time isn't asserted: single wall-clock samples are too noisy
+    // on shared runners. Every speed, out-of-range ones included, must
+    // still produce valid AVIF.
+    for speed in [0, 1, 10, 255] {
+        let out = encode_image_with(&img, ImageFormat::avif, 60, &EncodeOptions { avif_speed: speed, ..Default::default() }).unwrap();
+        assert_eq!(image::guess_format(&out).unwrap(), image::ImageFormat::Avif, "speed {}", speed);
+        assert_eq!(encoded_dimensions(&out), Some((128, 128)), "speed {}", speed);
+    }
+}
+
+// ====================================================================================
+// WEBP LOSSLESS TESTS
+// ====================================================================================
+
+#[test]
+fn test_webp_lossless_round_trip() {
+    // Sharp-edged checkerboard with partial transparency
+    let src = image::RgbaImage::from_fn(64, 64, |x, y| {
+        if (x / 8 + y / 8) % 2 == 0 {
+            image::Rgba([255, 0, 0, 255])
+        } else {
+            image::Rgba([0, 32, 255, 128])
+        }
+    });
+    let img = image::DynamicImage::ImageRgba8(src.clone());
+
+    let options = EncodeOptions { webp_lossless: true, ..Default::default() };
+    let encoded = encode_image_with(&img, ImageFormat::webp, 1, &options).unwrap();
+    let (decoded, format) = decode_image(&encoded).unwrap();
+
+    assert_eq!(format, Some(ImageFormat::webp));
+    assert_eq!(decoded.to_rgba8(), src,
+               "Lossless WebP must decode back pixel-identical, alpha included");
+}
+
+// ====================================================================================
+// AVIF COLORSPACE TESTS
+// ====================================================================================
+
+/// Reads (primaries, transfer, matrix) from the AVIF `colr` nclx box
+fn avif_cicp(avif: &[u8]) -> (u16, u16, u16) {
+    let pos = avif.windows(8).position(|w| w == b"colrnclx").expect("nclx colr box") + 8;
+    let read = |i: usize| u16::from_be_bytes([avif[i], avif[i + 1]]);
+    (read(pos), read(pos + 2), read(pos + 4))
+}
+
+#[test]
+fn test_avif_colorspace_sets_cicp() {
+    let img = image::DynamicImage::ImageRgb8(image::RgbImage::from_fn(32, 32, |x, y| {
+        image::Rgb([(x * 8) as u8, (y * 8) as u8, 128])
+    }));
+
+    for (colorspace, expected) in [(AvifColorSpace::Bt709, 1), (AvifColorSpace::Bt601, 6)] {
+        let options = EncodeOptions { avif_colorspace: colorspace, avif_speed: 10, ..Default::default() };
+        let avif = encode_image_with(&img, ImageFormat::avif, 70, &options).unwrap();
+
+        assert_eq!(avif_cicp(&avif), (expected, expected, expected),
+                   "Unexpected CICP for {}", colorspace);
+    }
+}
+
+#[test]
+fn test_avif_depth() {
+    let img = image::DynamicImage::ImageRgba16(image::ImageBuffer::from_fn(32, 32, |x, y| {
+        image::Rgba([(x * 2048) as u16, (y * 2048) as u16, 40_000, 65_535])
+    }));
+
+    let eight = encode_image_with(&img, ImageFormat::avif, 70, &EncodeOptions { avif_speed: 10, ..Default::default() }).unwrap();
+    assert_eq!(sniff_output_format(&eight), Some(ImageFormat::avif));
+    assert_eq!(avif_bit_depth(&eight), Some(8));
+
+    for colorspace in [AvifColorSpace::Srgb, AvifColorSpace::Bt709] {
+        let options = EncodeOptions { avif_depth: 10, avif_colorspace: colorspace, avif_speed: 10, ..Default::default() };
+        let ten = encode_image_with(&img, ImageFormat::avif, 70, &options).unwrap();
+        assert_eq!(sniff_output_format(&ten), Some(ImageFormat::avif));
+        assert_eq!(avif_bit_depth(&ten), Some(10), "{} should encode 10-bit", colorspace);
+        assert_eq!(imagekit::transform::encoded_dimensions(&ten), Some((32, 32)));
+    }
+
+    let jpeg = encode_image(&img, ImageFormat::jpeg, 80).unwrap();
+    assert_eq!(avif_bit_depth(&jpeg), None);
+}
+
+// ====================================================================================
+// GRAVITY / SMART CROP TESTS
+// ====================================================================================
+
+/// Luma variance as a rough measure of retained detail
+fn luma_variance(img: &image::DynamicImage) -> f64 {
+    let luma = img.to_luma8();
+    let n = (luma.width() * luma.height()) as f64;
+    let mean = luma.pixels().map(|p| p[0] as f64).sum::<f64>() / n;
+    luma.pixels().map(|p| (p[0] as f64 - mean).powi(2)).sum::<f64>() / n
+}
+
+#[test]
+fn test_smart_crop_keeps_detailed_region() {
+    // 300x100: high-detail checkerboard on the left, flat gray elsewhere
+    let src = image::RgbImage::from_fn(300, 100, |x, y| {
+        if x < 80 && (x / 4 + y / 4) % 2 == 0 {
+            image::Rgb([255, 255, 255])
+        } else if x < 80 {
+            image::Rgb([0, 0, 0])
+        } else {
+            image::Rgb([128, 128, 128])
+        }
+    });
+    let img = image::DynamicImage::ImageRgb8(src);
+
+    let smart = crop_with_gravity(img.clone(), 100, 100, Gravity::Smart).unwrap();
+    let center = crop_with_gravity(img, 100, 100, Gravity::Center).unwrap();
+
+    assert_eq!(smart.dimensions(), (100, 100));
+    assert!(luma_variance(&smart) > luma_variance(&center),
+            "Smart crop should retain more detail than center crop");
+}
+
+#[test]
+fn test_compass_gravity_pins_edges() {
+    // 200x100: left half black, right half white
+    let src = image::RgbImage::from_fn(200, 100, |x, _| {
+        if x < 100 { image::Rgb([0, 0, 0]) } else { image::Rgb([255, 255, 255]) }
+    });
+    let img = image::DynamicImage::ImageRgb8(src);
+
+    let west = crop_with_gravity(img.clone(), 100, 100, Gravity::West).unwrap().to_rgb8();
+    let east = crop_with_gravity(img, 100, 100, Gravity::East).unwrap().to_rgb8();
+
+    assert_eq!(west.get_pixel(50, 50), &image::Rgb([0, 0, 0]));
+    assert_eq!(east.get_pixel(50, 50), &image::Rgb([255, 255, 255]));
+}
+
+#[test]
+fn test_encode_to_budget_respects_byte_limit() {
+    // Smooth gradient with mild texture: compresses well but scales with quality
+    let img = image::DynamicImage::ImageRgb8(image::ImageBuffer::from_fn(256, 256, |x, y| {
+        let noise = ((x * 7 + y * 13) % 17) as u8;
+        image::Rgb([x as u8, y as u8, (x as u8 / 2).wrapping_add(noise)])
+    }));
+
+    let full = encode_image(&img, ImageFormat::jpeg, 95).unwrap();
+    let budget = full.len() / 2;
+
+    let (out, quality) = encode_to_budget(&img, ImageFormat::jpeg, 95, budget, &EncodeOptions::default()).unwrap();
+    assert!(out.len() <= budget, "{} bytes exceeds budget of {}", out.len(), budget);
+    assert!(quality < 95 && quality >= BUDGET_MIN_QUALITY, "unexpected quality {}", quality);
+    image::load_from_memory(&out).expect("budgeted output decodes");
+
+    // A generous budget keeps the requested quality
+    let (_, quality) = encode_to_budget(&img, ImageFormat::jpeg, 95, full.len(), &EncodeOptions::default()).unwrap();
+    assert_eq!(quality, 95);
+}
+
+#[test]
+fn test_decode_svg_reports_unsupported_format() {
+    let svg = br#"<?xml version="1.0"?>
+<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10"><rect width="10" height="10"/></svg>"#;
+    let err = decode_image(svg).unwrap_err().to_string();
+    assert!(err.contains("unsupported format: svg"), "unexpected error: {}", err);
+}
+
+#[test]
+fn test_decode_truncated_jpeg_reports_format_and_bytes() {
+    let img = image::DynamicImage::new_rgb8(64, 64);
+    let jpeg = encode_image(&img, ImageFormat::jpeg, 80).unwrap();
+    let truncated = &jpeg[..jpeg.len() / 3];
+
+    let err = decode_image(truncated).unwrap_err().to_string();
+    assert!(err.contains("Jpeg"), "missing guessed format: {}", err);
+    assert!(err.contains("ffd8ff"), "missing leading bytes: {}", err);
+}
+
+/// ICO with 16x16 red, 64x64 green and 32x32 blue PNG entries, in that order
+fn multi_size_ico() -> Vec<u8> {
+    use image::codecs::ico::{IcoEncoder, IcoFrame};
+
+    let frames: Vec<IcoFrame> = [(16, [255, 0, 0, 255]), (64, [0, 255, 0, 255]), (32, [0, 0, 255, 255])]
+        .into_iter()
+        .map(|(size, color)| {
+            let img = image::RgbaImage::from_pixel(size, size, image::Rgba(color));
+            IcoFrame::as_png(img.as_raw(), size, size, image::ExtendedColorType::Rgba8).unwrap()
+        })
+        .collect();
+    let mut ico = Vec::new();
+    IcoEncoder::new(&mut ico).encode_images(&frames).unwrap();
+    ico
+}
+
+#[test]
+fn test_decode_ico_selects_largest_entry() {
+    let ico = multi_size_ico();
+    assert!(is_ico(&ico));
+    assert!(!is_ico(&encode_image(&image::DynamicImage::new_rgb8(4, 4), ImageFormat::png, 80).unwrap()));
+
+    let (img, format) = decode_image(&ico).unwrap();
+    assert_eq!(img.dimensions(), (64, 64));
+    assert_eq!(img.to_rgba8().get_pixel(32, 32).0, [0, 255, 0, 255]);
+    assert_eq!(format, Some(ImageFormat::ico));
+}
+
+#[test]
+fn test_ico_output_is_a_single_favicon() {
+    let (img, _) = decode_image(&multi_size_ico()).unwrap();
+    let favicon = resize_image(img, Some(32), Some(32)).unwrap();
+    let encoded = encode_image(&favicon, ImageFormat::ico, 80).unwrap();
+    assert_eq!(sniff_output_format(&encoded), Some(ImageFormat::ico));
+    assert_eq!(u16::from_le_bytes([encoded[4], encoded[5]]), 1, "one directory entry");
+    assert_eq!(decode_image(&encoded).unwrap().0.dimensions(), (32, 32));
+
+    // ICO entries top out at 256px, so larger output is scaled to fit
+    let wide = image::DynamicImage::new_rgba8(600, 300);
+    let encoded = encode_image(&wide, ImageFormat::ico, 80).unwrap();
+    assert_eq!(decode_image(&encoded).unwrap().0.dimensions(), (ICO_MAX_SIZE, 128));
+}
+
+#[test]
+fn test_encode_to_budget_encodes_ico_once() {
+    let img = image::DynamicImage::ImageRgb8(image::ImageBuffer::from_fn(64, 64, |x, y| {
+        image::Rgb([(x * 4) as u8, (y * 4) as u8, ((x ^ y) * 4) as u8])
+    }));
+    let full = encode_image(&img, ImageFormat::ico, 80).unwrap();
+
+    // Quality doesn't change ICO output, so an unmet budget returns it as is
+    let (out, quality) = encode_to_budget(&img, ImageFormat::ico, 80, full.len() / 4, &EncodeOptions::default()).unwrap();
+    assert_eq!(out, full);
+    assert_eq!(quality, 80);
+}
+
+#[test]
+fn test_pixelate_blocks_are_uniform() {
+    let img = image::DynamicImage::ImageRgb8(image::ImageBuffer::from_fn(64, 48, |x, y| {
+        image::Rgb([(x * 4) as u8, (y * 5) as u8, ((x + y) * 2) as u8])
+    }));
+
+    let out = pixelate_image(img, 8);
+    assert_eq!(out.dimensions(), (64, 48), "pixelate must preserve dimensions");
+
+    let rgb = out.to_rgb8();
+    for by in 0..6 {
+        for bx in 0..8 {
+            let anchor = rgb.get_pixel(bx * 8, by * 8);
+            for dy in 0..8 {
+                for dx in 0..8 {
+                    assert_eq!(rgb.get_pixel(bx * 8 + dx, by * 8 + dy), anchor,
+                               "block ({}, {}) is not uniform", bx, by);
+                }
+            }
+        }
+    }
+
+    // Neighbouring blocks of a gradient differ
+    assert_ne!(rgb.get_pixel(0, 0), rgb.get_pixel(8, 0));
+}
+
+#[test]
+fn test_tint_maps_gray_ramp_through_color() {
+    let ramp = image::DynamicImage::ImageLuma8(image::ImageBuffer::from_fn(256, 1, |x, _| image::Luma([x as u8])));
+    let orange = parse_hex_color("#ff8000").unwrap();
+
+    let out = tint_image(ramp.clone(), orange).to_rgb8();
+    assert_eq!(out.get_pixel(0, 0).0, [0, 0, 0], "shadows stay black");
+    assert_eq!(out.get_pixel(255, 0).0, [255, 255, 255], "highlights stay white");
+
+    // Mid-tones carry the tint's hue: red dominates green dominates blue
+    let mid = out.get_pixel(100, 0).0;
+    assert!(mid[0] > mid[1] && mid[1] > mid[2], "unexpected mid-tone {:?}", mid);
+
+    // White and black tints degrade to grayscale
+    for color in [[255, 255, 255], [0, 0, 0]] {
+        let gray = tint_image(ramp.clone(), color).to_rgb8();
+        for px in gray.pixels() {
+            assert!(px[0] == px[1] && px[1] == px[2], "tint {:?} produced color {:?}", color, px);
+        }
+    }
+
+    assert_eq!(parse_hex_color("zzzzzz"), None);
+    assert_eq!(parse_hex_color("fff"), None);
+}
+
+#[test]
+fn test_ring_drawn_at_inscribed_radius() {
+    let gray = image::Rgba([128, 128, 128, 255]);
+    let img = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(100, 100, gray));
+    let (color, width) = parse_ring("ff0000,6").unwrap();
+    assert_eq!(width, 6);
+
+    let out = draw_ring(img, color, width).to_rgba8();
+    // Mid-ring (radius ~47) on each axis is fully covered
+    for (x, y) in [(96, 50), (3, 50), (50, 96), (50, 3)] {
+        assert_eq!(out.get_pixel(x, y).0, [255, 0, 0, 255], "no ring at ({}, {})", x, y);
+    }
+    // Inside the ring and outside the inscribed circle are untouched
+    assert_eq!(*out.get_pixel(50, 50), gray);
+    assert_eq!(*out.get_pixel(80, 50), gray);
+    assert_eq!(*out.get_pixel(0, 0), gray);
+
+    // The edge is anti-aliased: some pixels are blends of ring and image
+    let blended = out.pixels().any(|p| p[0] > 128 && p[0] < 255 && p[1] > 0 && p[1] < 128);
+    assert!(blended, "ring edge should be anti-aliased");
+
+    assert_eq!(parse_ring("ff0000").unwrap().1, 4);
+    assert!(parse_ring("ff0000,0").is_none());
+}
+
+#[test]
+fn test_badge_drawn_in_lower_right() {
+    let img = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(100, 100, image::Rgba([0, 0, 0, 255])));
+    let out = draw_badge(img, [0, 255, 0]).to_rgba8();
+    assert_eq!(out.get_pixel(77, 77).0, [0, 255, 0, 255]);
+    assert_eq!(out.get_pixel(22, 22).0, [0, 0, 0, 255]);
+}
+
+#[test]
+fn test_fits_within_ignores_omitted_sides() {
+    let small = image::DynamicImage::new_rgb8(100, 100);
+    let large = image::DynamicImage::new_rgb8(800, 400);
+    assert!(fits_within(&small, Some(500), Some(500)));
+    assert!(fits_within(&small, Some(100), None));
+    assert!(!fits_within(&large, Some(500), Some(500)));
+    assert!(!fits_within(&large, None, Some(300)));
+}
+
+#[test]
+fn test_rotate_arbitrary_expands_canvas() {
+    let img = image::DynamicImage::ImageRgb8(image::RgbImage::from_pixel(100, 50, image::Rgb([200, 80, 40])));
+
+    let rotated = rotate_arbitrary(img.clone(), 45.0, None);
+    // |100cos45| + |50sin45| ~= 106.07 on both axes
+    assert_eq!(rotated.dimensions(), (107, 107));
+    let rgba = rotated.to_rgba8();
+    assert_eq!(rgba.get_pixel(0, 0).0[3], 0, "new corners are transparent");
+    let center = rgba.get_pixel(53, 53).0;
+    for (got, want) in center.iter().zip([200u8, 80, 40, 255]) {
+        assert!(got.abs_diff(want) <= 1, "center keeps the source color, got {:?}", center);
+    }
+
+    let filled = rotate_arbitrary(img.clone(), -45.0, Some([0, 0, 255]));
+    assert!(!filled.color().has_alpha(), "an opaque source stays opaque with a fill");
+    assert_eq!(filled.to_rgb8().get_pixel(0, 0).0, [0, 0, 255]);
+
+    // Right angles swap dimensions exactly; full turns are a no-op
+    assert_eq!(rotate_arbitrary(img.clone(), 90.0, None).dimensions(), (50, 100));
+    assert_eq!(rotate_arbitrary(img, 360.0, None).dimensions(), (100, 50));
+}
+
+#[test]
+fn test_invert_round_trips_and_keeps_alpha() {
+    let img = image::DynamicImage::ImageRgba8(image::ImageBuffer::from_fn(16, 16, |x, y| {
+        image::Rgba([(x * 16) as u8, (y * 16) as u8, 200, (x * 8 + y) as u8])
+    }));
+
+    let once = invert_image(img.clone());
+    let px = once.to_rgba8().get_pixel(3, 5).0;
+    assert_eq!(px, [255 - 48, 255 - 80, 55, 29], "colors inverted, alpha preserved");
+
+    let twice = invert_image(once);
+    assert_eq!(twice.to_rgba8(), img.to_rgba8(), "double inversion must restore the original");
+}
+
+#[test]
+fn test_sepia_warms_neutral_gray_and_keeps_alpha() {
+    let img = image::DynamicImage::ImageRgba8(image::ImageBuffer::from_pixel(8, 8, image::Rgba([128, 128, 128, 77])));
+
+    let px = sepia_image(img).to_rgba8().get_pixel(4, 4).0;
+    assert!(px[0] > px[1] && px[1] > px[2], "sepia should be warm, got {:?}", px);
+    assert_eq!(px[3], 77, "alpha preserved");
+
+    let opaque = image::DynamicImage::ImageRgb8(image::ImageBuffer::from_pixel(4, 4, image::Rgb([128, 128, 128])));
+    let out = sepia_image(opaque);
+    assert!(!out.color().has_alpha());
+    let px = out.to_rgb8().get_pixel(0, 0).0;
+    assert!(px[0] > px[2], "red should exceed blue, got {:?}", px);
+}
+
+#[test]
+fn test_upscale_filter_keeps_pixel_art_edges() {
+    let checker = |size: u32| image::DynamicImage::ImageLuma8(image::ImageBuffer::from_fn(size, size, |x, y| {
+        image::Luma([if (x + y) % 2 == 0 { 0 } else { 255 }])
+    }));
+    let filters = ResizeFilters { upscale: ResizeFilter::Nearest, ..Default::default() };
+
+    // Enlarging 8x with nearest leaves only the original two colors
+    let up = resize_image_with(checker(4), Some(32), None, filters).unwrap().to_luma8();
+    assert_eq!(up.dimensions(), (32, 32));
+    assert!(up.pixels().all(|p| p[0] == 0 || p[0] == 255), "upscaled edges were smoothed");
+    assert_eq!(up.get_pixel(7, 0)[0], 0);
+    assert_eq!(up.get_pixel(8, 0)[0], 255);
+
+    // Shrinking the same image still goes through Lanczos and blends
+    let down = resize_image_with(checker(64), Some(16), None, filters).unwrap().to_luma8();
+    assert_eq!(down.dimensions(), (16, 16));
+    assert!(down.pixels().any(|p| p[0] > 0 && p[0] < 255), "downscale should blend cells");
+
+    // Default filters match the existing behavior
+    let default = resize_image(checker(4), Some(32), None).unwrap().to_luma8();
+    assert!(default.pixels().any(|p| p[0] > 0 && p[0] < 255));
+}
+
+#[test]
+fn test_staged_downscale_prepasses_and_stays_exact() {
+    // Smooth gradient with some texture, so both paths have real work
+    let src = image::DynamicImage::ImageRgb8(image::ImageBuffer::from_fn(3200, 2400, |x, y| {
+        image::Rgb([(x / 13) as u8, (y / 10) as u8, ((x ^ y) & 0x3f) as u8])
+    }));
+    let single = ResizeFilters { staged_downscale: None, ..Default::default() };
+    let staged = ResizeFilters::default();
+
+    let slow = resize_image_with(src.clone(), Some(160), Some(120), single).unwrap();
+    let fast = resize_image_with(src.clone(), Some(160), Some(120), staged).unwrap();
+    assert_eq!(fast.dimensions(), (160, 120));
+    assert_eq!(slow.dimensions(), (160, 120));
+
+    // A 20x shrink takes the box pass to twice the target, then the filter
+    let expected = src.thumbnail_exact(320, 240).resize_exact(160, 120, ResizeFilter::default().into());
+    assert_eq!(fast.to_rgb8(), expected.to_rgb8());
+    assert_ne!(fast.to_rgb8(), slow.to_rgb8());
+
+    // Comparable quality: per-channel mean difference stays small
+    let (a, b) = (slow.to_rgb8(), fast.to_rgb8());
+    let diff: u64 = a.as_raw().iter().zip(b.as_raw()).map(|(x, y)| x.abs_diff(*y) as u64).sum();
+    let mean = diff as f64 / a.as_raw().len() as f64;
+    assert!(mean < 4.0, "staged output drifted: mean difference {}", mean);
+}
+
+#[test]
+fn test_jpeg_chroma_444_reduces_color_bleed() {
+    // Red/blue edge between columns 6 and 7, splitting a 2-pixel chroma block
+    let img = image::DynamicImage::ImageRgb8(image::ImageBuffer::from_fn(16, 16, |x, _| {
+        if x < 7 { image::Rgb([255, 0, 0]) } else { image::Rgb([0, 0, 255]) }
+    }));
+    let bleed = |chroma: ChromaSubsampling| {
+        let options = EncodeOptions { jpeg_chroma: Some(chroma), ..Default::default() };
+        let encoded = encode_image_with(&img, ImageFormat::jpeg, 90, &options).unwrap();
+        let decoded = image::load_from_memory(&encoded).unwrap().to_rgb8();
+        let original = img.to_rgb8();
+        // Error in the columns either side of the edge
+        (0..16).flat_map(|y| (5..9).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let (a, b) = (decoded.get_pixel(x, y).0, original.get_pixel(x, y).0);
+                a.iter().zip(b).map(|(p, q)| p.abs_diff(q) as u32).sum::<u32>()
+            })
+            .sum::<u32>()
+    };
+
+    let full = bleed(ChromaSubsampling::Cs444);
+    let subsampled = bleed(ChromaSubsampling::Cs420);
+    assert!(full < subsampled, "4:4:4 bleed {} should be below 4:2:0 bleed {}", full, subsampled);
+
+    let options = EncodeOptions { jpeg_chroma: Some(ChromaSubsampling::Cs422), ..Default::default() };
+    let encoded = encode_image_with(&img, ImageFormat::jpeg, 90, &options).unwrap();
+    assert_eq!(image::guess_format(&encoded).unwrap(), image::ImageFormat::Jpeg);
+}
+
+#[test]
+fn test_gamma_curve() {
+    let mut img = image::RgbaImage::from_pixel(4, 1, image::Rgba([0, 0, 0, 90]));
+    img.put_pixel(1, 0, image::Rgba([128, 64, 192, 90]));
+    img.put_pixel(2, 0, image::Rgba([255, 255, 255, 90]));
+    let img = image::DynamicImage::ImageRgba8(img);
+
+    assert_eq!(gamma_image(img.clone(), 1.0).to_rgba8(), img.to_rgba8(), "gamma=1.0 must be a no-op");
+
+    let brighter = gamma_image(img, 0.5).to_rgba8();
+    let mid = brighter.get_pixel(1, 0);
+    assert!(mid[0] > 128 && mid[1] > 64 && mid[2] > 192, "midtones should brighten, got {:?}", mid);
+    assert_eq!(mid[3], 90, "alpha is untouched");
+    // The endpoints stay fixed
+    assert_eq!(brighter.get_pixel(0, 0).0, [0, 0, 0, 90]);
+    assert_eq!(brighter.get_pixel(2, 0).0, [255, 255, 255, 90]);
+}
+
+#[test]
+fn test_probe_image_reads_headers_only() {
+    let img = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(64, 48, image::Rgba([1, 2, 3, 255])));
+    let png = encode_image(&img, ImageFormat::png, 80).unwrap();
+
+    // Cut just past the first IDAT header: no pixel data left to decode
+    let idat = png.windows(4).position(|w| w == b"IDAT").unwrap();
+    let truncated = &png[..idat + 8];
+    assert!(decode_image(truncated).is_err());
+    assert_eq!(probe_image(truncated), Some(((64, 48), image::ColorType::Rgba8)));
+
+    // Favicons are left to a full decode, which picks the largest entry
+    let ico = encode_image(&img, ImageFormat::ico, 80).unwrap();
+    assert_eq!(probe_image(&ico), None);
+}
+
+#[test]
+fn test_icc_profile_round_trips_through_jpeg_and_webp() {
+    let icc: Vec<u8> = (0..301u32).map(|i| (i % 251) as u8).collect();
+    let opaque = image::DynamicImage::ImageRgb8(image::RgbImage::from_pixel(10, 6, image::Rgb([30, 60, 90])));
+    let mut translucent = image::RgbaImage::from_pixel(10, 6, image::Rgba([30, 60, 90, 255]));
+    translucent.put_pixel(0, 0, image::Rgba([0, 0, 0, 10]));
+    let translucent = image::DynamicImage::ImageRgba8(translucent);
+    let lossless = EncodeOptions { webp_lossless: true, ..EncodeOptions::default() };
+
+    let cases = [
+        (&opaque, ImageFormat::jpeg, EncodeOptions::default()),
+        (&opaque, ImageFormat::webp, EncodeOptions::default()),      // simple VP8
+        (&translucent, ImageFormat::webp, EncodeOptions::default()), // VP8X + ALPH
+        (&opaque, ImageFormat::webp, lossless),                      // simple VP8L
+    ];
+    for (img, format, options) in cases {
+        let encoded = encode_image_with(img, format, 80, &options).unwrap();
+        assert_eq!(source_icc_profile(&encoded), None, "encoders strip profiles");
+
+        let tagged = embed_icc_profile(encoded, format, &icc);
+        assert_eq!(source_icc_profile(&tagged).as_deref(), Some(icc.as_slice()), "{} {:?}", format, options);
+        let decoded = image::load_from_memory(&tagged).unwrap();
+        assert_eq!(decoded.dimensions(), (10, 6));
+    }
+}
+
+#[test]
+fn test_set_opacity_scales_alpha() {
+    let img = image::DynamicImage::ImageRgb8(image::RgbImage::from_pixel(4, 4, image::Rgb([200, 100, 50])));
+
+    let half = set_opacity(img.clone(), 0.5).to_rgba8();
+    assert!(half.pixels().all(|p| p.0 == [200, 100, 50, 128]), "got {:?}", half.get_pixel(0, 0));
+
+    assert!(set_opacity(img.clone(), 0.0).to_rgba8().pixels().all(|p| p[3] == 0));
+    assert!(set_opacity(img.clone(), 1.0).to_rgba8().pixels().all(|p| p[3] == 255));
+
+    // Lossy WebP keeps the reduced alpha
+    let webp = encode_image_with(&set_opacity(img, 0.25), ImageFormat::webp, 90, &EncodeOptions::default()).unwrap();
+    let decoded = image::load_from_memory(&webp).unwrap().to_rgba8();
+    let alpha = decoded.get_pixel(1, 1)[3];
+    assert!((60..=68).contains(&alpha), "expected alpha near 64, got {}", alpha);
+}
+
+#[test]
+fn test_encode_fallback_skips_failing_encoder() {
+    let img = image::DynamicImage::new_rgb8(8, 8);
+    let chain = [ImageFormat::avif, ImageFormat::webp, ImageFormat::jpeg];
+    let mut tried = Vec::new();
+    let (bytes, format) = encode_with_fallback(ImageFormat::avif, &chain, |format| {
+        tried.push(format);
+        match format {
+            ImageFormat::avif => Err(imagekit::ImageKitError::TransformError("avif unavailable".into())),
+            _ => encode_image(&img, format, 80),
+        }
+    })
+    .unwrap();
+    assert_eq!(tried, vec![ImageFormat::avif, ImageFormat::webp]);
+    assert_eq!(format, ImageFormat::webp);
+    assert_eq!(imagekit::cache::content_type_from_format(format), "image/webp");
+    assert_eq!(&bytes[8..12], b"WEBP");
+
+    // A format outside the chain has nothing to fall back to
+    let err = encode_with_fallback(ImageFormat::png, &chain, |_| {
+        Err(imagekit::ImageKitError::TransformError("png unavailable".into()))
+    });
+    assert!(err.is_err());
+}
+
+#[test]
+fn test_draw_text_marks_only_its_position() {
+    let blank = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(200, 100, image::Rgba([0, 0, 0, 255])));
+    let out = draw_text(blank, "PREVIEW", TextPosition::Center, 32, [255, 255, 255]).to_rgba8();
+    let lit = |x0: u32, x1: u32, y0: u32, y1: u32| {
+        (y0..y1).flat_map(|y| (x0..x1).map(move |x| (x, y))).filter(|&(x, y)| out.get_pixel(x, y)[0] > 128).count()
+    };
+    assert!(lit(40, 160, 30, 70) > 50, "text should cover the center");
+    assert_eq!(lit(0, 30, 0, 20), 0, "corners stay untouched");
+    assert_eq!(lit(170, 200, 80, 100), 0, "corners stay untouched");
+
+    assert_eq!(sanitize_text("  \u{7}© 2024\n "), Some("© 2024".to_string()));
+    assert_eq!(sanitize_text(" \t "), None);
+    assert_eq!(sanitize_text(&"x".repeat(101)), None);
+}
+
+#[test]
+fn test_auto_quality_rises_with_complexity() {
+    let flat = image::DynamicImage::ImageRgb8(image::RgbImage::from_pixel(64, 64, image::Rgb([120, 160, 200])));
+    // Deterministic pseudo-random noise
+    let mut seed = 0x2545_f491u32;
+    let noisy = image::DynamicImage::ImageRgb8(image::RgbImage::from_fn(64, 64, |_, _| {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        let v = (seed & 0xff) as u8;
+        image::Rgb([v, v, v])
+    }));
+
+    for format in [ImageFormat::jpeg, ImageFormat::webp, ImageFormat::avif] {
+        let (flat_q, noisy_q) = (auto_quality(&flat, format), auto_quality(&noisy, format));
+        assert!(noisy_q > flat_q, "{}: noisy {} should exceed flat {}", format, noisy_q, flat_q);
+    }
+    // AVIF's scale sits lower than JPEG's for the same image
+    assert!(auto_quality(&noisy, ImageFormat::avif) < auto_quality(&noisy, ImageFormat::jpeg));
+}
+
+#[test]
+fn test_parse_crop() {
+    assert_eq!(parse_crop("10,20,30,40"), Some(Crop::Rect { x: 10, y: 20, w: 30, h: 40 }));
+    assert_eq!(parse_crop("400x400,north"), Some(Crop::Anchored { w: 400, h: 400, gravity: Gravity::North }));
+    assert_eq!(parse_crop("400x300"), Some(Crop::Anchored { w: 400, h: 300, gravity: Gravity::Center }));
+    for bad in ["", "10,20,30", "0,0,0,10", "400x0,north", "400x400,up", "400,north", "axb"] {
+        assert_eq!(parse_crop(bad), None, "{:?} should be rejected", bad);
+    }
+}
+
+#[test]
+fn test_crop_gravity_offsets() {
+    let img = image::DynamicImage::new_rgb8(100, 60);
+    let rect = |gravity| crop_rect(&img, Crop::Anchored { w: 40, h: 40, gravity }).unwrap();
+
+    assert_eq!(rect(Gravity::Center), (30, 10, 40, 40));
+    assert_eq!(rect(Gravity::North), (30, 0, 40, 40));
+    assert_eq!(rect(Gravity::South), (30, 20, 40, 40));
+    assert_eq!(rect(Gravity::East), (60, 10, 40, 40));
+    assert_eq!(rect(Gravity::West), (0, 10, 40, 40));
+
+    // Detail only in the bottom-right corner pulls a smart window there
+    let mut detailed = image::RgbImage::new(100, 60);
+    for (x, y, px) in detailed.enumerate_pixels_mut() {
+        if x >= 70 && y >= 30 && (x + y) % 2 == 0 {
+            *px = image::Rgb([255, 255, 255]);
+        }
+    }
+    let detailed = image::DynamicImage::ImageRgb8(detailed);
+    assert_eq!(crop_rect(&detailed, Crop::Anchored { w: 40, h: 40, gravity: Gravity::Smart }), Some((60, 20, 40, 40)));
+
+    // Oversized windows shrink to the image
+    assert_eq!(crop_rect(&img, Crop::Anchored { w: 400, h: 400, gravity: Gravity::North }), Some((0, 0, 100, 60)));
+}
+
+#[test]
+fn test_crop_rect_is_clipped() {
+    let img = image::DynamicImage::new_rgb8(100, 60);
+    assert_eq!(crop_rect(&img, Crop::Rect { x: 80, y: 50, w: 40, h: 40 }), Some((80, 50, 20, 10)));
+    assert_eq!(crop_rect(&img, Crop::Rect { x: 100, y: 0, w: 10, h: 10 }), None);
+
+    let cropped = crop_image(img.clone(), Crop::Rect { x: 10, y: 10, w: 25, h: 15 }).unwrap();
+    assert_eq!(cropped.dimensions(), (25, 15));
+    assert!(crop_image(img, Crop::Rect { x: 0, y: 60, w: 10, h: 10 }).is_err());
+}